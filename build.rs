@@ -0,0 +1,9 @@
+//! Generates the protobuf messages in `proto` from `proto/catfact.proto`, with
+//! a vendored `protoc` so building doesn't need one installed.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto/catfact.proto");
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    prost_build::compile_protos(&["proto/catfact.proto"], &["proto"])?;
+
+    Ok(())
+}
@@ -0,0 +1,158 @@
+//! Checks that a would-be subscriber's email address is one we could actually
+//! deliver to, before it's stored.
+use anyhow::anyhow;
+use lettre::Address;
+use serde::Deserialize;
+use shuttle_secrets::SecretStore;
+
+const MAX_ADDRESS_LEN: usize = 254;
+const MAX_LOCAL_PART_LEN: usize = 64;
+const MAX_LABEL_LEN: usize = 63;
+
+/// Cloudflare's DNS-over-HTTPS resolver, which answers in JSON.
+const DEFAULT_RESOLVER: &str = "https://cloudflare-dns.com/dns-query";
+
+/// DNS record types, as numbered in the resolver's answers.
+const RECORD_A: u16 = 1;
+const RECORD_MX: u16 = 15;
+const RECORD_AAAA: u16 = 28;
+
+/// The DNS response code for a domain that doesn't exist.
+const NXDOMAIN: u16 = 3;
+
+/// Rejects addresses that can't be right, explaining what's wrong with them.
+pub fn check_syntax(email: &str) -> Result<(), String> {
+    if email.is_empty() {
+        return Err("the email address is empty".to_string());
+    }
+    if email.len() > MAX_ADDRESS_LEN {
+        return Err(format!(
+            "the email address is longer than {MAX_ADDRESS_LEN} characters"
+        ));
+    }
+
+    let Some((local, domain)) = email.rsplit_once('@') else {
+        return Err("the email address is missing an @".to_string());
+    };
+    if local.is_empty() {
+        return Err("there's nothing before the @".to_string());
+    }
+    if local.len() > MAX_LOCAL_PART_LEN {
+        return Err(format!(
+            "the part before the @ is longer than {MAX_LOCAL_PART_LEN} characters"
+        ));
+    }
+    if domain.is_empty() {
+        return Err("there's no domain after the @".to_string());
+    }
+
+    let labels: Vec<&str> = domain.split('.').collect();
+    if labels.len() < 2 {
+        return Err(format!("{domain:?} isn't a full domain name"));
+    }
+    for label in &labels {
+        if label.is_empty() || label.len() > MAX_LABEL_LEN {
+            return Err(format!("{domain:?} isn't a valid domain name"));
+        }
+        if label.starts_with('-')
+            || label.ends_with('-')
+            || !label.chars().all(|c| c.is_alphanumeric() || c == '-')
+        {
+            return Err(format!("{domain:?} isn't a valid domain name"));
+        }
+    }
+    if labels
+        .last()
+        .is_some_and(|tld| tld.chars().all(|c| c.is_ascii_digit()))
+    {
+        return Err(format!("{domain:?} isn't a valid domain name"));
+    }
+
+    email
+        .parse::<Address>()
+        .map(|_| ())
+        .map_err(|e| format!("the email address isn't valid: {e}"))
+}
+
+/// Looks up whether an address's domain can receive mail. Enabled by setting
+/// `EMAIL_CHECK_MX` to `true`; `EMAIL_MX_RESOLVER` picks a different
+/// DNS-over-HTTPS resolver that speaks the JSON API.
+#[derive(Clone)]
+pub struct MxCheck {
+    resolver: String,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct DnsResponse {
+    #[serde(rename = "Status")]
+    status: u16,
+    #[serde(default, rename = "Answer")]
+    answer: Vec<DnsAnswer>,
+}
+
+#[derive(Deserialize)]
+struct DnsAnswer {
+    #[serde(rename = "type")]
+    record_type: u16,
+}
+
+impl MxCheck {
+    pub fn from_secrets(store: &SecretStore) -> Option<Self> {
+        if store.get("EMAIL_CHECK_MX").as_deref() != Some("true") {
+            return None;
+        }
+
+        Some(Self {
+            resolver: store
+                .get("EMAIL_MX_RESOLVER")
+                .unwrap_or_else(|| DEFAULT_RESOLVER.to_string()),
+            client: reqwest::Client::new(),
+        })
+    }
+
+    /// Checks that the domain has an MX record, or failing that an address
+    /// record, which mail servers fall back to. Errs if the resolver can't
+    /// be reached, which callers shouldn't hold against the address.
+    pub async fn check(&self, email: &str) -> Result<Result<(), String>, anyhow::Error> {
+        let Some((_, domain)) = email.rsplit_once('@') else {
+            return Ok(Err("the email address is missing an @".to_string()));
+        };
+
+        let mx = self.lookup(domain, "MX").await?;
+        if mx.status == NXDOMAIN {
+            return Ok(Err(format!("the domain {domain:?} doesn't exist")));
+        }
+        if has_record(&mx, &[RECORD_MX]) {
+            return Ok(Ok(()));
+        }
+
+        for record_type in ["A", "AAAA"] {
+            let res = self.lookup(domain, record_type).await?;
+            if has_record(&res, &[RECORD_A, RECORD_AAAA]) {
+                return Ok(Ok(()));
+            }
+        }
+
+        Ok(Err(format!("the domain {domain:?} doesn't accept email")))
+    }
+
+    async fn lookup(&self, domain: &str, record_type: &str) -> Result<DnsResponse, anyhow::Error> {
+        self.client
+            .get(&self.resolver)
+            .query(&[("name", domain), ("type", record_type)])
+            .header("accept", "application/dns-json")
+            .send()
+            .await
+            .map_err(|e| anyhow!("couldn't reach the DNS resolver: {e}"))?
+            .json()
+            .await
+            .map_err(|e| anyhow!("unexpected response from the DNS resolver: {e}"))
+    }
+}
+
+fn has_record(res: &DnsResponse, types: &[u16]) -> bool {
+    res.answer
+        .iter()
+        .any(|answer| types.contains(&answer.record_type))
+}
@@ -0,0 +1,250 @@
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::delivery::DeliveryWindow;
+use crate::store::{self, FromRow, Row, Statement};
+use crate::{error::ApiError, segments, AppState};
+
+#[derive(Serialize)]
+pub struct SubscriberAnalytics {
+    /// Current number of confirmed subscribers.
+    total: i64,
+    /// Current confirmed subscribers by delivery window.
+    by_delivery_window: BTreeMap<String, i64>,
+    daily: Vec<DailyCounts>,
+}
+
+/// Signups and unsubscribes on one day, and the running total at the end of it.
+#[derive(Serialize)]
+pub struct DailyCounts {
+    date: String,
+    signups: i64,
+    unsubscribes: i64,
+    total: i64,
+}
+
+impl FromRow for DailyCounts {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            date: store::text(row, 0)?,
+            signups: store::integer(row, 1)?,
+            unsubscribes: store::integer(row, 2)?,
+            total: 0,
+        })
+    }
+}
+
+struct WindowCount {
+    delivery_hour: i64,
+    count: i64,
+}
+
+impl FromRow for WindowCount {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            delivery_hour: store::integer(row, 0)?,
+            count: store::integer(row, 1)?,
+        })
+    }
+}
+
+/// `GET /admin/analytics/subscribers` - subscriber growth, from the
+/// `subscriber_events` log and the counts kept for events it no longer has.
+pub async fn subscriber_analytics(
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let results = state
+        .db
+        .batch([
+            "SELECT day, sum(signups), sum(unsubscribes) FROM (
+                SELECT date(occurred_at) AS day,
+                    event = 'subscribed' AS signups,
+                    event = 'unsubscribed' AS unsubscribes
+                FROM subscriber_events
+                UNION ALL
+                SELECT day,
+                    CASE kind WHEN 'subscribed' THEN count ELSE 0 END,
+                    CASE kind WHEN 'unsubscribed' THEN count ELSE 0 END
+                FROM event_rollups WHERE source = 'subscriber_events'
+            ) GROUP BY day ORDER BY day",
+            "SELECT delivery_hour, count(*) FROM subscribers WHERE confirmed = 1 GROUP BY delivery_hour",
+        ])
+        .await?;
+
+    let (Some(daily), Some(windows)) = (results.first(), results.get(1)) else {
+        return Err(ApiError::internal("Missing analytics results"));
+    };
+
+    let mut daily: Vec<DailyCounts> = store::rows(daily)?;
+    let windows: Vec<WindowCount> = store::rows(windows)?;
+
+    let mut running = 0;
+    for day in &mut daily {
+        running += day.signups - day.unsubscribes;
+        day.total = running;
+    }
+
+    let by_delivery_window = windows
+        .iter()
+        .map(|window| {
+            let name = match DeliveryWindow::from_hour(window.delivery_hour) {
+                Some(delivery_window) => delivery_window.name().to_string(),
+                None => format!("{:02}:00", window.delivery_hour),
+            };
+            (name, window.count)
+        })
+        .collect();
+
+    Ok(Json(SubscriberAnalytics {
+        total: windows.iter().map(|window| window.count).sum(),
+        by_delivery_window,
+        daily,
+    }))
+}
+
+#[derive(Serialize, Default)]
+pub struct DomainCounts {
+    domain: String,
+    /// Confirmed subscribers.
+    subscribers: i64,
+    /// Signed up but not confirmed yet.
+    unconfirmed: i64,
+    /// Suppressed after a bounce, complaint or by hand.
+    suppressed: i64,
+    /// Spam complaints in the period.
+    complaints: i64,
+    /// Daily emails sent in the period.
+    sent: i64,
+    /// Daily emails in the period that failed at least once.
+    failed: i64,
+    /// Daily emails in the period that were given up on.
+    dead: i64,
+}
+
+#[derive(Serialize)]
+pub struct DomainAnalytics {
+    days: u32,
+    domains: Vec<DomainCounts>,
+}
+
+#[derive(Deserialize)]
+pub struct DomainQuery {
+    /// How far back complaints and sends are counted. Defaults to 7.
+    days: Option<u32>,
+    limit: Option<u32>,
+}
+
+struct DomainCount {
+    domain: String,
+    counts: Vec<i64>,
+}
+
+impl FromRow for DomainCount {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            domain: store::text(row, 0)?,
+            counts: (1..row.values.len())
+                .map(|idx| store::integer(row, idx))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+const DEFAULT_DOMAIN_DAYS: u32 = 7;
+const DEFAULT_DOMAIN_LIMIT: u32 = 50;
+const MAX_DOMAIN_LIMIT: u32 = 500;
+
+/// `GET /admin/analytics/domains?days=7&limit=50` - subscribers and
+/// deliverability by email domain, biggest first, for spotting a provider
+/// that's started bouncing or junking our mail.
+pub async fn domain_analytics(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<DomainQuery>,
+) -> Result<Json<DomainAnalytics>, ApiError> {
+    let days = query.days.unwrap_or(DEFAULT_DOMAIN_DAYS).clamp(1, 365);
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_DOMAIN_LIMIT)
+        .clamp(1, MAX_DOMAIN_LIMIT);
+    let since = format!("-{days} days");
+
+    let results = state
+        .db
+        .batch([
+            Statement::new(format!(
+                "SELECT {} AS domain, sum(confirmed = 1), sum(confirmed = 0)
+                FROM subscribers GROUP BY domain",
+                segments::domain_of("email")
+            )),
+            Statement::new(format!(
+                "SELECT {} AS domain, count(*) FROM suppressions GROUP BY domain",
+                segments::domain_of("email")
+            )),
+            Statement::with_args(
+                format!(
+                    "SELECT {} AS domain, count(*) FROM complaint_events
+                    WHERE received_at >= datetime('now', ?) GROUP BY domain",
+                    segments::domain_of("email")
+                ),
+                &[&since],
+            ),
+            Statement::with_args(
+                format!(
+                    "SELECT {} AS domain, sum(status = 'sent'), sum(attempts > 0), sum(status = 'dead')
+                    FROM email_outbox WHERE created_at >= datetime('now', ?) GROUP BY domain",
+                    segments::domain_of("recipient")
+                ),
+                &[&since],
+            ),
+        ])
+        .await?;
+    let (Some(subscribers), Some(suppressed), Some(complaints), Some(sends)) = (
+        results.first(),
+        results.get(1),
+        results.get(2),
+        results.get(3),
+    ) else {
+        return Err(ApiError::internal("Missing analytics results"));
+    };
+
+    let mut by_domain: BTreeMap<String, DomainCounts> = BTreeMap::new();
+    let mut add = |res, apply: fn(&mut DomainCounts, &[i64])| -> Result<(), ApiError> {
+        for row in store::rows::<DomainCount>(res)? {
+            let counts = by_domain
+                .entry(row.domain.clone())
+                .or_insert_with(|| DomainCounts {
+                    domain: row.domain,
+                    ..DomainCounts::default()
+                });
+            apply(counts, &row.counts);
+        }
+        Ok(())
+    };
+    add(subscribers, |counts, row| {
+        counts.subscribers = row[0];
+        counts.unconfirmed = row[1];
+    })?;
+    add(suppressed, |counts, row| counts.suppressed = row[0])?;
+    add(complaints, |counts, row| counts.complaints = row[0])?;
+    add(sends, |counts, row| {
+        counts.sent = row[0];
+        counts.failed = row[1];
+        counts.dead = row[2];
+    })?;
+
+    let mut domains: Vec<DomainCounts> = by_domain.into_values().collect();
+    domains.sort_by(|a, b| {
+        (b.subscribers + b.unconfirmed)
+            .cmp(&(a.subscribers + a.unconfirmed))
+            .then(b.sent.cmp(&a.sent))
+    });
+    domains.truncate(limit as usize);
+
+    Ok(Json(DomainAnalytics { days, domains }))
+}
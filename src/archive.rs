@@ -0,0 +1,349 @@
+//! The public archive: every fact of the day so far, on a month calendar at
+//! `GET /archive` (this month, or another with `?month=2024-02`) that links
+//! each day to its page at `GET /archive/:date`. It's read from
+//! `daily_facts`, so a day with no stored fact is left blank, and tomorrow's
+//! fact, which is picked just after midnight, isn't shown until its day.
+//!
+//! Both take `?tag=kittens` to only show days whose fact has that tag, and
+//! keep it on the links between months and days.
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Html,
+};
+use chrono::{Datelike, Duration, Months, NaiveDate};
+use serde::Deserialize;
+use std::sync::Arc;
+use utoipa::IntoParams;
+
+use crate::store::{self, Statement, Value};
+use crate::{html, sanitize, tags, AppState};
+
+const TITLE: &str = "Cat Facts - Archive";
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MonthQuery {
+    /// The month to show, like `2024-02`. Defaults to this month.
+    month: Option<String>,
+    /// Only show days whose fact has this tag.
+    tag: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TagQuery {
+    /// Only link to days whose fact has this tag.
+    tag: Option<String>,
+}
+
+fn first_of_month(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).unwrap_or(date)
+}
+
+fn parse_month(month: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(&format!("{}-01", month.trim()), "%Y-%m-%d").ok()
+}
+
+fn month_link(month: NaiveDate, tag: Option<&str>, text: &str) -> String {
+    let tag = tag.map_or_else(String::new, |tag| format!("&amp;tag={tag}"));
+    format!(
+        r#"<a href="/archive?month={}{tag}">{text}</a>"#,
+        month.format("%Y-%m")
+    )
+}
+
+fn day_link(date: NaiveDate, tag: Option<&str>, text: &str) -> String {
+    let tag = tag.map_or_else(String::new, |tag| format!("?tag={tag}"));
+    format!(r#"<a href="/archive/{date}{tag}">{text}</a>"#)
+}
+
+/// The tag to filter by, if there's one, checked so it's safe in links.
+fn parse_tag(tag: Option<&str>) -> Result<Option<String>, (StatusCode, Html<String>)> {
+    tag.map(|tag| tags::normalize_one(tag).map_err(|_| not_found("That isn't a tag.")))
+        .transpose()
+}
+
+/// `daily_facts` joined to their facts, with `?1` the tag to filter by or
+/// `NULL`.
+fn tagged_days() -> String {
+    format!(
+        "daily_facts JOIN catfacts ON catfacts.id = daily_facts.catfact_id
+        WHERE (?1 IS NULL OR {})",
+        tags::HAS_TAG
+    )
+}
+
+fn not_found(message: &str) -> (StatusCode, Html<String>) {
+    (
+        StatusCode::NOT_FOUND,
+        Html(html::page(TITLE, &format!("<p>{message}</p>"))),
+    )
+}
+
+/// `GET /archive?month=2024-02&tag=kittens` - a calendar of the month's facts
+/// of the day.
+#[utoipa::path(
+    get,
+    path = "/archive",
+    tag = "facts",
+    params(MonthQuery),
+    responses(
+        (status = 200, description = "The month's calendar", body = String, content_type = "text/html"),
+        (status = 404, description = "The month or tag isn't valid, or the month hasn't started", body = String, content_type = "text/html"),
+    )
+)]
+pub async fn archive_page(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<MonthQuery>,
+) -> Result<Html<String>, (StatusCode, Html<String>)> {
+    let tag = parse_tag(query.tag.as_deref())?;
+    let today = state.zone.today();
+    let this_month = first_of_month(today);
+    let month = match query.month.as_deref() {
+        Some(month) => parse_month(month)
+            .ok_or_else(|| not_found("That isn't a month. Months look like 2024-02."))?,
+        None => this_month,
+    };
+    if month > this_month {
+        return Err(not_found("That month hasn't happened yet."));
+    }
+    let next_month = month + Months::new(1);
+    let last_day = (next_month - Duration::days(1)).min(today);
+
+    let tag_arg = tag.as_deref().map_or(Value::Null, Value::from);
+    let results = state
+        .db
+        .batch([
+            Statement::with_args(
+                format!(
+                    "SELECT daily_facts.date FROM {}
+                    AND daily_facts.date >= ?2 AND daily_facts.date <= ?3
+                    ORDER BY daily_facts.date",
+                    tagged_days()
+                ),
+                &[
+                    tag_arg.clone(),
+                    Value::from(month.to_string()),
+                    Value::from(last_day.to_string()),
+                ],
+            ),
+            Statement::with_args(
+                format!("SELECT min(daily_facts.date) FROM {}", tagged_days()),
+                &[tag_arg],
+            ),
+        ])
+        .await
+        .map_err(|e| html::server_error(TITLE, e))?;
+    let (Some(days), Some(earliest)) = (results.first(), results.get(1)) else {
+        return Err(html::server_error(TITLE, "Missing archive results"));
+    };
+    let days = store::rows::<String>(days).map_err(|e| html::server_error(TITLE, e))?;
+    // `min` over no rows is a single null.
+    let earliest = earliest
+        .rows
+        .first()
+        .and_then(|row| store::optional_text(row, 0).ok().flatten())
+        .and_then(|date| NaiveDate::parse_from_str(&date, "%Y-%m-%d").ok())
+        .map(first_of_month);
+
+    Ok(Html(render_month(
+        month,
+        this_month,
+        earliest,
+        tag.as_deref(),
+        &days,
+    )))
+}
+
+fn render_month(
+    month: NaiveDate,
+    this_month: NaiveDate,
+    earliest: Option<NaiveDate>,
+    tag: Option<&str>,
+    days: &[String],
+) -> String {
+    let next_month = month + Months::new(1);
+    let mut rows = String::from("<tr>");
+    // Monday-first, with blanks before the 1st.
+    for _ in 0..month.weekday().num_days_from_monday() {
+        rows.push_str("<td></td>");
+    }
+    let mut day = month;
+    while day < next_month {
+        if day != month && day.weekday().num_days_from_monday() == 0 {
+            rows.push_str("</tr>\n<tr>");
+        }
+        let number = day.day().to_string();
+        if days.contains(&day.to_string()) {
+            rows.push_str(&format!("<td>{}</td>", day_link(day, tag, &number)));
+        } else {
+            rows.push_str(&format!("<td>{number}</td>"));
+        }
+        day += Duration::days(1);
+    }
+    rows.push_str("</tr>");
+
+    let mut nav = Vec::new();
+    if earliest.is_some_and(|earliest| earliest < month) {
+        let previous = month - Months::new(1);
+        nav.push(month_link(
+            previous,
+            tag,
+            &format!("← {}", previous.format("%B %Y")),
+        ));
+    }
+    if next_month <= this_month {
+        nav.push(month_link(
+            next_month,
+            tag,
+            &format!("{} →", next_month.format("%B %Y")),
+        ));
+    }
+
+    let (heading, summary) = match tag {
+        Some(tag) => (
+            format!("Facts of the day tagged {tag}, {}", month.format("%B %Y")),
+            format!(
+                "<p>{}<a href=\"/archive?month={}\">Show every day</a> or follow <a href=\"/feed.xml?tag={tag}\">this tag's feed</a>.</p>\n",
+                if days.is_empty() {
+                    "There are no facts of the day with this tag in this month. "
+                } else {
+                    ""
+                },
+                month.format("%Y-%m"),
+            ),
+        ),
+        None => (
+            format!("Facts of the day, {}", month.format("%B %Y")),
+            if days.is_empty() {
+                "<p>There are no facts of the day in this month.</p>\n".to_string()
+            } else {
+                String::new()
+            },
+        ),
+    };
+
+    html::page(
+        TITLE,
+        &format!(
+            "<h2>{heading}</h2>\n{summary}<table>\n<thead><tr><th>Mon</th><th>Tue</th><th>Wed</th><th>Thu</th><th>Fri</th><th>Sat</th><th>Sun</th></tr></thead>\n<tbody>\n{rows}\n</tbody>\n</table>\n<nav><p>{}</p></nav>\n<p><a href=\"/subscribe\">Get a cat fact in your inbox every day</a></p>",
+            nav.join(" | "),
+        ),
+    )
+}
+
+/// `GET /archive/:date?tag=kittens` - the fact of the day for `date`, e.g.
+/// `2024-02-03`. With a tag, it's only shown if it has the tag, and the
+/// links either side go to the nearest days with it.
+#[utoipa::path(
+    get,
+    path = "/archive/{date}",
+    tag = "facts",
+    params(("date" = String, Path, description = "A day, like `2024-02-03`"), TagQuery),
+    responses(
+        (status = 200, description = "The day's fact", body = String, content_type = "text/html"),
+        (status = 404, description = "No fact (with the tag) for that day (yet)", body = String, content_type = "text/html"),
+    )
+)]
+pub async fn day_page(
+    State(state): State<Arc<AppState>>,
+    Path(date): Path<String>,
+    Query(query): Query<TagQuery>,
+) -> Result<Html<String>, (StatusCode, Html<String>)> {
+    let tag = parse_tag(query.tag.as_deref())?;
+    let no_fact = || not_found("There's no fact of the day for that date.");
+    let date = NaiveDate::parse_from_str(&date, "%Y-%m-%d").map_err(|_| no_fact())?;
+    let today = state.zone.today();
+    if date > today {
+        return Err(no_fact());
+    }
+
+    let fact = match state.db.fact_of_day(date).await {
+        Ok(Some(fact)) => fact,
+        Ok(None) => return Err(no_fact()),
+        Err(e) => return Err(html::server_error(TITLE, e)),
+    };
+
+    let tag_arg = tag.as_deref().map_or(Value::Null, Value::from);
+    let results = state
+        .db
+        .batch([
+            Statement::with_args(
+                format!(
+                    "SELECT max(daily_facts.date) FROM {} AND daily_facts.date < ?2",
+                    tagged_days()
+                ),
+                &[tag_arg.clone(), Value::from(date.to_string())],
+            ),
+            Statement::with_args(
+                format!(
+                    "SELECT min(daily_facts.date) FROM {}
+                    AND daily_facts.date > ?2 AND daily_facts.date <= ?3",
+                    tagged_days()
+                ),
+                &[
+                    tag_arg.clone(),
+                    Value::from(date.to_string()),
+                    Value::from(today.to_string()),
+                ],
+            ),
+            Statement::with_args(
+                format!(
+                    "SELECT count(*) FROM catfacts WHERE id = ?2 AND (?1 IS NULL OR {})",
+                    tags::HAS_TAG
+                ),
+                &[tag_arg, Value::from(fact.id)],
+            ),
+        ])
+        .await
+        .map_err(|e| html::server_error(TITLE, e))?;
+    let tagged = results
+        .get(2)
+        .and_then(|res| res.rows.first())
+        .and_then(|row| store::integer(row, 0).ok())
+        .unwrap_or(0);
+    if tagged == 0 {
+        return Err(not_found(
+            "The fact of the day for that date doesn't have that tag.",
+        ));
+    }
+    let adjacent = |idx: usize| {
+        results
+            .get(idx)
+            .and_then(|res| res.rows.first())
+            .and_then(|row| store::optional_text(row, 0).ok().flatten())
+            .and_then(|date| NaiveDate::parse_from_str(&date, "%Y-%m-%d").ok())
+    };
+
+    let mut nav = Vec::new();
+    if let Some(previous) = adjacent(0) {
+        nav.push(day_link(
+            previous,
+            tag.as_deref(),
+            &format!("← {}", previous.format("%-d %B")),
+        ));
+    }
+    nav.push(month_link(
+        first_of_month(date),
+        tag.as_deref(),
+        &format!("{}", date.format("%B %Y")),
+    ));
+    if let Some(next) = adjacent(1) {
+        nav.push(day_link(
+            next,
+            tag.as_deref(),
+            &format!("{} →", next.format("%-d %B")),
+        ));
+    }
+
+    Ok(Html(html::page(
+        &format!("Cat fact of the day for {}", date.format("%-d %B %Y")),
+        &format!(
+            "<h2>The cat fact of the day for {}</h2>\n<p>{}</p>\n<nav><p>{}</p></nav>\n<p><a href=\"/subscribe\">Get a cat fact in your inbox every day</a></p>",
+            date.format("%-d %B %Y"),
+            sanitize::html_text(&fact.fact),
+            nav.join(" | "),
+        ),
+    )))
+}
@@ -0,0 +1,76 @@
+//! A record of consequential admin actions - who did what, and when.
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::store::{self, FromRow, Row, Statement};
+use crate::{error::ApiError, AppState};
+
+const DEFAULT_LIMIT: u32 = 50;
+const MAX_LIMIT: u32 = 500;
+
+/// The statement that records `action`, to run in the same batch as the
+/// change itself so one is never stored without the other.
+pub fn entry(actor: &str, action: &str, detail: &str) -> Statement {
+    Statement::with_args(
+        "INSERT INTO audit_log (actor, action, detail) VALUES (?, ?, ?)",
+        &[actor, action, detail],
+    )
+}
+
+/// Like `entry`, but only recorded if the statement just before it in the
+/// batch changed something - so an action that turned out to be a no-op
+/// isn't logged as if it happened.
+pub fn entry_if_changed(actor: &str, action: &str, detail: &str) -> Statement {
+    Statement::with_args(
+        "INSERT INTO audit_log (actor, action, detail) SELECT ?, ?, ? WHERE changes() > 0",
+        &[actor, action, detail],
+    )
+}
+
+#[derive(Serialize)]
+pub struct AuditEntry {
+    id: i64,
+    actor: String,
+    action: String,
+    detail: String,
+    created_at: String,
+}
+
+impl FromRow for AuditEntry {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            id: store::integer(row, 0)?,
+            actor: store::text(row, 1)?,
+            action: store::text(row, 2)?,
+            detail: store::text(row, 3)?,
+            created_at: store::text(row, 4)?,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    limit: Option<u32>,
+}
+
+/// `GET /admin/audit-log?limit=50` - the most recent entries, newest first.
+pub async fn audit_log(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<Vec<AuditEntry>>, ApiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let entries = state
+        .db
+        .execute(Statement::with_args(
+            "SELECT id, actor, action, detail, created_at FROM audit_log ORDER BY id DESC LIMIT ?",
+            &[limit],
+        ))
+        .await
+        .and_then(|res| store::rows::<AuditEntry>(&res))?;
+
+    Ok(Json(entries))
+}
@@ -0,0 +1,229 @@
+//! API-key authentication for admin routes. A request is authorized by the
+//! `ADMIN_TOKEN` secret or by any unrevoked admin key in the `api_keys` table,
+//! sent as `Authorization: Bearer <key>` or `X-Api-Key: <key>`.
+//!
+//! Keys issued with a rate limit tier (`free` or `partner`) are for client
+//! apps instead: they can't use admin routes, and only raise the app's rate
+//! limits (see `rate_limit`).
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path, State},
+    http::{header, request::Parts, HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::sync::Arc;
+
+use crate::rate_limit::Tier;
+use crate::store::{self, FromRow, Row, Statement, Value};
+use crate::strict::StrictJson;
+use crate::{crypto, error::ApiError, AppState};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Extractor for individual handlers that need an admin caller. Routes in the
+/// `/admin` group are already covered by `require_admin`, which leaves the
+/// caller here for handlers that need to know who it was.
+#[derive(Clone)]
+pub struct Admin {
+    /// `ADMIN_TOKEN`, or the name the API key was issued under.
+    pub name: String,
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for Admin {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        if let Some(admin) = parts.extensions.get::<Admin>() {
+            return Ok(admin.clone());
+        }
+
+        authorize(state, &parts.headers).await
+    }
+}
+
+/// Middleware guarding the `/admin` router group.
+pub async fn require_admin<B>(
+    State(state): State<Arc<AppState>>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    match authorize(&state, request.headers()).await {
+        Ok(admin) => {
+            request.extensions_mut().insert(admin);
+            next.run(request).await
+        }
+        Err(rejection) => rejection.into_response(),
+    }
+}
+
+async fn authorize(state: &AppState, headers: &HeaderMap) -> Result<Admin, ApiError> {
+    let Some(key) = provided_key(headers) else {
+        return Err(ApiError::Unauthorized("Missing API key".to_string()));
+    };
+
+    if let Some(token) = &state.admin_token {
+        if crypto::constant_time_eq(token, key) {
+            return Ok(Admin {
+                name: "ADMIN_TOKEN".to_string(),
+            });
+        }
+    }
+
+    let name = state
+        .db
+        .execute(Statement::with_args(
+            "SELECT name FROM api_keys WHERE key_hash = ? AND revoked_at IS NULL AND tier IS NULL",
+            &[hash_key(key)],
+        ))
+        .await
+        .and_then(|res| store::first::<String>(&res))?;
+
+    name.map(|name| Admin { name })
+        .ok_or_else(|| ApiError::Unauthorized("Invalid API key".to_string()))
+}
+
+pub fn provided_key(headers: &HeaderMap) -> Option<&str> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let api_key = headers
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok());
+
+    bearer
+        .or(api_key)
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+}
+
+/// Keys are random, so a plain hash is enough to avoid storing them as-is.
+pub fn hash_key(key: &str) -> String {
+    crypto::hex(&Sha1::digest(key.as_bytes()))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewApiKey {
+    /// Who or what the key is for, e.g. "staging sync".
+    name: String,
+    /// `free` or `partner` for a client app's key, which only raises its rate
+    /// limits. Without one, the key is an admin key.
+    tier: Option<Tier>,
+}
+
+#[derive(Serialize)]
+pub struct CreatedApiKey {
+    id: Option<i64>,
+    name: String,
+    /// Only ever shown here; the database keeps a hash.
+    key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tier: Option<Tier>,
+}
+
+/// `POST /admin/api-keys` - issues a new admin API key.
+pub async fn create_api_key(
+    State(state): State<Arc<AppState>>,
+    StrictJson(new_key): StrictJson<NewApiKey>,
+) -> Result<impl IntoResponse, ApiError> {
+    let key = state
+        .db
+        .execute("SELECT lower(hex(randomblob(24)))")
+        .await
+        .and_then(|res| store::first::<String>(&res))?
+        .ok_or_else(|| ApiError::internal("Couldn't generate a key"))?;
+
+    if new_key.tier == Some(Tier::Anonymous) {
+        return Err(ApiError::Validation(
+            "A key's tier is free or partner".to_string(),
+        ));
+    }
+
+    let res = state
+        .db
+        .execute(Statement::with_args(
+            "INSERT INTO api_keys (name, key_hash, tier) VALUES (?, ?, ?)",
+            &[
+                Value::from(new_key.name.as_str()),
+                Value::from(hash_key(&key)),
+                Value::from(new_key.tier.map(|tier| tier.name().to_string())),
+            ],
+        ))
+        .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedApiKey {
+            id: res.last_insert_rowid,
+            name: new_key.name,
+            key,
+            tier: new_key.tier,
+        }),
+    ))
+}
+
+#[derive(Serialize)]
+pub struct ApiKey {
+    id: i64,
+    name: String,
+    /// For a client app's key; admin keys have none.
+    tier: Option<String>,
+    created_at: String,
+    revoked_at: Option<String>,
+}
+
+impl FromRow for ApiKey {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            id: store::integer(row, 0)?,
+            name: store::text(row, 1)?,
+            tier: store::optional_text(row, 2)?,
+            created_at: store::text(row, 3)?,
+            revoked_at: store::optional_text(row, 4)?,
+        })
+    }
+}
+
+/// `GET /admin/api-keys` - every key that's been issued, without the keys.
+pub async fn list_api_keys(
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let keys = state
+        .db
+        .execute("SELECT id, name, tier, created_at, revoked_at FROM api_keys ORDER BY id")
+        .await
+        .and_then(|res| store::rows::<ApiKey>(&res))?;
+
+    Ok(Json(keys))
+}
+
+/// `DELETE /admin/api-keys/:id` - revokes a key.
+pub async fn revoke_api_key(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, ApiError> {
+    let res = state
+        .db
+        .execute(Statement::with_args(
+            "UPDATE api_keys SET revoked_at = current_timestamp WHERE id = ? AND revoked_at IS NULL",
+            &[Value::from(id)],
+        ))
+        .await?;
+
+    if res.rows_affected == 0 {
+        Err(ApiError::NotFound(format!(
+            "There's no active API key {id}"
+        )))
+    } else {
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
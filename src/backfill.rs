@@ -0,0 +1,94 @@
+//! Backfills that rewrite existing rows, run in the background once the
+//! service is up rather than holding up boot. Each batch is its own short
+//! transaction, with a pause in between, so requests writing to the same
+//! table never wait long.
+use std::sync::Arc;
+use tokio::time::{sleep, Duration};
+
+use crate::store::{self, Store};
+use crate::{fact_id, schema, slug};
+
+const BATCH_SIZE: u32 = 100;
+const PAUSE: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Copy)]
+enum Backfill {
+    Slugs,
+    FactIds,
+}
+
+impl Backfill {
+    const ALL: [Backfill; 2] = [Self::Slugs, Self::FactIds];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Slugs => "slugs",
+            Self::FactIds => "fact ids",
+        }
+    }
+
+    /// Counts the rows still to backfill.
+    fn remaining(&self) -> &'static str {
+        match self {
+            Self::Slugs => "SELECT count(*) FROM catfacts WHERE slug IS NULL",
+            Self::FactIds => "SELECT count(*) FROM catfacts WHERE fact_id IS NULL",
+        }
+    }
+
+    /// Backfills up to `limit` rows, returning how many it updated.
+    async fn batch(&self, db: &dyn Store, limit: u32) -> Result<usize, anyhow::Error> {
+        match self {
+            Self::Slugs => slug::backfill_batch(db, limit).await,
+            Self::FactIds => fact_id::backfill_batch(db, limit).await,
+        }
+    }
+}
+
+/// Runs every backfill to completion. The post-deploy migrations, which may
+/// rely on the backfilled data, wait for an admin to ask for them (see
+/// `migrations`).
+pub async fn run(db: Arc<dyn Store>) {
+    for backfill in Backfill::ALL {
+        let mut total = 0;
+        loop {
+            match backfill.batch(&*db, BATCH_SIZE).await {
+                Ok(0) => break,
+                Ok(updated) => total += updated,
+                Err(e) => {
+                    tracing::error!(
+                        "Stopped backfilling {} after {total} rows: {e}",
+                        backfill.name()
+                    );
+                    return;
+                }
+            }
+            sleep(PAUSE).await;
+        }
+
+        if total > 0 {
+            tracing::info!("Backfilled {} for {total} facts", backfill.name());
+        }
+    }
+
+    match schema::pending(&*db, schema::Phase::PostDeploy).await {
+        Ok(pending) if !pending.is_empty() => tracing::info!(
+            "Post-deploy migrations {} are waiting for POST /admin/migrations/post-deploy",
+            pending.join(", ")
+        ),
+        Ok(_) => {}
+        Err(e) => tracing::error!("Couldn't check for post-deploy migrations: {e}"),
+    }
+}
+
+/// The backfills that still have rows to rewrite.
+pub async fn unfinished(db: &dyn Store) -> Result<Vec<&'static str>, anyhow::Error> {
+    let mut unfinished = Vec::new();
+    for backfill in Backfill::ALL {
+        let res = db.execute(backfill.remaining()).await?;
+        if store::first::<i64>(&res)?.unwrap_or(0) > 0 {
+            unfinished.push(backfill.name());
+        }
+    }
+
+    Ok(unfinished)
+}
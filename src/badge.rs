@@ -0,0 +1,68 @@
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use std::sync::Arc;
+
+use crate::{daily, error::ApiError, html::escape, AppState};
+
+const LABEL: &str = "cat fact";
+const MAX_MESSAGE_CHARS: usize = 80;
+
+// Rough per-character width of 11px Verdana, which is what shields.io badges use.
+const CHAR_WIDTH: usize = 7;
+const PADDING: usize = 10;
+
+/// `GET /badge.svg` - the fact of the day as a shields.io-style badge, for
+/// embedding in READMEs.
+#[utoipa::path(
+    get,
+    path = "/badge.svg",
+    tag = "facts",
+    responses((status = 200, description = "An SVG badge", body = String, content_type = "image/svg+xml"))
+)]
+pub async fn fact_badge(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse, ApiError> {
+    let fact = daily::fact_for_date(&*state.db, &state.ranking, state.zone.today())
+        .await?
+        .unwrap_or_else(|| "no facts yet".to_string());
+
+    Ok((
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "image/svg+xml")],
+        render(LABEL, &truncate(&fact, MAX_MESSAGE_CHARS)),
+    ))
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+
+    let truncated: String = text.chars().take(max_chars - 1).collect();
+    format!("{}…", truncated.trim_end())
+}
+
+/// Renders a flat two-part badge in the same layout shields.io produces.
+pub fn render(label: &str, message: &str) -> String {
+    let label_width = label.chars().count() * CHAR_WIDTH + PADDING;
+    let message_width = message.chars().count() * CHAR_WIDTH + PADDING;
+    let width = label_width + message_width;
+    let label_x = label_width / 2;
+    let message_x = label_width + message_width / 2;
+    let label = escape(label);
+    let message = escape(message);
+
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="20" role="img" aria-label="{label}: {message}">
+<title>{label}: {message}</title>
+<linearGradient id="s" x2="0" y2="100%"><stop offset="0" stop-color="#bbb" stop-opacity=".1"/><stop offset="1" stop-opacity=".1"/></linearGradient>
+<clipPath id="r"><rect width="{width}" height="20" rx="3" fill="#fff"/></clipPath>
+<g clip-path="url(#r)"><rect width="{label_width}" height="20" fill="#555"/><rect x="{label_width}" width="{message_width}" height="20" fill="#e05d44"/><rect width="{width}" height="20" fill="url(#s)"/></g>
+<g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11">
+<text x="{label_x}" y="15" fill="#010101" fill-opacity=".3">{label}</text><text x="{label_x}" y="14">{label}</text>
+<text x="{message_x}" y="15" fill="#010101" fill-opacity=".3">{message}</text><text x="{message_x}" y="14">{message}</text>
+</g>
+</svg>"##
+    )
+}
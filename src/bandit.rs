@@ -0,0 +1,180 @@
+//! An experimental ranker that learns which facts people like, turned on with
+//! `RANKING_STRATEGY=bandit`. It's a multi-armed bandit using Thompson
+//! sampling: each fact's chance of engaging someone is modelled as a Beta
+//! distribution over what it's earned so far, and each pick draws from every
+//! candidate's and takes the highest draws. Facts with little history have
+//! wide distributions, so they still get tried; facts that have done well
+//! keep winning without locking everything else out.
+//!
+//! A fact's successes are its email opens (see `opens`), net upvotes and net
+//! promoters from feedback; its failures are sends nobody opened, net
+//! downvotes and net detractors.
+//!
+//! To tell whether it's doing better than chance, a share of days'
+//! facts (`RANKING_CONTROL_SHARE`, by default half) are still picked
+//! uniformly as a control, and `GET /admin/ranking/experiment` compares the
+//! two.
+use axum::{
+    async_trait,
+    extract::{Query, State},
+    Json,
+};
+use chrono::Duration;
+use rand_distr::{Beta, Distribution};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::ranking::{Candidate, Ranker, Selection};
+use crate::store::{self, FromRow, Row, Statement, Value};
+use crate::{error::ApiError, votes, AppState};
+
+const DEFAULT_REPORT_DAYS: u32 = 30;
+
+/// `daily_facts.strategy` for facts it picked.
+pub const BANDIT: &str = "bandit";
+
+/// `daily_facts.strategy` for the uniform picks it's compared against.
+pub const CONTROL: &str = "control";
+
+pub struct Bandit;
+
+/// How many times `candidate` has engaged someone, and how many times it
+/// hasn't.
+fn outcomes(candidate: &Candidate) -> (i64, i64) {
+    let votes = candidate.score;
+    let feedback = candidate
+        .feedback_nps
+        .map_or(0, |nps| nps * candidate.feedback_responses / 100);
+    let unopened = (candidate.recipients - candidate.opens).max(0);
+
+    (
+        candidate.opens + votes.max(0) + feedback.max(0),
+        unopened + (-votes).max(0) + (-feedback).max(0),
+    )
+}
+
+#[async_trait]
+impl Ranker for Bandit {
+    async fn rank(
+        &self,
+        _selection: Selection<'_>,
+        _count: u32,
+        candidates: &[Candidate],
+    ) -> Result<Vec<i64>, anyhow::Error> {
+        let mut rng = rand::thread_rng();
+        let mut draws = Vec::with_capacity(candidates.len());
+        for candidate in candidates {
+            let (successes, failures) = outcomes(candidate);
+            let beta = Beta::new(1.0 + successes as f64, 1.0 + failures as f64)?;
+            draws.push((beta.sample(&mut rng), candidate.id));
+        }
+        draws.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        Ok(draws.into_iter().map(|(_, id)| id).collect())
+    }
+}
+
+#[derive(Serialize)]
+pub struct Arm {
+    /// `bandit` or `control`.
+    strategy: String,
+    /// Days whose fact it picked.
+    days: i64,
+    recipients: i64,
+    opens: i64,
+    /// Opens per recipient.
+    open_rate: Option<f64>,
+    feedback_responses: i64,
+    feedback_nps: Option<i64>,
+    /// The mean vote score of the facts it picked.
+    average_score: Option<f64>,
+}
+
+impl FromRow for Arm {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        let days = store::integer(row, 1)?;
+        let recipients = store::integer(row, 2)?;
+        let opens = store::integer(row, 3)?;
+        let responses = store::integer(row, 4)?;
+        let promoters = store::integer(row, 5)?;
+        let detractors = store::integer(row, 6)?;
+        let score = store::integer(row, 7)?;
+
+        Ok(Self {
+            strategy: store::text(row, 0)?,
+            days,
+            recipients,
+            opens,
+            open_rate: (recipients > 0).then(|| opens as f64 / recipients as f64),
+            feedback_responses: responses,
+            feedback_nps: (responses > 0).then(|| {
+                ((promoters - detractors) as f64 * 100.0 / responses as f64).round() as i64
+            }),
+            average_score: (days > 0).then(|| score as f64 / days as f64),
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct ExperimentReport {
+    days: u32,
+    arms: Vec<Arm>,
+    /// The bandit's open rate minus the control's, once both have one.
+    open_rate_lift: Option<f64>,
+}
+
+#[derive(Deserialize)]
+pub struct ExperimentQuery {
+    /// How many days of facts of the day are compared. Defaults to 30.
+    days: Option<u32>,
+}
+
+/// `GET /admin/ranking/experiment?days=30` - how the bandit's facts of the
+/// day have done against the uniformly picked control's.
+pub async fn experiment_report(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ExperimentQuery>,
+) -> Result<Json<ExperimentReport>, ApiError> {
+    let days = query.days.unwrap_or(DEFAULT_REPORT_DAYS).clamp(1, 365);
+    let today = state.zone.today();
+    let since = today - Duration::days(i64::from(days) - 1);
+
+    let res = state
+        .db
+        .execute(Statement::with_args(
+            format!(
+                "SELECT strategy, count(*), coalesce(sum(recipients), 0),
+                coalesce(sum((SELECT count(*) FROM fact_opens WHERE fact_opens.date = daily_facts.date)), 0),
+                coalesce(sum((SELECT count(*) FROM fact_feedback WHERE fact_feedback.date = daily_facts.date)), 0),
+                coalesce(sum((SELECT count(*) FROM fact_feedback WHERE fact_feedback.date = daily_facts.date AND score >= 9)), 0),
+                coalesce(sum((SELECT count(*) FROM fact_feedback WHERE fact_feedback.date = daily_facts.date AND score <= 6)), 0),
+                coalesce(sum((SELECT {} FROM catfacts WHERE catfacts.id = daily_facts.catfact_id)), 0)
+                FROM daily_facts WHERE date >= ? AND date <= ? AND strategy IN (?, ?)
+                GROUP BY strategy ORDER BY strategy",
+                votes::SCORE
+            ),
+            &[
+                Value::from(since.to_string()),
+                Value::from(today.to_string()),
+                Value::from(BANDIT),
+                Value::from(CONTROL),
+            ],
+        ))
+        .await?;
+    let arms: Vec<Arm> = store::rows(&res)?;
+
+    let open_rate = |strategy: &str| {
+        arms.iter()
+            .find(|arm| arm.strategy == strategy)
+            .and_then(|arm| arm.open_rate)
+    };
+    let open_rate_lift = open_rate(BANDIT)
+        .zip(open_rate(CONTROL))
+        .map(|(bandit, control)| bandit - control);
+
+    Ok(Json(ExperimentReport {
+        days,
+        arms,
+        open_rate_lift,
+    }))
+}
@@ -0,0 +1,329 @@
+//! `POST /catfact/bulk`, for seeding a deployment with many facts at once.
+//! Takes a JSON array of the same objects `POST /v1/catfacts` takes, or CSV
+//! with a header row, and inserts every valid row in one transaction. Rows
+//! that aren't valid are reported back rather than failing the whole import.
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, HeaderMap},
+    Json,
+};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use crate::auth::Admin;
+use crate::license::License;
+use crate::store::{Statement, Value};
+use crate::{error::ApiError, fact_id, slug, store, tag_rules, tags, AppState, CatFact};
+
+/// The most rows one import can have.
+const MAX_ROWS: usize = 1000;
+
+#[derive(Serialize, ToSchema)]
+pub struct ImportReport {
+    inserted: usize,
+    failed: usize,
+    #[schema(inline)]
+    rows: Vec<RowResult>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum RowOutcome {
+    Inserted { id: Option<i64> },
+    Failed { error: String },
+}
+
+#[derive(Serialize, ToSchema)]
+struct RowResult {
+    /// Counted from 1, not counting a CSV header.
+    row: usize,
+    #[serde(flatten)]
+    #[schema(inline)]
+    outcome: RowOutcome,
+}
+
+struct Valid {
+    fact: String,
+    fact_id: String,
+    license: License,
+    tags: Vec<String>,
+}
+
+/// `POST /catfact/bulk` (admin only) - inserts a JSON array of facts, or CSV
+/// with a `Content-Type: text/csv` header, and reports how each row went.
+#[utoipa::path(
+    post,
+    path = "/catfact/bulk",
+    tag = "facts",
+    request_body(
+        content = Vec<CatFact>,
+        description = "A JSON array of facts, or CSV with a header row as `text/csv`",
+    ),
+    responses(
+        (status = 200, description = "How each row went", body = ImportReport),
+        (status = 400, description = "The body isn't valid JSON or CSV", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+pub async fn import_facts(
+    State(state): State<Arc<AppState>>,
+    _: Admin,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<ImportReport>, ApiError> {
+    let body = std::str::from_utf8(&body)
+        .map_err(|_| ApiError::BadRequest("The body isn't valid UTF-8".to_string()))?;
+    let rows = if is_csv(&headers) {
+        csv_rows(body)?
+    } else {
+        json_rows(body)?
+    };
+    if rows.len() > MAX_ROWS {
+        return Err(ApiError::Validation(format!(
+            "An import can have at most {MAX_ROWS} rows, but this one has {}",
+            rows.len()
+        )));
+    }
+
+    let mut outcomes: Vec<Option<RowOutcome>> = Vec::with_capacity(rows.len());
+    let mut valid = Vec::new();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for (idx, row) in rows.into_iter().enumerate() {
+        let checked = row.and_then(validate).and_then(|fact| {
+            match seen.insert(fact.fact_id.clone(), idx + 1) {
+                Some(first) => Err(format!("this is the same fact as row {first}")),
+                None => Ok(fact),
+            }
+        });
+        match checked {
+            Ok(fact) => {
+                valid.push((idx, fact));
+                outcomes.push(None);
+            }
+            Err(error) => outcomes.push(Some(RowOutcome::Failed { error })),
+        }
+    }
+
+    // Facts that are already here are reported rather than added twice.
+    let mut existing = HashSet::new();
+    for chunk in valid.chunks(500) {
+        let placeholders = vec!["?"; chunk.len()].join(", ");
+        let args: Vec<Value> = chunk
+            .iter()
+            .map(|(_, fact)| Value::from(&fact.fact_id))
+            .collect();
+        let res = state
+            .db
+            .execute(Statement::with_args(
+                format!("SELECT fact_id FROM catfacts WHERE fact_id IN ({placeholders})"),
+                &args,
+            ))
+            .await?;
+        existing.extend(store::rows::<String>(&res)?);
+    }
+
+    let rules = tag_rules::Rules::load(&*state.db).await?;
+    let mut statements = Vec::new();
+    let mut inserts = Vec::new();
+    for (idx, fact) in valid {
+        if existing.contains(&fact.fact_id) {
+            outcomes[idx] = Some(RowOutcome::Failed {
+                error: "this fact is already here".to_string(),
+            });
+            continue;
+        }
+
+        inserts.push((idx, statements.len(), fact.fact.clone()));
+        statements.push(Statement::with_args(
+            "INSERT INTO catfacts (fact, fact_id, license) VALUES (?, ?, ?)",
+            &[
+                Value::from(&fact.fact),
+                Value::from(&fact.fact_id),
+                Value::from(fact.license.name()),
+            ],
+        ));
+        statements.push(Statement::with_args(
+            "UPDATE catfacts SET slug = ? || id WHERE id = last_insert_rowid()",
+            &[slug::slug_prefix(&fact.fact)],
+        ));
+        statements.extend(tags::tag_fact_by_hash(
+            &fact.fact_id,
+            &rules.apply(&fact.fact, &fact.tags),
+        ));
+    }
+
+    let results = if statements.is_empty() {
+        Vec::new()
+    } else {
+        state.db.batch(statements).await?
+    };
+    for (idx, statement, fact) in &inserts {
+        let id = results
+            .get(*statement)
+            .and_then(|inserted| inserted.last_insert_rowid);
+        outcomes[*idx] = Some(RowOutcome::Inserted { id });
+        // Announced like a fact added on its own.
+        if let Some(mqtt) = &state.mqtt {
+            mqtt.publish_new_fact(fact).await;
+        }
+    }
+
+    let rows: Vec<RowResult> = outcomes
+        .into_iter()
+        .enumerate()
+        .filter_map(|(idx, outcome)| {
+            Some(RowResult {
+                row: idx + 1,
+                outcome: outcome?,
+            })
+        })
+        .collect();
+    let inserted = inserts.len();
+    tracing::info!(
+        "Imported {inserted} facts, {} rows failed",
+        rows.len() - inserted
+    );
+
+    Ok(Json(ImportReport {
+        inserted,
+        failed: rows.len() - inserted,
+        rows,
+    }))
+}
+
+fn is_csv(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/csv"))
+}
+
+fn validate(fact: CatFact) -> Result<Valid, String> {
+    if fact.fact.trim().is_empty() {
+        return Err("the fact is empty".to_string());
+    }
+    let tags =
+        tags::normalize(fact.tags.as_deref().unwrap_or_default()).map_err(|e| e.message())?;
+
+    Ok(Valid {
+        fact_id: fact_id::fact_id(&fact.fact),
+        fact: fact.fact,
+        license: fact.license.unwrap_or_default(),
+        tags,
+    })
+}
+
+/// Each element of a JSON array, parsed on its own so one bad row doesn't
+/// stop the others.
+fn json_rows(body: &str) -> Result<Vec<Result<CatFact, String>>, ApiError> {
+    let rows: Vec<serde_json::Value> = serde_json::from_str(body).map_err(|e| {
+        ApiError::Validation(format!("The body should be a JSON array of facts: {e}"))
+    })?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| serde_json::from_value(row).map_err(|e| e.to_string()))
+        .collect())
+}
+
+/// CSV rows as facts. The header row names the columns: `fact`, and
+/// optionally `license` and `tags` (comma-separated, so quote them).
+fn csv_rows(body: &str) -> Result<Vec<Result<CatFact, String>>, ApiError> {
+    let mut records = parse_csv(body).map_err(ApiError::Validation)?.into_iter();
+    let Some(header) = records.next() else {
+        return Ok(Vec::new());
+    };
+
+    let mut columns = (None, None, None);
+    for (idx, name) in header.iter().enumerate() {
+        match name.trim() {
+            "fact" => columns.0 = Some(idx),
+            "license" => columns.1 = Some(idx),
+            "tags" => columns.2 = Some(idx),
+            other => {
+                return Err(ApiError::Validation(format!(
+                    "Unknown CSV column {other:?}; the columns are fact, license and tags"
+                )))
+            }
+        }
+    }
+    let (Some(fact), license, tags) = columns else {
+        return Err(ApiError::Validation(
+            "The CSV header needs a fact column".to_string(),
+        ));
+    };
+
+    Ok(records
+        .map(|record| {
+            let cell = |idx: Option<usize>| {
+                idx.and_then(|idx| record.get(idx))
+                    .map(|value| value.trim())
+                    .filter(|value| !value.is_empty())
+            };
+            if record.len() != header.len() {
+                return Err(format!(
+                    "this row has {} columns, but the header has {}",
+                    record.len(),
+                    header.len()
+                ));
+            }
+
+            Ok(CatFact {
+                fact: cell(Some(fact)).unwrap_or_default().to_string(),
+                license: cell(license)
+                    .map(|name| {
+                        License::from_name(name)
+                            .ok_or_else(|| format!("{name:?} isn't a license we know"))
+                    })
+                    .transpose()?,
+                tags: cell(tags).map(|tags| tags.split(',').map(str::to_string).collect()),
+            })
+        })
+        .collect())
+}
+
+/// Splits CSV into records of fields. Fields can be quoted with `"`, with `""`
+/// for a quote inside one; quoted fields can span lines. Blank lines are
+/// skipped.
+fn parse_csv(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if field.is_empty() => quoted = true,
+            (',', false) => record.push(std::mem::take(&mut field)),
+            ('\r', false) => {}
+            ('\n', false) => {
+                record.push(std::mem::take(&mut field));
+                if record.iter().any(|field| !field.is_empty()) {
+                    records.push(std::mem::take(&mut record));
+                } else {
+                    record.clear();
+                }
+            }
+            (c, _) => field.push(c),
+        }
+    }
+
+    if quoted {
+        return Err("The CSV has a quoted field that's never closed".to_string());
+    }
+    record.push(field);
+    if record.iter().any(|field| !field.is_empty()) {
+        records.push(record);
+    }
+
+    Ok(records)
+}
@@ -0,0 +1,96 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderValue, StatusCode},
+    response::Response,
+};
+use chrono::{Days, Duration, Utc};
+
+use crate::scheduler::Zone;
+
+/// The default `max-age` for cacheable routes: one day, which lines up with how
+/// often the fact of the day changes.
+pub const DEFAULT_MAX_AGE_SECS: u64 = 60 * 60 * 24;
+
+/// How a route's responses may be cached by browsers and CDNs.
+#[derive(Clone, Copy)]
+pub enum CachePolicy {
+    /// Never cache - for random and admin routes where every response differs.
+    NoStore,
+    /// Cache publicly for the given number of seconds.
+    Public { max_age: u64 },
+    /// Cache publicly until the next midnight in `zone` (when the fact of the
+    /// day rolls over), but never for longer than `max_age` seconds.
+    UntilMidnight { max_age: u64, zone: Zone },
+}
+
+impl CachePolicy {
+    /// A long-lived policy for content that only changes daily, such as the
+    /// fact of the day and per-fact pages. `max_age` comes from the
+    /// `CACHE_MAX_AGE` secret.
+    pub fn long_lived(max_age: u64) -> Self {
+        Self::Public { max_age }
+    }
+
+    fn max_age(&self) -> Option<u64> {
+        match self {
+            Self::NoStore => None,
+            Self::Public { max_age } => Some(*max_age),
+            Self::UntilMidnight { max_age, zone } => Some(secs_until_midnight(*zone).min(*max_age)),
+        }
+    }
+
+    fn cache_control(&self) -> String {
+        match self.max_age() {
+            Some(max_age) => format!("public, max-age={max_age}"),
+            None => "no-store".to_string(),
+        }
+    }
+
+    fn expires(&self) -> String {
+        match self.max_age() {
+            Some(max_age) => {
+                let expires = Utc::now() + Duration::seconds(max_age as i64);
+                expires.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+            }
+            None => "0".to_string(),
+        }
+    }
+}
+
+fn secs_until_midnight(zone: Zone) -> u64 {
+    let now = zone.now();
+
+    now.date()
+        .checked_add_days(Days::new(1))
+        .and_then(|tomorrow| tomorrow.and_hms_opt(0, 0, 0))
+        .map(|midnight| midnight.signed_duration_since(now).num_seconds().max(0) as u64)
+        .unwrap_or(0)
+}
+
+/// Response mapper that stamps `Cache-Control` and `Expires` headers onto a
+/// route. Apply it with `axum::middleware::map_response_with_state`. Only
+/// successful responses (and `304 Not Modified`, which renews a cached copy)
+/// get the route's policy; errors, redirects and the like are `no-store`, so
+/// a 404 for a fact that's about to be added or a passing 500 isn't cached
+/// for a day.
+pub async fn apply_cache_policy<B>(
+    State(policy): State<CachePolicy>,
+    mut response: Response<B>,
+) -> Response<B> {
+    let policy = if response.status().is_success() || response.status() == StatusCode::NOT_MODIFIED
+    {
+        policy
+    } else {
+        CachePolicy::NoStore
+    };
+    let headers = response.headers_mut();
+
+    if let Ok(value) = HeaderValue::from_str(&policy.cache_control()) {
+        headers.insert(header::CACHE_CONTROL, value);
+    }
+    if let Ok(value) = HeaderValue::from_str(&policy.expires()) {
+        headers.insert(header::EXPIRES, value);
+    }
+
+    response
+}
@@ -0,0 +1,154 @@
+//! A content calendar: facts pinned to particular future dates, e.g. for a
+//! themed week. On a pinned date the fact of the day is the pinned one, and
+//! automatic selection only picks for dates without a pin.
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::store::{self, FromRow, Row, Statement, Value};
+use crate::strict::StrictJson;
+use crate::{error::ApiError, AppState};
+
+#[derive(Serialize)]
+pub struct CalendarEntry {
+    date: String,
+    catfact_id: i64,
+    fact: String,
+    note: Option<String>,
+    updated_at: String,
+}
+
+impl FromRow for CalendarEntry {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            date: store::text(row, 0)?,
+            catfact_id: store::integer(row, 1)?,
+            fact: store::text(row, 2)?,
+            note: store::optional_text(row, 3)?,
+            updated_at: store::text(row, 4)?,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PinFact {
+    /// The `id` of the fact to send that day.
+    catfact_id: i64,
+    /// Why it's pinned, e.g. "International Cat Day".
+    note: Option<String>,
+}
+
+pub fn parse_date(date: &str) -> Result<NaiveDate, ApiError> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|e| ApiError::BadRequest(format!("date should look like 2024-01-31: {e}")))
+}
+
+async fn entry(state: &AppState, date: NaiveDate) -> Result<CalendarEntry, ApiError> {
+    state
+        .db
+        .execute(Statement::with_args(
+            "SELECT calendar.date, calendar.catfact_id, catfacts.fact, calendar.note, calendar.updated_at
+            FROM calendar JOIN catfacts ON catfacts.id = calendar.catfact_id
+            WHERE calendar.date = ?",
+            &[date.to_string()],
+        ))
+        .await
+        .and_then(|res| store::first::<CalendarEntry>(&res))?
+        .ok_or_else(|| ApiError::NotFound(format!("Nothing is pinned to {date}")))
+}
+
+/// `GET /admin/calendar/:date` - the fact pinned to `date`, if any.
+pub async fn get_entry(
+    State(state): State<Arc<AppState>>,
+    Path(date): Path<String>,
+) -> Result<Json<CalendarEntry>, ApiError> {
+    let date = parse_date(&date)?;
+
+    Ok(Json(entry(&state, date).await?))
+}
+
+/// `PUT /admin/calendar/:date` - pins a fact to `date`, replacing any earlier
+/// pin. Only future dates can be pinned, since today's fact may already have
+/// gone out. If the date's fact was already picked, it's picked again.
+pub async fn pin_fact(
+    State(state): State<Arc<AppState>>,
+    Path(date): Path<String>,
+    StrictJson(pin): StrictJson<PinFact>,
+) -> Result<Json<CalendarEntry>, ApiError> {
+    let date = parse_date(&date)?;
+    if date <= state.zone.today() {
+        return Err(ApiError::Validation(format!(
+            "{date} isn't in the future, so its fact can't be changed"
+        )));
+    }
+
+    let res = state
+        .db
+        .batch([
+            Statement::with_args(
+                "INSERT INTO calendar (date, catfact_id, note)
+                SELECT ?1, id, ?3 FROM catfacts WHERE id = ?2 AND needs_review = 0
+                ON CONFLICT (date) DO UPDATE SET
+                catfact_id = excluded.catfact_id,
+                note = excluded.note,
+                updated_at = current_timestamp",
+                &[
+                    Value::from(date.to_string()),
+                    Value::from(pin.catfact_id),
+                    pin.note.map_or(Value::Null, Value::from),
+                ],
+            ),
+            Statement::with_args(
+                "DELETE FROM daily_facts WHERE date = ?1
+                AND EXISTS (SELECT 1 FROM calendar WHERE date = ?1 AND catfact_id = ?2)",
+                &[Value::from(date.to_string()), Value::from(pin.catfact_id)],
+            ),
+        ])
+        .await?;
+
+    if res.first().map_or(0, |pinned| pinned.rows_affected) == 0 {
+        return Err(ApiError::NotFound(format!(
+            "There's no cat fact {} in circulation",
+            pin.catfact_id
+        )));
+    }
+
+    Ok(Json(entry(&state, date).await?))
+}
+
+/// `DELETE /admin/calendar/:date` - unpins `date`, leaving it to automatic
+/// selection.
+pub async fn unpin_fact(
+    State(state): State<Arc<AppState>>,
+    Path(date): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let date = parse_date(&date)?;
+    if date <= state.zone.today() {
+        return Err(ApiError::Validation(format!(
+            "{date} isn't in the future, so its fact can't be changed"
+        )));
+    }
+
+    let res = state
+        .db
+        .batch([
+            Statement::with_args("DELETE FROM calendar WHERE date = ?", &[date.to_string()]),
+            Statement::with_args(
+                "DELETE FROM daily_facts WHERE date = ?",
+                &[date.to_string()],
+            ),
+        ])
+        .await?;
+
+    if res.first().map_or(0, |deleted| deleted.rows_affected) == 0 {
+        return Err(ApiError::NotFound(format!("Nothing is pinned to {date}")));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
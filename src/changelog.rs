@@ -0,0 +1,183 @@
+//! The public API changelog, so integrators can see when behaviour they rely
+//! on changed. It's kept in `api-changelog.json` at the root of the repo,
+//! newest version first, and built into the binary, so `GET /changelog` always
+//! describes the code that's running. Add an entry there with any change to
+//! what the public routes accept or return.
+use anyhow::anyhow;
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue},
+    response::{Html, IntoResponse, Response},
+    Json,
+};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use crate::{html, AppState};
+
+const SOURCE: &str = include_str!("../api-changelog.json");
+
+/// Sent on every response, with the newest version in the changelog.
+pub const VERSION_HEADER: &str = "x-api-version";
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Changelog {
+    /// Newest first.
+    #[schema(inline)]
+    versions: Vec<Release>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct Release {
+    version: String,
+    date: NaiveDate,
+    #[schema(inline)]
+    changes: Vec<Change>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct Change {
+    #[serde(rename = "type")]
+    #[schema(inline)]
+    kind: ChangeKind,
+    summary: String,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum ChangeKind {
+    Added,
+    Changed,
+    Deprecated,
+    Removed,
+    Fixed,
+}
+
+impl ChangeKind {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Added => "Added",
+            Self::Changed => "Changed",
+            Self::Deprecated => "Deprecated",
+            Self::Removed => "Removed",
+            Self::Fixed => "Fixed",
+        }
+    }
+}
+
+impl Changelog {
+    /// The changelog built into this binary. Errs if the file is malformed,
+    /// so a bad entry stops the deploy rather than serving a broken changelog.
+    pub fn embedded() -> Result<Self, anyhow::Error> {
+        let changelog: Self = serde_json::from_str(SOURCE)
+            .map_err(|e| anyhow!("api-changelog.json isn't valid: {e}"))?;
+
+        let Some(latest) = changelog.versions.first() else {
+            return Err(anyhow!("api-changelog.json doesn't list any versions"));
+        };
+        if HeaderValue::from_str(&latest.version).is_err() {
+            return Err(anyhow!(
+                "api-changelog.json's version {:?} can't be sent in a header",
+                latest.version
+            ));
+        }
+        if let Some(pair) = changelog
+            .versions
+            .windows(2)
+            .find(|pair| pair[0].date < pair[1].date)
+        {
+            return Err(anyhow!(
+                "api-changelog.json should list the newest version first, but {} is older than {}",
+                pair[0].version,
+                pair[1].version
+            ));
+        }
+
+        Ok(changelog)
+    }
+
+    /// The current API version: the newest one in the changelog.
+    pub fn version(&self) -> &str {
+        self.versions
+            .first()
+            .map_or("", |release| release.version.as_str())
+    }
+
+    fn to_html(&self) -> String {
+        let mut body = String::from("<h2>API changelog</h2>\n");
+
+        for release in &self.versions {
+            body.push_str(&format!(
+                "<h3>{} <small>({})</small></h3>\n<ul>\n",
+                html::escape(&release.version),
+                release.date
+            ));
+            for change in &release.changes {
+                body.push_str(&format!(
+                    "<li><strong>{}:</strong> {}</li>\n",
+                    change.kind.label(),
+                    html::escape(&change.summary)
+                ));
+            }
+            body.push_str("</ul>\n");
+        }
+
+        html::page("API changelog", &body)
+    }
+}
+
+fn accepts_html(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media_type| {
+            media_type
+                .split(';')
+                .next()
+                .is_some_and(|media_type| media_type.trim() == "text/html")
+        })
+}
+
+/// `GET /changelog` - the changelog as JSON, or as a page for browsers.
+#[utoipa::path(
+    get,
+    path = "/changelog",
+    tag = "meta",
+    responses(
+        (status = 200, description = "The changelog; browsers asking for `text/html` get a page", content(
+            ("application/json" = Changelog),
+            ("text/html" = String),
+        )),
+    )
+)]
+pub async fn get_changelog(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response {
+    let mut response = if accepts_html(&headers) {
+        Html(state.changelog.to_html()).into_response()
+    } else {
+        Json(state.changelog.as_ref()).into_response()
+    };
+    response
+        .headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("accept"));
+
+    response
+}
+
+/// Adds the `X-Api-Version` header to a response.
+pub async fn add_version_header<B>(
+    State(changelog): State<Arc<Changelog>>,
+    mut response: axum::http::Response<B>,
+) -> axum::http::Response<B> {
+    if let Ok(value) = HeaderValue::from_str(changelog.version()) {
+        response.headers_mut().insert(VERSION_HEADER, value);
+    }
+
+    response
+}
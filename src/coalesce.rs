@@ -0,0 +1,82 @@
+//! Single-flight request coalescing: while one caller is running a query for a
+//! key, everyone else asking for the same key waits for that result instead of
+//! running the query again.
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+pub struct SingleFlight<K, T> {
+    in_flight: Mutex<HashMap<K, broadcast::Sender<T>>>,
+}
+
+impl<K, T> SingleFlight<K, T>
+where
+    K: Eq + Hash + Clone,
+    T: Clone,
+{
+    pub fn new() -> Self {
+        Self {
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Runs `work` for `key`, unless a call for `key` is already in flight, in
+    /// which case this waits for and returns that call's result.
+    pub async fn run<F, Fut>(&self, key: K, work: F) -> T
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let waiting = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            match in_flight.get(&key) {
+                Some(sender) => Some(sender.subscribe()),
+                None => {
+                    in_flight.insert(key.clone(), broadcast::channel(1).0);
+                    None
+                }
+            }
+        };
+
+        if let Some(mut receiver) = waiting {
+            // An error means the leader was cancelled before it finished
+            // (e.g. its client hung up), so do the work ourselves.
+            return match receiver.recv().await {
+                Ok(value) => value,
+                Err(_) => work().await,
+            };
+        }
+
+        let leader = Leader { flight: self, key };
+        let value = work().await;
+        if let Some(sender) = leader.finish() {
+            let _ = sender.send(value.clone());
+        }
+        value
+    }
+}
+
+/// Clears the in-flight entry when the leading call ends, even if it's
+/// cancelled part way through, so waiters never hang on a dead call.
+struct Leader<'a, K: Eq + Hash, T> {
+    flight: &'a SingleFlight<K, T>,
+    key: K,
+}
+
+impl<K: Eq + Hash, T> Leader<'_, K, T> {
+    /// Takes the sender so the result can be broadcast. Dropping `self`
+    /// afterwards finds nothing left to remove.
+    fn finish(self) -> Option<broadcast::Sender<T>> {
+        self.flight.in_flight.lock().unwrap().remove(&self.key)
+    }
+}
+
+impl<K: Eq + Hash, T> Drop for Leader<'_, K, T> {
+    fn drop(&mut self) {
+        if let Ok(mut in_flight) = self.flight.in_flight.lock() {
+            in_flight.remove(&self.key);
+        }
+    }
+}
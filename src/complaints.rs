@@ -0,0 +1,92 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use crate::store::Statement;
+use crate::{crypto, error::ApiError, AppState};
+
+/// The header email providers must send the shared webhook secret in.
+pub const SECRET_HEADER: &str = "x-webhook-secret";
+
+/// A spam/abuse complaint forwarded by the email provider's feedback loop.
+#[derive(Deserialize, ToSchema)]
+pub struct Complaint {
+    email: String,
+    /// Who reported the complaint, e.g. "gmail" or "outlook".
+    #[serde(default)]
+    source: Option<String>,
+    /// The feedback type from the report, e.g. "abuse".
+    #[serde(default)]
+    feedback_type: Option<String>,
+}
+
+/// `POST /webhooks/complaints` - suppresses the complaining address so it never
+/// gets mailed again, and records the complaint.
+#[utoipa::path(
+    post,
+    path = "/webhooks/complaints",
+    tag = "subscriptions",
+    params(("x-webhook-secret" = String, Header, description = "The shared webhook secret")),
+    request_body = Complaint,
+    responses(
+        (status = 200, description = "The address is suppressed"),
+        (status = 401, description = "The secret is wrong", body = ErrorBody),
+        (status = 503, description = "Complaint webhooks aren't configured", body = ErrorBody),
+    )
+)]
+pub async fn receive_complaint(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(complaint): Json<Complaint>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Some(secret) = &state.complaint_webhook_secret else {
+        return Err(ApiError::Unavailable(
+            "Complaint webhooks aren't configured".to_string(),
+        ));
+    };
+
+    let provided = headers
+        .get(SECRET_HEADER)
+        .and_then(|value| value.to_str().ok());
+    if !provided.is_some_and(|provided| crypto::constant_time_eq(provided, secret)) {
+        return Err(ApiError::Unauthorized("Invalid webhook secret".to_string()));
+    }
+
+    let email = complaint.email.trim().to_lowercase();
+    let subscriber_id = state.privacy.subscriber_id(&email);
+    let source = complaint.source.unwrap_or_else(|| "unknown".to_string());
+    let feedback_type = complaint
+        .feedback_type
+        .unwrap_or_else(|| "abuse".to_string());
+
+    state
+        .db
+        .batch([
+            Statement::with_args(
+                "INSERT OR IGNORE INTO suppressions (email, reason) VALUES (?, ?)",
+                &[email.as_str(), "complaint"],
+            ),
+            Statement::with_args(
+                "INSERT INTO complaint_events (email, source, feedback_type) VALUES (?, ?, ?)",
+                &[
+                    subscriber_id.as_str(),
+                    source.as_str(),
+                    feedback_type.as_str(),
+                ],
+            ),
+        ])
+        .await?;
+
+    tracing::info!(
+        "Suppressed address {} after a {feedback_type} complaint from {source}",
+        crypto::email_hash(&email)
+    );
+
+    Ok((StatusCode::OK, "Complaint recorded".to_string()))
+}
@@ -0,0 +1,198 @@
+//! Configuration stored in the database, in the `settings` table. Settings
+//! there take precedence over the same keys in `Secrets.toml`, and are read at
+//! startup, so a change applies from the next deploy or restart.
+//!
+//! Only the keys in `KEYS` can be stored this way - templates, schedules, spam
+//! rules and sending limits. Credentials and anything tied to one environment
+//! (the database, SMTP login, `PUBLIC_URL`, ...) stay in secrets, so one
+//! environment's export can be imported into another.
+use axum::{extract::State, Json};
+use serde::{Deserialize, Serialize};
+use shuttle_secrets::SecretStore;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::error::ApiError;
+use crate::store::{self, FromRow, Row, Statement, Store};
+use crate::strict::StrictJson;
+use crate::{
+    rate_limit::RateLimits,
+    retention::Retention,
+    scheduler::Scheduler,
+    spam::SpamScorer,
+    templates::{self, LintWarning, Templates},
+    AppState,
+};
+
+/// Bumped if the document's shape changes, so an old export isn't misread.
+const VERSION: u32 = 1;
+
+const KEYS: &[&str] = &[
+    "EMAIL_SUBJECT",
+    "EMAIL_TEMPLATE_TEXT",
+    "EMAIL_TEMPLATE_HTML",
+    "EMAIL_SUBJECT_DE",
+    "EMAIL_TEMPLATE_TEXT_DE",
+    "EMAIL_TEMPLATE_HTML_DE",
+    "EMAIL_SUBJECT_ES",
+    "EMAIL_TEMPLATE_TEXT_ES",
+    "EMAIL_TEMPLATE_HTML_ES",
+    "EMAIL_SUBJECT_FR",
+    "EMAIL_TEMPLATE_TEXT_FR",
+    "EMAIL_TEMPLATE_HTML_FR",
+    "SCHEDULE_CRON",
+    "SCHEDULE_TIMEZONE",
+    "SPAM_WEIGHTS",
+    "SPAM_FLAG_AT",
+    "SPAM_REJECT_AT",
+    "SPAM_PHRASES",
+    "PENDING_SUBMISSION_CAP",
+    "REENGAGE_AFTER_MONTHS",
+    "REENGAGE_GRACE_DAYS",
+    "EMAIL_RATE_PER_MINUTE",
+    "EMAIL_RATE_PER_DAY",
+    "CACHE_MAX_AGE",
+    "SUBSCRIBER_CAP",
+    "RATE_LIMIT_SUBMIT",
+    "RATE_LIMIT_SUBMIT_FREE",
+    "RATE_LIMIT_SUBMIT_PARTNER",
+    "RATE_LIMIT_SUBSCRIBE",
+    "RATE_LIMIT_SUBSCRIBE_FREE",
+    "RATE_LIMIT_SUBSCRIBE_PARTNER",
+    "RETENTION_AUDIT_LOG_DAYS",
+    "RETENTION_SUBSCRIBER_EVENTS_DAYS",
+    "RETENTION_COMPLAINT_EVENTS_DAYS",
+    "RETENTION_EMAIL_OUTBOX_DAYS",
+];
+
+/// The whole stored configuration. Settings are sorted by key, so two exports
+/// diff cleanly.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigDocument {
+    version: u32,
+    settings: BTreeMap<String, String>,
+}
+
+/// What an import stored, and anything about its templates that could hurt
+/// deliverability.
+#[derive(Serialize)]
+pub struct Imported {
+    #[serde(flatten)]
+    document: ConfigDocument,
+    warnings: Vec<LintWarning>,
+}
+
+struct Setting {
+    key: String,
+    value: String,
+}
+
+impl FromRow for Setting {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            key: store::text(row, 0)?,
+            value: store::text(row, 1)?,
+        })
+    }
+}
+
+async fn settings(db: &dyn Store) -> Result<BTreeMap<String, String>, anyhow::Error> {
+    let settings = db
+        .execute("SELECT key, value FROM settings")
+        .await
+        .and_then(|res| store::rows::<Setting>(&res))?;
+
+    Ok(settings
+        .into_iter()
+        .map(|setting| (setting.key, setting.value))
+        .collect())
+}
+
+/// `secrets` with the stored settings laid over it.
+fn overlay(secrets: &SecretStore, settings: &BTreeMap<String, String>) -> SecretStore {
+    let mut merged: BTreeMap<String, String> = secrets.clone().into_iter().collect();
+    merged.extend(settings.clone());
+
+    SecretStore::new(merged)
+}
+
+/// The configuration to start with: the secrets, overridden by whatever is in
+/// the `settings` table. Needs `schema::migrate` to have run.
+pub async fn load(db: &dyn Store, secrets: &SecretStore) -> Result<SecretStore, anyhow::Error> {
+    let settings = settings(db).await?;
+    if !settings.is_empty() {
+        tracing::info!(
+            "Using stored settings for {}",
+            settings.keys().cloned().collect::<Vec<_>>().join(", ")
+        );
+    }
+
+    Ok(overlay(secrets, &settings))
+}
+
+/// `GET /admin/config/export` - the stored configuration as one JSON document.
+pub async fn export_config(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ConfigDocument>, ApiError> {
+    Ok(Json(ConfigDocument {
+        version: VERSION,
+        settings: settings(&*state.db).await?,
+    }))
+}
+
+/// `POST /admin/config/import` - replaces the stored configuration with the
+/// document's, e.g. one exported from another environment. Settings that
+/// aren't in the document are removed. Nothing is stored unless every
+/// setting parses, and the new configuration applies from the next restart.
+/// Templates are linted as well, and any warnings returned with the document;
+/// they don't stop it being stored.
+pub async fn import_config(
+    State(state): State<Arc<AppState>>,
+    StrictJson(document): StrictJson<ConfigDocument>,
+) -> Result<Json<Imported>, ApiError> {
+    if document.version != VERSION {
+        return Err(ApiError::Validation(format!(
+            "This is a version {} config document, but only version {VERSION} can be imported",
+            document.version
+        )));
+    }
+
+    if let Some(key) = document
+        .settings
+        .keys()
+        .find(|key| !KEYS.contains(&key.as_str()))
+    {
+        return Err(ApiError::Validation(format!(
+            "{key} can't be stored in the database; the keys that can are {}",
+            KEYS.join(", ")
+        )));
+    }
+
+    let merged = overlay(&state.secrets, &document.settings);
+    Templates::from_secrets(&merged)
+        .and_then(|_| Scheduler::from_secrets(&merged))
+        .and_then(|_| SpamScorer::from_secrets(&merged))
+        .and_then(|_| RateLimits::from_secrets(&merged, state.client_addresses))
+        .and_then(|_| Retention::from_secrets(&merged))
+        .map_err(|e| ApiError::Validation(format!("The configuration isn't valid: {e}")))?;
+
+    let mut statements = vec![Statement::new("DELETE FROM settings")];
+    statements.extend(document.settings.iter().map(|(key, value)| {
+        Statement::with_args(
+            "INSERT INTO settings (key, value) VALUES (?, ?)",
+            &[key, value],
+        )
+    }));
+    state.db.batch(statements).await?;
+
+    tracing::info!(
+        "Imported {} settings; they apply from the next restart",
+        document.settings.len()
+    );
+
+    Ok(Json(Imported {
+        warnings: templates::lint_secrets(&merged),
+        document,
+    }))
+}
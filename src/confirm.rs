@@ -0,0 +1,222 @@
+//! Double opt-in: new subscribers start out unconfirmed and only get the daily
+//! email once they've followed the link in a confirmation email, so nobody can
+//! sign up an address they don't own. The `confirmations` job sends the link
+//! once more to anyone who hasn't used it a day on, and deletes signups still
+//! unconfirmed after a week.
+use anyhow::anyhow;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse},
+};
+use lettre::message::Mailbox;
+use serde::Deserialize;
+use std::sync::Arc;
+use utoipa::IntoParams;
+
+use crate::mailer::{Email, Mail};
+use crate::store::{self, FromRow, Row, Statement, Store, Value};
+use crate::{html, AppState};
+
+const TITLE: &str = "Cat Facts - Confirm";
+
+/// How long after the confirmation email the reminder goes.
+const REMIND_AFTER: &str = "-24 hours";
+
+/// How long after the confirmation email an unconfirmed signup is deleted.
+const PURGE_AFTER: &str = "-7 days";
+
+/// The most reminders one run of the job sends.
+const REMINDERS_PER_RUN: i64 = 500;
+
+pub fn confirmation_url(public_url: &str, token: &str) -> String {
+    format!("{}/confirm?token={token}", public_url.trim_end_matches('/'))
+}
+
+/// Emails `to` a link to confirm their subscription.
+pub async fn send_confirmation(
+    state: &AppState,
+    to: &str,
+    token: &str,
+) -> Result<(), anyhow::Error> {
+    send(
+        &state.mailer,
+        state.sender.as_ref(),
+        &state.public_url,
+        to,
+        token,
+    )
+    .await
+}
+
+async fn send(
+    mailer: &Mail,
+    sender: Option<&Mailbox>,
+    public_url: &str,
+    to: &str,
+    token: &str,
+) -> Result<(), anyhow::Error> {
+    let Some(sender) = sender else {
+        return Err(anyhow!(
+            "MAIL_FROM (or GMAIL_USER) isn't a valid email address, so confirmation emails can't be sent"
+        ));
+    };
+    let to: Mailbox = to
+        .parse()
+        .map_err(|e| anyhow!("{to:?} isn't a valid email address: {e}"))?;
+
+    let email = Email::new(sender, &to, "Confirm your Cat Facts subscription", format!(
+            "Hey there! Someone (hopefully you) asked to get a cat fact by email every day.\n\nConfirm your subscription here: {}\n\nIf that wasn't you, just ignore this email and you won't hear from us again.",
+            confirmation_url(public_url, token)
+        ),
+    );
+
+    mailer.send(&email).await
+}
+
+/// Follows up on signups nobody's confirmed, for the `confirmations` job.
+pub struct Confirmations {
+    pub mailer: Mail,
+    pub sender: Option<Mailbox>,
+    pub public_url: String,
+}
+
+/// What one run of the `confirmations` job did.
+#[derive(Default)]
+pub struct FollowUp {
+    pub reminded: usize,
+    pub failed: usize,
+    pub purged: u64,
+}
+
+struct Pending {
+    id: i64,
+    email: String,
+    confirmation_token: String,
+}
+
+impl FromRow for Pending {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            id: store::integer(row, 0)?,
+            email: store::text(row, 1)?,
+            confirmation_token: store::text(row, 2)?,
+        })
+    }
+}
+
+impl Confirmations {
+    /// Sends the confirmation link again to signups that got it a day or
+    /// more ago, once each, then deletes the ones that got it a week or more
+    /// ago. Signing up again sends a fresh link and starts both over.
+    pub async fn follow_up(&self, db: &dyn Store) -> Result<FollowUp, anyhow::Error> {
+        let mut report = FollowUp::default();
+
+        // Left for the next run rather than used up while sends are refused.
+        let due = if self.mailer.is_paused().await {
+            Vec::new()
+        } else {
+            let res = db
+                .execute(Statement::with_args(
+                    "SELECT id, email, confirmation_token FROM subscribers
+                    WHERE confirmed = 0 AND confirmation_reminded = 0
+                    AND confirmation_token IS NOT NULL
+                    AND coalesce(confirmation_sent_at, created_at) <= datetime('now', ?1)
+                    AND coalesce(confirmation_sent_at, created_at) > datetime('now', ?2)
+                    AND lower(email) NOT IN (SELECT email FROM suppressions)
+                    ORDER BY id LIMIT ?3",
+                    &[
+                        Value::from(REMIND_AFTER),
+                        Value::from(PURGE_AFTER),
+                        Value::from(REMINDERS_PER_RUN),
+                    ],
+                ))
+                .await?;
+            store::rows::<Pending>(&res)?
+        };
+
+        for pending in due {
+            // Claimed before it's sent, so it only ever goes once, even if
+            // sending fails or another instance is running the job too.
+            let claimed = db
+                .execute(Statement::with_args(
+                    "UPDATE subscribers SET confirmation_reminded = 1
+                    WHERE id = ? AND confirmation_token = ? AND confirmation_reminded = 0",
+                    &[
+                        Value::from(pending.id),
+                        Value::from(&pending.confirmation_token),
+                    ],
+                ))
+                .await?;
+            if claimed.rows_affected == 0 {
+                continue;
+            }
+
+            match send(
+                &self.mailer,
+                self.sender.as_ref(),
+                &self.public_url,
+                &pending.email,
+                &pending.confirmation_token,
+            )
+            .await
+            {
+                Ok(()) => report.reminded += 1,
+                Err(e) => {
+                    tracing::warn!("Couldn't remind subscriber {} to confirm: {e}", pending.id);
+                    report.failed += 1;
+                }
+            }
+        }
+
+        // Never subscribed, so there's no unsubscribe event to log.
+        report.purged = db
+            .execute(Statement::with_args(
+                "DELETE FROM subscribers WHERE confirmed = 0
+                AND coalesce(confirmation_sent_at, created_at) <= datetime('now', ?)",
+                &[PURGE_AFTER],
+            ))
+            .await?
+            .rows_affected;
+
+        Ok(report)
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ConfirmQuery {
+    /// From the confirmation email.
+    token: String,
+}
+
+/// `GET /confirm?token=` - activates a pending subscription.
+#[utoipa::path(
+    get,
+    path = "/confirm",
+    tag = "subscriptions",
+    params(ConfirmQuery),
+    responses(
+        (status = 200, description = "Confirmed", body = String, content_type = "text/html"),
+        (status = 404, description = "The link isn't valid anymore", body = String, content_type = "text/html"),
+    )
+)]
+pub async fn confirm(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ConfirmQuery>,
+) -> Result<impl IntoResponse, impl IntoResponse> {
+    match state.db.confirm(&query.token).await {
+        Ok(true) => Ok(Html(html::page(
+            TITLE,
+            "<p>You're confirmed! Your first cat fact is on its way.</p>",
+        ))),
+        Ok(false) => Err((
+            StatusCode::NOT_FOUND,
+            Html(html::page(
+                TITLE,
+                "<p>This confirmation link isn't valid anymore. You may already have confirmed.</p>",
+            )),
+        )),
+        Err(e) => Err(html::server_error(TITLE, e)),
+    }
+}
@@ -0,0 +1,129 @@
+use anyhow::anyhow;
+use chrono::{Datelike, NaiveDate};
+
+use crate::ranking::{Ranking, Selection};
+use crate::store::{Statement, Store, Value};
+use crate::{bandit, store, votes};
+
+/// Returns the fact of the day for `date`, picking and storing it in
+/// `daily_facts` first if that hasn't happened yet. Once stored, the choice is
+/// fixed, so adding facts during the day doesn't change it. Returns `None` if
+/// there are no facts yet.
+pub async fn fact_for_date(
+    db: &dyn Store,
+    ranking: &Ranking,
+    date: NaiveDate,
+) -> Result<Option<String>, anyhow::Error> {
+    if let Some(fact) = stored_fact(db, date).await? {
+        return Ok(Some(fact));
+    }
+
+    materialize(db, ranking, date).await?;
+    stored_fact(db, date).await
+}
+
+/// Picks the fact for `date` and stores it in `daily_facts`, unless one is
+/// already stored. A fact pinned to the date in the content calendar wins;
+/// otherwise the ranker picks one, or failing that one is chosen uniformly,
+/// skipping facts voted below zero unless there's nothing else. How it was
+/// picked is kept in `daily_facts.strategy`, for the bandit experiment's
+/// report. The scheduler calls this just after midnight for the next day, so
+/// readers normally find the row already there.
+pub async fn materialize(
+    db: &dyn Store,
+    ranking: &Ranking,
+    date: NaiveDate,
+) -> Result<(), anyhow::Error> {
+    let pinned = db
+        .execute(Statement::with_args(
+            "INSERT OR IGNORE INTO daily_facts (date, catfact_id, strategy)
+            SELECT calendar.date, calendar.catfact_id, 'calendar' FROM calendar
+            JOIN catfacts ON catfacts.id = calendar.catfact_id
+            WHERE calendar.date = ? AND catfacts.needs_review = 0",
+            &[date.to_string()],
+        ))
+        .await
+        .map_err(|e| anyhow!("error when trying to get the pinned fact for {date}: {e}"))?;
+
+    if pinned.rows_affected > 0 {
+        return Ok(());
+    }
+
+    let well_rated = format!("needs_review = 0 AND {} >= 0", votes::SCORE);
+    let mut candidates = well_rated.as_str();
+    let mut count = count_facts(db, candidates).await?;
+    if count == 0 {
+        candidates = "needs_review = 0";
+        count = count_facts(db, candidates).await?;
+    }
+
+    if count == 0 {
+        return Ok(());
+    }
+
+    let selection = Selection::Daily { date };
+    let (control, strategy) = ranking.assign();
+    if !control {
+        if let Some(ranked) = ranking.pick(db, selection, candidates, &[], 1).await {
+            db.execute(Statement::with_args(
+                "INSERT OR IGNORE INTO daily_facts (date, catfact_id, strategy) VALUES (?, ?, ?)",
+                &[
+                    Value::from(date.to_string()),
+                    Value::from(ranked[0]),
+                    Value::from(strategy),
+                ],
+            ))
+            .await
+            .map_err(|e| {
+                anyhow!("error when trying to store the fact of the day for {date}: {e}")
+            })?;
+
+            return Ok(());
+        }
+    }
+    let strategy = if control { bandit::CONTROL } else { "uniform" };
+
+    // Deterministic, so every instance picks the same fact for a given day.
+    let offset = i64::from(date.num_days_from_ce()).rem_euclid(count);
+
+    db.execute(Statement::with_args(
+        format!(
+            "INSERT OR IGNORE INTO daily_facts (date, catfact_id, strategy)
+            SELECT ?, id, ? FROM catfacts WHERE {candidates} order by id limit 1 offset ?"
+        ),
+        &[
+            Value::from(date.to_string()),
+            Value::from(strategy),
+            Value::from(offset),
+        ],
+    ))
+    .await
+    .map_err(|e| anyhow!("error when trying to pick the fact of the day for {date}: {e}"))?;
+
+    Ok(())
+}
+
+async fn count_facts(db: &dyn Store, filter: &str) -> Result<i64, anyhow::Error> {
+    match db
+        .execute(format!("SELECT count(*) FROM catfacts WHERE {filter}"))
+        .await
+    {
+        Ok(res) => Ok(store::first::<i64>(&res)?.unwrap_or(0)),
+        Err(e) => Err(anyhow!("error when trying to count cat facts: {e}")),
+    }
+}
+
+async fn stored_fact(db: &dyn Store, date: NaiveDate) -> Result<Option<String>, anyhow::Error> {
+    match db
+        .execute(Statement::with_args(
+            "SELECT catfacts.fact FROM daily_facts
+            JOIN catfacts ON catfacts.id = daily_facts.catfact_id
+            WHERE daily_facts.date = ?",
+            &[date.to_string()],
+        ))
+        .await
+    {
+        Ok(res) => store::first(&res),
+        Err(e) => Err(anyhow!("error when trying to get the fact of the day: {e}")),
+    }
+}
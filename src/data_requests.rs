@@ -0,0 +1,549 @@
+//! Lets anyone see or erase everything stored about their email address.
+//! `POST /subscriber/data-request` emails the address a link that's good for
+//! a day, so only whoever reads that inbox can act on it. The link leads to a
+//! JSON export (`GET /subscriber/data`) and to erasure (`DELETE /subscriber`,
+//! or the confirmation page at `GET /subscriber/erase` for people clicking
+//! through from the email).
+//!
+//! What's covered is every row keyed on the address: the subscription, any
+//! waitlist entry, suppressions and complaint reports. Votes are keyed on a
+//! hash of the voter's IP address, not their email, so they can't be found
+//! from it.
+use anyhow::anyhow;
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse},
+    Form, Json,
+};
+use lettre::message::Mailbox;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::privacy::Privacy;
+use crate::store::{self, FromRow, Row, Statement, Store};
+use crate::{error::ApiError, html, mailer::Email, strict::JsonOrForm, AppState};
+
+const TITLE: &str = "Cat Facts - Your data";
+
+/// How long an emailed link works for.
+const TOKEN_TTL: &str = "-1 day";
+
+#[derive(Deserialize, ToSchema)]
+pub struct DataRequest {
+    email: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Accepted {
+    message: &'static str,
+}
+
+/// `POST /subscriber/data-request` - emails a link to export or erase the
+/// address's data. Answers the same whether or not we know the address, so it
+/// can't be used to find out who's subscribed.
+#[utoipa::path(
+    post,
+    path = "/subscriber/data-request",
+    tag = "your data",
+    request_body(content = DataRequest, content_type = "application/json"),
+    responses(
+        (status = 202, description = "A link is on its way, if we know the address", body = Accepted),
+        (status = 429, description = "Too many requests; see `Retry-After`", body = ErrorBody),
+    )
+)]
+pub async fn request_data(
+    State(state): State<Arc<AppState>>,
+    JsonOrForm(req): JsonOrForm<DataRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let email = req.email.trim().to_lowercase();
+    let accepted = (
+        StatusCode::ACCEPTED,
+        Json(Accepted {
+            message: "If we have any data about this address, we've emailed it a link to download or erase it.",
+        }),
+    );
+
+    if !has_data(&*state.db, &state.privacy, &email).await? {
+        return Ok(accepted);
+    }
+
+    let token = state
+        .db
+        .execute("SELECT lower(hex(randomblob(16)))")
+        .await
+        .and_then(|res| store::first::<String>(&res))?
+        .ok_or_else(|| ApiError::internal("Couldn't generate a data request token"))?;
+    state
+        .db
+        .batch([
+            Statement::new(format!(
+                "DELETE FROM data_requests WHERE created_at < datetime('now', '{TOKEN_TTL}')"
+            )),
+            Statement::with_args(
+                "INSERT INTO data_requests (token, email) VALUES (?, ?)",
+                &[&token, &email],
+            ),
+        ])
+        .await?;
+
+    send_links(&state, &email, &token)
+        .await
+        .map_err(|e| ApiError::Mail(e.context("couldn't send a data request email".to_string())))?;
+
+    Ok(accepted)
+}
+
+async fn has_data(db: &dyn Store, privacy: &Privacy, email: &str) -> Result<bool, anyhow::Error> {
+    let [email, subscriber_id] = privacy.subscriber_ids(email);
+    let found = db
+        .execute(Statement::with_args(
+            "SELECT EXISTS (SELECT 1 FROM subscribers WHERE lower(trim(email)) = ?1)
+            OR EXISTS (SELECT 1 FROM waitlist WHERE lower(trim(email)) = ?1)
+            OR EXISTS (SELECT 1 FROM suppressions WHERE email = ?1)
+            OR EXISTS (SELECT 1 FROM complaint_events WHERE email IN (?1, ?2))
+            OR EXISTS (SELECT 1 FROM fact_feedback WHERE subscriber IN (?1, ?2))
+            OR EXISTS (SELECT 1 FROM fact_opens WHERE subscriber IN (?1, ?2))",
+            &[email, subscriber_id],
+        ))
+        .await
+        .and_then(|res| store::first::<i64>(&res))?;
+
+    Ok(found == Some(1))
+}
+
+async fn send_links(state: &AppState, to: &str, token: &str) -> Result<(), anyhow::Error> {
+    let Some(sender) = state.sender.clone() else {
+        return Err(anyhow!(
+            "MAIL_FROM (or GMAIL_USER) isn't a valid email address, so data request emails can't be sent"
+        ));
+    };
+    let to: Mailbox = to
+        .parse()
+        .map_err(|e| anyhow!("{to:?} isn't a valid email address: {e}"))?;
+    let base = state.public_url.trim_end_matches('/');
+
+    let email = Email::new(&sender, &to, "Your Cat Facts data", format!(
+            "Hey there! Someone (hopefully you) asked to see or erase the data Cat Facts has about this address.\n\nDownload it here: {base}/subscriber/data?token={token}\n\nOr erase all of it, unsubscribing you if you're subscribed: {base}/subscriber/erase?token={token}\n\nThese links work for a day. If you didn't ask for this, just ignore this email."
+        ),
+    );
+
+    state.mailer.send(&email).await
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TokenQuery {
+    /// From the emailed link, or sent as `Authorization: Bearer <token>`.
+    token: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams, ToSchema)]
+#[into_params(parameter_in = Query)]
+pub struct TokenForm {
+    /// From the emailed link.
+    token: String,
+}
+
+/// The address a data request token is for, if it's still valid.
+async fn email_for(db: &dyn Store, token: &str) -> Result<Option<String>, anyhow::Error> {
+    let res = db
+        .execute(Statement::with_args(
+            format!(
+                "SELECT email FROM data_requests
+                WHERE token = ? AND created_at >= datetime('now', '{TOKEN_TTL}')"
+            ),
+            &[token],
+        ))
+        .await?;
+
+    store::first(&res)
+}
+
+/// The token from `?token=`, or an `Authorization: Bearer` header.
+fn token(query: TokenQuery, headers: &HeaderMap) -> Option<String> {
+    query.token.or_else(|| {
+        headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::to_string)
+    })
+}
+
+async fn verified_email(state: &AppState, token: Option<String>) -> Result<String, ApiError> {
+    let expired = || {
+        ApiError::NotFound(
+            "This link has expired or already been used. You can ask for a new one at POST /subscriber/data-request."
+                .to_string(),
+        )
+    };
+    let token = token.ok_or_else(expired)?;
+
+    email_for(&*state.db, &token).await?.ok_or_else(expired)
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DataExport {
+    email: String,
+    exported_at: String,
+    #[schema(inline)]
+    subscription: Option<Subscription>,
+    #[schema(inline)]
+    waitlist: Option<WaitlistEntry>,
+    #[schema(inline)]
+    suppression: Option<Suppression>,
+    #[schema(inline)]
+    complaints: Vec<Complaint>,
+    /// Scores given to facts from the links in emails.
+    #[schema(inline)]
+    feedback: Vec<Feedback>,
+    /// Emails counted as opened, for the bandit experiment.
+    #[schema(inline)]
+    opens: Vec<Open>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Subscription {
+    subscribed_at: String,
+    confirmed: bool,
+    delivery_hour: i64,
+    weekdays: i64,
+    email_format: String,
+    needs_review: bool,
+    language: String,
+    /// Minutes east of UTC the delivery hour is in, if not the schedule's
+    /// zone.
+    utc_offset: Option<i64>,
+}
+
+impl FromRow for Subscription {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            subscribed_at: store::text(row, 0)?,
+            confirmed: store::integer(row, 1)? != 0,
+            delivery_hour: store::integer(row, 2)?,
+            weekdays: store::integer(row, 3)?,
+            email_format: store::text(row, 4)?,
+            needs_review: store::integer(row, 5)? != 0,
+            language: store::text(row, 6)?,
+            utc_offset: store::optional_integer(row, 7)?,
+        })
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct WaitlistEntry {
+    joined_at: String,
+    delivery_hour: i64,
+    weekdays: i64,
+}
+
+impl FromRow for WaitlistEntry {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            joined_at: store::text(row, 0)?,
+            delivery_hour: store::integer(row, 1)?,
+            weekdays: store::integer(row, 2)?,
+        })
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct Suppression {
+    reason: String,
+    created_at: String,
+}
+
+impl FromRow for Suppression {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            reason: store::text(row, 0)?,
+            created_at: store::text(row, 1)?,
+        })
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct Complaint {
+    source: String,
+    feedback_type: String,
+    received_at: String,
+}
+
+impl FromRow for Complaint {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            source: store::text(row, 0)?,
+            feedback_type: store::text(row, 1)?,
+            received_at: store::text(row, 2)?,
+        })
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct Feedback {
+    /// The day the fact was sent.
+    date: String,
+    catfact_id: i64,
+    score: i64,
+    updated_at: String,
+}
+
+impl FromRow for Feedback {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            date: store::text(row, 0)?,
+            catfact_id: store::integer(row, 1)?,
+            score: store::integer(row, 2)?,
+            updated_at: store::text(row, 3)?,
+        })
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct Open {
+    /// The day the fact was sent.
+    date: String,
+    catfact_id: i64,
+    opened_at: String,
+}
+
+impl FromRow for Open {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            date: store::text(row, 0)?,
+            catfact_id: store::integer(row, 1)?,
+            opened_at: store::text(row, 2)?,
+        })
+    }
+}
+
+/// `GET /subscriber/data?token=` - everything stored about the token's
+/// address, as JSON.
+#[utoipa::path(
+    get,
+    path = "/subscriber/data",
+    tag = "your data",
+    params(TokenQuery),
+    responses(
+        (status = 200, description = "Everything stored about the address, as a download", body = DataExport),
+        (status = 404, description = "The link has expired or been used", body = ErrorBody),
+    )
+)]
+pub async fn export_data(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<TokenQuery>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let email = verified_email(&state, token(query, &headers)).await?;
+
+    let res = state
+        .db
+        .batch([
+            Statement::with_args(
+                "SELECT created_at, confirmed, delivery_hour, weekdays, email_format, needs_review,
+                language, utc_offset FROM subscribers WHERE lower(trim(email)) = ?",
+                &[&email],
+            ),
+            Statement::with_args(
+                "SELECT created_at, delivery_hour, weekdays FROM waitlist WHERE lower(trim(email)) = ?",
+                &[&email],
+            ),
+            Statement::with_args(
+                "SELECT reason, created_at FROM suppressions WHERE email = ?",
+                &[&email],
+            ),
+            Statement::with_args(
+                "SELECT source, feedback_type, received_at FROM complaint_events
+                WHERE email IN (?, ?) ORDER BY id",
+                &state.privacy.subscriber_ids(&email),
+            ),
+            Statement::with_args(
+                "SELECT date, catfact_id, score, updated_at FROM fact_feedback
+                WHERE subscriber IN (?, ?) ORDER BY date",
+                &state.privacy.subscriber_ids(&email),
+            ),
+            Statement::with_args(
+                "SELECT date, catfact_id, opened_at FROM fact_opens
+                WHERE subscriber IN (?, ?) ORDER BY date",
+                &state.privacy.subscriber_ids(&email),
+            ),
+            Statement::new("SELECT strftime('%Y-%m-%dT%H:%M:%SZ', 'now')"),
+        ])
+        .await?;
+    let result = |idx: usize| {
+        res.get(idx)
+            .ok_or_else(|| ApiError::internal("A data export query returned nothing"))
+    };
+
+    let export = DataExport {
+        subscription: store::first(result(0)?)?,
+        waitlist: store::first(result(1)?)?,
+        suppression: store::first(result(2)?)?,
+        complaints: store::rows(result(3)?)?,
+        feedback: store::rows(result(4)?)?,
+        opens: store::rows(result(5)?)?,
+        exported_at: store::first(result(6)?)?.unwrap_or_default(),
+        email,
+    };
+
+    Ok((
+        [(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"cat-facts-data.json\"",
+        )],
+        Json(export),
+    ))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Erased {
+    subscriptions: u64,
+    waitlist: u64,
+    suppressions: u64,
+    complaints: u64,
+    feedback: u64,
+    opens: u64,
+    /// Copies of emails sent to the address, kept for retries.
+    emails: u64,
+}
+
+/// Deletes every row about `email`, including its data request tokens, in
+/// one transaction.
+async fn erase(db: &dyn Store, privacy: &Privacy, email: &str) -> Result<Erased, anyhow::Error> {
+    let ids = privacy.subscriber_ids(email);
+    let res = db
+        .batch([
+            Statement::with_args(
+                "DELETE FROM subscribers WHERE lower(trim(email)) = ?",
+                &[email],
+            ),
+            Statement::with_args(
+                "DELETE FROM waitlist WHERE lower(trim(email)) = ?",
+                &[email],
+            ),
+            Statement::with_args("DELETE FROM suppressions WHERE email = ?", &[email]),
+            Statement::with_args("DELETE FROM complaint_events WHERE email IN (?, ?)", &ids),
+            Statement::with_args("DELETE FROM fact_feedback WHERE subscriber IN (?, ?)", &ids),
+            Statement::with_args("DELETE FROM fact_opens WHERE subscriber IN (?, ?)", &ids),
+            Statement::with_args("DELETE FROM email_outbox WHERE recipient IN (?, ?)", &ids),
+            Statement::with_args("DELETE FROM data_requests WHERE email = ?", &[email]),
+            Statement::with_args(
+                "DELETE FROM email_dead_letters WHERE recipient IN (?, ?)",
+                &ids,
+            ),
+        ])
+        .await?;
+    let deleted = |idx: usize| res.get(idx).map_or(0, |deleted| deleted.rows_affected);
+
+    // The address itself isn't logged, since it's just been erased.
+    tracing::info!("Erased a subscriber's data on request");
+
+    Ok(Erased {
+        subscriptions: deleted(0),
+        waitlist: deleted(1),
+        suppressions: deleted(2),
+        complaints: deleted(3),
+        feedback: deleted(4),
+        opens: deleted(5),
+        emails: deleted(6),
+    })
+}
+
+/// `DELETE /subscriber?token=` - erases everything stored about the token's
+/// address. The token can also be sent as `Authorization: Bearer <token>`.
+#[utoipa::path(
+    delete,
+    path = "/subscriber",
+    tag = "your data",
+    params(TokenQuery),
+    responses(
+        (status = 200, description = "What was deleted", body = Erased),
+        (status = 404, description = "The link has expired or been used", body = ErrorBody),
+    )
+)]
+pub async fn erase_data(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<TokenQuery>,
+    headers: HeaderMap,
+) -> Result<Json<Erased>, ApiError> {
+    let email = verified_email(&state, token(query, &headers)).await?;
+
+    Ok(Json(erase(&*state.db, &state.privacy, &email).await?))
+}
+
+fn expired_page() -> (StatusCode, Html<String>) {
+    (
+        StatusCode::NOT_FOUND,
+        Html(html::page(
+            TITLE,
+            "<p>This link has expired or already been used. You can ask for a new one from the signup page.</p>",
+        )),
+    )
+}
+
+/// `GET /subscriber/erase?token=` - asks the person following the emailed
+/// link to confirm before anything is deleted.
+#[utoipa::path(
+    get,
+    path = "/subscriber/erase",
+    tag = "your data",
+    params(TokenForm),
+    responses(
+        (status = 200, description = "A page asking to confirm", body = String, content_type = "text/html"),
+        (status = 404, description = "The link has expired or been used", body = String, content_type = "text/html"),
+    )
+)]
+pub async fn erase_page(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<TokenForm>,
+) -> Result<Html<String>, (StatusCode, Html<String>)> {
+    let email = email_for(&*state.db, &query.token)
+        .await
+        .map_err(|e| html::server_error(TITLE, e))?
+        .ok_or_else(expired_page)?;
+
+    Ok(Html(html::page(
+        TITLE,
+        &format!(
+            r#"<p>This will permanently delete everything Cat Facts has about <strong>{email}</strong>, and unsubscribe it if it's subscribed.</p>
+<form method="post" action="/subscriber/erase">
+  <input type="hidden" name="token" value="{token}">
+  <button type="submit">Erase my data</button>
+</form>"#,
+            email = html::escape(&email),
+            token = html::escape(&query.token),
+        ),
+    )))
+}
+
+/// `POST /subscriber/erase` - the confirmation page's form.
+#[utoipa::path(
+    post,
+    path = "/subscriber/erase",
+    tag = "your data",
+    request_body(content = TokenForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "The data was erased", body = String, content_type = "text/html"),
+        (status = 404, description = "The link has expired or been used", body = String, content_type = "text/html"),
+    )
+)]
+pub async fn erase_form(
+    State(state): State<Arc<AppState>>,
+    Form(form): Form<TokenForm>,
+) -> Result<Html<String>, (StatusCode, Html<String>)> {
+    let email = email_for(&*state.db, &form.token)
+        .await
+        .map_err(|e| html::server_error(TITLE, e))?
+        .ok_or_else(expired_page)?;
+    erase(&*state.db, &state.privacy, &email)
+        .await
+        .map_err(|e| html::server_error(TITLE, e))?;
+
+    Ok(Html(html::page(
+        TITLE,
+        "<p>Done. Everything we had about your address has been deleted.</p>",
+    )))
+}
@@ -0,0 +1,43 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+/// When in the day a subscriber would like their fact, in `SCHEDULE_TIMEZONE`.
+/// The scheduler sends to each window's subscribers when its hour comes round.
+#[derive(Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryWindow {
+    #[default]
+    Midnight,
+    Morning,
+    Noon,
+    Evening,
+}
+
+impl DeliveryWindow {
+    pub const ALL: [DeliveryWindow; 4] = [Self::Midnight, Self::Morning, Self::Noon, Self::Evening];
+
+    pub fn hour(&self) -> u32 {
+        match self {
+            Self::Midnight => 0,
+            Self::Morning => 8,
+            Self::Noon => 12,
+            Self::Evening => 18,
+        }
+    }
+
+    pub fn from_hour(hour: i64) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|window| i64::from(window.hour()) == hour)
+    }
+
+    /// The name used in JSON bodies and HTML forms.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Midnight => "midnight",
+            Self::Morning => "morning",
+            Self::Noon => "noon",
+            Self::Evening => "evening",
+        }
+    }
+}
@@ -0,0 +1,1283 @@
+use anyhow::anyhow;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike, Utc};
+use lettre::message::Mailbox;
+use serde::Serialize;
+use shuttle_secrets::SecretStore;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tokio::time::{sleep, Duration};
+
+use crate::store::{Row, Statement, Store, Value};
+use crate::{
+    daily,
+    delivery::DeliveryWindow,
+    email_format::EmailFormat,
+    email_metrics::EmailMetrics,
+    feedback,
+    language::Language,
+    mailer::{Email, Mail},
+    mqtt::FactPublisher,
+    opens, outbox, preferences,
+    privacy::Privacy,
+    ranking::Ranking,
+    sanitize,
+    scheduler::Zone,
+    segments::Segment,
+    shutdown::Shutdown,
+    store::{self, FromRow},
+    templates::{Templates, Values},
+    timezone::Timezone,
+    unsubscribe::UnsubscribeSigner,
+    weekdays::Weekdays,
+};
+
+/// How many messages the SMTP provider lets us send. Defaults are the
+/// provider's published limits and can be overridden with the
+/// `EMAIL_RATE_PER_MINUTE` / `EMAIL_RATE_PER_DAY` secrets.
+pub struct SendLimits {
+    per_minute: u32,
+    per_day: u32,
+}
+
+impl SendLimits {
+    /// Known limits for a provider (see `mailer::provider`). Gmail allows
+    /// roughly 500 messages a day. SES starts accounts out of its sandbox at
+    /// 14 a second and 50,000 a day. SendGrid and Mailgun go by plan, so
+    /// theirs are about what their entry-level plans allow.
+    pub fn for_provider(provider: &str) -> Self {
+        match provider {
+            "gmail" => Self {
+                per_minute: 20,
+                per_day: 500,
+            },
+            "sendgrid" => Self {
+                per_minute: 600,
+                per_day: 3_000,
+            },
+            "mailgun" => Self {
+                per_minute: 300,
+                per_day: 1_500,
+            },
+            "ses" => Self {
+                per_minute: 14 * 60,
+                per_day: 50_000,
+            },
+            _ => Self {
+                per_minute: 60,
+                per_day: 10_000,
+            },
+        }
+    }
+
+    pub fn from_secrets(store: &SecretStore, provider: &str) -> Self {
+        let defaults = Self::for_provider(provider);
+
+        Self {
+            per_minute: store
+                .get("EMAIL_RATE_PER_MINUTE")
+                .and_then(|limit| limit.parse().ok())
+                .unwrap_or(defaults.per_minute),
+            per_day: store
+                .get("EMAIL_RATE_PER_DAY")
+                .and_then(|limit| limit.parse().ok())
+                .unwrap_or(defaults.per_day),
+        }
+    }
+}
+
+/// Counts sends in the current minute and day windows. Days are in the
+/// schedules' zone, so the daily cap resets at the same midnight the fact of
+/// the day changes.
+struct RateLimiter {
+    limits: SendLimits,
+    zone: Zone,
+    minute: (NaiveDateTime, u32),
+    day: (NaiveDate, u32),
+}
+
+impl RateLimiter {
+    fn new(limits: SendLimits, zone: Zone) -> Self {
+        let now = zone.now();
+
+        Self {
+            limits,
+            zone,
+            minute: (minute_start(now), 0),
+            day: (now.date(), 0),
+        }
+    }
+
+    /// Waits for room in the per-minute window. Returns false if the daily cap
+    /// has been hit, in which case nothing more should be sent today.
+    async fn acquire(&mut self) -> bool {
+        let now = self.zone.now();
+        if now.date() != self.day.0 {
+            self.day = (now.date(), 0);
+        }
+        if self.day.1 >= self.limits.per_day {
+            return false;
+        }
+
+        if minute_start(now) != self.minute.0 {
+            self.minute = (minute_start(now), 0);
+        }
+        if self.minute.1 >= self.limits.per_minute {
+            let next_minute = self.minute.0 + chrono::Duration::minutes(1);
+            let wait = next_minute
+                .signed_duration_since(self.zone.now())
+                .to_std()
+                .unwrap_or(Duration::ZERO);
+            sleep(wait).await;
+            self.minute = (next_minute, 0);
+        }
+
+        self.minute.1 += 1;
+        self.day.1 += 1;
+        true
+    }
+}
+
+fn minute_start(time: NaiveDateTime) -> NaiveDateTime {
+    time.date()
+        .and_hms_opt(time.hour(), time.minute(), 0)
+        .unwrap()
+}
+
+pub struct Recipient {
+    pub id: i64,
+    pub email: String,
+    token: Option<String>,
+    format: EmailFormat,
+    language: Language,
+}
+
+impl FromRow for Recipient {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            id: store::integer(row, 0)?,
+            email: store::text(row, 1)?,
+            token: store::optional_text(row, 2)?,
+            format: EmailFormat::from_name(&store::text(row, 3)?).unwrap_or_default(),
+            language: Language::from_name(&store::text(row, 4)?).unwrap_or_default(),
+        })
+    }
+}
+
+/// Sends the daily fact to each delivery window's subscribers, within the
+/// provider's rate limits. Recipients are read a page at a time, so memory
+/// stays flat however long the list gets. Those that don't fit under the daily
+/// cap spill over and are sent first in the next window that has room.
+///
+/// When the service stops, sending stops before the next recipient and the
+/// queue is saved, for the next instance to resume (see `shutdown`).
+pub struct Dispatcher {
+    mailer: Mail,
+    sender: Option<Mailbox>,
+    db: Arc<dyn Store>,
+    mqtt: Option<FactPublisher>,
+    ranking: Ranking,
+    privacy: Privacy,
+    composer: Composer,
+    metrics: Arc<EmailMetrics>,
+    limiter: RateLimiter,
+    spillover: VecDeque<Spillover>,
+    shutdown: Shutdown,
+    /// The zone delivery hours are in for subscribers without their own.
+    zone: Zone,
+}
+
+/// What one run of the daily send did.
+#[derive(Default, Serialize)]
+pub struct SendReport {
+    pub sent: usize,
+    pub failed: usize,
+    /// Left queued for the next window because of the daily cap.
+    pub deferred: usize,
+}
+
+/// The rest of a window's recipients, from just after `after`, still to send.
+/// Kept as a position in the subscriber list rather than the recipients
+/// themselves, however many the daily cap held back.
+struct Spillover {
+    window: Window,
+    after: i64,
+    /// Later windows these recipients were also due in, who already got that
+    /// window's email.
+    reached: Vec<Window>,
+    /// Only these recipients, for a send to one segment.
+    segment: Option<Segment>,
+    /// Unix seconds, for the queue age metric.
+    queued_at: i64,
+    /// Roughly how many are left, for the queue depth metric.
+    remaining: usize,
+}
+
+impl Dispatcher {
+    pub fn new(
+        mailer: Mail,
+        sender: Option<Mailbox>,
+        db: Arc<dyn Store>,
+        mqtt: Option<FactPublisher>,
+        composer: Composer,
+        metrics: Arc<EmailMetrics>,
+        limits: SendLimits,
+    ) -> Self {
+        Self {
+            mailer,
+            sender,
+            db,
+            mqtt,
+            ranking: Ranking::default(),
+            privacy: Privacy::default(),
+            composer,
+            metrics,
+            limiter: RateLimiter::new(limits, Zone::Local),
+            spillover: VecDeque::new(),
+            shutdown: Shutdown::default(),
+            zone: Zone::Local,
+        }
+    }
+
+    /// Has the ranker pick any fact of the day this needs, rather than
+    /// picking uniformly.
+    pub fn with_ranking(mut self, ranking: Ranking) -> Self {
+        self.ranking = ranking;
+        self
+    }
+
+    /// Pseudonymizes finished emails in the outbox after each send, if
+    /// `privacy` is on.
+    pub fn with_privacy(mut self, privacy: Privacy) -> Self {
+        self.privacy = privacy;
+        self
+    }
+
+    /// Stops sending once `shutdown` is requested.
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Opens the windows of subscribers without a time zone of their own,
+    /// and counts the daily cap, by the clock in `zone` rather than the
+    /// server's.
+    pub fn with_zone(mut self, zone: Zone) -> Self {
+        self.limiter = RateLimiter::new(self.limiter.limits, zone);
+        self.zone = zone;
+        self
+    }
+
+    /// The scheduler's send at `now`: every window that's opened since the
+    /// last one sent (see `due_windows`), or with none on record, since the
+    /// scheduler last ran at `since`. Each is skipped if another instance - or
+    /// this one before a restart - has already started it, so two instances
+    /// running at once around a deploy don't both send it. Each time zone
+    /// subscribers are in has its own windows, opening on its own clock. The
+    /// day's first send in the schedule's zone also publishes its fact over
+    /// MQTT. A window that can't be sent yet, e.g. during a lockdown, is left
+    /// unclaimed for a later run.
+    pub async fn send_scheduled(
+        &mut self,
+        since: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Result<SendReport, anyhow::Error> {
+        let results = self
+            .db
+            .batch([
+                Statement::new(
+                    "DELETE FROM dispatch_windows WHERE started_at < datetime('now', '-7 days')",
+                ),
+                Statement::new("SELECT delivery_window FROM dispatch_windows"),
+                Statement::new(
+                    "SELECT DISTINCT utc_offset FROM subscribers WHERE utc_offset IS NOT NULL AND confirmed = 1",
+                ),
+            ])
+            .await?;
+        let (Some(sent), Some(offsets)) = (results.get(1), results.get(2)) else {
+            return Err(anyhow!("missing delivery window results"));
+        };
+        let sent = store::rows::<String>(sent)?
+            .iter()
+            .map(|key| Window::from_key(key))
+            .collect::<Result<Vec<_>, _>>()?;
+        let timezones: Vec<Timezone> = store::rows::<i64>(offsets)?
+            .into_iter()
+            .filter_map(|minutes| Timezone::from_minutes(Some(minutes)))
+            .collect();
+
+        let today = self.zone.wall_clock(now).date();
+        let mut first_today = !sent
+            .iter()
+            .any(|window| window.timezone == Some(Timezone::Schedule) && window.date == today);
+        let last = sent
+            .into_iter()
+            .max_by_key(|window| (window.opens_at(self.zone), window.key()));
+
+        let mut report = SendReport::default();
+        for window in due_windows(last, since, now, self.zone, &timezones) {
+            let Some(prepared) = self.prepare(window.date).await? else {
+                continue;
+            };
+            let claimed = self
+                .db
+                .execute(Statement::with_args(
+                    "INSERT INTO dispatch_windows (delivery_window) VALUES (?) ON CONFLICT DO NOTHING",
+                    &[window.key()],
+                ))
+                .await?;
+            if claimed.rows_affected == 0 {
+                tracing::info!("The {} window has already been sent", window.key());
+                continue;
+            }
+
+            let publish =
+                first_today && window.timezone == Some(Timezone::Schedule) && window.date == today;
+            first_today &= !publish;
+            let sent = self.send_prepared(window, None, publish, prepared).await?;
+            report.sent += sent.sent;
+            report.failed += sent.failed;
+            report.deferred = sent.deferred;
+        }
+
+        Ok(report)
+    }
+
+    /// Sends the day's email to everyone due at `hour`, whatever their time
+    /// zone, or with a `segment`, only those of them in it.
+    #[tracing::instrument(skip(self, segment))]
+    pub async fn send_subscriber_mail(
+        &mut self,
+        date: NaiveDate,
+        hour: u32,
+        segment: Option<Segment>,
+    ) -> Result<SendReport, anyhow::Error> {
+        let publish = hour == 0 && segment.is_none();
+        let window = Window {
+            date,
+            hour,
+            timezone: None,
+        };
+        self.send_window(window, segment, publish).await
+    }
+
+    /// Sends the email for `window`'s date to its subscribers, first
+    /// publishing the fact over MQTT if `publish`.
+    async fn send_window(
+        &mut self,
+        window: Window,
+        segment: Option<Segment>,
+        publish: bool,
+    ) -> Result<SendReport, anyhow::Error> {
+        match self.prepare(window.date).await? {
+            Some(prepared) => self.send_prepared(window, segment, publish, prepared).await,
+            None => Ok(SendReport::default()),
+        }
+    }
+
+    /// `send_window`, once `prepare` has found the sender and fact.
+    async fn send_prepared(
+        &mut self,
+        window: Window,
+        segment: Option<Segment>,
+        publish: bool,
+        (sender, cat_fact): (Mailbox, String),
+    ) -> Result<SendReport, anyhow::Error> {
+        let mut report = SendReport::default();
+        let date = window.date;
+
+        if publish {
+            if let Some(mqtt) = &self.mqtt {
+                mqtt.publish_daily(&cat_fact).await;
+            }
+        }
+
+        // Anyone held back from an earlier window who's also due in this one
+        // only needs one email, so they're left to this window. A segment's
+        // send doesn't reach everyone, so it leaves them be.
+        if segment.is_none() {
+            for spillover in &mut self.spillover {
+                spillover.reached.push(window);
+            }
+        }
+        self.spillover.push_back(Spillover {
+            window,
+            after: 0,
+            reached: Vec::new(),
+            segment,
+            queued_at: Utc::now().timestamp(),
+            remaining: 0,
+        });
+        self.send_queue(&sender, date, &cat_fact, &mut report)
+            .await?;
+
+        Ok(report)
+    }
+
+    /// Takes over the queue a stopped instance saved, if there is one, and
+    /// sends it `date`'s fact.
+    pub async fn resume(&mut self, date: NaiveDate) -> Result<SendReport, anyhow::Error> {
+        let mut report = SendReport::default();
+        if self.shutdown.is_requested() {
+            return Ok(report);
+        }
+
+        // Read and cleared together, so only one instance takes it.
+        let results = self
+            .db
+            .batch([
+                Statement::new(
+                    "SELECT delivery_window, after, reached, domains, queued_at
+                    FROM dispatch_queue ORDER BY id",
+                ),
+                Statement::new("DELETE FROM dispatch_queue"),
+            ])
+            .await?;
+        let saved: Vec<Spillover> = match results.first() {
+            Some(saved) => store::rows(saved)?,
+            None => return Err(anyhow!("missing saved queue results")),
+        };
+        if saved.is_empty() {
+            return Ok(report);
+        }
+        tracing::info!(
+            "Resuming a stopped instance's send ({} delivery windows)",
+            saved.len()
+        );
+        self.spillover.extend(saved);
+
+        let Some((sender, cat_fact)) = self.prepare(date).await? else {
+            return Ok(report);
+        };
+        self.send_queue(&sender, date, &cat_fact, &mut report)
+            .await?;
+
+        Ok(report)
+    }
+
+    /// Saves what's left of the queue for the next instance to `resume`, once
+    /// a stop has ended the sending. Returns how many recipients were left.
+    pub async fn save_queue(&mut self) -> Result<usize, anyhow::Error> {
+        if self.spillover.is_empty() {
+            return Ok(0);
+        }
+
+        let left = self.queued();
+        self.db
+            .batch(self.spillover.iter().map(|spillover| {
+                Statement::with_args(
+                    "INSERT INTO dispatch_queue (delivery_window, after, reached, domains, queued_at)
+                    VALUES (?, ?, ?, ?, ?)",
+                    &[
+                        Value::from(spillover.window.key()),
+                        Value::from(spillover.after),
+                        Value::from(
+                            spillover
+                                .reached
+                                .iter()
+                                .map(Window::key)
+                                .collect::<Vec<_>>()
+                                .join(","),
+                        ),
+                        Value::from(
+                            spillover
+                                .segment
+                                .as_ref()
+                                .map(|segment| segment.domains().join(",")),
+                        ),
+                        Value::from(spillover.queued_at),
+                    ],
+                )
+            }))
+            .await?;
+        self.spillover.clear();
+        self.report_queue();
+
+        Ok(left)
+    }
+
+    /// The sender and `date`'s fact, or `None` if nothing can be sent right
+    /// now.
+    async fn prepare(&self, date: NaiveDate) -> Result<Option<(Mailbox, String)>, anyhow::Error> {
+        if self.mailer.is_paused().await {
+            tracing::warn!("Not sending subscriber mail: there's a lockdown on");
+            return Ok(None);
+        }
+        let Some(sender) = self.sender.clone() else {
+            tracing::warn!("Not sending subscriber mail: MAIL_FROM (or GMAIL_USER) isn't a valid email address");
+            return Ok(None);
+        };
+
+        // Every delivery window on a given day gets the same fact.
+        let cat_fact = match daily::fact_for_date(&*self.db, &self.ranking, date).await? {
+            Some(fact) => sanitize::plain_text(&fact),
+            None => return Ok(None),
+        };
+
+        Ok(Some((sender, cat_fact)))
+    }
+
+    /// Counts who's left in the queue, then sends to them with `date`'s fact.
+    async fn send_queue(
+        &mut self,
+        sender: &Mailbox,
+        date: NaiveDate,
+        cat_fact: &str,
+        report: &mut SendReport,
+    ) -> Result<(), anyhow::Error> {
+        let db = &*self.db;
+        for spillover in &mut self.spillover {
+            spillover.remaining = count_recipients(
+                db,
+                spillover.window,
+                spillover.after,
+                &spillover.reached,
+                spillover.segment.as_ref(),
+            )
+            .await
+            .map_err(|e| anyhow!("Had an error while sending emails: {e}"))?;
+        }
+
+        self.report_queue();
+        self.metrics.start_draining();
+        let before = report.sent + report.failed;
+        let drained = self.drain(sender, date, cat_fact, report).await;
+        self.metrics.stop_draining();
+        self.count_recipients(date, report.sent + report.failed - before)
+            .await;
+        drained?;
+        self.scrub_outbox().await;
+
+        Ok(())
+    }
+
+    /// Sends to the spilled-over recipients a page at a time until they've all
+    /// been sent or the daily cap is hit. Whichever day they were held back
+    /// from, they get `date`'s fact, so their feedback links are for `date`.
+    async fn drain(
+        &mut self,
+        sender: &Mailbox,
+        date: NaiveDate,
+        cat_fact: &str,
+        report: &mut SendReport,
+    ) -> Result<(), anyhow::Error> {
+        while let Some(spillover) = self.spillover.front() {
+            let page = recipients_page(
+                &*self.db,
+                spillover.window,
+                spillover.after,
+                &spillover.reached,
+                spillover.segment.as_ref(),
+            )
+            .await
+            .map_err(|e| anyhow!("Had an error while sending emails: {e}"))?;
+            if page.is_empty() {
+                self.spillover.pop_front();
+                self.report_queue();
+                continue;
+            }
+
+            for recipient in page {
+                // A bad address shouldn't take the rest of the batch down with it.
+                let to = match recipient.email.parse::<Mailbox>() {
+                    Ok(to) => to,
+                    Err(e) => {
+                        tracing::warn!(
+                            "Skipping invalid subscriber address {:?}: {e}",
+                            recipient.email
+                        );
+                        self.flag_for_review(&recipient.email).await;
+                        self.advance(recipient.id);
+                        continue;
+                    }
+                };
+
+                let acquired = tokio::select! {
+                    acquired = self.limiter.acquire() => acquired,
+                    // Waiting for the rate limit shouldn't hold up a stop.
+                    _ = self.shutdown.requested() => false,
+                };
+                if self.shutdown.is_requested() {
+                    report.deferred = self.queued();
+                    tracing::info!(
+                        "Stopping with {} recipients left to send, for the next instance",
+                        report.deferred
+                    );
+                    return Ok(());
+                }
+                if !acquired {
+                    report.deferred = self.queued();
+                    tracing::warn!(
+                        "Hit the daily cap of {} emails, deferring {} recipients to the next window",
+                        self.limiter.limits.per_day,
+                        report.deferred
+                    );
+                    return Ok(());
+                }
+
+                let sent = self.send(sender, to, &recipient, date, cat_fact).await;
+                self.metrics.record_send(sent);
+                // Stays queued until it's sent, so the depth gauge counts it.
+                self.advance(recipient.id);
+                if sent {
+                    report.sent += 1;
+                } else {
+                    report.failed += 1;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves the front of the queue past the recipient with `id`.
+    fn advance(&mut self, id: i64) {
+        if let Some(spillover) = self.spillover.front_mut() {
+            spillover.after = id;
+            spillover.remaining = spillover.remaining.saturating_sub(1);
+        }
+        self.report_queue();
+    }
+
+    /// Roughly how many recipients are left in the queue.
+    fn queued(&self) -> usize {
+        self.spillover
+            .iter()
+            .map(|spillover| spillover.remaining)
+            .sum()
+    }
+
+    fn report_queue(&self) {
+        self.metrics.set_queue(
+            self.queued(),
+            self.spillover.front().map(|spillover| spillover.queued_at),
+        );
+    }
+
+    /// Adds `count` to how many `date`'s fact was sent to, for open rates.
+    async fn count_recipients(&self, date: NaiveDate, count: usize) {
+        if count == 0 {
+            return;
+        }
+        if let Err(e) = self
+            .db
+            .execute(Statement::with_args(
+                "UPDATE daily_facts SET recipients = recipients + ? WHERE date = ?",
+                &[Value::from(count as i64), Value::from(date.to_string())],
+            ))
+            .await
+        {
+            tracing::warn!("Couldn't count who {date}'s fact was sent to: {e}");
+        }
+    }
+
+    /// Marks a subscriber so they're skipped by future sends until someone
+    /// looks at their address.
+    async fn flag_for_review(&self, email: &str) {
+        if let Err(e) = self
+            .db
+            .execute(Statement::with_args(
+                "UPDATE subscribers SET needs_review = 1 WHERE email = ?",
+                &[email],
+            ))
+            .await
+        {
+            tracing::warn!("Couldn't flag {email:?} for review: {e}");
+        }
+    }
+
+    /// Returns whether the email went out.
+    async fn send(
+        &self,
+        from: &Mailbox,
+        to: Mailbox,
+        recipient: &Recipient,
+        date: NaiveDate,
+        cat_fact: &str,
+    ) -> bool {
+        let email = self.composer.compose(from, &to, recipient, date, cat_fact);
+
+        // If it can't be queued it's still worth a try, just without retries.
+        let queued = match outbox::enqueue(&*self.db, &recipient.email, date, &email).await {
+            Ok(id) => Some(id),
+            Err(e) => {
+                tracing::warn!(
+                    "Couldn't queue the email for subscriber {}: {e}",
+                    recipient.id
+                );
+                None
+            }
+        };
+
+        let sent = self.mailer.deliver(&email).await;
+        if let Err(e) = &sent {
+            tracing::error!("Something went wrong while sending mail: {e}");
+        }
+        if let Some(id) = queued {
+            self.record_attempt(id, &sent).await;
+        }
+
+        sent.is_ok()
+    }
+
+    /// Marks a queued email sent, or schedules its retry.
+    async fn record_attempt(&self, id: i64, sent: &Result<String, anyhow::Error>) {
+        let recorded = match sent {
+            Ok(relay) => outbox::mark_sent(&*self.db, id, relay).await,
+            Err(e) => match outbox::mark_failed(&*self.db, id, &e.to_string()).await {
+                Ok(true) => {
+                    self.metrics.record_dead_letter();
+                    tracing::error!(
+                        "Alert: queued email {id} failed {} times and was dead-lettered: {e}",
+                        outbox::MAX_ATTEMPTS
+                    );
+                    Ok(())
+                }
+                Ok(false) => Ok(()),
+                Err(e) => Err(e),
+            },
+        };
+        if let Err(e) = recorded {
+            tracing::warn!("Couldn't record how sending queued email {id} went: {e}");
+        }
+    }
+
+    /// Retries the queued emails that are due another attempt, within the
+    /// same rate limits as the daily send.
+    #[tracing::instrument(skip(self))]
+    pub async fn retry_failed(&mut self) -> Result<SendReport, anyhow::Error> {
+        let mut report = SendReport::default();
+        if self.mailer.is_paused().await {
+            return Ok(report);
+        }
+
+        let due = outbox::due(&*self.db, PAGE_SIZE).await?;
+        let total = due.len();
+        for queued in due {
+            let acquired = tokio::select! {
+                acquired = self.limiter.acquire() => acquired,
+                _ = self.shutdown.requested() => false,
+            };
+            // Whatever's left stays queued where the next instance finds it.
+            if !acquired || self.shutdown.is_requested() {
+                report.deferred = total - report.sent - report.failed;
+                break;
+            }
+
+            let sent = self.mailer.deliver(&queued.email).await;
+            if let Err(e) = &sent {
+                tracing::warn!("Retrying queued email {} failed: {e}", queued.id);
+            }
+            self.metrics.record_send(sent.is_ok());
+            self.record_attempt(queued.id, &sent).await;
+            if sent.is_ok() {
+                report.sent += 1;
+            } else {
+                report.failed += 1;
+            }
+        }
+
+        if total > 0 {
+            tracing::info!(
+                "Retried {total} queued emails: {} sent, {} failed, {} deferred",
+                report.sent,
+                report.failed,
+                report.deferred
+            );
+        }
+        self.scrub_outbox().await;
+
+        Ok(report)
+    }
+
+    /// Pseudonymizes the emails that are finished with, in privacy mode.
+    async fn scrub_outbox(&self) {
+        if let Err(e) = outbox::scrub(&*self.db, &self.privacy).await {
+            tracing::warn!("Couldn't scrub finished emails in the outbox: {e}");
+        }
+    }
+}
+
+/// How many recipients are read from the database at a time.
+pub const PAGE_SIZE: u32 = 500;
+
+/// One day's delivery window in one time zone: everyone in it whose delivery
+/// hour is `hour`, on `date` by their clock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Window {
+    pub date: NaiveDate,
+    pub hour: u32,
+    /// Whose clock it is, or `None` for everyone due at `hour`, whatever
+    /// their zone, as in a send from `POST /admin/send-digest`.
+    pub timezone: Option<Timezone>,
+}
+
+impl Window {
+    /// When it opens, with the schedule's clock in `zone`.
+    fn opens_at(&self, zone: Zone) -> DateTime<Utc> {
+        let time = self.date.and_time(NaiveTime::MIN) + chrono::Duration::hours(self.hour.into());
+        match self.timezone.and_then(|timezone| timezone.offset()) {
+            Some(offset) => Utc.from_utc_datetime(&(time - offset)),
+            None => zone.instant(time),
+        }
+    }
+
+    /// Like `2024-02-03T07` in the schedule's zone, `2024-02-03T07+05:30` in
+    /// a subscriber's own or `2024-02-03T07*` in any, for keeping in the
+    /// database.
+    fn key(&self) -> String {
+        let timezone = match self.timezone {
+            Some(timezone) => timezone.name(),
+            None => "*".to_string(),
+        };
+        format!("{}T{:02}{timezone}", self.date, self.hour)
+    }
+
+    fn from_key(key: &str) -> Result<Self, anyhow::Error> {
+        key.split_once('T')
+            .and_then(|(date, rest)| {
+                let (hour, timezone) = rest.split_at_checked(2)?;
+                Some(Self {
+                    date: NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?,
+                    hour: hour.parse().ok()?,
+                    timezone: match timezone {
+                        "*" => None,
+                        timezone => Some(Timezone::parse(timezone)?),
+                    },
+                })
+            })
+            .ok_or_else(|| anyhow!("{key:?} isn't a delivery window"))
+    }
+
+    /// The condition and arguments selecting the subscribers in this window.
+    fn condition(&self, args: &mut Vec<Value>) -> String {
+        args.push(Value::from(self.hour));
+        args.push(Value::from(u32::from(Weekdays::bit_for(self.date))));
+        match self.timezone {
+            Some(Timezone::Schedule) => {
+                "delivery_hour = ? AND weekdays & ? != 0 AND utc_offset IS NULL".to_string()
+            }
+            Some(Timezone::Offset(minutes)) => {
+                args.push(Value::from(minutes));
+                "delivery_hour = ? AND weekdays & ? != 0 AND utc_offset = ?".to_string()
+            }
+            None => "delivery_hour = ? AND weekdays & ? != 0".to_string(),
+        }
+    }
+}
+
+/// The windows a scheduled send at `now` covers: yesterday's and today's, in
+/// the schedule's `zone` and each of the `timezones` subscribers are in, that
+/// have opened since `last`, the last one sent - or with none on record,
+/// since `since`. So a schedule that doesn't run every hour, like
+/// `0 0 8,18 * * *`, still reaches every window, at the first run after it
+/// opens. They're in the order they open.
+pub fn due_windows(
+    last: Option<Window>,
+    since: DateTime<Utc>,
+    now: DateTime<Utc>,
+    zone: Zone,
+    timezones: &[Timezone],
+) -> Vec<Window> {
+    // Windows opening at the same moment go in order of their keys, so
+    // there's always a window after `last` to carry on from.
+    let order = |window: &Window| (window.opens_at(zone), window.key());
+    let last = last.as_ref().map(order);
+
+    let mut due: Vec<Window> = std::iter::once(Timezone::Schedule)
+        .chain(timezones.iter().copied())
+        .flat_map(|timezone| {
+            let today = match timezone.offset() {
+                Some(offset) => now.with_timezone(&offset).date_naive(),
+                None => zone.wall_clock(now).date(),
+            };
+            [today.pred_opt(), Some(today)]
+                .into_iter()
+                .flatten()
+                .flat_map(move |date| {
+                    DeliveryWindow::ALL.map(|window| Window {
+                        date,
+                        hour: window.hour(),
+                        timezone: Some(timezone),
+                    })
+                })
+        })
+        .filter(|window| window.opens_at(zone) <= now)
+        .filter(|window| match &last {
+            Some(last) => order(window) > *last,
+            None => window.opens_at(zone) > since,
+        })
+        .collect();
+    due.sort_by_cached_key(order);
+
+    due
+}
+
+/// A row of `dispatch_queue`, saved by `Dispatcher::save_queue`.
+impl FromRow for Spillover {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        let segment = match store::optional_text(row, 3)? {
+            Some(domains) => Some(
+                Segment::parse(&domains)
+                    .map_err(|_| anyhow!("the saved segment {domains:?} isn't valid"))?,
+            ),
+            None => None,
+        };
+
+        Ok(Self {
+            window: Window::from_key(&store::text(row, 0)?)?,
+            after: store::integer(row, 1)?,
+            reached: store::text(row, 2)?
+                .split(',')
+                .filter(|key| !key.is_empty())
+                .map(Window::from_key)
+                .collect::<Result<_, _>>()?,
+            segment,
+            queued_at: store::integer(row, 4)?,
+            remaining: 0,
+        })
+    }
+}
+
+/// The condition and arguments selecting everyone due the daily email in
+/// `window` whose id is after `after`, leaving out anyone also due in one of
+/// the `reached` windows, anyone outside `segment`, and anyone whose topics
+/// the day's fact isn't on.
+fn due_in(
+    window: Window,
+    after: i64,
+    reached: &[Window],
+    segment: Option<&Segment>,
+) -> (String, Vec<Value>) {
+    let mut args = Vec::new();
+    let mut condition = window.condition(&mut args);
+    condition.push_str(" AND id > ? AND confirmed = 1 AND needs_review = 0 AND lower(email) NOT IN (SELECT email FROM suppressions)");
+    args.push(Value::from(after));
+    for reached in reached {
+        condition.push_str(&format!(" AND NOT ({})", reached.condition(&mut args)));
+    }
+    if let Some(segment) = segment {
+        condition.push_str(" AND ");
+        condition.push_str(&segment.condition(&mut args));
+    }
+    // Subscribers who chose topics skip days whose fact isn't on any of them.
+    condition.push_str(
+        " AND (NOT EXISTS (SELECT 1 FROM subscriber_topics WHERE subscriber_id = subscribers.id)
+        OR EXISTS (SELECT 1 FROM subscriber_topics
+            JOIN catfact_tags ON catfact_tags.tag_id = subscriber_topics.tag_id
+            JOIN daily_facts ON daily_facts.catfact_id = catfact_tags.catfact_id
+            WHERE subscriber_topics.subscriber_id = subscribers.id AND daily_facts.date = ?))",
+    );
+    args.push(Value::from(window.date.to_string()));
+
+    (condition, args)
+}
+
+/// The next `PAGE_SIZE` recipients due in `window` after the one with id
+/// `after`, by id. See `due_in` for `reached` and `segment`.
+pub async fn recipients_page(
+    db: &dyn Store,
+    window: Window,
+    after: i64,
+    reached: &[Window],
+    segment: Option<&Segment>,
+) -> Result<Vec<Recipient>, anyhow::Error> {
+    let (condition, mut args) = due_in(window, after, reached, segment);
+    args.push(Value::from(PAGE_SIZE));
+
+    let res = db
+        .execute(Statement::with_args(
+            format!(
+                "SELECT id, email, token, email_format, language FROM subscribers WHERE {condition}
+                ORDER BY id LIMIT ?"
+            ),
+            &args,
+        ))
+        .await?;
+
+    store::rows::<Recipient>(&res)
+}
+
+async fn count_recipients(
+    db: &dyn Store,
+    window: Window,
+    after: i64,
+    reached: &[Window],
+    segment: Option<&Segment>,
+) -> Result<usize, anyhow::Error> {
+    let (condition, args) = due_in(window, after, reached, segment);
+
+    let count = db
+        .execute(Statement::with_args(
+            format!("SELECT count(*) FROM subscribers WHERE {condition}"),
+            &args,
+        ))
+        .await
+        .and_then(|res| store::first::<i64>(&res))?;
+
+    Ok(count.unwrap_or(0) as usize)
+}
+
+/// Everything needed to turn the day's fact into an email for one recipient.
+#[derive(Clone)]
+pub struct Composer {
+    pub public_url: String,
+    pub unsubscribe: Option<UnsubscribeSigner>,
+    pub templates: Arc<Templates>,
+}
+
+impl Composer {
+    /// Builds the daily email for one recipient, with the fact for `date`.
+    pub fn compose(
+        &self,
+        from: &Mailbox,
+        to: &Mailbox,
+        recipient: &Recipient,
+        date: NaiveDate,
+        cat_fact: &str,
+    ) -> Email {
+        let preferences_url = match &recipient.token {
+            Some(token) => preferences::preferences_url(&self.public_url, token),
+            None => self.public_url.to_string(),
+        };
+
+        let unsubscribe_url = match (&self.unsubscribe, &recipient.token) {
+            (Some(signer), Some(token)) => Some(signer.unsubscribe_url(&self.public_url, token)),
+            _ => None,
+        };
+        let feedback_url = recipient
+            .token
+            .as_deref()
+            .map(|token| feedback::feedback_url(&self.public_url, date, token));
+        let values = Values {
+            fact: cat_fact,
+            preferences_url: &preferences_url,
+            unsubscribe_url: unsubscribe_url.as_deref(),
+            feedback_url: feedback_url.as_deref(),
+        };
+
+        let mut html = self
+            .templates
+            .html(recipient.language, recipient.format, &values);
+        // Counts opens (see `opens`).
+        if let Some(token) = &recipient.token {
+            let pixel = format!(
+                "<img src=\"{}\" width=\"1\" height=\"1\" alt=\"\">\n",
+                opens::open_url(&self.public_url, date, token)
+            );
+            // Inside the body, for a template that's a whole document.
+            let at = html.rfind("</body>").unwrap_or(html.len());
+            html.insert_str(at, &pixel);
+        }
+
+        Email {
+            html: Some(html),
+            unsubscribe_url: unsubscribe_url.clone(),
+            ..Email::new(
+                from,
+                to,
+                self.templates.subject(recipient.language, &values),
+                self.templates.text(recipient.language, &values),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date: &str, hour: u32) -> DateTime<Utc> {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+            .and_utc()
+    }
+
+    fn window(date: &str, hour: u32) -> Window {
+        Window {
+            date: NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap(),
+            hour,
+            timezone: Some(Timezone::Schedule),
+        }
+    }
+
+    /// The schedule's windows, with it on UTC.
+    fn due_windows(last: Option<Window>, since: DateTime<Utc>, now: DateTime<Utc>) -> Vec<Window> {
+        super::due_windows(last, since, now, utc(), &[])
+    }
+
+    fn utc() -> Zone {
+        Zone::Fixed(chrono::FixedOffset::east_opt(0).unwrap())
+    }
+
+    #[test]
+    fn hourly_runs_send_each_window_as_it_opens() {
+        let last = Some(window("2024-02-03", 8));
+        assert_eq!(
+            due_windows(last, at("2024-02-03", 11), at("2024-02-03", 12)),
+            [window("2024-02-03", 12)]
+        );
+        assert!(due_windows(last, at("2024-02-03", 9), at("2024-02-03", 10)).is_empty());
+    }
+
+    #[test]
+    fn runs_at_8_and_18_reach_every_window() {
+        // Started at 07:30 with nothing sent yet, so midnight's window has
+        // passed before this instance ran.
+        let first = due_windows(None, at("2024-02-03", 7), at("2024-02-03", 8));
+        assert_eq!(first, [window("2024-02-03", 8)]);
+
+        let evening = due_windows(
+            first.last().copied(),
+            at("2024-02-03", 8),
+            at("2024-02-03", 18),
+        );
+        assert_eq!(
+            evening,
+            [window("2024-02-03", 12), window("2024-02-03", 18)]
+        );
+
+        let next_morning = due_windows(
+            evening.last().copied(),
+            at("2024-02-03", 18),
+            at("2024-02-04", 8),
+        );
+        assert_eq!(
+            next_morning,
+            [window("2024-02-04", 0), window("2024-02-04", 8)]
+        );
+    }
+
+    #[test]
+    fn a_once_a_day_run_catches_up_on_yesterday() {
+        let last = Some(window("2024-02-03", 8));
+        assert_eq!(
+            due_windows(last, at("2024-02-03", 8), at("2024-02-04", 8)),
+            [
+                window("2024-02-03", 12),
+                window("2024-02-03", 18),
+                window("2024-02-04", 0),
+                window("2024-02-04", 8),
+            ]
+        );
+    }
+
+    #[test]
+    fn windows_before_yesterday_are_skipped() {
+        let last = Some(window("2024-01-30", 18));
+        assert_eq!(
+            due_windows(last, at("2024-02-03", 23), at("2024-02-04", 0)),
+            [
+                window("2024-02-03", 0),
+                window("2024-02-03", 8),
+                window("2024-02-03", 12),
+                window("2024-02-03", 18),
+                window("2024-02-04", 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn each_time_zone_opens_its_windows_on_its_own_clock() {
+        let india = Timezone::parse("+05:30").unwrap();
+        let new_york = Timezone::parse("-05:00").unwrap();
+        let in_zone = |timezone, date, hour| Window {
+            timezone: Some(timezone),
+            ..window(date, hour)
+        };
+
+        // India's morning window opened at 02:30 UTC, and New York's
+        // midnight one at 05:00.
+        let due = super::due_windows(
+            Some(window("2024-02-03", 0)),
+            at("2024-02-03", 4),
+            at("2024-02-03", 5),
+            utc(),
+            &[india, new_york],
+        );
+        assert_eq!(
+            due,
+            [
+                in_zone(india, "2024-02-03", 8),
+                in_zone(new_york, "2024-02-03", 0)
+            ]
+        );
+
+        // Every window's key reads back as the same window.
+        for window in due {
+            assert_eq!(Window::from_key(&window.key()).unwrap(), window);
+        }
+    }
+
+    struct NoMail;
+
+    #[axum::async_trait]
+    impl crate::mailer::Mailer for NoMail {
+        fn name(&self) -> &'static str {
+            "none"
+        }
+
+        async fn send(&self, _: &Email) -> Result<(), anyhow::Error> {
+            Ok(())
+        }
+    }
+
+    async fn dispatcher(db: Arc<dyn Store>) -> Dispatcher {
+        let lockdown = Arc::new(crate::lockdown::Lockdown::load(db.clone()).await.unwrap());
+        let templates = Templates::from_secrets(&SecretStore::new(Default::default())).unwrap();
+
+        Dispatcher::new(
+            Mail::new(Arc::new(NoMail), lockdown),
+            Some("Cats <cats@example.com>".parse().unwrap()),
+            db,
+            None,
+            Composer {
+                public_url: "https://example.com".to_string(),
+                unsubscribe: None,
+                templates: Arc::new(templates),
+            },
+            Arc::new(EmailMetrics::default()),
+            SendLimits::for_provider("gmail"),
+        )
+        .with_zone(utc())
+    }
+
+    async fn claimed_windows(db: &dyn Store) -> i64 {
+        let res = db
+            .execute("SELECT count(*) FROM dispatch_windows")
+            .await
+            .unwrap();
+        store::first::<i64>(&res).unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn windows_due_during_a_lockdown_are_sent_after_it() {
+        let db: Arc<dyn Store> = Arc::new(crate::store::SqliteStore::open(":memory:").unwrap());
+        crate::schema::migrate(&*db).await.unwrap();
+        db.batch([
+            "INSERT INTO catfacts (fact) VALUES ('Cats sleep most of the day.')",
+            "INSERT INTO lockdown (reason, started_by) VALUES ('testing', 'tests')",
+        ])
+        .await
+        .unwrap();
+        let now = Utc::now();
+        let since = now - chrono::Duration::days(1);
+
+        let report = dispatcher(db.clone())
+            .await
+            .send_scheduled(since, now)
+            .await
+            .unwrap();
+        assert_eq!(report.sent, 0);
+        assert_eq!(claimed_windows(&*db).await, 0);
+
+        db.execute("DELETE FROM lockdown").await.unwrap();
+        dispatcher(db.clone())
+            .await
+            .send_scheduled(since, now)
+            .await
+            .unwrap();
+        assert!(claimed_windows(&*db).await > 0);
+    }
+}
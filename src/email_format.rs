@@ -0,0 +1,37 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+/// How a subscriber's HTML email is laid out. The plain-text part is the same
+/// either way.
+#[derive(Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailFormat {
+    #[default]
+    Standard,
+    /// High-contrast, larger text and semantic markup for screen readers.
+    Accessible,
+}
+
+impl EmailFormat {
+    pub const ALL: [EmailFormat; 2] = [Self::Standard, Self::Accessible];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|format| format.name() == name)
+    }
+
+    /// The name used in JSON bodies, HTML forms and the `email_format` column.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Standard => "standard",
+            Self::Accessible => "accessible",
+        }
+    }
+
+    /// What the preference center calls this format.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Standard => "Standard",
+            Self::Accessible => "Accessible (high contrast, screen-reader friendly)",
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use chrono::Utc;
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::Mutex;
+use tokio::time::{sleep, Duration};
+
+/// How long the queue can go without a send finishing, while the dispatcher
+/// is working through it, before it counts as stalled.
+const STALL_AFTER_SECS: i64 = 15 * 60;
+
+/// Gauges and counters for the daily send, shared between the dispatcher that
+/// updates them and `GET /metrics`.
+#[derive(Default)]
+pub struct EmailMetrics {
+    depth: AtomicU64,
+    /// Unix seconds when the oldest queued email was queued, or 0 when empty.
+    oldest_queued_at: AtomicI64,
+    sent: AtomicU64,
+    failed: AtomicU64,
+    dead_lettered: AtomicU64,
+    /// Completion times of the sends in the last minute.
+    recent_sends: Mutex<VecDeque<i64>>,
+    /// Set while the dispatcher is sending. A queue that's parked until the
+    /// next window because of the daily cap isn't expected to drain.
+    draining: AtomicBool,
+    last_progress_at: AtomicI64,
+    stalled: AtomicBool,
+}
+
+impl EmailMetrics {
+    /// Records the queue's current size and when its oldest entry was queued.
+    pub fn set_queue(&self, depth: usize, oldest_queued_at: Option<i64>) {
+        self.depth.store(depth as u64, Ordering::Relaxed);
+        self.oldest_queued_at
+            .store(oldest_queued_at.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    pub fn start_draining(&self) {
+        self.last_progress_at.store(now(), Ordering::Relaxed);
+        self.draining.store(true, Ordering::Relaxed);
+    }
+
+    pub fn stop_draining(&self) {
+        self.draining.store(false, Ordering::Relaxed);
+    }
+
+    pub fn record_send(&self, ok: bool) {
+        let now = now();
+        self.last_progress_at.store(now, Ordering::Relaxed);
+
+        if ok {
+            self.sent.fetch_add(1, Ordering::Relaxed);
+            if let Ok(mut recent) = self.recent_sends.lock() {
+                recent.push_back(now);
+                prune(&mut recent, now);
+            }
+        } else {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts an email given up on after its last retry.
+    pub fn record_dead_letter(&self) {
+        self.dead_lettered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn sends_last_minute(&self) -> usize {
+        match self.recent_sends.lock() {
+            Ok(mut recent) => {
+                prune(&mut recent, now());
+                recent.len()
+            }
+            Err(_) => 0,
+        }
+    }
+
+    fn oldest_age_secs(&self) -> i64 {
+        match self.oldest_queued_at.load(Ordering::Relaxed) {
+            0 => 0,
+            queued_at => (now() - queued_at).max(0),
+        }
+    }
+
+    fn is_stalled(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+            && self.depth.load(Ordering::Relaxed) > 0
+            && now() - self.last_progress_at.load(Ordering::Relaxed) > STALL_AFTER_SECS
+    }
+
+    /// Appends the email metrics to a Prometheus text body.
+    pub fn render(&self, body: &mut String) {
+        let metrics = [
+            (
+                "email_queue_depth",
+                "gauge",
+                "Emails waiting to be sent.",
+                self.depth.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "email_queue_oldest_age_seconds",
+                "gauge",
+                "How long the oldest queued email has been waiting.",
+                self.oldest_age_secs().to_string(),
+            ),
+            (
+                "email_sends_last_minute",
+                "gauge",
+                "Emails sent in the last minute.",
+                self.sends_last_minute().to_string(),
+            ),
+            (
+                "email_queue_stalled",
+                "gauge",
+                "1 if the queue has stopped draining mid-send.",
+                u8::from(self.stalled.load(Ordering::Relaxed)).to_string(),
+            ),
+            (
+                "emails_sent_total",
+                "counter",
+                "Emails sent since startup.",
+                self.sent.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "emails_failed_total",
+                "counter",
+                "Emails the provider rejected since startup.",
+                self.failed.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "emails_dead_lettered_total",
+                "counter",
+                "Emails given up on after their last retry since startup.",
+                self.dead_lettered.load(Ordering::Relaxed).to_string(),
+            ),
+        ];
+
+        for (name, kind, help, value) in metrics {
+            let _ = writeln!(body, "# HELP {name} {help}");
+            let _ = writeln!(body, "# TYPE {name} {kind}");
+            let _ = writeln!(body, "{name} {value}");
+        }
+    }
+}
+
+/// Checks once a minute whether the queue has stopped draining, which usually
+/// means a wedged SMTP connection, and logs an alert when it starts and stops.
+pub async fn watch(metrics: std::sync::Arc<EmailMetrics>) {
+    loop {
+        sleep(Duration::from_secs(60)).await;
+
+        let stalled = metrics.is_stalled();
+        let was_stalled = metrics.stalled.swap(stalled, Ordering::Relaxed);
+
+        if stalled && !was_stalled {
+            tracing::error!(
+                "Alert: the email queue isn't draining - {} emails waiting, oldest for {}s, nothing sent in over {}m",
+                metrics.depth.load(Ordering::Relaxed),
+                metrics.oldest_age_secs(),
+                STALL_AFTER_SECS / 60
+            );
+        } else if was_stalled && !stalled {
+            tracing::info!("The email queue is draining again");
+        }
+    }
+}
+
+fn prune(recent: &mut VecDeque<i64>, now: i64) {
+    while recent.front().is_some_and(|sent_at| now - sent_at > 60) {
+        recent.pop_front();
+    }
+}
+
+fn now() -> i64 {
+    Utc::now().timestamp()
+}
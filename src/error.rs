@@ -0,0 +1,146 @@
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// The error side of the JSON API's handlers. Every variant becomes
+/// `{"error": {"code": ..., "message": ...}}` with a matching status code.
+/// Server-side failures are logged in full but only described vaguely to the
+/// client, so database and provider errors don't leak out.
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    /// The body parsed but doesn't make sense, e.g. an unknown field.
+    Validation(String),
+    /// An extractor rejected the request with its own status, e.g. a 415 for
+    /// a missing `Content-Type`.
+    InvalidBody(StatusCode, String),
+    /// An optional feature that isn't configured on this deployment.
+    Unavailable(String),
+    /// Too many requests from one client; holds the seconds until it can
+    /// try again.
+    RateLimited(u64),
+    /// A service we depend on (a sync source, Turnstile) failed.
+    Upstream(anyhow::Error),
+    Mail(anyhow::Error),
+    Database(anyhow::Error),
+    Internal(String),
+}
+
+/// What every JSON route's errors look like.
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ErrorDetail {
+    /// e.g. `not_found` or `validation_failed`.
+    code: &'static str,
+    message: String,
+}
+
+impl ApiError {
+    pub fn internal(e: impl std::fmt::Display) -> Self {
+        Self::Internal(e.to_string())
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::InvalidBody(status, _) => *status,
+            Self::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::Upstream(_) | Self::Mail(_) => StatusCode::BAD_GATEWAY,
+            Self::Database(_) | Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::BadRequest(_) => "bad_request",
+            Self::Unauthorized(_) => "unauthorized",
+            Self::Forbidden(_) => "forbidden",
+            Self::NotFound(_) => "not_found",
+            Self::Conflict(_) => "conflict",
+            Self::Validation(_) => "validation_failed",
+            Self::InvalidBody(..) => "invalid_body",
+            Self::Unavailable(_) => "unavailable",
+            Self::RateLimited(_) => "rate_limited",
+            Self::Upstream(_) => "upstream_error",
+            Self::Mail(_) => "mail_error",
+            Self::Database(_) => "database_error",
+            Self::Internal(_) => "internal_error",
+        }
+    }
+
+    /// What the client is told.
+    pub fn message(&self) -> String {
+        match self {
+            Self::BadRequest(message)
+            | Self::Unauthorized(message)
+            | Self::Forbidden(message)
+            | Self::NotFound(message)
+            | Self::Conflict(message)
+            | Self::Validation(message)
+            | Self::InvalidBody(_, message)
+            | Self::Unavailable(message) => message.clone(),
+            Self::RateLimited(retry_after) => {
+                format!("Slow down! Try again in {retry_after} seconds")
+            }
+            Self::Upstream(_) => {
+                "A service we depend on isn't responding, please try again later".to_string()
+            }
+            Self::Mail(_) => "We couldn't send email just now, please try again later".to_string(),
+            Self::Database(_) | Self::Internal(_) => {
+                "Something went wrong on our end, please try again later".to_string()
+            }
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::Database(e)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        match &self {
+            Self::Upstream(e) => tracing::error!("Upstream error: {e}"),
+            Self::Mail(e) => tracing::error!("Mail error: {e}"),
+            Self::Database(e) => tracing::error!("Database error: {e}"),
+            Self::Internal(e) => tracing::error!("Internal error: {e}"),
+            _ => {}
+        }
+
+        let body = ErrorBody {
+            error: ErrorDetail {
+                code: self.code(),
+                message: self.message(),
+            },
+        };
+
+        let mut response = (self.status(), Json(body)).into_response();
+        if let Self::RateLimited(retry_after) = self {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, retry_after.into());
+        }
+
+        response
+    }
+}
@@ -0,0 +1,189 @@
+//! `GET /admin/export/facts`, the whole facts table as CSV or JSON Lines, for
+//! backups and migrations. It's read and sent a page at a time, so the export
+//! never has to fit in memory.
+use axum::{
+    body::{boxed, Body, Bytes},
+    extract::{Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::store::{self, FromRow, Row, Statement, Store, Value};
+use crate::AppState;
+
+const PAGE_SIZE: u32 = 500;
+
+const CSV_HEADER: &str = "id,fact,slug,fact_id,created_at,license,needs_review,tags\r\n";
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Csv,
+    Jsonl,
+}
+
+impl Format {
+    fn content_type(&self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Jsonl => "application/x-ndjson",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Jsonl => "jsonl",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    format: Format,
+}
+
+/// Every column worth keeping, including facts still waiting for review.
+#[derive(Serialize)]
+struct ExportedFact {
+    id: i64,
+    fact: String,
+    slug: Option<String>,
+    fact_id: Option<String>,
+    created_at: String,
+    license: String,
+    needs_review: bool,
+    tags: Vec<String>,
+}
+
+impl FromRow for ExportedFact {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            id: store::integer(row, 0)?,
+            fact: store::text(row, 1)?,
+            slug: store::optional_text(row, 2)?,
+            fact_id: store::optional_text(row, 3)?,
+            created_at: store::text(row, 4)?,
+            license: store::text(row, 5)?,
+            needs_review: store::integer(row, 6)? != 0,
+            tags: store::optional_text(row, 7)?
+                .map(|tags| tags.split(',').map(str::to_string).collect())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+impl ExportedFact {
+    fn to_csv(&self) -> String {
+        let fields = [
+            self.id.to_string(),
+            csv_field(&self.fact),
+            csv_field(self.slug.as_deref().unwrap_or_default()),
+            csv_field(self.fact_id.as_deref().unwrap_or_default()),
+            csv_field(&self.created_at),
+            csv_field(&self.license),
+            i64::from(self.needs_review).to_string(),
+            csv_field(&self.tags.join(",")),
+        ];
+
+        format!("{}\r\n", fields.join(","))
+    }
+}
+
+/// Quotes a CSV field if it needs it.
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+async fn page(db: &dyn Store, after: i64) -> Result<Vec<ExportedFact>, anyhow::Error> {
+    let res = db
+        .execute(Statement::with_args(
+            "SELECT id, fact, slug, fact_id, created_at, license, needs_review,
+            (SELECT group_concat(tags.name, ',') FROM catfact_tags
+                JOIN tags ON tags.id = catfact_tags.tag_id
+                WHERE catfact_tags.catfact_id = catfacts.id)
+            FROM catfacts WHERE id > ? ORDER BY id LIMIT ?",
+            &[Value::from(after), Value::from(PAGE_SIZE)],
+        ))
+        .await?;
+
+    store::rows::<ExportedFact>(&res)
+}
+
+/// `GET /admin/export/facts?format=csv|jsonl` - every fact, oldest first,
+/// streamed as it's read. Defaults to CSV.
+pub async fn export_facts(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ExportQuery>,
+) -> Response {
+    let format = query.format;
+    let (mut sender, body) = Body::channel();
+    let db = state.db.clone();
+
+    tokio::spawn(async move {
+        if let Format::Csv = format {
+            if sender.send_data(Bytes::from(CSV_HEADER)).await.is_err() {
+                return;
+            }
+        }
+
+        let mut after = 0;
+        let mut exported = 0;
+        loop {
+            let facts = match page(&*db, after).await {
+                Ok(facts) => facts,
+                Err(e) => {
+                    // Cuts the response off, so the client can tell the
+                    // export is incomplete.
+                    tracing::error!("Stopped exporting facts after id {after}: {e}");
+                    sender.abort();
+                    return;
+                }
+            };
+            let Some(last) = facts.last() else {
+                break;
+            };
+            after = last.id;
+            exported += facts.len();
+
+            let mut chunk = String::new();
+            for fact in &facts {
+                match format {
+                    Format::Csv => chunk.push_str(&fact.to_csv()),
+                    Format::Jsonl => {
+                        if let Ok(line) = serde_json::to_string(fact) {
+                            chunk.push_str(&line);
+                            chunk.push('\n');
+                        }
+                    }
+                }
+            }
+            // The client went away.
+            if sender.send_data(Bytes::from(chunk)).await.is_err() {
+                return;
+            }
+        }
+
+        tracing::info!("Exported {exported} facts");
+    });
+
+    (
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"catfacts.{}\"", format.extension()),
+            ),
+        ],
+        boxed(body),
+    )
+        .into_response()
+}
@@ -0,0 +1,64 @@
+//! Content-addressed fact identifiers: a hash of a fact's text that's the same
+//! in every environment, unlike the autoincrement `id`.
+use anyhow::anyhow;
+use sha1::{Digest, Sha1};
+
+use crate::crypto;
+use crate::store::{self, FromRow, Row, Statement, Store, Value};
+
+/// Returns the fact id for `fact`: the hex SHA-1 of its text with surrounding
+/// whitespace trimmed and inner runs of whitespace collapsed, so trivially
+/// reformatted copies of a fact hash the same.
+pub fn fact_id(fact: &str) -> String {
+    let normalized = fact.split_whitespace().collect::<Vec<_>>().join(" ");
+    crypto::hex(&Sha1::digest(normalized.as_bytes()))
+}
+
+/// Whether a lookup key looks like a fact id rather than a slug.
+pub fn is_fact_id(key: &str) -> bool {
+    key.len() == 40 && key.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+struct Unhashed {
+    id: i64,
+    fact: String,
+}
+
+impl FromRow for Unhashed {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            id: store::integer(row, 0)?,
+            fact: store::text(row, 1)?,
+        })
+    }
+}
+
+/// Gives a fact id to up to `limit` facts that don't have one yet, such as
+/// facts created before fact ids existed. Returns how many it updated.
+pub async fn backfill_batch(db: &dyn Store, limit: u32) -> Result<usize, anyhow::Error> {
+    let res = db
+        .execute(Statement::with_args(
+            "SELECT id, fact FROM catfacts WHERE fact_id IS NULL ORDER BY id LIMIT ?",
+            &[limit],
+        ))
+        .await
+        .map_err(|e| anyhow!("couldn't find facts without fact ids: {e}"))?;
+    let facts = store::rows::<Unhashed>(&res)?;
+
+    if !facts.is_empty() {
+        let updates: Vec<Statement> = facts
+            .iter()
+            .map(|fact| {
+                Statement::with_args(
+                    "UPDATE catfacts SET fact_id = ? WHERE id = ?",
+                    &[Value::from(fact_id(&fact.fact)), Value::from(fact.id)],
+                )
+            })
+            .collect();
+        db.batch(updates)
+            .await
+            .map_err(|e| anyhow!("couldn't backfill fact ids: {e}"))?;
+    }
+
+    Ok(facts.len())
+}
@@ -0,0 +1,372 @@
+//! Feeds of the newest facts, as RSS at `GET /feed.xml` (or `GET /feed.rss`),
+//! Atom at `GET /feed.atom` and JSON Feed at `GET /feed.json`, so feed
+//! readers can follow new facts without subscribing by email. Only facts in
+//! circulation are included, and each entry's id is the fact's permanent link
+//! by numeric id, which stays the same if the fact is corrected.
+//!
+//! `?tag=kittens` narrows a feed to facts with that tag. Each tag's feed is
+//! its own document at its own URL, with its own title and self link, so
+//! caches keep them apart.
+//!
+//! Feed readers poll often and mostly find nothing new, so rendered feeds
+//! (and the sitemap) are kept in a `FeedCache` until the content version in
+//! the database moves on, and are sent with an `ETag` so a poll that has the
+//! latest copy gets a bodiless `304 Not Modified`.
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use utoipa::IntoParams;
+
+use crate::html::escape;
+use crate::store::{self, Store};
+use crate::{error::ApiError, tags, templates, AppState, CatFactRecord};
+
+/// How many facts a feed lists.
+const FEED_LENGTH: u32 = 50;
+
+/// The most rendered documents kept, so requests for made-up tags can't fill
+/// memory. The cache starts over once it's full.
+const MAX_CACHED: usize = 500;
+
+const TITLE: &str = "Cat Facts";
+const DESCRIPTION: &str = "The newest facts about cats.";
+
+const RSS: &str = "application/rss+xml; charset=utf-8";
+const ATOM: &str = "application/atom+xml; charset=utf-8";
+const JSON_FEED: &str = "application/feed+json; charset=utf-8";
+
+/// A counter the database bumps whenever facts, their tags or the facts of
+/// the day change (see the `content_version` migration).
+pub async fn content_version(db: &dyn Store) -> Result<i64, anyhow::Error> {
+    let res = db.execute("SELECT version FROM content_version").await?;
+
+    Ok(store::first::<i64>(&res)?.unwrap_or(0))
+}
+
+struct Document {
+    /// What the document was rendered from, which is also its `ETag`.
+    edition: String,
+    body: Arc<String>,
+}
+
+/// Rendered feeds and sitemaps, by what was asked for, e.g. `rss kittens`.
+#[derive(Default)]
+pub struct FeedCache(Mutex<HashMap<String, Document>>);
+
+impl FeedCache {
+    fn get(&self, key: &str, edition: &str) -> Option<Arc<String>> {
+        let documents = self.0.lock().unwrap_or_else(|e| e.into_inner());
+
+        documents
+            .get(key)
+            .filter(|document| document.edition == edition)
+            .map(|document| document.body.clone())
+    }
+
+    fn put(&self, key: String, edition: String, body: Arc<String>) {
+        let mut documents = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if documents.len() >= MAX_CACHED && !documents.contains_key(&key) {
+            documents.clear();
+        }
+        documents.insert(key, Document { edition, body });
+    }
+}
+
+/// Whether the `If-None-Match` in `headers` already has `etag`.
+fn not_modified(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == etag || tag == "*")
+}
+
+/// Answers with the document for `key` as of `edition`, rendering it with
+/// `render` only if the cache doesn't have that edition yet.
+pub async fn cached<F, Fut>(
+    cache: &FeedCache,
+    headers: &HeaderMap,
+    key: String,
+    edition: String,
+    content_type: &'static str,
+    render: F,
+) -> Result<Response, ApiError>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<String, ApiError>>,
+{
+    let etag = format!("\"{edition}\"");
+    let etag_header = HeaderValue::from_str(&etag).map_err(ApiError::internal)?;
+    if not_modified(headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag_header)]).into_response());
+    }
+
+    let body = match cache.get(&key, &edition) {
+        Some(body) => body,
+        None => {
+            let body = Arc::new(render().await?);
+            cache.put(key, edition, body.clone());
+            body
+        }
+    };
+
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static(content_type)),
+            (header::ETAG, etag_header),
+        ],
+        body.to_string(),
+    )
+        .into_response())
+}
+
+/// When `fact` was added, from its SQLite timestamp, which is in UTC.
+fn added_at(fact: &CatFactRecord) -> DateTime<Utc> {
+    NaiveDateTime::parse_from_str(&fact.created_at, "%Y-%m-%d %H:%M:%S")
+        .map(|at| at.and_utc())
+        .unwrap_or_default()
+}
+
+pub fn fact_url(base: &str, fact_id: i64) -> String {
+    format!("{base}/catfact/{fact_id}")
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FeedQuery {
+    /// Only include facts with this tag.
+    tag: Option<String>,
+}
+
+impl FeedQuery {
+    fn tag(&self) -> Result<Option<String>, ApiError> {
+        self.tag.as_deref().map(tags::normalize_one).transpose()
+    }
+}
+
+/// A feed's facts, title, description and the query string of its own URL.
+struct Feed {
+    facts: Vec<CatFactRecord>,
+    title: String,
+    description: String,
+    query: String,
+}
+
+async fn load(state: &AppState, tag: Option<&str>) -> Result<Feed, ApiError> {
+    let facts = state.db.recent_facts(tag, FEED_LENGTH).await?;
+
+    Ok(match tag {
+        Some(tag) => Feed {
+            facts,
+            title: format!("{TITLE}: {tag}"),
+            description: format!("The newest facts about cats tagged {tag}."),
+            query: format!("?tag={tag}"),
+        },
+        None => Feed {
+            facts,
+            title: TITLE.to_string(),
+            description: DESCRIPTION.to_string(),
+            query: String::new(),
+        },
+    })
+}
+
+/// Serves the feed `kind` for `query`'s tag from the cache, rendering it with
+/// `render` when it's out of date.
+async fn serve_feed(
+    state: &AppState,
+    headers: &HeaderMap,
+    query: FeedQuery,
+    kind: &str,
+    content_type: &'static str,
+    render: fn(&str, &Feed) -> String,
+) -> Result<Response, ApiError> {
+    let tag = query.tag()?;
+    let version = content_version(&*state.db).await?;
+    let key = format!("{kind} {}", tag.as_deref().unwrap_or_default());
+
+    cached(
+        &state.feeds,
+        headers,
+        key,
+        version.to_string(),
+        content_type,
+        || async {
+            let feed = load(state, tag.as_deref()).await?;
+            Ok(render(state.public_url.trim_end_matches('/'), &feed))
+        },
+    )
+    .await
+}
+
+/// `GET /feed.xml?tag=kittens` - the newest facts as RSS 2.0, also at
+/// `GET /feed.rss`.
+#[utoipa::path(
+    get,
+    path = "/feed.xml",
+    tag = "facts",
+    params(FeedQuery),
+    responses(
+        (status = 200, description = "An RSS feed of the newest facts", body = String, content_type = "application/rss+xml"),
+        (status = 304, description = "The feed hasn't changed since the `If-None-Match` copy"),
+        (status = 422, description = "The tag isn't a valid tag name"),
+    )
+)]
+pub async fn rss(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<FeedQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    serve_feed(&state, &headers, query, "rss", RSS, render_rss).await
+}
+
+fn render_rss(base: &str, feed: &Feed) -> String {
+    let mut items = String::new();
+    for fact in &feed.facts {
+        let url = escape(&fact_url(base, fact.id));
+        items.push_str(&format!(
+            "<item><title>{}</title><link>{url}</link><description>{}</description><guid isPermaLink=\"true\">{url}</guid><pubDate>{}</pubDate></item>\n",
+            escape(&templates::teaser(&fact.fact)),
+            escape(&fact.fact),
+            added_at(fact).to_rfc2822(),
+        ));
+    }
+    let built = feed
+        .facts
+        .first()
+        .map_or_else(Utc::now, added_at)
+        .to_rfc2822();
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom">
+<channel>
+<title>{title}</title>
+<link>{base}/</link>
+<description>{description}</description>
+<atom:link href="{base}/feed.xml{query}" rel="self" type="application/rss+xml"/>
+<lastBuildDate>{built}</lastBuildDate>
+{items}</channel>
+</rss>
+"#,
+        base = escape(base),
+        title = escape(&feed.title),
+        description = escape(&feed.description),
+        query = escape(&feed.query),
+    )
+}
+
+/// `GET /feed.atom?tag=kittens` - the newest facts as Atom.
+#[utoipa::path(
+    get,
+    path = "/feed.atom",
+    tag = "facts",
+    params(FeedQuery),
+    responses(
+        (status = 200, description = "An Atom feed of the newest facts", body = String, content_type = "application/atom+xml"),
+        (status = 304, description = "The feed hasn't changed since the `If-None-Match` copy"),
+        (status = 422, description = "The tag isn't a valid tag name"),
+    )
+)]
+pub async fn atom(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<FeedQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    serve_feed(&state, &headers, query, "atom", ATOM, render_atom).await
+}
+
+fn render_atom(base: &str, feed: &Feed) -> String {
+    let mut entries = String::new();
+    for fact in &feed.facts {
+        let url = escape(&fact_url(base, fact.id));
+        entries.push_str(&format!(
+            "<entry><id>{url}</id><title>{}</title><link href=\"{url}\"/><updated>{}</updated><content type=\"text\">{}</content><rights>{}</rights></entry>\n",
+            escape(&templates::teaser(&fact.fact)),
+            added_at(fact).to_rfc3339(),
+            escape(&fact.fact),
+            fact.license.name(),
+        ));
+    }
+    let updated = feed
+        .facts
+        .first()
+        .map_or_else(Utc::now, added_at)
+        .to_rfc3339();
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+<id>{base}/feed.atom{query}</id>
+<title>{title}</title>
+<subtitle>{description}</subtitle>
+<link href="{base}/"/>
+<link href="{base}/feed.atom{query}" rel="self" type="application/atom+xml"/>
+<author><name>{TITLE}</name></author>
+<updated>{updated}</updated>
+{entries}</feed>
+"#,
+        base = escape(base),
+        title = escape(&feed.title),
+        description = escape(&feed.description),
+        query = escape(&feed.query),
+    )
+}
+
+/// `GET /feed.json?tag=kittens` - the newest facts as JSON Feed 1.1.
+#[utoipa::path(
+    get,
+    path = "/feed.json",
+    tag = "facts",
+    params(FeedQuery),
+    responses(
+        (status = 200, description = "A JSON feed of the newest facts", body = String, content_type = "application/feed+json"),
+        (status = 304, description = "The feed hasn't changed since the `If-None-Match` copy"),
+        (status = 422, description = "The tag isn't a valid tag name"),
+    )
+)]
+pub async fn json_feed(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<FeedQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    serve_feed(&state, &headers, query, "json", JSON_FEED, render_json).await
+}
+
+fn render_json(base: &str, feed: &Feed) -> String {
+    let items: Vec<_> = feed
+        .facts
+        .iter()
+        .map(|fact| {
+            let url = fact_url(base, fact.id);
+            json!({
+                "id": url,
+                "url": url,
+                "title": templates::teaser(&fact.fact),
+                "content_text": fact.fact,
+                "date_published": added_at(fact).to_rfc3339(),
+            })
+        })
+        .collect();
+
+    json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": feed.title,
+        "description": feed.description,
+        "home_page_url": format!("{base}/"),
+        "feed_url": format!("{base}/feed.json{}", feed.query),
+        "items": items,
+    })
+    .to_string()
+}
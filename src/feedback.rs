@@ -0,0 +1,251 @@
+//! One-click feedback on the daily fact. Each email asks "Was this fact
+//! interesting?" with a 😿 and a 😺 link to `GET /feedback/:token/:score`,
+//! where the token names the send (the day and the subscriber) and the score
+//! is 0 to 10, so a custom template can offer the whole NPS scale; the
+//! built-in ones link 0 and 10. Each send keeps one score, and following
+//! another of its links changes it.
+//!
+//! Scores are summed up per fact by `GET /admin/feedback`, and offered to
+//! the ranker with each candidate (see `ranking`).
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Html,
+    Json,
+};
+use chrono::{Duration, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::store::{self, FromRow, Row, Statement, Value};
+use crate::{error::ApiError, html, AppState};
+
+const TITLE: &str = "Cat Facts - Feedback";
+
+/// The highest score; 9 and up is a promoter and 6 and below a detractor,
+/// as in NPS.
+pub const MAX_SCORE: u8 = 10;
+
+/// How long after a send its links still record a score.
+const FEEDBACK_DAYS: i64 = 30;
+
+const DEFAULT_REPORT_DAYS: u32 = 30;
+const DEFAULT_REPORT_LIMIT: u32 = 50;
+const MAX_REPORT_LIMIT: u32 = 500;
+
+/// How many scores a fact has had, as a correlated subquery on `catfacts`.
+pub const RESPONSES: &str =
+    "(SELECT count(*) FROM fact_feedback WHERE fact_feedback.catfact_id = catfacts.id)";
+
+/// A fact's NPS from -100 to 100, or null if it hasn't been scored, as a
+/// correlated subquery on `catfacts`.
+pub const NPS: &str =
+    "(SELECT CAST(round(100.0 * (sum(score >= 9) - sum(score <= 6)) / count(*)) AS integer)
+    FROM fact_feedback WHERE fact_feedback.catfact_id = catfacts.id)";
+
+/// The link for scoring the fact sent on `date` to the subscriber with
+/// `token`, without the score on the end.
+pub fn feedback_url(public_url: &str, date: NaiveDate, token: &str) -> String {
+    format!(
+        "{}/feedback/{date}.{token}",
+        public_url.trim_end_matches('/')
+    )
+}
+
+/// `<date>.<subscriber token>`, as `feedback_url` writes it.
+pub fn parse_token(token: &str) -> Option<(NaiveDate, &str)> {
+    let (date, token) = token.split_once('.')?;
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+
+    (!token.is_empty()).then_some((date, token))
+}
+
+/// `GET /feedback/:token/:score` - the link in an email, recording how
+/// interesting its fact was.
+#[utoipa::path(
+    get,
+    path = "/feedback/{token}/{score}",
+    tag = "subscriptions",
+    params(
+        ("token" = String, Path, description = "The send, from the link in an email"),
+        ("score" = u8, Path, description = "From 0 (😿) to 10 (😺)"),
+    ),
+    responses(
+        (status = 200, description = "The score was recorded", body = String, content_type = "text/html"),
+        (status = 404, description = "The link isn't valid or has expired", body = String, content_type = "text/html"),
+    )
+)]
+pub async fn record_feedback(
+    State(state): State<Arc<AppState>>,
+    Path((token, score)): Path<(String, String)>,
+) -> Result<Html<String>, (StatusCode, Html<String>)> {
+    let invalid = || (StatusCode::NOT_FOUND, Html(invalid_link_page()));
+
+    let score = score
+        .parse::<u8>()
+        .ok()
+        .filter(|score| *score <= MAX_SCORE)
+        .ok_or_else(invalid)?;
+    let (date, token) = parse_token(&token).ok_or_else(invalid)?;
+    let today = state.zone.today();
+    if date > today || date <= today - Duration::days(FEEDBACK_DAYS) {
+        return Err(invalid());
+    }
+
+    let email = match state.db.preferences(token).await {
+        Ok(Some(preferences)) => preferences.email,
+        Ok(None) => return Err(invalid()),
+        Err(e) => return Err(html::server_error(TITLE, e)),
+    };
+
+    let recorded = state
+        .db
+        .execute(Statement::with_args(
+            "INSERT INTO fact_feedback (date, subscriber, catfact_id, score)
+            SELECT date, ?2, catfact_id, ?3 FROM daily_facts WHERE date = ?1
+            ON CONFLICT (date, subscriber) DO UPDATE SET
+            score = excluded.score,
+            updated_at = current_timestamp",
+            &[
+                Value::from(date.to_string()),
+                Value::from(state.privacy.subscriber_id(&email)),
+                Value::from(i64::from(score)),
+            ],
+        ))
+        .await
+        .map_err(|e| html::server_error(TITLE, e))?;
+    if recorded.rows_affected == 0 {
+        return Err(invalid());
+    }
+
+    Ok(Html(html::page(
+        TITLE,
+        "<p>Thanks for letting us know! It helps pick the facts everyone gets.</p><p>Changed your mind? The other link in the email changes your answer.</p>",
+    )))
+}
+
+fn invalid_link_page() -> String {
+    html::page(
+        TITLE,
+        "<p>This link isn't valid anymore. Feedback links work for a month after the email is sent.</p>",
+    )
+}
+
+/// The NPS of `responses` scores with this many promoters and detractors.
+fn nps(responses: i64, promoters: i64, detractors: i64) -> Option<i64> {
+    (responses > 0)
+        .then(|| ((promoters - detractors) as f64 * 100.0 / responses as f64).round() as i64)
+}
+
+#[derive(Serialize)]
+pub struct FactFeedback {
+    id: i64,
+    fact: String,
+    responses: i64,
+    /// Scores of 9 or 10.
+    promoters: i64,
+    /// Scores of 7 or 8.
+    passives: i64,
+    /// Scores of 6 or below.
+    detractors: i64,
+    nps: Option<i64>,
+    /// The mean score, out of 10.
+    average: f64,
+}
+
+impl FromRow for FactFeedback {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        let responses = store::integer(row, 2)?;
+        let promoters = store::integer(row, 3)?;
+        let detractors = store::integer(row, 5)?;
+
+        Ok(Self {
+            id: store::integer(row, 0)?,
+            fact: store::text(row, 1)?,
+            responses,
+            promoters,
+            passives: store::integer(row, 4)?,
+            detractors,
+            nps: nps(responses, promoters, detractors),
+            average: store::integer(row, 6)? as f64 / responses.max(1) as f64,
+        })
+    }
+}
+
+struct Totals {
+    responses: i64,
+    promoters: i64,
+    detractors: i64,
+}
+
+impl FromRow for Totals {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            responses: store::integer(row, 0)?,
+            promoters: store::integer(row, 1)?,
+            detractors: store::integer(row, 2)?,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct FeedbackReport {
+    days: u32,
+    responses: i64,
+    /// Across every send in the period.
+    nps: Option<i64>,
+    /// The facts scored most often first.
+    facts: Vec<FactFeedback>,
+}
+
+#[derive(Deserialize)]
+pub struct FeedbackQuery {
+    /// How many days of sends are counted. Defaults to 30.
+    days: Option<u32>,
+    limit: Option<u32>,
+}
+
+/// `GET /admin/feedback?days=30&limit=50` - how subscribers scored the facts
+/// sent in the last `days` days, overall and per fact.
+pub async fn feedback_report(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<FeedbackQuery>,
+) -> Result<Json<FeedbackReport>, ApiError> {
+    let days = query.days.unwrap_or(DEFAULT_REPORT_DAYS).clamp(1, 365);
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_REPORT_LIMIT)
+        .clamp(1, MAX_REPORT_LIMIT);
+    let since = (state.zone.today() - Duration::days(i64::from(days) - 1)).to_string();
+
+    let results = state
+        .db
+        .batch([
+            Statement::with_args(
+                "SELECT count(*), coalesce(sum(score >= 9), 0), coalesce(sum(score <= 6), 0)
+                FROM fact_feedback WHERE date >= ?",
+                &[&since],
+            ),
+            Statement::with_args(
+                "SELECT catfacts.id, catfacts.fact, count(*), sum(score >= 9),
+                sum(score BETWEEN 7 AND 8), sum(score <= 6), sum(score)
+                FROM fact_feedback JOIN catfacts ON catfacts.id = fact_feedback.catfact_id
+                WHERE fact_feedback.date >= ?
+                GROUP BY catfacts.id ORDER BY count(*) DESC, catfacts.id LIMIT ?",
+                &[Value::from(since), Value::from(limit)],
+            ),
+        ])
+        .await?;
+    let (Some(totals), Some(facts)) = (results.first(), results.get(1)) else {
+        return Err(ApiError::internal("Missing feedback results"));
+    };
+    let totals = store::first::<Totals>(totals)?
+        .ok_or_else(|| ApiError::internal("Counting feedback returned nothing"))?;
+
+    Ok(Json(FeedbackReport {
+        days,
+        responses: totals.responses,
+        nps: nps(totals.responses, totals.promoters, totals.detractors),
+        facts: store::rows(facts)?,
+    }))
+}
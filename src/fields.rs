@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::IntoParams;
+
+/// Query parameters for trimming a response down to a subset of its fields,
+/// e.g. `?fields=fact,created_at`.
+#[derive(Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FieldsQuery {
+    /// Only these top-level fields, comma-separated, e.g. `id,fact`.
+    fields: Option<String>,
+}
+
+impl FieldsQuery {
+    fn selected(&self) -> Option<Vec<&str>> {
+        let fields = self.fields.as_deref()?;
+
+        let selected: Vec<&str> = fields
+            .split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .collect();
+
+        if selected.is_empty() {
+            None
+        } else {
+            Some(selected)
+        }
+    }
+}
+
+/// Serializes `value` and drops any top-level fields that weren't asked for.
+/// Arrays are shaped element by element, so list endpoints can use this too.
+pub fn shape<T: Serialize>(value: &T, query: &FieldsQuery) -> Result<Value, serde_json::Error> {
+    let value = serde_json::to_value(value)?;
+
+    match query.selected() {
+        Some(selected) => Ok(retain_fields(value, &selected)),
+        None => Ok(value),
+    }
+}
+
+fn retain_fields(value: Value, selected: &[&str]) -> Value {
+    match value {
+        Value::Object(mut map) => {
+            map.retain(|key, _| selected.contains(&key.as_str()));
+            Value::Object(map)
+        }
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(|item| retain_fields(item, selected))
+                .collect(),
+        ),
+        other => other,
+    }
+}
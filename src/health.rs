@@ -0,0 +1,144 @@
+//! Health checks for the platform. Liveness only says the process is up;
+//! readiness checks each dependency and answers 503 when any of them is
+//! down, with the status of each so it's clear which one.
+//!
+//! The SMTP check opens a connection to the relay, so it's off unless
+//! `HEALTH_CHECK_SMTP` is `true`.
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+use crate::{schema, AppState};
+
+/// How long a dependency gets to answer before it counts as down.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Serialize, ToSchema)]
+pub struct DependencyStatus {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+impl DependencyStatus {
+    fn ok() -> Self {
+        Self {
+            status: "ok",
+            detail: None,
+        }
+    }
+
+    fn down(status: &'static str, detail: impl std::fmt::Display) -> Self {
+        Self {
+            status,
+            detail: Some(detail.to_string()),
+        }
+    }
+
+    fn is_ok(&self) -> bool {
+        self.status == "ok"
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Readiness {
+    /// `ready` or `unavailable`.
+    status: &'static str,
+    /// Keyed on the dependency, e.g. `database`.
+    checks: BTreeMap<&'static str, DependencyStatus>,
+    /// Post-deploy migrations waiting for `POST /admin/migrations/post-deploy`.
+    /// The previous version's schema still works with this code, so they
+    /// don't hold up readiness.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    post_deploy_pending: Vec<&'static str>,
+}
+
+/// `GET /health/live` - the process is up and serving requests.
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "health",
+    responses((status = 200, description = "The service is up", body = String))
+)]
+pub async fn liveness_check() -> impl IntoResponse {
+    (StatusCode::OK, "It works!".to_string())
+}
+
+/// `GET /health/ready` - whether this instance should get traffic: the
+/// database answers and has every pre-deploy migration this code needs, and
+/// if `HEALTH_CHECK_SMTP` is on, the SMTP relay accepts a connection. Any
+/// post-deploy migrations still to run are listed too.
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "health",
+    responses(
+        (status = 200, description = "Ready for traffic", body = Readiness),
+        (status = 503, description = "Not ready, with the status of each check", body = Readiness),
+    )
+)]
+pub async fn readiness_check(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let mut checks = BTreeMap::new();
+    checks.insert("database", database(&state).await);
+    if state.health_check_smtp {
+        checks.insert("smtp", smtp(&state).await);
+    }
+
+    let ready = checks.values().all(DependencyStatus::is_ok);
+    let post_deploy_pending = if ready {
+        schema::pending(&*state.db, schema::Phase::PostDeploy)
+            .await
+            .unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    for (name, check) in checks.iter().filter(|(_, check)| !check.is_ok()) {
+        tracing::warn!(
+            "Readiness check failed for {name}: {}",
+            check.detail.as_deref().unwrap_or(check.status)
+        );
+    }
+
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (
+        status,
+        Json(Readiness {
+            status: if ready { "ready" } else { "unavailable" },
+            checks,
+            post_deploy_pending,
+        }),
+    )
+}
+
+async fn database(state: &AppState) -> DependencyStatus {
+    let check = async {
+        state.db.execute("SELECT 1").await?;
+        schema::pending(&*state.db, schema::Phase::PreDeploy).await
+    };
+
+    match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(Ok(pending)) if pending.is_empty() => DependencyStatus::ok(),
+        Ok(Ok(pending)) => DependencyStatus::down(
+            "migrating",
+            format!("pending migrations: {}", pending.join(", ")),
+        ),
+        Ok(Err(e)) => DependencyStatus::down("down", e),
+        Err(_) => DependencyStatus::down("down", "timed out"),
+    }
+}
+
+async fn smtp(state: &AppState) -> DependencyStatus {
+    match tokio::time::timeout(CHECK_TIMEOUT, state.mailer.check()).await {
+        Ok(Ok(())) => DependencyStatus::ok(),
+        Ok(Err(e)) => DependencyStatus::down("down", e),
+        Err(_) => DependencyStatus::down("down", "timed out"),
+    }
+}
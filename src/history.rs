@@ -0,0 +1,165 @@
+//! Every version of every fact, kept in `catfact_revisions` by triggers on
+//! `catfacts`, so the facts in circulation at any past moment can be worked
+//! out again - e.g. to answer what the newsletter could have picked on a
+//! given day.
+//!
+//! History starts when the table was added: facts that were there then are
+//! taken to have been as they were since they were created, and facts deleted
+//! before then aren't known about at all.
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::auth::Admin;
+use crate::license::License;
+use crate::store::{self, FromRow, Row, Statement, Value};
+use crate::{error::ApiError, AppState};
+
+const DEFAULT_PER_PAGE: u32 = 100;
+const MAX_PER_PAGE: u32 = 1000;
+
+/// The latest revision of each fact at `?1`, as `latest`.
+const LATEST_REVISIONS: &str = "SELECT catfact_id, max(id) AS id FROM catfact_revisions
+    WHERE changed_at <= ?1 GROUP BY catfact_id";
+
+/// One fact as it was at the requested moment.
+#[derive(Serialize, ToSchema)]
+pub struct PastFact {
+    id: i64,
+    fact: String,
+    fact_id: Option<String>,
+    license: License,
+    /// When the fact was added, as far as its revisions go back.
+    created_at: String,
+    /// When it last changed before the requested moment.
+    revised_at: String,
+}
+
+impl FromRow for PastFact {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            id: store::integer(row, 0)?,
+            fact: store::text(row, 1)?,
+            fact_id: store::optional_text(row, 2)?,
+            license: License::from_name(&store::text(row, 3)?).unwrap_or_default(),
+            created_at: store::text(row, 4)?,
+            revised_at: store::text(row, 5)?,
+        })
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PastFacts {
+    as_of: String,
+    data: Vec<PastFact>,
+    page: u32,
+    per_page: u32,
+    total: i64,
+    total_pages: i64,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AsOfQuery {
+    timestamp: String,
+    page: Option<u32>,
+    per_page: Option<u32>,
+}
+
+/// Parses `2024-03-03T09:30:00Z` (or with an offset), `2024-03-03 09:30:00`
+/// in UTC, or `2024-03-03` for the start of that day in UTC, into the format
+/// SQLite timestamps are stored in.
+fn parse_timestamp(timestamp: &str) -> Result<String, ApiError> {
+    let timestamp = timestamp.trim();
+    let parsed = DateTime::parse_from_rfc3339(timestamp)
+        .map(|at| at.naive_utc())
+        .or_else(|_| NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S"))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(timestamp, "%Y-%m-%d")
+                .map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default())
+        })
+        .map_err(|_| {
+            ApiError::BadRequest(format!(
+                "timestamp {timestamp:?} should look like 2024-03-03T09:30:00Z or 2024-03-03"
+            ))
+        })?;
+
+    Ok(parsed.format("%Y-%m-%d %H:%M:%S").to_string())
+}
+
+/// `GET /catfacts/as-of?timestamp=2024-03-03` (admin only) - the facts that
+/// were in circulation at `timestamp`, with the text and license they had
+/// then, by id, a page at a time.
+#[utoipa::path(
+    get,
+    path = "/catfacts/as-of",
+    tag = "facts",
+    params(AsOfQuery),
+    responses(
+        (status = 200, description = "The facts in circulation at `timestamp`", body = PastFacts),
+        (status = 400, description = "`timestamp` isn't a date or time", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+pub async fn facts_as_of(
+    State(state): State<Arc<AppState>>,
+    _: Admin,
+    Query(query): Query<AsOfQuery>,
+) -> Result<Json<PastFacts>, ApiError> {
+    let as_of = parse_timestamp(&query.timestamp)?;
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query
+        .per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+    let offset = i64::from(page - 1) * i64::from(per_page);
+
+    let results = state
+        .db
+        .batch([
+            Statement::with_args(
+                format!(
+                    "SELECT count(*) FROM catfact_revisions revision
+                    JOIN ({LATEST_REVISIONS}) latest ON latest.id = revision.id
+                    WHERE revision.deleted = 0 AND revision.needs_review = 0"
+                ),
+                &[&as_of],
+            ),
+            Statement::with_args(
+                format!(
+                    "SELECT revision.catfact_id, revision.fact, revision.fact_id, revision.license,
+                    (SELECT min(changed_at) FROM catfact_revisions WHERE catfact_id = revision.catfact_id),
+                    revision.changed_at
+                    FROM catfact_revisions revision
+                    JOIN ({LATEST_REVISIONS}) latest ON latest.id = revision.id
+                    WHERE revision.deleted = 0 AND revision.needs_review = 0
+                    ORDER BY revision.catfact_id LIMIT ?2 OFFSET ?3"
+                ),
+                &[
+                    Value::from(&as_of),
+                    Value::from(per_page),
+                    Value::from(offset),
+                ],
+            ),
+        ])
+        .await?;
+
+    let (Some(count), Some(rows)) = (results.first(), results.get(1)) else {
+        return Err(ApiError::internal("Missing history results"));
+    };
+    let total: i64 = store::first(count)?.unwrap_or(0);
+
+    Ok(Json(PastFacts {
+        as_of,
+        data: store::rows(rows)?,
+        page,
+        per_page,
+        total,
+        total_pages: (total + i64::from(per_page) - 1) / i64::from(per_page),
+    }))
+}
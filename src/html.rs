@@ -0,0 +1,44 @@
+use axum::{http::StatusCode, response::Html};
+
+/// Escapes text for safe interpolation into HTML/SVG markup and attribute values.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Wraps `body` in the minimal page layout shared by the hosted HTML pages.
+pub fn page(title: &str, body: &str) -> String {
+    let title = escape(title);
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <meta name="viewport" content="width=device-width, initial-scale=1">
+  <title>{title}</title>
+</head>
+<body>
+<h1>Cat Facts</h1>
+{body}
+</body>
+</html>"#
+    )
+}
+
+/// The 500 response for the hosted HTML pages. The error itself is logged
+/// rather than shown to the visitor.
+pub fn server_error(title: &str, e: impl std::fmt::Display) -> (StatusCode, Html<String>) {
+    tracing::error!("Error serving {title:?}: {e}");
+
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Html(page(
+            title,
+            "<p>Something went wrong on our end, please try again later.</p>",
+        )),
+    )
+}
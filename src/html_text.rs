@@ -0,0 +1,341 @@
+//! Turns an HTML email body into a readable plain-text one, for the text part
+//! of emails whose HTML template was customised on its own. Paragraphs and
+//! headings become blank-line separated blocks, list items get a `- `, and
+//! links keep their address in brackets after the link text. `{{placeholder}}`s
+//! pass through untouched, so this runs on the template rather than on each
+//! rendered email.
+//!
+//! `article_text` does the same for a web page, keeping just its main content.
+
+/// Elements that start and end a block of text.
+const BLOCKS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "div",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "section",
+    "table",
+    "tr",
+    "ul",
+];
+
+/// Elements whose contents aren't part of the message.
+const HIDDEN: &[&str] = &["head", "script", "style", "title"];
+
+/// Elements whose contents aren't part of a page's article, on top of
+/// `HIDDEN`.
+const PAGE_CHROME: &[&str] = &[
+    "aside",
+    "button",
+    "figcaption",
+    "footer",
+    "form",
+    "header",
+    "iframe",
+    "nav",
+    "noscript",
+    "select",
+    "svg",
+    "template",
+];
+
+/// The elements a page's main content is looked for in, best first.
+const CONTENT: &[&str] = &["article", "main", "body"];
+
+struct Tag<'a> {
+    name: String,
+    closing: bool,
+    attributes: &'a str,
+}
+
+impl<'a> Tag<'a> {
+    /// Parses what's between `<` and `>`, or `None` for comments, doctypes and
+    /// the like.
+    fn parse(source: &'a str) -> Option<Self> {
+        let source = source.trim();
+        let (closing, source) = match source.strip_prefix('/') {
+            Some(rest) => (true, rest),
+            None => (false, source),
+        };
+
+        let end = source
+            .find(|c: char| c.is_whitespace() || c == '/')
+            .unwrap_or(source.len());
+        let name = source[..end].to_ascii_lowercase();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+
+        Some(Self {
+            name,
+            closing,
+            attributes: &source[end..],
+        })
+    }
+
+    fn attribute(&self, name: &str) -> Option<String> {
+        let lower = self.attributes.to_ascii_lowercase();
+        let mut from = 0;
+
+        while let Some(found) = lower[from..].find(name) {
+            let start = from + found;
+            from = start + name.len();
+
+            let preceded_by_space = lower[..start]
+                .chars()
+                .next_back()
+                .is_none_or(char::is_whitespace);
+            let rest = lower[from..].trim_start();
+            if !preceded_by_space || !rest.starts_with('=') {
+                continue;
+            }
+
+            let value_start = self.attributes.len() - rest.len() + 1;
+            let value = self.attributes[value_start..].trim_start();
+            let value = match value.chars().next() {
+                Some(quote @ ('"' | '\'')) => value[1..].split(quote).next().unwrap_or(""),
+                _ => value.split(char::is_whitespace).next().unwrap_or(""),
+            };
+
+            return Some(decode_entities(value));
+        }
+
+        None
+    }
+}
+
+/// Accumulates the output, collapsing whitespace the way a browser would.
+#[derive(Default)]
+struct Text {
+    out: String,
+    /// Line breaks owed before the next word.
+    breaks: usize,
+    /// Whether a space is owed before the next word.
+    space: bool,
+}
+
+impl Text {
+    fn push_words(&mut self, text: &str) {
+        if text.starts_with(char::is_whitespace) {
+            self.space = true;
+        }
+
+        for word in text.split_whitespace() {
+            self.push_word(word);
+            self.space = true;
+        }
+
+        if !text.ends_with(char::is_whitespace) {
+            self.space = false;
+        }
+    }
+
+    fn push_word(&mut self, word: &str) {
+        if self.out.is_empty() {
+            self.breaks = 0;
+        } else if self.breaks > 0 {
+            self.out.push_str(&"\n".repeat(self.breaks));
+            self.breaks = 0;
+        } else if self.space {
+            self.out.push(' ');
+        }
+        self.space = false;
+        self.out.push_str(word);
+    }
+
+    /// Ends the current line, keeping at least `count` line breaks before
+    /// whatever comes next.
+    fn line_break(&mut self, count: usize) {
+        self.breaks = self.breaks.max(count);
+        self.space = false;
+    }
+}
+
+/// The plain-text version of `html`.
+pub fn to_text(html: &str) -> String {
+    convert(html, HIDDEN, true)
+}
+
+/// The readable text of a web page: what's in its `<article>`, or failing
+/// that its `<main>` or `<body>`, without navigation, headers, footers and
+/// forms, and without link addresses.
+pub fn article_text(html: &str) -> String {
+    let hidden: Vec<&str> = HIDDEN.iter().chain(PAGE_CHROME).copied().collect();
+    convert(content(html), &hidden, false)
+}
+
+/// The text of a page's `<title>`, if it has one.
+pub fn title(html: &str) -> Option<String> {
+    let (start, end) = element(html, "title")?;
+    let title = convert(&html[start..end], &[], false);
+    (!title.is_empty()).then_some(title)
+}
+
+/// The part of `html` inside the first of `CONTENT` it has.
+fn content(html: &str) -> &str {
+    CONTENT
+        .iter()
+        .find_map(|name| element(html, name))
+        .map_or(html, |(start, end)| &html[start..end])
+}
+
+/// Where the inside of the first `<name>` element starts, and where the last
+/// `</name>` ends it, so nested ones are included.
+fn element(html: &str, name: &str) -> Option<(usize, usize)> {
+    let lower = html.to_ascii_lowercase();
+    let open = format!("<{name}");
+    let start = lower.match_indices(&open).find_map(|(at, _)| {
+        let after = lower[at + open.len()..].chars().next()?;
+        (after == '>' || after.is_whitespace()).then_some(at)
+    })?;
+    let start = start + lower[start..].find('>')? + 1;
+    let end = lower[start..]
+        .rfind(&format!("</{name}"))
+        .map_or(html.len(), |end| start + end);
+
+    Some((start, end))
+}
+
+/// Converts `html` to text, skipping the contents of `hidden` elements, and
+/// with `links_shown` adding link addresses after their text.
+fn convert(html: &str, hidden_elements: &[&str], links_shown: bool) -> String {
+    let mut text = Text::default();
+    let mut hidden: Option<String> = None;
+    let mut links: Vec<(Option<String>, usize)> = Vec::new();
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+
+        let Some(open) = rest.find('<') else {
+            if hidden.is_none() {
+                text.push_words(&decode_entities(rest));
+            }
+            break;
+        };
+
+        if open > 0 && hidden.is_none() {
+            text.push_words(&decode_entities(&rest[..open]));
+        }
+
+        let Some(close) = rest[open..].find('>') else {
+            if hidden.is_none() {
+                text.push_words(&decode_entities(&rest[open..]));
+            }
+            break;
+        };
+        let tag = Tag::parse(&rest[open + 1..open + close]);
+        rest = &rest[open + close + 1..];
+
+        let Some(tag) = tag else {
+            continue;
+        };
+
+        if let Some(name) = &hidden {
+            if tag.closing && &tag.name == name {
+                hidden = None;
+            }
+            continue;
+        }
+
+        match (tag.name.as_str(), tag.closing) {
+            (name, false) if hidden_elements.contains(&name) => hidden = Some(tag.name.clone()),
+            (name, _) if BLOCKS.contains(&name) => text.line_break(2),
+            ("br", _) => text.line_break(1),
+            ("hr", _) => {
+                text.line_break(2);
+                text.push_word("--");
+                text.line_break(1);
+            }
+            ("li", false) => {
+                text.line_break(1);
+                text.push_word("-");
+                text.space = true;
+            }
+            ("td" | "th", false) => text.space = true,
+            ("a", false) if links_shown => links.push((tag.attribute("href"), text.out.len())),
+            ("a", true) if links_shown => {
+                let Some((Some(href), start)) = links.pop() else {
+                    continue;
+                };
+                let label = text.out[start.min(text.out.len())..].trim();
+                let href = href.trim_start_matches("mailto:");
+                if !href.is_empty() && label != href && !href.starts_with('#') {
+                    text.space = true;
+                    text.push_word(&format!("({href})"));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    text.out
+}
+
+/// Replaces the named entities a template is likely to use, and numeric ones.
+fn decode_entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+
+        let decoded = rest.find(';').filter(|end| *end <= 10).and_then(|end| {
+            let entity = &rest[1..end];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                "mdash" => Some('—'),
+                "ndash" => Some('–'),
+                "hellip" => Some('…'),
+                "rsquo" => Some('’'),
+                "lsquo" => Some('‘'),
+                "rdquo" => Some('”'),
+                "ldquo" => Some('“'),
+                _ => entity
+                    .strip_prefix("#x")
+                    .or_else(|| entity.strip_prefix("#X"))
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                    .and_then(char::from_u32),
+            }?;
+
+            Some((c, end))
+        });
+
+        match decoded {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
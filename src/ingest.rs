@@ -0,0 +1,450 @@
+//! `POST /catfact/from-url`, for turning an article about cats into fact
+//! submissions. The page is fetched, its main text pulled out, and sentences
+//! that read like cat facts are returned as suggestions for an admin to edit
+//! and send on to `POST /catfact/bulk` - nothing is inserted here.
+use anyhow::anyhow;
+use axum::{extract::State, Json};
+use reqwest::{header, redirect, Url};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+use crate::auth::Admin;
+use crate::store::{Statement, Value};
+use crate::strict::StrictJson;
+use crate::{error::ApiError, fact_id, html_text, store, AppState, CatFact};
+
+/// The most of a page that's read; anything after it is ignored.
+const MAX_PAGE_BYTES: usize = 2 * 1024 * 1024;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+const MAX_REDIRECTS: usize = 5;
+
+/// The most suggestions one page gives.
+const MAX_SUGGESTIONS: usize = 20;
+
+/// How long, in characters, a sentence can be and still be suggested.
+const MIN_LENGTH: usize = 30;
+const MAX_LENGTH: usize = 300;
+
+/// Words that make a sentence about cats.
+const CAT_WORDS: &[&str] = &[
+    "cat", "cats", "cat's", "kitten", "kittens", "feline", "felines", "kitty", "kitties", "tomcat",
+    "tomcats",
+];
+
+/// Words that make a sentence an anecdote rather than a fact.
+const FIRST_PERSON: &[&str] = &["i", "i'm", "i've", "me", "my", "we", "we're", "our", "us"];
+
+/// Page furniture that slips through extraction, e.g. cookie banners.
+const BOILERPLATE: &[&str] = &[
+    "http",
+    "www.",
+    "cookie",
+    "subscribe",
+    "newsletter",
+    "sign up",
+    "click",
+    "privacy",
+    "copyright",
+    "©",
+    "all rights reserved",
+];
+
+/// Abbreviations a sentence doesn't end at.
+const ABBREVIATIONS: &[&str] = &[
+    "dr", "mr", "mrs", "ms", "st", "vs", "e.g", "i.e", "etc", "approx", "no", "fig",
+];
+
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct FromUrl {
+    url: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Suggestion {
+    /// Ready to be sent to `POST /catfact/bulk`, once checked.
+    submission: CatFact,
+    source_url: String,
+    /// Whether this instance already has the fact.
+    already_here: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Suggestions {
+    url: String,
+    title: Option<String>,
+    suggestions: Vec<Suggestion>,
+}
+
+/// `POST /catfact/from-url` (admin only) - fetches `{"url": "..."}` and
+/// suggests the facts in it, best first.
+#[utoipa::path(
+    post,
+    path = "/catfact/from-url",
+    tag = "facts",
+    request_body = FromUrl,
+    responses(
+        (status = 200, description = "Facts found on the page, best first", body = Suggestions),
+        (status = 422, description = "The URL isn't valid", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+pub async fn suggest_facts(
+    State(state): State<Arc<AppState>>,
+    _: Admin,
+    StrictJson(body): StrictJson<FromUrl>,
+) -> Result<Json<Suggestions>, ApiError> {
+    let url = Url::parse(body.url.trim())
+        .map_err(|e| ApiError::Validation(format!("{:?} isn't a valid URL: {e}", body.url)))?;
+    let addrs = check_url(&url).await.map_err(ApiError::Validation)?;
+
+    let page = fetch(&url, addrs).await.map_err(ApiError::Upstream)?;
+    let text = html_text::article_text(&page);
+    let candidates = candidates(&text);
+
+    let known = known_fact_ids(&state, &candidates).await?;
+    let suggestions = candidates
+        .into_iter()
+        .map(|fact| Suggestion {
+            already_here: known.contains(&fact_id::fact_id(&fact)),
+            submission: CatFact {
+                fact,
+                license: None,
+                tags: None,
+            },
+            source_url: url.to_string(),
+        })
+        .collect();
+
+    Ok(Json(Suggestions {
+        url: url.to_string(),
+        title: html_text::title(&page),
+        suggestions,
+    }))
+}
+
+/// The addresses to fetch `url` from. Refuses anything but public `http(s)`
+/// addresses, so the route can't be used to reach services on the
+/// deployment's own network: a host name has to resolve only to public
+/// addresses, and `fetch` connects to just those, so it can't resolve to
+/// another one by the time the page is fetched.
+async fn check_url(url: &Url) -> Result<Vec<SocketAddr>, String> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("{url} should be an http or https URL"));
+    }
+
+    let host = url.host_str().unwrap_or("").to_ascii_lowercase();
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addrs: Vec<SocketAddr> = match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(ip) => vec![SocketAddr::new(ip, port)],
+        Err(_)
+            if host.is_empty()
+                || host == "localhost"
+                || host.ends_with(".localhost")
+                || host.ends_with(".local")
+                || host.ends_with(".internal") =>
+        {
+            Vec::new()
+        }
+        Err(_) => tokio::net::lookup_host((host.as_str(), port))
+            .await
+            .map_err(|e| format!("couldn't look up {host}: {e}"))?
+            .collect(),
+    };
+
+    if !addrs.is_empty() && addrs.iter().all(|addr| is_public(addr.ip())) {
+        Ok(addrs)
+    } else {
+        Err(format!("{url} isn't a public address"))
+    }
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // "This network" (0.0.0.0/8) and carrier-grade NAT
+                // (100.64.0.0/10).
+                || first == 0
+                || (first == 100 && (second & 0xc0) == 64))
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            // IPv4-mapped (::ffff:0:0/96) and NAT64 (64:ff9b::/96) addresses
+            // reach an IPv4 address, so are as public as it is.
+            let nat64 = (segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0]).then(|| {
+                let [.., a, b, c, d] = ip.octets();
+                Ipv4Addr::new(a, b, c, d)
+            });
+            if let Some(ip) = ip.to_ipv4_mapped().or(nat64) {
+                return is_public(IpAddr::V4(ip));
+            }
+
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                // Unique local (fc00::/7), link-local (fe80::/10) and
+                // multicast (ff00::/8) addresses.
+                || (segments[0] & 0xfe00) == 0xfc00
+                || (segments[0] & 0xffc0) == 0xfe80
+                || (segments[0] & 0xff00) == 0xff00
+                // Documentation (2001:db8::/32), and the rest of the NAT64
+                // prefixes, which only translate within a network
+                // (64:ff9b:1::/48, and 64:ff9b::/32 around it).
+                || segments[..2] == [0x2001, 0xdb8]
+                || segments[..2] == [0x64, 0xff9b])
+        }
+    }
+}
+
+/// The first `MAX_PAGE_BYTES` of the page at `url`, connecting only to
+/// `addrs`, from `check_url`. Redirects are followed one at a time, each
+/// checked the same way.
+async fn fetch(url: &Url, addrs: Vec<SocketAddr>) -> Result<String, anyhow::Error> {
+    let (mut url, mut addrs) = (url.clone(), addrs);
+    let mut redirects = 0;
+    let mut res = loop {
+        let mut client = reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            .redirect(redirect::Policy::none())
+            // A proxy would look the host up again itself.
+            .no_proxy();
+        if let Some(host) = url.domain() {
+            client = client.resolve_to_addrs(host, &addrs);
+        }
+
+        let res = client
+            .build()?
+            .get(url.clone())
+            .header(header::ACCEPT, "text/html, text/plain;q=0.9")
+            .send()
+            .await
+            .map_err(|e| anyhow!("couldn't fetch {url}: {e}"))?;
+        let location = res
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|location| location.to_str().ok());
+        let Some(location) = location.filter(|_| res.status().is_redirection()) else {
+            break res;
+        };
+
+        redirects += 1;
+        if redirects > MAX_REDIRECTS {
+            return Err(anyhow!("{url} redirected too many times"));
+        }
+        let next = url
+            .join(location)
+            .map_err(|e| anyhow!("{url} redirected to an invalid URL: {e}"))?;
+        addrs = check_url(&next)
+            .await
+            .map_err(|e| anyhow!("{url} redirected somewhere it can't be fetched from: {e}"))?;
+        url = next;
+    };
+
+    if !res.status().is_success() {
+        return Err(anyhow!("{url} responded with {}", res.status()));
+    }
+
+    let content_type = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("text/html")
+        .to_ascii_lowercase();
+    if !content_type.starts_with("text/html")
+        && !content_type.starts_with("application/xhtml+xml")
+        && !content_type.starts_with("text/plain")
+    {
+        return Err(anyhow!(
+            "{url} is {content_type}, not a web page or plain text"
+        ));
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = res
+        .chunk()
+        .await
+        .map_err(|e| anyhow!("couldn't read {url}: {e}"))?
+    {
+        body.extend_from_slice(&chunk);
+        if body.len() >= MAX_PAGE_BYTES {
+            body.truncate(MAX_PAGE_BYTES);
+            break;
+        }
+    }
+
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// The sentences in `text` that look like cat facts, best first, without
+/// repeats.
+fn candidates(text: &str) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut scored: Vec<(usize, usize, String)> = text
+        .lines()
+        .flat_map(sentences)
+        .filter_map(|sentence| score(&sentence).map(|score| (score, sentence)))
+        .filter(|(_, sentence)| seen.insert(fact_id::fact_id(sentence)))
+        .enumerate()
+        .map(|(idx, (score, sentence))| (score, idx, sentence))
+        .collect();
+
+    // Best first, and in page order among equally good ones.
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    scored
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, _, sentence)| sentence)
+        .collect()
+}
+
+/// Splits a paragraph into sentences, at a `.`, `!` or `?` followed by a space
+/// and a capital letter, unless it ends an abbreviation.
+fn sentences(paragraph: &str) -> Vec<String> {
+    let chars: Vec<char> = paragraph.chars().collect();
+    let mut sentences = Vec::new();
+    let mut start = 0;
+
+    for idx in 0..chars.len() {
+        if !matches!(chars[idx], '.' | '!' | '?') {
+            continue;
+        }
+        let next = chars.get(idx + 1);
+        let after = chars.get(idx + 2);
+        let ends = next.is_some_and(|c| c.is_whitespace())
+            && after.is_some_and(|c| c.is_uppercase() || c.is_ascii_digit() || *c == '"');
+        if !ends && next.is_some() {
+            continue;
+        }
+
+        let sentence: String = chars[start..=idx].iter().collect();
+        let last_word = sentence
+            .trim_end_matches(['.', '!', '?'])
+            .rsplit(char::is_whitespace)
+            .next()
+            .unwrap_or("")
+            .to_lowercase();
+        if next.is_some() && ABBREVIATIONS.contains(&last_word.as_str()) {
+            continue;
+        }
+
+        sentences.push(sentence.trim().to_string());
+        start = idx + 1;
+    }
+
+    sentences
+}
+
+/// How good a fact `sentence` would make, or `None` if it doesn't look like
+/// one at all. Sentences with a number in them, or that mention cats more
+/// than once, tend to be the factual ones.
+fn score(sentence: &str) -> Option<usize> {
+    let length = sentence.chars().count();
+    if !(MIN_LENGTH..=MAX_LENGTH).contains(&length)
+        || !sentence.starts_with(|c: char| c.is_uppercase() || c.is_ascii_digit())
+        || !sentence.ends_with(['.', '!'])
+    {
+        return None;
+    }
+
+    let lower = sentence.to_lowercase();
+    if BOILERPLATE.iter().any(|phrase| lower.contains(phrase)) {
+        return None;
+    }
+
+    let words: Vec<&str> = lower
+        .split(|c: char| !(c.is_alphanumeric() || c == '\''))
+        .filter(|word| !word.is_empty())
+        .collect();
+    if words.iter().any(|word| FIRST_PERSON.contains(word)) {
+        return None;
+    }
+
+    let mentions = words.iter().filter(|word| CAT_WORDS.contains(word)).count();
+    if mentions == 0 {
+        return None;
+    }
+
+    let has_number = sentence.chars().any(|c| c.is_ascii_digit());
+    Some(mentions.min(3) + if has_number { 2 } else { 0 })
+}
+
+/// Which of `facts` this instance already has, by fact id.
+async fn known_fact_ids(state: &AppState, facts: &[String]) -> Result<HashSet<String>, ApiError> {
+    if facts.is_empty() {
+        return Ok(HashSet::new());
+    }
+
+    let ids: Vec<Value> = facts
+        .iter()
+        .map(|fact| Value::from(fact_id::fact_id(fact)))
+        .collect();
+    let res = state
+        .db
+        .execute(Statement::with_args(
+            format!(
+                "SELECT fact_id FROM catfacts WHERE fact_id IN ({})",
+                vec!["?"; ids.len()].join(", ")
+            ),
+            &ids,
+        ))
+        .await?;
+
+    Ok(res
+        .rows
+        .iter()
+        .filter_map(|row| store::text(row, 0).ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn public(ip: &str) -> bool {
+        is_public(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn rejects_non_public_ipv6() {
+        assert!(public("2606:4700::1111"));
+        for ip in [
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "ff02::1",
+            "ff0e::1",
+            "2001:db8::1",
+            "64:ff9b:1::a00:1",
+        ] {
+            assert!(!public(ip), "{ip}");
+        }
+    }
+
+    #[test]
+    fn checks_the_ipv4_address_inside_mapped_and_nat64_ones() {
+        assert!(public("::ffff:1.1.1.1"));
+        assert!(public("64:ff9b::1.1.1.1"));
+        for ip in [
+            "::ffff:127.0.0.1",
+            "::ffff:10.0.0.1",
+            "64:ff9b::127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+            "64:ff9b::192.168.1.1",
+        ] {
+            assert!(!public(ip), "{ip}");
+        }
+    }
+}
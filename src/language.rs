@@ -0,0 +1,54 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+/// The language a subscriber's daily email is written in. Facts themselves
+/// aren't translated, only the copy around them (see `templates`).
+#[derive(Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[default]
+    En,
+    De,
+    Es,
+    Fr,
+}
+
+impl Language {
+    pub const ALL: [Language; 4] = [Self::En, Self::De, Self::Es, Self::Fr];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|language| language.name() == name)
+    }
+
+    /// The ISO 639-1 code used in JSON bodies, HTML forms and the `language`
+    /// column.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::En => "en",
+            Self::De => "de",
+            Self::Es => "es",
+            Self::Fr => "fr",
+        }
+    }
+
+    /// What the preference center calls this language, in itself.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::En => "English",
+            Self::De => "Deutsch",
+            Self::Es => "Español",
+            Self::Fr => "Français",
+        }
+    }
+
+    /// The secret holding this language's variant of the template in
+    /// `secret`, e.g. `EMAIL_SUBJECT_FR`. English is the template itself.
+    pub fn secret(&self, secret: &str) -> String {
+        match self {
+            Self::En => secret.to_string(),
+            _ => format!("{secret}_{}", self.name().to_uppercase()),
+        }
+    }
+}
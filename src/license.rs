@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// The terms a fact can be reused under, so apps that republish facts can
+/// stick to ones they're allowed to.
+#[derive(Clone, Copy, Default, PartialEq, Deserialize, Serialize, ToSchema)]
+pub enum License {
+    /// No rights reserved.
+    #[serde(rename = "cc0")]
+    Cc0,
+    /// Reusable with attribution. What facts get unless they say otherwise.
+    #[default]
+    #[serde(rename = "cc-by")]
+    CcBy,
+    /// Reusable with attribution, under the same license.
+    #[serde(rename = "cc-by-sa")]
+    CcBySa,
+    /// Not licensed for reuse.
+    #[serde(rename = "all-rights-reserved")]
+    AllRightsReserved,
+}
+
+impl License {
+    pub const ALL: [License; 4] = [Self::Cc0, Self::CcBy, Self::CcBySa, Self::AllRightsReserved];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|license| license.name() == name)
+    }
+
+    /// The name used in JSON bodies, query strings and the `license` column.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Cc0 => "cc0",
+            Self::CcBy => "cc-by",
+            Self::CcBySa => "cc-by-sa",
+            Self::AllRightsReserved => "all-rights-reserved",
+        }
+    }
+}
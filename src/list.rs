@@ -0,0 +1,123 @@
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::fields::{self, FieldsQuery};
+use crate::license::License;
+use crate::store::{self, Statement, Value};
+use crate::{error::ApiError, AppState, CatFactRecord, CATFACT_COLUMNS};
+
+const DEFAULT_PER_PAGE: u32 = 20;
+const MAX_PER_PAGE: u32 = 100;
+
+#[derive(Clone, Copy, Default, Deserialize, ToSchema)]
+pub enum Sort {
+    #[default]
+    #[serde(rename = "id")]
+    Id,
+    #[serde(rename = "-id")]
+    IdDesc,
+    #[serde(rename = "created_at")]
+    CreatedAt,
+    #[serde(rename = "-created_at")]
+    CreatedAtDesc,
+}
+
+impl Sort {
+    fn order_by(&self) -> &'static str {
+        match self {
+            Self::Id => "id ASC",
+            Self::IdDesc => "id DESC",
+            Self::CreatedAt => "created_at ASC, id ASC",
+            Self::CreatedAtDesc => "created_at DESC, id DESC",
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListQuery {
+    /// From 1.
+    page: Option<u32>,
+    /// Up to 100; defaults to 20.
+    per_page: Option<u32>,
+    #[serde(default)]
+    #[param(inline)]
+    sort: Sort,
+    /// Only facts under this license.
+    license: Option<License>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct FactPage {
+    #[schema(value_type = Vec<CatFactRecord>)]
+    data: serde_json::Value,
+    page: u32,
+    per_page: u32,
+    total: i64,
+    total_pages: i64,
+}
+
+/// `GET /catfacts?page=&per_page=&sort=&license=` - every fact, a page at a
+/// time. `sort` is one of `id` (the default), `-id`, `created_at` or
+/// `-created_at`, and `license` limits the list to facts under that license.
+#[utoipa::path(
+    get,
+    path = "/catfacts",
+    tag = "facts",
+    params(ListQuery, FieldsQuery),
+    responses((status = 200, description = "A page of facts", body = FactPage))
+)]
+pub async fn list_facts(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ListQuery>,
+    Query(fields): Query<FieldsQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query
+        .per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+    let offset = i64::from(page - 1) * i64::from(per_page);
+    let license = query
+        .license
+        .map_or(Value::Null, |license| Value::from(license.name()));
+
+    let results = state
+        .db
+        .batch([
+            Statement::with_args(
+                "SELECT count(*) FROM catfacts WHERE needs_review = 0 AND (?1 IS NULL OR license = ?1)",
+                std::slice::from_ref(&license),
+            ),
+            Statement::with_args(
+                format!(
+                    "SELECT {CATFACT_COLUMNS} FROM catfacts WHERE needs_review = 0 AND (?1 IS NULL OR license = ?1) ORDER BY {} LIMIT ?2 OFFSET ?3",
+                    query.sort.order_by()
+                ),
+                &[license, Value::from(per_page), Value::from(offset)],
+            ),
+        ])
+        .await?;
+
+    let (Some(count), Some(rows)) = (results.first(), results.get(1)) else {
+        return Err(ApiError::internal("Missing listing results"));
+    };
+
+    let total: i64 = store::first(count)?.unwrap_or(0);
+    let facts: Vec<CatFactRecord> = store::rows(rows)?;
+    let data = fields::shape(&facts, &fields).map_err(ApiError::internal)?;
+
+    Ok(Json(FactPage {
+        data,
+        page,
+        per_page,
+        total,
+        total_pages: (total + i64::from(per_page) - 1) / i64::from(per_page),
+    }))
+}
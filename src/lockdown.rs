@@ -0,0 +1,192 @@
+//! The "break glass" control for when the API is being abused. A lockdown
+//! turns off fact submissions, votes, signups and all outgoing email at once,
+//! until it's cleared. It's stored in the database, so a restart doesn't lift
+//! it, and every instance picks it up within a few seconds (`CHECK_TTL`).
+use axum::{
+    extract::State,
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::store::{self, FromRow, Row, Statement, Store};
+use crate::strict::StrictJson;
+use crate::{audit, auth::Admin, error::ApiError, AppState};
+
+/// How long a read of the lockdown is trusted before it's read again, so a
+/// lockdown started or cleared on one instance reaches the others this soon
+/// without a query on every request.
+const CHECK_TTL: Duration = Duration::from_secs(5);
+
+/// Whether a lockdown is on, checked on every guarded request and send.
+pub struct Lockdown {
+    db: Arc<dyn Store>,
+    /// The last read, and when it was made.
+    checked: Mutex<(bool, Instant)>,
+}
+
+impl Lockdown {
+    /// Picks up a lockdown that was on before a restart.
+    pub async fn load(db: Arc<dyn Store>) -> Result<Self, anyhow::Error> {
+        let active = current(&*db).await?.is_some();
+        if active {
+            tracing::warn!("Starting in lockdown: submissions, signups and email are off");
+        }
+
+        Ok(Self {
+            db,
+            checked: Mutex::new((active, Instant::now())),
+        })
+    }
+
+    /// Reads the lockdown from the database once the last read is older than
+    /// `CHECK_TTL`. If that fails, the last read stands.
+    pub async fn is_active(&self) -> bool {
+        let (active, checked_at) = *self.checked.lock().unwrap_or_else(|e| e.into_inner());
+        if checked_at.elapsed() < CHECK_TTL {
+            return active;
+        }
+
+        match current(&*self.db).await {
+            Ok(current) => {
+                self.set(current.is_some());
+                current.is_some()
+            }
+            Err(e) => {
+                tracing::warn!("Couldn't check for a lockdown: {e}");
+                active
+            }
+        }
+    }
+
+    fn set(&self, active: bool) {
+        *self.checked.lock().unwrap_or_else(|e| e.into_inner()) = (active, Instant::now());
+    }
+}
+
+#[derive(Serialize)]
+pub struct LockdownStatus {
+    active: bool,
+    reason: Option<String>,
+    started_by: Option<String>,
+    started_at: Option<String>,
+}
+
+struct Current {
+    reason: String,
+    started_by: String,
+    started_at: String,
+}
+
+impl FromRow for Current {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            reason: store::text(row, 0)?,
+            started_by: store::text(row, 1)?,
+            started_at: store::text(row, 2)?,
+        })
+    }
+}
+
+async fn current(db: &dyn Store) -> Result<Option<Current>, anyhow::Error> {
+    db.execute("SELECT reason, started_by, started_at FROM lockdown WHERE id = 1")
+        .await
+        .and_then(|res| store::first::<Current>(&res))
+}
+
+impl From<Option<Current>> for LockdownStatus {
+    fn from(current: Option<Current>) -> Self {
+        Self {
+            active: current.is_some(),
+            reason: current.as_ref().map(|current| current.reason.clone()),
+            started_by: current.as_ref().map(|current| current.started_by.clone()),
+            started_at: current.map(|current| current.started_at),
+        }
+    }
+}
+
+/// Middleware for the routes a lockdown turns off.
+pub async fn guard<B>(
+    State(lockdown): State<Arc<Lockdown>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if lockdown.is_active().await {
+        return ApiError::Unavailable(
+            "This is switched off for the moment, please try again later".to_string(),
+        )
+        .into_response();
+    }
+
+    next.run(request).await
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StartLockdown {
+    /// What's going on, for whoever clears it.
+    reason: String,
+}
+
+/// `GET /admin/lockdown` - whether a lockdown is on, and who started it.
+pub async fn lockdown_status(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<LockdownStatus>, ApiError> {
+    Ok(Json(current(&*state.db).await?.into()))
+}
+
+/// `POST /admin/lockdown` - turns off submissions, signups and email now.
+pub async fn start(
+    State(state): State<Arc<AppState>>,
+    admin: Admin,
+    StrictJson(body): StrictJson<StartLockdown>,
+) -> Result<Json<LockdownStatus>, ApiError> {
+    let res = state
+        .db
+        .batch([
+            Statement::with_args(
+                "INSERT INTO lockdown (id, reason, started_by) VALUES (1, ?, ?) ON CONFLICT (id) DO NOTHING",
+                &[body.reason.as_str(), admin.name.as_str()],
+            ),
+            audit::entry_if_changed(&admin.name, "lockdown", &body.reason),
+        ])
+        .await?;
+
+    if res.first().map_or(0, |inserted| inserted.rows_affected) == 0 {
+        return Err(ApiError::Conflict("A lockdown is already on".to_string()));
+    }
+
+    state.lockdown.set(true);
+    tracing::warn!("Lockdown started by {}: {}", admin.name, body.reason);
+
+    Ok(Json(current(&*state.db).await?.into()))
+}
+
+/// `POST /admin/lockdown/clear` - turns everything back on.
+pub async fn clear(
+    State(state): State<Arc<AppState>>,
+    admin: Admin,
+) -> Result<StatusCode, ApiError> {
+    let res = state
+        .db
+        .batch([
+            Statement::new("DELETE FROM lockdown WHERE id = 1"),
+            audit::entry_if_changed(&admin.name, "lockdown_clear", ""),
+        ])
+        .await?;
+
+    if res.first().map_or(0, |deleted| deleted.rows_affected) == 0 {
+        return Err(ApiError::Conflict(
+            "There's no lockdown to clear".to_string(),
+        ));
+    }
+
+    state.lockdown.set(false);
+    tracing::info!("Lockdown cleared by {}", admin.name);
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,682 @@
+//! Outgoing email. Handlers build an `Email` and hand it to `Mail`, which
+//! sends it through whichever `Mailer` the `MAILER` secret picks: SMTP (the
+//! default), SendGrid, Mailgun, Amazon SES, or files on disk for local
+//! development. A new provider only needs a `Mailer` implementation.
+//!
+//! SMTP can go through several relays, listed in `SMTP_RELAYS` in order of
+//! preference: if one can't be reached or won't log in, the email goes to the
+//! next. Which one took each daily email is kept in the outbox.
+use anyhow::anyhow;
+use axum::async_trait;
+use base64::Engine;
+use chrono::{Local, Utc};
+use lettre::{
+    message::{Mailbox, MultiPart},
+    transport::smtp::{self, authentication::Credentials, PoolConfig},
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use shuttle_secrets::SecretStore;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::crypto;
+use crate::lockdown::Lockdown;
+use crate::unsubscribe::{ListUnsubscribe, ListUnsubscribePost};
+
+/// An email to send, in a shape every provider can take.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Email {
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    pub html: Option<String>,
+    /// Sent as `List-Unsubscribe` headers, so mail clients can show their
+    /// own unsubscribe button (RFC 8058).
+    pub unsubscribe_url: Option<String>,
+}
+
+impl Email {
+    /// A plain-text email.
+    pub fn new(from: &Mailbox, to: &Mailbox, subject: impl Into<String>, text: String) -> Self {
+        Self {
+            from: from.to_string(),
+            to: to.to_string(),
+            subject: subject.into(),
+            text,
+            html: None,
+            unsubscribe_url: None,
+        }
+    }
+
+    pub fn sender_mailbox(&self) -> Result<Mailbox, anyhow::Error> {
+        self.from
+            .parse()
+            .map_err(|e| anyhow!("the sender {:?} isn't valid: {e}", self.from))
+    }
+
+    pub fn recipient_mailbox(&self) -> Result<Mailbox, anyhow::Error> {
+        self.to
+            .parse()
+            .map_err(|e| anyhow!("the recipient {:?} isn't valid: {e}", self.to))
+    }
+
+    /// The email as a MIME message, for providers that take one.
+    pub fn to_message(&self) -> Result<Message, anyhow::Error> {
+        let mut builder = Message::builder()
+            .from(self.sender_mailbox()?)
+            .to(self.recipient_mailbox()?)
+            .subject(&self.subject);
+        if let Some(unsubscribe_url) = &self.unsubscribe_url {
+            builder = builder
+                .header(ListUnsubscribe(unsubscribe_url.clone()))
+                .header(ListUnsubscribePost);
+        }
+
+        let message = match &self.html {
+            Some(html) => builder.multipart(MultiPart::alternative_plain_html(
+                self.text.clone(),
+                html.clone(),
+            )),
+            None => builder.body(self.text.clone()),
+        };
+
+        message.map_err(|e| anyhow!("couldn't build the email to {}: {e}", self.to))
+    }
+
+    /// The `List-Unsubscribe` headers, for providers that take headers by
+    /// name.
+    fn unsubscribe_headers(&self) -> Vec<(&'static str, String)> {
+        match &self.unsubscribe_url {
+            Some(url) => vec![
+                ("List-Unsubscribe", format!("<{url}>")),
+                (
+                    "List-Unsubscribe-Post",
+                    "List-Unsubscribe=One-Click".to_string(),
+                ),
+            ],
+            None => Vec::new(),
+        }
+    }
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    /// Names the provider in logs.
+    fn name(&self) -> &'static str;
+
+    async fn send(&self, email: &Email) -> Result<(), anyhow::Error>;
+
+    /// Sends `email`, returning what took it for the outbox: the provider's
+    /// name, or for SMTP the relay.
+    async fn deliver(&self, email: &Email) -> Result<String, anyhow::Error> {
+        self.send(email).await?;
+        Ok(self.name().to_string())
+    }
+
+    /// Checks that mail could be sent, for `GET /health/ready`.
+    async fn check(&self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
+/// Every outgoing email goes through here, so a lockdown stops all of it.
+#[derive(Clone)]
+pub struct Mail {
+    mailer: Arc<dyn Mailer>,
+    lockdown: Arc<Lockdown>,
+}
+
+impl Mail {
+    pub fn new(mailer: Arc<dyn Mailer>, lockdown: Arc<Lockdown>) -> Self {
+        Self { mailer, lockdown }
+    }
+
+    /// Whether sends are refused right now.
+    pub async fn is_paused(&self) -> bool {
+        self.lockdown.is_active().await
+    }
+
+    pub async fn check(&self) -> Result<(), anyhow::Error> {
+        self.mailer.check().await
+    }
+
+    pub async fn send(&self, email: &Email) -> Result<(), anyhow::Error> {
+        self.deliver(email).await.map(drop)
+    }
+
+    /// Sends `email` like `send`, returning what took it (see
+    /// `Mailer::deliver`).
+    #[tracing::instrument(skip_all, fields(mailer = self.mailer.name()))]
+    pub async fn deliver(&self, email: &Email) -> Result<String, anyhow::Error> {
+        if self.is_paused().await {
+            return Err(anyhow!("outgoing email is paused by a lockdown"));
+        }
+
+        self.mailer.deliver(email).await
+    }
+}
+
+/// The provider `MAILER` names, which decides the default sending limits (see
+/// `SendLimits`). SMTP without `SMTP_RELAYS` goes through Gmail.
+pub fn provider(store: &SecretStore) -> String {
+    match store.get("MAILER").as_deref() {
+        None | Some("smtp") if store.get("SMTP_RELAYS").is_none() => "gmail".to_string(),
+        None => "smtp".to_string(),
+        Some(provider) => provider.to_string(),
+    }
+}
+
+/// Picks the provider named by the `MAILER` secret: `smtp` (the default),
+/// `sendgrid`, `mailgun`, `ses` or `file`.
+pub fn from_secrets(store: &SecretStore) -> Result<Arc<dyn Mailer>, anyhow::Error> {
+    let required = |key: &str, provider: &str| {
+        store
+            .get(key)
+            .ok_or_else(|| anyhow!("MAILER is {provider}, but {key} isn't set"))
+    };
+
+    let mailer: Arc<dyn Mailer> = match store.get("MAILER").as_deref() {
+        None | Some("smtp") => Arc::new(SmtpConfig::from_secrets(store)?.build()?),
+        Some("file") => Arc::new(FileMailer(PathBuf::from(
+            store
+                .get("MAILER_OUTBOX")
+                .unwrap_or_else(|| "./outbox".to_string()),
+        ))),
+        Some("sendgrid") => Arc::new(SendGridMailer {
+            api_key: required("SENDGRID_API_KEY", "sendgrid")?,
+            client: reqwest::Client::new(),
+        }),
+        Some("mailgun") => Arc::new(MailgunMailer {
+            api_key: required("MAILGUN_API_KEY", "mailgun")?,
+            domain: required("MAILGUN_DOMAIN", "mailgun")?,
+            base_url: match store.get("MAILGUN_REGION").as_deref() {
+                None | Some("us") => "https://api.mailgun.net",
+                Some("eu") => "https://api.eu.mailgun.net",
+                Some(other) => {
+                    return Err(anyhow!("MAILGUN_REGION {other:?} should be us or eu"));
+                }
+            },
+            client: reqwest::Client::new(),
+        }),
+        Some("ses") => Arc::new(SesMailer {
+            region: required("SES_REGION", "ses")?,
+            access_key_id: required("SES_ACCESS_KEY_ID", "ses")?,
+            secret_access_key: required("SES_SECRET_ACCESS_KEY", "ses")?,
+            client: reqwest::Client::new(),
+        }),
+        Some(other) => {
+            return Err(anyhow!(
+                "MAILER {other:?} should be smtp, sendgrid, mailgun, ses or file"
+            ))
+        }
+    };
+
+    Ok(mailer)
+}
+
+/// The `From` mailbox for outgoing mail: `MAIL_FROM`, which can include a
+/// name (`Cat Facts <facts@example.com>`), or else the SMTP user. `None` if
+/// that isn't a valid address.
+pub fn sender(store: &SecretStore) -> Option<Mailbox> {
+    let (key, parsed) = match store.get("MAIL_FROM") {
+        Some(from) => ("MAIL_FROM", from.parse::<Mailbox>()),
+        None => (
+            "GMAIL_USER",
+            store
+                .get("GMAIL_USER")
+                .unwrap_or_default()
+                .parse()
+                .map(|address| Mailbox::new(Some("Cat Facts".to_string()), address)),
+        ),
+    };
+
+    match parsed {
+        Ok(mailbox) => Some(mailbox),
+        Err(e) => {
+            tracing::warn!("{key} isn't a valid email address: {e}");
+            None
+        }
+    }
+}
+
+/// How long a relay that was shutting down or turned the login down is passed
+/// over before it's tried first again.
+const RELAY_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// Reply codes that mean the relay won't take any mail from us right now:
+/// it's shutting down (421), or turned the login down (454, 530, 534, 535,
+/// 538).
+const RELAY_FAILURE_CODES: &[&str] = &["421", "454", "530", "534", "535", "538"];
+
+/// Whether `e`, from sending through a relay, means the relay won't take mail
+/// right now rather than that it refused this email, which the next relay
+/// would most likely refuse too. An error without a reply code, like the
+/// connection dropping, may have come after the relay took the email, so it
+/// doesn't count: sending it through the next relay could deliver it twice.
+fn is_relay_failure(e: &smtp::Error) -> bool {
+    e.status()
+        .is_some_and(|code| RELAY_FAILURE_CODES.contains(&code.to_string().as_str()))
+}
+
+/// One of the relays an `SmtpMailer` sends through.
+struct SmtpRelay {
+    /// The host (and port, if it isn't the default), for logs and the
+    /// outbox. Never the login.
+    name: String,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    /// Set when the relay fails, until it's worth trying first again.
+    down_until: Mutex<Option<Instant>>,
+}
+
+impl SmtpRelay {
+    fn is_down(&self) -> bool {
+        let down_until = self.down_until.lock().unwrap_or_else(|e| e.into_inner());
+        down_until.is_some_and(|until| Instant::now() < until)
+    }
+
+    fn mark_down(&self) {
+        let mut down_until = self.down_until.lock().unwrap_or_else(|e| e.into_inner());
+        *down_until = Some(Instant::now() + RELAY_COOLDOWN);
+    }
+}
+
+/// Sends through SMTP relays in order of preference, moving on to the next
+/// when one is shutting down or won't log in.
+pub struct SmtpMailer {
+    relays: Vec<SmtpRelay>,
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    fn name(&self) -> &'static str {
+        "smtp"
+    }
+
+    async fn send(&self, email: &Email) -> Result<(), anyhow::Error> {
+        self.deliver(email).await.map(drop)
+    }
+
+    async fn deliver(&self, email: &Email) -> Result<String, anyhow::Error> {
+        let message = email.to_message()?;
+
+        // Relays that failed recently go last, so they're still tried if
+        // every other one fails too.
+        let (up, down): (Vec<_>, Vec<_>) = self.relays.iter().partition(|relay| !relay.is_down());
+        let mut failures = Vec::new();
+        for relay in up.into_iter().chain(down) {
+            match relay.transport.send(message.clone()).await {
+                Ok(_) => return Ok(relay.name.clone()),
+                Err(e) if is_relay_failure(&e) => {
+                    tracing::warn!("SMTP relay {} couldn't take the email: {e}", relay.name);
+                    relay.mark_down();
+                    failures.push(format!("{}: {e}", relay.name));
+                }
+                Err(e) => return Err(anyhow!("SMTP relay {} refused the email: {e}", relay.name)),
+            }
+        }
+
+        Err(anyhow!(
+            "no SMTP relay could take the email ({})",
+            failures.join("; ")
+        ))
+    }
+
+    /// Passes if any relay accepts a connection, since that's enough to send.
+    async fn check(&self) -> Result<(), anyhow::Error> {
+        let mut failures = Vec::new();
+        for relay in &self.relays {
+            match relay.transport.test_connection().await {
+                Ok(true) => return Ok(()),
+                Ok(false) => failures.push(format!("{} didn't accept a connection", relay.name)),
+                Err(e) => failures.push(format!("couldn't connect to {}: {e}", relay.name)),
+            }
+        }
+
+        Err(anyhow!(failures.join("; ")))
+    }
+}
+
+/// Writes each email to `{dir}/{timestamp}.eml` instead of sending it, for
+/// local development.
+pub struct FileMailer(PathBuf);
+
+#[async_trait]
+impl Mailer for FileMailer {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    async fn send(&self, email: &Email) -> Result<(), anyhow::Error> {
+        let dir = &self.0;
+        std::fs::create_dir_all(dir)
+            .map_err(|e| anyhow!("couldn't create outbox {}: {e}", dir.display()))?;
+
+        let path = dir.join(format!("{}.eml", Local::now().format("%Y%m%dT%H%M%S%.6f")));
+        std::fs::write(&path, email.to_message()?.formatted())
+            .map_err(|e| anyhow!("couldn't write {}: {e}", path.display()))
+    }
+
+    async fn check(&self) -> Result<(), anyhow::Error> {
+        std::fs::create_dir_all(&self.0)
+            .map_err(|e| anyhow!("couldn't create outbox {}: {e}", self.0.display()))
+    }
+}
+
+/// Turns a provider's error response into an error, with its body since
+/// that's where providers explain what was wrong.
+async fn check_response(provider: &str, res: reqwest::Response) -> Result<(), anyhow::Error> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(());
+    }
+
+    let body = res.text().await.unwrap_or_default();
+    Err(anyhow!("{provider} answered {status}: {}", body.trim()))
+}
+
+/// Sends with SendGrid's v3 API, authenticated by `SENDGRID_API_KEY`.
+pub struct SendGridMailer {
+    api_key: String,
+    client: reqwest::Client,
+}
+
+#[async_trait]
+impl Mailer for SendGridMailer {
+    fn name(&self) -> &'static str {
+        "sendgrid"
+    }
+
+    async fn send(&self, email: &Email) -> Result<(), anyhow::Error> {
+        let from = email.sender_mailbox()?;
+        let to = email.recipient_mailbox()?;
+        let mut content = vec![json!({"type": "text/plain", "value": email.text})];
+        if let Some(html) = &email.html {
+            content.push(json!({"type": "text/html", "value": html}));
+        }
+        let headers: serde_json::Map<String, serde_json::Value> = email
+            .unsubscribe_headers()
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.into()))
+            .collect();
+
+        let mut body = json!({
+            "personalizations": [{"to": [{"email": to.email.to_string()}]}],
+            "from": {"email": from.email.to_string(), "name": from.name},
+            "subject": email.subject,
+            "content": content,
+        });
+        if !headers.is_empty() {
+            body["headers"] = headers.into();
+        }
+
+        let res = self
+            .client
+            .post("https://api.sendgrid.com/v3/mail/send")
+            .bearer_auth(&self.api_key)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| anyhow!("couldn't reach SendGrid: {e}"))?;
+
+        check_response("SendGrid", res).await
+    }
+}
+
+/// Sends with Mailgun's messages API for `MAILGUN_DOMAIN`, authenticated by
+/// `MAILGUN_API_KEY`. `MAILGUN_REGION` is `us` (the default) or `eu`.
+pub struct MailgunMailer {
+    api_key: String,
+    domain: String,
+    base_url: &'static str,
+    client: reqwest::Client,
+}
+
+#[async_trait]
+impl Mailer for MailgunMailer {
+    fn name(&self) -> &'static str {
+        "mailgun"
+    }
+
+    async fn send(&self, email: &Email) -> Result<(), anyhow::Error> {
+        let mut form = vec![
+            ("from".to_string(), email.from.clone()),
+            ("to".to_string(), email.to.clone()),
+            ("subject".to_string(), email.subject.clone()),
+            ("text".to_string(), email.text.clone()),
+        ];
+        if let Some(html) = &email.html {
+            form.push(("html".to_string(), html.clone()));
+        }
+        form.extend(
+            email
+                .unsubscribe_headers()
+                .into_iter()
+                .map(|(name, value)| (format!("h:{name}"), value)),
+        );
+
+        let res = self
+            .client
+            .post(format!("{}/v3/{}/messages", self.base_url, self.domain))
+            .basic_auth("api", Some(&self.api_key))
+            .form(&form)
+            .send()
+            .await
+            .map_err(|e| anyhow!("couldn't reach Mailgun: {e}"))?;
+
+        check_response("Mailgun", res).await
+    }
+}
+
+/// Sends with Amazon SES's v2 API in `SES_REGION`, signing requests with
+/// `SES_ACCESS_KEY_ID` / `SES_SECRET_ACCESS_KEY`. The email goes as a raw MIME
+/// message, so it's the same as one sent over SMTP.
+pub struct SesMailer {
+    region: String,
+    access_key_id: String,
+    secret_access_key: String,
+    client: reqwest::Client,
+}
+
+impl SesMailer {
+    /// The `Authorization` header for a request, signed with AWS Signature
+    /// Version 4.
+    fn authorization(&self, host: &str, path: &str, amz_date: &str, body: &[u8]) -> String {
+        let date = &amz_date[..8];
+        let signed_headers = "content-type;host;x-amz-date";
+        let canonical_request = format!(
+            "POST\n{path}\n\ncontent-type:application/json\nhost:{host}\nx-amz-date:{amz_date}\n\n{signed_headers}\n{}",
+            crypto::hex(&crypto::sha256(body))
+        );
+        let scope = format!("{date}/{}/ses/aws4_request", self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            crypto::hex(&crypto::sha256(canonical_request.as_bytes()))
+        );
+
+        let key = [date, self.region.as_str(), "ses", "aws4_request"]
+            .iter()
+            .fold(
+                format!("AWS4{}", self.secret_access_key).into_bytes(),
+                |key, part| crypto::hmac_sha256(&key, part.as_bytes()),
+            );
+        let signature = crypto::hex(&crypto::hmac_sha256(&key, string_to_sign.as_bytes()));
+
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            self.access_key_id
+        )
+    }
+}
+
+#[async_trait]
+impl Mailer for SesMailer {
+    fn name(&self) -> &'static str {
+        "ses"
+    }
+
+    async fn send(&self, email: &Email) -> Result<(), anyhow::Error> {
+        let raw = email.to_message()?.formatted();
+        let body = serde_json::to_vec(&json!({
+            "Content": {
+                "Raw": {"Data": base64::engine::general_purpose::STANDARD.encode(raw)}
+            }
+        }))?;
+
+        let host = format!("email.{}.amazonaws.com", self.region);
+        let path = "/v2/email/outbound-emails";
+        let amz_date = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let authorization = self.authorization(&host, path, &amz_date, &body);
+
+        let res = self
+            .client
+            .post(format!("https://{host}{path}"))
+            .header("content-type", "application/json")
+            .header("x-amz-date", &amz_date)
+            .header("authorization", authorization)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| anyhow!("couldn't reach SES: {e}"))?;
+
+        check_response("SES", res).await
+    }
+}
+
+/// One relay from `SMTP_RELAYS`, written `user:password@host:port`. The login
+/// can be left out for a relay that doesn't need one, and the port for 465.
+struct RelayConfig {
+    host: String,
+    port: Option<u16>,
+    credentials: Option<Credentials>,
+}
+
+impl RelayConfig {
+    fn parse(relay: &str) -> Result<Self, anyhow::Error> {
+        // The host can't have an `@` in it, but the login can.
+        let (credentials, address) = match relay.rsplit_once('@') {
+            Some((login, address)) => {
+                let (user, password) = login.split_once(':').ok_or_else(|| {
+                    anyhow!("the SMTP relay {address:?} has a user but no password; it should be user:password@{address}")
+                })?;
+                (
+                    Some(Credentials::new(user.to_string(), password.to_string())),
+                    address,
+                )
+            }
+            None => (None, relay),
+        };
+        let (host, port) = match address.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                Some(
+                    port.parse()
+                        .map_err(|_| anyhow!("the SMTP relay {address:?} has an invalid port"))?,
+                ),
+            ),
+            None => (address, None),
+        };
+        if host.is_empty() {
+            return Err(anyhow!("an SMTP relay in SMTP_RELAYS has no host"));
+        }
+
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            credentials,
+        })
+    }
+
+    fn name(&self) -> String {
+        match self.port {
+            Some(port) if port != 465 => format!("{}:{port}", self.host),
+            _ => self.host.clone(),
+        }
+    }
+}
+
+/// SMTP settings. Each relay's transport keeps a pool of open connections so
+/// that a burst of sends reuses TLS sessions instead of opening one per
+/// message.
+pub struct SmtpConfig {
+    relays: Vec<RelayConfig>,
+    pool_size: u32,
+    idle_timeout: Duration,
+}
+
+impl SmtpConfig {
+    /// The relays in `SMTP_RELAYS`, separated by `;`, or else Gmail with
+    /// `GMAIL_USER` / `GMAIL_PASSWORD`.
+    pub fn from_secrets(store: &SecretStore) -> Result<Self, anyhow::Error> {
+        let relays = match store.get("SMTP_RELAYS") {
+            Some(relays) => relays
+                .split(';')
+                .map(str::trim)
+                .filter(|relay| !relay.is_empty())
+                .map(RelayConfig::parse)
+                .collect::<Result<Vec<_>, _>>()?,
+            None => vec![RelayConfig {
+                host: "smtp.gmail.com".to_string(),
+                port: None,
+                credentials: Some(Credentials::new(
+                    store
+                        .get("GMAIL_USER")
+                        .unwrap_or_else(|| "None".to_string()),
+                    store
+                        .get("GMAIL_PASSWORD")
+                        .unwrap_or_else(|| "None".to_string()),
+                )),
+            }],
+        };
+        if relays.is_empty() {
+            return Err(anyhow!("SMTP_RELAYS is set, but doesn't list any relays"));
+        }
+
+        Ok(Self {
+            relays,
+            pool_size: store
+                .get("SMTP_POOL_SIZE")
+                .and_then(|size| size.parse().ok())
+                .unwrap_or(10),
+            idle_timeout: Duration::from_secs(
+                store
+                    .get("SMTP_POOL_IDLE_TIMEOUT")
+                    .and_then(|secs| secs.parse().ok())
+                    .unwrap_or(60),
+            ),
+        })
+    }
+
+    /// Builds a transport per relay. Port 465 uses TLS from the start, and any
+    /// other port STARTTLS.
+    pub fn build(self) -> Result<SmtpMailer, anyhow::Error> {
+        let mut relays = Vec::new();
+        for relay in self.relays {
+            let name = relay.name();
+            let builder = match relay.port {
+                None | Some(465) => AsyncSmtpTransport::<Tokio1Executor>::relay(&relay.host),
+                Some(port) => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&relay.host)
+                    .map(|builder| builder.port(port)),
+            }
+            .map_err(|e| anyhow!("couldn't set up the SMTP relay {name}: {e}"))?;
+            let builder = match relay.credentials {
+                Some(credentials) => builder.credentials(credentials),
+                None => builder,
+            };
+            let pool = PoolConfig::new()
+                .max_size(self.pool_size)
+                .idle_timeout(self.idle_timeout);
+
+            relays.push(SmtpRelay {
+                name,
+                transport: builder.pool_config(pool).build(),
+                down_until: Mutex::new(None),
+            });
+        }
+
+        Ok(SmtpMailer { relays })
+    }
+}
@@ -1,271 +1,1270 @@
-use anyhow::anyhow;
 use axum::{
-    extract::State,
-    http::StatusCode,
-    response::IntoResponse,
-    routing::{get, post},
+    extract::{Path, Query, State},
+    handler::Handler,
+    http::{HeaderMap, StatusCode},
+    middleware::{from_fn_with_state, map_response_with_state},
+    response::{IntoResponse, Redirect, Response},
+    routing::{delete, get, post, put},
     Json, Router,
 };
-use chrono::naive::{Days, NaiveDateTime};
-use chrono::Local;
-use lettre::{
-    message::header::ContentType, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
-    AsyncTransport, Message, Tokio1Executor,
-};
-use libsql_client::{client::Client, Statement};
+use lettre::message::Mailbox;
 use serde::{Deserialize, Serialize};
 use shuttle_secrets::SecretStore;
 use std::sync::Arc;
-use std::time::Duration;
 use tokio::sync::Mutex;
-use tokio::time::{sleep, Duration as TokioDuration};
+use utoipa::{IntoParams, ToSchema};
+
+mod address;
+mod analytics;
+mod archive;
+mod audit;
+mod auth;
+mod backfill;
+mod badge;
+mod bandit;
+mod bulk;
+mod cache;
+mod calendar;
+mod changelog;
+mod coalesce;
+mod complaints;
+mod config;
+mod confirm;
+mod crypto;
+mod daily;
+mod data_requests;
+mod delivery;
+mod dispatch;
+mod email_format;
+mod email_metrics;
+mod error;
+mod export;
+mod fact_id;
+mod feed;
+mod feedback;
+mod fields;
+mod health;
+mod history;
+mod html;
+mod html_text;
+mod ingest;
+mod language;
+mod license;
+mod list;
+mod lockdown;
+mod mailer;
+mod metrics;
+mod migrations;
+mod mqtt;
+mod negotiate;
+mod openapi;
+mod opens;
+mod origins;
+mod outbox;
+mod preferences;
+mod privacy;
+mod proto;
+mod ranking;
+mod rate_limit;
+mod reengage;
+mod request_id;
+mod retention;
+mod routes;
+mod sanitize;
+mod scheduler;
+mod schema;
+mod search;
+mod segments;
+mod send_daily;
+mod send_report;
+mod shutdown;
+mod signup;
+mod sitemap;
+mod slug;
+mod spam;
+#[cfg(feature = "standalone")]
+mod standalone;
+mod stats;
+mod store;
+mod strict;
+mod suppressions;
+mod sync;
+mod tag_rules;
+mod tags;
+mod templates;
+mod timezone;
+mod turnstile;
+mod unsubscribe;
+mod votes;
+mod waitlist;
+mod weekdays;
+mod weekly;
 
-#[derive(Deserialize, Serialize)]
+use address::MxCheck;
+use auth::Admin;
+use cache::{apply_cache_policy, CachePolicy};
+use changelog::Changelog;
+use coalesce::SingleFlight;
+use confirm::Confirmations;
+use delivery::DeliveryWindow;
+use dispatch::{Composer, Dispatcher, SendLimits};
+use email_metrics::EmailMetrics;
+use error::ApiError;
+use feed::FeedCache;
+use fields::FieldsQuery;
+use language::Language;
+use license::License;
+use lockdown::Lockdown;
+use mailer::Mail;
+use mqtt::{FactPublisher, MqttConfig};
+use negotiate::FactFormat;
+use origins::AllowedOrigins;
+use privacy::Privacy;
+use proto::Protobuf;
+use ranking::{Ranking, Selection};
+use rate_limit::{ClientAddresses, ClientIp, KeyTiers, RateLimits};
+use reengage::Reengagement;
+use retention::Retention;
+use routes::RouteRegistry;
+use scheduler::{Jobs, Scheduler, Zone};
+use shutdown::Shutdown;
+use spam::{SpamScorer, Submission, Verdict};
+use store::{FromRow, Row, Store, Value};
+use strict::{JsonOrForm, StrictJson};
+use sync::SyncSource;
+use templates::Templates;
+use timezone::Timezone;
+use turnstile::Turnstile;
+use unsubscribe::UnsubscribeSigner;
+use weekdays::Weekdays;
+use weekly::WeeklyDigest;
+
+#[derive(Deserialize, Serialize, ToSchema)]
+#[serde(deny_unknown_fields)]
 pub struct CatFact {
     fact: String,
+    /// Defaults to `License::default()` for a new fact, and to the current
+    /// license when correcting one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    license: Option<License>,
+    /// Replaces the fact's tags when correcting one; leave it out to keep
+    /// them.
+    #[schema(example = json!(["behavior", "sleep"]))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tags: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RandomQuery {
+    /// Only facts with this tag.
+    tag: Option<String>,
+    /// How many distinct facts to return, as a list. Without it the response
+    /// is a single fact.
+    count: Option<u32>,
+}
+
+/// The most facts `GET /catfact?count=` returns at once.
+const MAX_RANDOM_COUNT: u32 = 50;
+
+/// The columns `CatFactRecord::from_row` expects, in order.
+const CATFACT_COLUMNS: &str = "id, fact, slug, fact_id, created_at, license";
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct CatFactRecord {
+    id: i64,
+    fact: String,
+    slug: Option<String>,
+    /// A hash of the fact's text, stable across environments.
+    fact_id: Option<String>,
+    created_at: String,
+    license: License,
+}
+
+impl FromRow for CatFactRecord {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            id: store::integer(row, 0)?,
+            fact: store::text(row, 1)?,
+            slug: store::optional_text(row, 2)?,
+            fact_id: store::optional_text(row, 3)?,
+            created_at: store::text(row, 4)?,
+            license: License::from_name(&store::text(row, 5)?).unwrap_or_default(),
+        })
+    }
+}
+
+impl From<CatFactRecord> for proto::CatFact {
+    fn from(record: CatFactRecord) -> Self {
+        Self {
+            fact: record.fact,
+            created_at: record.created_at,
+            id: record.id,
+            slug: record.slug.unwrap_or_default(),
+            fact_id: record.fact_id.unwrap_or_default(),
+            license: record.license.name().to_string(),
+        }
+    }
 }
 
 pub struct CustomService {
-    db: Arc<Mutex<Client>>,
-    gmail_user: String,
-    gmail_password: String,
+    db: Arc<dyn Store>,
+    dispatcher: Arc<Mutex<Dispatcher>>,
+    email_metrics: Arc<EmailMetrics>,
+    scheduler: Scheduler,
+    jobs: Jobs,
     router: Router,
+    shutdown: Shutdown,
+}
+
+impl CustomService {
+    /// Stops the service gracefully once requested; see `shutdown`.
+    pub fn shutdown(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
 }
 
 pub struct AppState {
-    db: Arc<Mutex<Client>>,
+    /// Shared without a lock: the client takes `&self` and handles concurrent
+    /// statements itself, so a slow query doesn't hold up every other request.
+    db: Arc<dyn Store>,
+    mailer: Mail,
+    sender: Option<Mailbox>,
+    public_url: String,
+    mqtt: Option<FactPublisher>,
+    complaint_webhook_secret: Option<String>,
+    admin_token: Option<String>,
+    allowed_origins: AllowedOrigins,
+    turnstile: Option<Turnstile>,
+    mx_check: Option<MxCheck>,
+    subscriber_cap: Option<i64>,
+    health_check_smtp: bool,
+    sync_source: Option<SyncSource>,
+    /// Signs one-click unsubscribe links. Only set when
+    /// `UNSUBSCRIBE_SIGNING_KEY` is configured.
+    unsubscribe: Option<UnsubscribeSigner>,
+    composer: Composer,
+    dispatcher: Arc<Mutex<Dispatcher>>,
+    email_metrics: Arc<EmailMetrics>,
+    spam: SpamScorer,
+    /// How to tell which address a request came from.
+    client_addresses: ClientAddresses,
+    lockdown: Arc<Lockdown>,
+    /// The schedules' time zone, which decides which day it is.
+    zone: Zone,
+    /// What was read from `Secrets.toml`, before stored settings, to check an
+    /// imported configuration against.
+    secrets: SecretStore,
+    /// Shares one query between concurrent `GET /catfact` calls. Keyed on the
+    /// `?tag=` filter, if any, and the `?count=` asked for, so only calls
+    /// wanting the same facts share a result.
+    random_fact: SingleFlight<(Option<String>, u32), Result<Vec<CatFactRecord>, String>>,
+    /// Rendered feeds and the sitemap, until facts change.
+    feeds: FeedCache,
+    routes: Arc<RouteRegistry>,
+    changelog: Arc<Changelog>,
+    ranking: Ranking,
+    privacy: Privacy,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
 pub struct EmailRequest {
     email: String,
+    #[serde(default)]
+    delivery_window: DeliveryWindow,
+    /// `every_day` (the default), `weekdays`, `weekends`, or the days to get
+    /// emails on, as a list or comma-separated.
+    #[serde(default)]
+    #[schema(value_type = Option<String>, example = "mon,wed,fri")]
+    weekdays: Weekdays,
+    /// Which language the emails are in. Defaults to English.
+    #[serde(default)]
+    language: Language,
+    /// The time zone `delivery_window` is in, as an offset from UTC like
+    /// `+05:30`. Defaults to the schedule's (`SCHEDULE_TIMEZONE`).
+    #[serde(default)]
+    #[schema(value_type = Option<String>, example = "+05:30")]
+    timezone: Timezone,
+    /// Where to send the browser after a form submission. Must be on one of
+    /// the `SUBSCRIBE_ALLOWED_ORIGINS`.
+    #[serde(default)]
+    redirect_to: Option<String>,
+    /// The Turnstile widget's token, required on form submissions when
+    /// Turnstile is configured.
+    #[serde(default, rename = "cf-turnstile-response")]
+    turnstile_response: Option<String>,
 }
 
-async fn health_check() -> impl IntoResponse {
-    (StatusCode::OK, "It works!".to_string())
-}
-
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "meta",
+    responses((status = 200, description = "A list of the routes", body = String, content_type = "text/plain"))
+)]
 async fn homepage() -> impl IntoResponse {
     r#"Welcome to the Cat Facts API!
 
 Here are the following routes:
-    - GET /health - Health check route.
+    - GET /health/live - 200 whenever the service is up.
+    - GET /health/ready - 200 when the database (and optionally SMTP) is reachable and migrated, 503 with the status of each otherwise.
+    - GET /badge.svg - Today's cat fact as a badge you can embed in your README.
+    - GET /stats/subscribers.svg - A rounded subscriber count as a badge (or GET /stats/subscribers for JSON)
     - GET /catfact - Get a random cat fact.
-    - POST /catfact/create - Submit your own cat fact
-        - Takes the following JSON parameters: "fact"
+        - Takes an optional "fields" query parameter to trim the response, e.g. ?fields=fact
+        - Takes an optional "tag" query parameter to pick from facts with that tag, e.g. ?tag=behavior
+        - Takes an optional "count" query parameter (up to 50) to get a list of that many different facts, e.g. ?count=5
+        - Send "Accept: text/plain" to get just the fact's text, "Accept: application/xml" for XML, or "Accept: application/x-protobuf" for a protobuf body (see proto/catfact.proto)
+    - GET /catfact/today - The fact of the day: the same for everyone until midnight, and the one in today's email
+        - Takes the same "fields" parameter and "Accept" header as GET /catfact
+    - GET /catfact/:id - Get a specific cat fact by its numeric id, its slug (e.g. /catfact/cats-sleep-for-most-of-the-day-12) or its fact_id, a content hash that is the same in every environment
+        - Takes the same "fields" parameter and "Accept" header as GET /catfact
+    - PUT /catfact/:id - Correct a cat fact's text (admin only), with the JSON parameter "fact" and optionally "license" and "tags" (which replaces its tags)
+    - DELETE /catfact/:id - Remove a cat fact (admin only)
+    - GET /catfacts - List every cat fact, a page at a time
+        - Optionally takes the query parameters "page", "per_page" (up to 100) and "sort": one of "id" (default), "-id", "created_at" or "-created_at"
+        - Takes the same "fields" parameter as GET /catfact
+        - Takes an optional "license" parameter to only list facts you can republish under that license: "cc0", "cc-by", "cc-by-sa" or "all-rights-reserved"
+    - GET /catfacts/search?q=whiskers - Cat facts containing every word you give, best match first, each with a "snippet" where the matches are wrapped in <mark>. Takes an optional "limit" (default 20, up to 100)
+    - GET /docs - These routes in Swagger UI, from the OpenAPI spec at GET /openapi.json
+    - GET /feed.xml - The newest cat facts as RSS (also GET /feed.rss, or GET /feed.atom for Atom and GET /feed.json for JSON Feed), to follow in a feed reader; ?tag=kittens for one tag's facts
+    - GET /changelog - What's changed in this API and when, as JSON (or a page, in a browser). Every response's X-Api-Version header has the current version
+    - GET /tags - Every tag in use, with how many cat facts have it
+    - GET /weekly/:week - The week's most-voted cat facts, e.g. /weekly/2024-W05, published the Monday after
+    - GET /archive - Every cat fact of the day so far, on a calendar of this month (or ?month=2024-02, and ?tag=kittens for one tag's days), linking to each day's page at GET /archive/:date
+    - GET /catfacts/top - The community's favourite cat facts, highest score first, with an optional "limit" (default 10, up to 100)
+    - POST /catfact/:id/vote - Vote a cat fact up or down, with the JSON parameter "vote": "up" or "down". Voting again changes your vote
+    - POST /v1/catfacts - Submit your own cat fact
+        - Takes the following JSON parameters: "fact", and optionally "license" (defaults to "cc-by") and "tags", e.g. ["behavior", "sleep"]. Tag rules (see /admin/tag-rules) can add more.
+    - POST /catfact/bulk - Add many cat facts at once (admin only), inserting every valid row together and reporting how each row went
+        - Takes a JSON array of the same objects as POST /v1/catfacts, up to 1000 of them
+        - Or, with "Content-Type: text/csv", CSV with a header row naming the columns: "fact", and optionally "license" and "tags" (comma-separated)
+    - POST /catfact/create - Deprecated alias of POST /v1/catfacts, to be removed on 2027-04-14
+    - GET /subscribe - A hosted signup page you can link to
     - POST /subscribe - Subscribe to our free daily cat fact email service
         - Takes the following JSON parameters: "email"
+        - Optionally takes "delivery_window": one of "midnight" (default), "morning", "noon" or "evening"
+        - Optionally takes "weekdays": "every_day" (default), "weekdays", "weekends", or the days to get emails on, e.g. ["mon", "wed", "fri"] or "mon,wed,fri"
+    - GET /confirm?token=... - Confirm a subscription, linked from the email sent by POST /subscribe
+    - POST /subscriber/data-request - See or erase everything we store about your email address, with the JSON parameter "email". We'll email that address a link that works for a day
+    - GET /subscriber/data?token=... - Download everything we store about your address, as JSON
+    - DELETE /subscriber?token=... - Erase everything we store about your address, unsubscribing it. The token can also go in an "Authorization: Bearer" header
+    - GET /preferences/:token - Change your delivery time and days, or unsubscribe. Linked from every email.
+    - GET /unsubscribe?token=... - One-click unsubscribe, with the signed token from the link in every email
+    - GET /stay-subscribed?token=... - Keep getting cat facts, from the link in a "Still want cat facts?" email
+    - GET /feedback/:token/:score - Score the fact an email sent from 0 to 10 (the 😿 and 😺 links in every email)
+    - GET /open/:token.gif - Count an email as opened, while the bandit experiment is on
 "#
 }
 
+#[cfg(not(feature = "standalone"))]
 #[shuttle_runtime::main]
 async fn axum(
-    #[shuttle_secrets::Secrets] store: SecretStore,
+    #[shuttle_secrets::Secrets] secrets: SecretStore,
     #[shuttle_turso::Turso(addr = "{secrets.TURSO_ADDR}", token = "{secrets.TURSO_TOKEN}")]
-    db: Client,
+    db: libsql_client::Client,
 ) -> Result<CustomService, shuttle_runtime::Error> {
-    let gmail_user = store
-        .get("GMAIL_USER")
-        .unwrap_or_else(|| "None".to_string());
-    let gmail_password = store
-        .get("GMAIL_PASSWORD")
-        .unwrap_or_else(|| "None".to_string());
-
-    db.batch([
-        "CREATE TABLE IF NOT EXISTS catfacts (
-        id integer primary key autoincrement,
-        fact text not null,
-        created_at datetime default current_timestamp 
-        )",
-        "CREATE TABLE IF NOT EXISTS subscribers (
-                    id integer primary key autoincrement,
-                    email text not null,
-        created_at datetime default current_timestamp 
-                )",
-    ])
-    .await
-    .unwrap();
-
-    let db = Arc::new(Mutex::new(db));
-
-    let state = Arc::new(AppState { db: db.clone() });
+    let db: Arc<dyn Store> = match secrets.get("DATABASE_URL") {
+        Some(url) => Arc::new(store::SqliteStore::open(&url)?),
+        None => Arc::new(store::LibsqlStore::new(db)),
+    };
+
+    Ok(service(db, secrets).await?)
+}
+
+#[cfg(feature = "standalone")]
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    standalone::run().await
+}
+
+/// Everything but where the database and secrets come from, which is the same
+/// with or without Shuttle.
+async fn service(db: Arc<dyn Store>, secrets: SecretStore) -> Result<CustomService, anyhow::Error> {
+    schema::migrate(&*db).await?;
+    schema::verify(&*db).await?;
+
+    let store = config::load(&*db, &secrets).await?;
+    let lockdown = Arc::new(Lockdown::load(db.clone()).await?);
+
+    let sender = mailer::sender(&store);
+    let mailer = Mail::new(mailer::from_secrets(&store)?, lockdown.clone());
+    let send_limits = SendLimits::from_secrets(&store, &mailer::provider(&store));
+    let scheduler = Scheduler::from_secrets(&store)?;
+    let retention = Retention::from_secrets(&store)?;
+    let privacy = Privacy::from_secrets(&store)?;
+    let cache_max_age = store
+        .get("CACHE_MAX_AGE")
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(cache::DEFAULT_MAX_AGE_SECS);
+    let public_url = store
+        .get("PUBLIC_URL")
+        .unwrap_or_else(|| "https://turso-cat-facts.shuttleapp.rs".to_string());
+
+    let mqtt = MqttConfig::from_secrets(&store).map(FactPublisher::connect);
+    let ranking = Ranking::from_secrets(&store)?;
+    let unsubscribe = UnsubscribeSigner::from_secrets(&store);
+    let reengage = reengage::Policy::from_secrets(&store, unsubscribe.clone())?;
+    let composer = Composer {
+        public_url: public_url.clone(),
+        unsubscribe: unsubscribe.clone(),
+        templates: Arc::new(Templates::from_secrets(&store)?),
+    };
+    for warning in templates::lint_secrets(&store) {
+        tracing::warn!("{warning}");
+    }
+
+    let routes = Arc::new(RouteRegistry::new());
+    for route in openapi::undocumented(&routes) {
+        tracing::warn!("{route} isn't in the OpenAPI spec; annotate its handler");
+    }
+    let changelog = Arc::new(Changelog::embedded()?);
+    let email_metrics = Arc::new(EmailMetrics::default());
+    let shutdown = Shutdown::default();
+    // Shared by the scheduler and `POST /admin/send-digest`, so a manual send
+    // waits for a scheduled one (and vice versa) and both count towards the
+    // same rate limits.
+    let dispatcher = Arc::new(Mutex::new(
+        Dispatcher::new(
+            mailer.clone(),
+            sender.clone(),
+            db.clone(),
+            mqtt.clone(),
+            composer.clone(),
+            email_metrics.clone(),
+            send_limits,
+        )
+        .with_ranking(ranking.clone())
+        .with_privacy(privacy.clone())
+        .with_shutdown(shutdown.clone())
+        .with_zone(scheduler.zone()),
+    ));
+    let allowed_origins = AllowedOrigins::from_secrets(&store);
+    let cors = allowed_origins.cors_layer();
+
+    let client_addresses = ClientAddresses::from_secrets(&store);
+    let state = Arc::new(AppState {
+        db: db.clone(),
+        mailer: mailer.clone(),
+        sender: sender.clone(),
+        public_url: public_url.clone(),
+        mqtt: mqtt.clone(),
+        complaint_webhook_secret: store.get("COMPLAINT_WEBHOOK_SECRET"),
+        admin_token: store.get("ADMIN_TOKEN"),
+        allowed_origins,
+        turnstile: Turnstile::from_secrets(&store),
+        mx_check: MxCheck::from_secrets(&store),
+        subscriber_cap: waitlist::cap_from_secrets(&store),
+        health_check_smtp: store.get("HEALTH_CHECK_SMTP").as_deref() == Some("true"),
+        sync_source: SyncSource::from_secrets(&store),
+        unsubscribe,
+        composer,
+        dispatcher: dispatcher.clone(),
+        email_metrics: email_metrics.clone(),
+        spam: SpamScorer::from_secrets(&store)?,
+        client_addresses,
+        lockdown: lockdown.clone(),
+        zone: scheduler.zone(),
+        ranking: ranking.clone(),
+        privacy: privacy.clone(),
+        secrets,
+        random_fact: SingleFlight::new(),
+        feeds: FeedCache::default(),
+        routes: routes.clone(),
+        changelog: changelog.clone(),
+    });
+
+    let long_lived =
+        map_response_with_state(CachePolicy::long_lived(cache_max_age), apply_cache_policy);
+    let until_midnight = map_response_with_state(
+        CachePolicy::UntilMidnight {
+            max_age: cache_max_age,
+            zone: scheduler.zone(),
+        },
+        apply_cache_policy,
+    );
+    let no_store = map_response_with_state(CachePolicy::NoStore, apply_cache_policy);
+    let rate_limits = RateLimits::from_secrets(&store, client_addresses)?;
+    let key_tiers = Arc::new(KeyTiers::new(db.clone()));
+    let submit_limit =
+        from_fn_with_state((rate_limits.submit, key_tiers.clone()), rate_limit::limit);
+    let data_request_limit = from_fn_with_state(
+        (rate_limits.subscribe.clone(), key_tiers.clone()),
+        rate_limit::limit,
+    );
+    let subscribe_limit = from_fn_with_state((rate_limits.subscribe, key_tiers), rate_limit::limit);
+    let locked = from_fn_with_state(lockdown, lockdown::guard);
+
+    // Everything under /admin needs an API key or the ADMIN_TOKEN.
+    let admin = Router::new()
+        .route(
+            "/admin/analytics/subscribers",
+            get(analytics::subscriber_analytics),
+        )
+        .route("/admin/analytics/domains", get(analytics::domain_analytics))
+        .route("/admin/sync/facts", get(sync::list_facts))
+        .route("/admin/sync/pull", post(sync::pull))
+        .route("/admin/send-daily", post(send_daily::send_daily))
+        .route("/admin/send-digest", post(send_daily::send_digest))
+        .route("/admin/waitlist", get(waitlist::waitlist_status))
+        .route("/admin/waitlist/release", post(waitlist::release))
+        .route("/admin/catfacts/flagged", get(spam::flagged_facts))
+        .route("/admin/catfacts/:id/approve", post(spam::approve_fact))
+        .route(
+            "/admin/suppressions",
+            post(suppressions::create_suppression),
+        )
+        .route(
+            "/admin/suppressions/:email",
+            get(suppressions::get_suppression).delete(suppressions::delete_suppression),
+        )
+        .route(
+            "/admin/api-keys",
+            get(auth::list_api_keys).post(auth::create_api_key),
+        )
+        .route("/admin/api-keys/:id", delete(auth::revoke_api_key))
+        .route("/admin/config/export", get(config::export_config))
+        .route("/admin/export/facts", get(export::export_facts))
+        .route("/admin/config/import", post(config::import_config))
+        .route("/admin/templates/lint", post(templates::lint_templates))
+        .route(
+            "/admin/lockdown",
+            get(lockdown::lockdown_status).post(lockdown::start),
+        )
+        .route("/admin/lockdown/clear", post(lockdown::clear))
+        .route("/admin/audit-log", get(audit::audit_log))
+        .route("/admin/rollups", get(retention::list_rollups))
+        .route("/admin/outbox", get(outbox::list_outbox))
+        .route("/admin/queue", get(outbox::list_outbox))
+        .route("/admin/dead-letters", get(outbox::list_dead_letters))
+        .route(
+            "/admin/sends/:date/report.csv",
+            get(send_report::send_report),
+        )
+        .route("/admin/queue/retry", post(outbox::retry_all))
+        .route("/admin/queue/:id/retry", post(outbox::retry_email))
+        .route("/admin/migrations", get(migrations::migration_status))
+        .route(
+            "/admin/migrations/post-deploy",
+            post(migrations::apply_post_deploy),
+        )
+        .route("/admin/feedback", get(feedback::feedback_report))
+        .route(
+            "/admin/tag-rules",
+            get(tag_rules::list_rules).post(tag_rules::create_rule),
+        )
+        .route("/admin/tag-rules/:id", delete(tag_rules::delete_rule))
+        .route("/admin/tags", get(tags::list_all_tags))
+        .route("/admin/tags/:id/rename", post(tags::rename_tag))
+        .route("/admin/tags/merge", post(tags::merge_tags))
+        .route(
+            "/admin/tags/review",
+            get(tag_rules::list_pending).post(tag_rules::review),
+        )
+        .route("/admin/ranking/experiment", get(bandit::experiment_report))
+        .route(
+            "/admin/calendar/:date",
+            get(calendar::get_entry)
+                .put(calendar::pin_fact)
+                .delete(calendar::unpin_fact),
+        )
+        .route_layer(no_store.clone())
+        .route_layer(from_fn_with_state(state.clone(), auth::require_admin));
 
     let router = Router::new()
-        .route("/", get(homepage))
-        .route("/health", get(health_check))
-        .route("/catfact", get(get_record))
-        .route("/catfact/create", post(create_record))
-        .route("/subscribe", post(subscribe))
+        .route("/", get(homepage).layer(long_lived.clone()))
+        .route(
+            "/health",
+            get(health::liveness_check).layer(no_store.clone()),
+        )
+        .route(
+            "/health/live",
+            get(health::liveness_check).layer(no_store.clone()),
+        )
+        .route(
+            "/health/ready",
+            get(health::readiness_check).layer(no_store.clone()),
+        )
+        .route("/metrics", get(metrics::metrics).layer(no_store.clone()))
+        .route(
+            "/badge.svg",
+            get(badge::fact_badge).layer(until_midnight.clone()),
+        )
+        // The subscriber count only refreshes once a day, which keeps
+        // individual signups from showing up in it.
+        .route(
+            "/stats/subscribers",
+            get(stats::subscribers_json).layer(until_midnight.clone()),
+        )
+        .route(
+            "/stats/subscribers.svg",
+            get(stats::subscribers_badge).layer(until_midnight.clone()),
+        )
+        .route("/catfact", get(get_record).layer(no_store.clone()))
+        .route(
+            "/catfact/bulk",
+            post(bulk::import_facts).layer(no_store.clone()),
+        )
+        .route(
+            "/catfact/from-url",
+            post(ingest::suggest_facts).layer(no_store.clone()),
+        )
+        .route(
+            "/archive",
+            get(archive::archive_page).layer(until_midnight.clone()),
+        )
+        .route(
+            "/catfact/today",
+            get(get_today).layer(until_midnight.clone()),
+        )
+        .route("/catfacts", get(list::list_facts).layer(no_store.clone()))
+        .route(
+            "/changelog",
+            get(changelog::get_changelog).layer(long_lived.clone()),
+        )
+        .route(
+            "/openapi.json",
+            get(openapi::openapi_json).layer(long_lived.clone()),
+        )
+        .route("/docs", get(openapi::docs).layer(long_lived.clone()))
+        .route("/feed.xml", get(feed::rss).layer(long_lived.clone()))
+        .route("/feed.rss", get(feed::rss).layer(long_lived.clone()))
+        .route("/feed.atom", get(feed::atom).layer(long_lived.clone()))
+        .route("/feed.json", get(feed::json_feed).layer(long_lived.clone()))
+        .route("/sitemap.xml", get(sitemap::sitemap).layer(until_midnight))
+        .route("/tags", get(tags::list_tags).layer(no_store.clone()))
+        .route(
+            "/catfacts/search",
+            get(search::search_facts).layer(no_store.clone()),
+        )
+        .route(
+            "/weekly/:week",
+            get(weekly::weekly_page).layer(long_lived.clone()),
+        )
+        .route(
+            "/archive/:date",
+            get(archive::day_page).layer(long_lived.clone()),
+        )
+        .route(
+            "/catfacts/as-of",
+            get(history::facts_as_of).layer(no_store.clone()),
+        )
+        .route(
+            "/catfacts/top",
+            get(votes::top_facts).layer(no_store.clone()),
+        )
+        .route(
+            "/catfact/:key/vote",
+            post(votes::vote).layer(locked.clone()),
+        )
+        .route(
+            "/open/:token",
+            get(opens::record_open)
+                .layer(no_store.clone())
+                .layer(locked.clone()),
+        )
+        .route(
+            "/feedback/:token/:score",
+            get(feedback::record_feedback)
+                .layer(no_store.clone())
+                .layer(locked.clone()),
+        )
+        .route(
+            "/catfact/create",
+            post(create_record)
+                .layer(submit_limit.clone())
+                .layer(locked.clone()),
+        )
+        .route(
+            "/v1/catfacts",
+            post(create_record)
+                .layer(submit_limit)
+                .layer(locked.clone()),
+        )
+        .route(
+            "/catfact/:key",
+            get(get_record_by_key).layer(long_lived).merge(
+                put(update_record)
+                    .delete(delete_record)
+                    .layer(no_store.clone()),
+            ),
+        )
+        .route(
+            "/subscribe",
+            get(signup::subscribe_page)
+                .layer(no_store.clone())
+                .post(subscribe.layer(subscribe_limit).layer(locked))
+                .layer(cors),
+        )
+        .route(
+            "/preferences/:token",
+            get(preferences::preferences_page)
+                .post(preferences::update_preferences)
+                .layer(no_store.clone()),
+        )
+        .route("/confirm", get(confirm::confirm).layer(no_store.clone()))
+        .route(
+            "/stay-subscribed",
+            get(reengage::stay_subscribed).layer(no_store.clone()),
+        )
+        .route(
+            "/subscriber/data-request",
+            post(data_requests::request_data)
+                .layer(data_request_limit)
+                .layer(no_store.clone()),
+        )
+        .route(
+            "/subscriber/data",
+            get(data_requests::export_data).layer(no_store.clone()),
+        )
+        .route(
+            "/subscriber/erase",
+            get(data_requests::erase_page)
+                .post(data_requests::erase_form)
+                .layer(no_store.clone()),
+        )
+        .route(
+            "/subscriber",
+            delete(data_requests::erase_data).layer(no_store.clone()),
+        )
+        .route(
+            "/unsubscribe",
+            get(unsubscribe::unsubscribe)
+                .post(unsubscribe::one_click_unsubscribe)
+                .layer(no_store.clone()),
+        )
+        .route(
+            "/preferences/:token/unsubscribe",
+            post(preferences::unsubscribe),
+        )
+        .route("/webhooks/complaints", post(complaints::receive_complaint))
+        .merge(admin)
+        .fallback(routes::not_found)
+        .layer(from_fn_with_state(routes, routes::track_deprecations))
+        .layer(map_response_with_state(
+            changelog,
+            changelog::add_version_header,
+        ))
+        .layer(from_fn_with_state(privacy, request_id::tag))
         .with_state(state);
 
     Ok(CustomService {
         db,
-        gmail_user,
-        gmail_password,
+        dispatcher,
+        email_metrics,
+        scheduler,
+        jobs: Jobs {
+            weekly: WeeklyDigest {
+                public_url: public_url.clone(),
+                mqtt,
+            },
+            ranking,
+            retention,
+            confirmations: Confirmations {
+                mailer: mailer.clone(),
+                sender: sender.clone(),
+                public_url: public_url.clone(),
+            },
+            reengagement: Reengagement {
+                mailer,
+                sender,
+                public_url,
+                policy: reengage,
+            },
+        },
         router,
+        shutdown,
     })
 }
 
 #[shuttle_runtime::async_trait]
 impl shuttle_runtime::Service for CustomService {
     async fn bind(mut self, addr: std::net::SocketAddr) -> Result<(), shuttle_runtime::Error> {
-        let router = axum::Server::bind(&addr).serve(self.router.into_make_service());
+        let shutdown = self.shutdown.clone();
+        let router = axum::Server::bind(&addr)
+            .serve(
+                self.router
+                    .into_make_service_with_connect_info::<std::net::SocketAddr>(),
+            )
+            .with_graceful_shutdown(shutdown.requested());
+        tokio::spawn(backfill::run(self.db.clone()));
 
+        let router = async {
+            let served = router.await;
+            // If the server stops by itself, so does everything else.
+            shutdown.request();
+            served
+        };
+        let jobs = self.scheduler.run(
+            self.dispatcher.clone(),
+            self.db,
+            self.jobs,
+            shutdown.clone(),
+        );
         tokio::select!(
-            _ = router => {},
-            _ = scheduled_tasks(self.db, self.gmail_user, self.gmail_password) => {}
+            _ = async { tokio::join!(router, jobs) } => {},
+            _ = email_metrics::watch(self.email_metrics) => {}
         );
 
+        // Nothing's sending now, so what's left can be handed over.
+        match self.dispatcher.lock().await.save_queue().await {
+            Ok(0) => {}
+            Ok(left) => {
+                tracing::info!("Saved the email queue, {left} recipients, for the next instance")
+            }
+            Err(e) => tracing::error!("Couldn't save the email queue: {e}"),
+        }
+
         Ok(())
     }
 }
 
+/// A random fact, or with `count`, a list of that many different ones.
+#[utoipa::path(
+    get,
+    path = "/catfact",
+    tag = "facts",
+    params(RandomQuery, FieldsQuery),
+    responses(
+        (status = 200, description = "A fact, or a list of them with `count`. `Accept` can ask for `text/plain` (just the text, a fact per line), `application/xml` or `application/x-protobuf` instead", body = CatFactRecord),
+        (status = 404, description = "No facts (with the tag) yet", body = ErrorBody),
+        (status = 422, description = "`count` is out of range", body = ErrorBody),
+    )
+)]
 pub async fn get_record(
     State(state): State<Arc<AppState>>,
-) -> Result<impl IntoResponse, impl IntoResponse> {
-    let res = match state
-        .db
-        .lock()
-        .await
-        .execute("SELECT fact FROM catfacts order by random() limit 1")
+    Query(fields): Query<FieldsQuery>,
+    Query(query): Query<RandomQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let tag = query.tag.as_deref().map(tags::normalize_one).transpose()?;
+    if let Some(count) = query.count {
+        if !(1..=MAX_RANDOM_COUNT).contains(&count) {
+            return Err(ApiError::Validation(format!(
+                "count should be between 1 and {MAX_RANDOM_COUNT}, not {count}"
+            )));
+        }
+    }
+    let count = query.count.unwrap_or(1);
+
+    // Under a burst, every caller that arrives while a query is running gets
+    // that query's fact rather than queueing up for the database.
+    let random = state
+        .random_fact
+        .run((tag.clone(), count), || async {
+            random_facts(&state, tag.as_deref(), count)
+                .await
+                .map_err(|e| e.to_string())
+        })
+        .await;
+
+    let mut facts = random.map_err(|e| ApiError::Database(anyhow::anyhow!(e)))?;
+    if facts.is_empty() {
+        return Err(ApiError::NotFound(match tag {
+            Some(tag) => format!("No cat facts tagged {tag:?} yet!"),
+            None => "No cat facts yet!".to_string(),
+        }));
+    }
+
+    if query.count.is_none() {
+        return respond_with_record(facts.swap_remove(0), &fields, &headers);
+    }
+
+    // Fewer than `count` if there aren't that many facts.
+    let format = FactFormat::from_headers(&headers);
+    if format == FactFormat::Protobuf {
+        let facts = facts.into_iter().map(proto::CatFact::from).collect();
+        return Ok(negotiate::vary(
+            Protobuf(proto::CatFactList { facts }).into_response(),
+        ));
+    }
+
+    let facts = facts
+        .iter()
+        .map(|fact| fields::shape(fact, &fields))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(ApiError::internal)?;
+    Ok(negotiate::vary(match format {
+        FactFormat::Text => negotiate::text(&facts),
+        FactFormat::Xml => negotiate::xml_list(&facts),
+        FactFormat::Json | FactFormat::Protobuf => Json(facts).into_response(),
+    }))
+}
+
+/// Up to `count` random facts, optionally only ones tagged `tag`, chosen by
+/// the ranker if there is one.
+async fn random_facts(
+    state: &AppState,
+    tag: Option<&str>,
+    count: u32,
+) -> Result<Vec<CatFactRecord>, anyhow::Error> {
+    let filter = store::in_circulation();
+    let tag_arg = tag.map_or(Value::Null, Value::from);
+
+    let selection = Selection::Random { tag };
+    if let Some(ids) = state
+        .ranking
+        .pick(
+            &*state.db,
+            selection,
+            &filter,
+            std::slice::from_ref(&tag_arg),
+            count,
+        )
         .await
     {
-        Ok(res) => res,
-        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
-    };
+        let mut facts = state.db.facts_by_id(&ids).await?;
+        facts.sort_by_key(|fact| ids.iter().position(|id| *id == fact.id));
 
-    let res = CatFact {
-        fact: res.rows[0].values[0].to_string(),
-    };
+        return Ok(facts);
+    }
 
-    Ok((StatusCode::OK, Json(res)))
+    state.db.random_facts(tag, count).await
 }
 
-pub async fn create_record(
+/// `GET /catfact/today` - the fact of the day: the same for everyone all day,
+/// and the one that day's emails send.
+#[utoipa::path(
+    get,
+    path = "/catfact/today",
+    tag = "facts",
+    params(FieldsQuery),
+    responses(
+        (status = 200, description = "Today's fact", body = CatFactRecord),
+        (status = 404, description = "No facts yet", body = ErrorBody),
+    )
+)]
+pub async fn get_today(
     State(state): State<Arc<AppState>>,
-    Json(json): Json<CatFact>,
-) -> Result<impl IntoResponse, impl IntoResponse> {
-    match state
+    Query(fields): Query<FieldsQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let today = state.zone.today();
+    daily::materialize(&*state.db, &state.ranking, today).await?;
+
+    let res = state
         .db
-        .lock()
-        .await
-        .execute(Statement::with_args(
-            "INSERT into CATFACTS (fact) VALUES (?)",
-            &[json.fact],
-        ))
-        .await
-    {
-        Ok(_) => Ok((StatusCode::CREATED, "Fact created!".to_string())),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
-    }
+        .fact_of_day(today)
+        .await?
+        .ok_or_else(|| ApiError::NotFound("No cat facts yet!".to_string()))?;
+
+    respond_with_record(res, &fields, &headers)
 }
 
-pub async fn subscribe(
+/// `GET /catfact/:key` - looks a fact up by numeric id, by fact id, or by slug otherwise.
+#[utoipa::path(
+    get,
+    path = "/catfact/{key}",
+    tag = "facts",
+    params(("key" = String, Path, description = "A numeric id, a fact id or a slug"), FieldsQuery),
+    responses(
+        (status = 200, description = "The fact", body = CatFactRecord),
+        (status = 404, description = "No such fact", body = ErrorBody),
+    )
+)]
+pub async fn get_record_by_key(
     State(state): State<Arc<AppState>>,
-    Json(req): Json<EmailRequest>,
-) -> Result<impl IntoResponse, impl IntoResponse> {
-    if let Err(e) = state
+    Path(key): Path<String>,
+    Query(fields): Query<FieldsQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let res = state
         .db
-        .lock()
-        .await
-        .execute(Statement::with_args(
-            "INSERT INTO subscribers (email) values (?)",
-            &[req.email],
-        ))
-        .await
-    {
-        return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
-    };
+        .fact_by_key(&key)
+        .await?
+        .ok_or_else(|| no_such_fact(&key))?;
 
-    Ok((StatusCode::CREATED, "You're now subscribed!".to_string()))
+    respond_with_record(res, &fields, &headers)
 }
 
-#[allow(unreachable_code)]
-pub async fn scheduled_tasks(
-    db: Arc<Mutex<Client>>,
-    gmail_user: String,
-    gmail_password: String,
-) -> Result<(), anyhow::Error> {
-    let creds = Credentials::new(gmail_user.to_owned(), gmail_password.to_owned());
-
-    // Open a remote connection to gmail
-    let mailer: AsyncSmtpTransport<Tokio1Executor> =
-        AsyncSmtpTransport::<Tokio1Executor>::relay("smtp.gmail.com")
-            .unwrap()
-            .credentials(creds)
-            .build();
-
-    let mut tomorrow_midnight = Local::now()
-        .checked_add_days(Days::new(1))
-        .unwrap()
-        .date_naive()
-        .and_hms_opt(0, 0, 0)
-        .unwrap();
-
-    loop {
-        let duration = calculate_time_diff(tomorrow_midnight);
-
-        if duration == std::time::Duration::ZERO {
-            send_subscriber_mail(mailer.to_owned(), db.clone())
-                .await
-                .expect("Looks like something went wrong trying to send subscriber mail :(");
-
-            tomorrow_midnight = Local::now()
-                .checked_add_days(Days::new(1))
-                .unwrap()
-                .date_naive()
-                .and_hms_opt(0, 0, 0)
-                .unwrap();
+fn respond_with_record(
+    res: CatFactRecord,
+    fields: &FieldsQuery,
+    headers: &HeaderMap,
+) -> Result<Response, ApiError> {
+    let format = FactFormat::from_headers(headers);
+    if format == FactFormat::Protobuf {
+        return Ok(negotiate::vary(
+            Protobuf(proto::CatFact::from(res)).into_response(),
+        ));
+    }
+
+    let res = fields::shape(&res, fields).map_err(ApiError::internal)?;
+    Ok(negotiate::vary(match format {
+        FactFormat::Text => negotiate::text(std::slice::from_ref(&res)),
+        FactFormat::Xml => negotiate::xml(&res),
+        FactFormat::Json | FactFormat::Protobuf => (StatusCode::OK, Json(res)).into_response(),
+    }))
+}
+
+/// Submits a fact. Ones that look like spam are rejected, and borderline
+/// ones are held for review.
+#[utoipa::path(
+    post,
+    path = "/v1/catfacts",
+    tag = "facts",
+    request_body = CatFact,
+    responses(
+        (status = 201, description = "The fact was added", body = String),
+        (status = 202, description = "The fact will show up once it's been reviewed", body = String),
+        (status = 422, description = "The fact isn't valid or looks like spam", body = ErrorBody),
+        (status = 429, description = "Too many submissions, or too many waiting for review; see `Retry-After`", body = ErrorBody),
+    )
+)]
+pub async fn create_record(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    client: ClientIp,
+    StrictJson(json): StrictJson<CatFact>,
+) -> Result<impl IntoResponse, ApiError> {
+    let db = &*state.db;
+
+    let submitter = spam::submitter(&state, &headers, client.0).await?;
+    if db.pending_submissions(&submitter).await? >= state.spam.pending_cap {
+        tracing::info!("Turned away a submission from {submitter}, at its pending cap");
+        return Err(ApiError::RateLimited(spam::PENDING_RETRY_AFTER));
+    }
+
+    let recent_submissions = db.recent_submissions().await?;
+    let spam = state.spam.score(&Submission {
+        text: &json.fact,
+        recent_submissions,
+    });
+
+    if spam.verdict == Verdict::Reject {
+        tracing::info!("Rejected a submission with spam score {:.1}", spam.score);
+        return Err(ApiError::Validation(
+            "This fact looks like spam, so it wasn't saved".to_string(),
+        ));
+    }
+    let flagged = spam.verdict == Verdict::Flag;
+    let tag_names = tags::normalize(json.tags.as_deref().unwrap_or_default())?;
+    let rules = tag_rules::Rules::load(db).await?;
+    let applied = rules.apply(&json.fact, &tag_names);
+
+    let id = db
+        .insert_fact(
+            &json.fact,
+            json.license.unwrap_or_default(),
+            spam.score,
+            flagged,
+            &submitter,
+        )
+        .await?;
+
+    // If this fails the fact keeps working by id and gets a slug on the next boot.
+    if let Some(id) = id {
+        if let Err(e) = slug::set_slug(db, id, &json.fact).await {
+            tracing::warn!("{e}");
+        }
+        if !applied.is_empty() {
+            db.batch(tags::tag_fact(id, &applied)).await?;
         }
-        let duration = calculate_time_diff(tomorrow_midnight);
+    }
+
+    if flagged {
+        tracing::info!(
+            "Flagged fact {id:?} for review, spam score {:.1}",
+            spam.score
+        );
+        return Ok((
+            StatusCode::ACCEPTED,
+            "Thanks! Your fact will show up once it's been reviewed.".to_string(),
+        ));
+    }
 
-        sleep(TokioDuration::from_secs(duration.as_secs())).await;
+    if let Some(mqtt) = &state.mqtt {
+        mqtt.publish_new_fact(&json.fact).await;
     }
 
-    Ok(())
+    Ok((StatusCode::CREATED, "Fact created!".to_string()))
 }
 
-fn calculate_time_diff(midnight: NaiveDateTime) -> Duration {
-    let now = Local::now().naive_local();
+/// `PUT /catfact/:id` - corrects a fact's text. The slug is kept so existing
+/// links keep working; the fact id follows the new text.
+#[utoipa::path(
+    put,
+    path = "/catfact/{key}",
+    tag = "facts",
+    params(("key" = i64, Path, description = "The fact's numeric id")),
+    request_body = CatFact,
+    responses(
+        (status = 200, description = "The corrected fact", body = CatFactRecord),
+        (status = 404, description = "No such fact", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+pub async fn update_record(
+    State(state): State<Arc<AppState>>,
+    Path(key): Path<String>,
+    _: Admin,
+    StrictJson(json): StrictJson<CatFact>,
+) -> Result<Response, ApiError> {
+    let Ok(id) = key.parse::<i64>() else {
+        return Err(no_such_fact(&key));
+    };
+    let tag_names = json.tags.as_deref().map(tags::normalize).transpose()?;
 
-    midnight.signed_duration_since(now).to_std().unwrap()
-}
+    let Some(record) = state.db.update_fact(id, &json.fact, json.license).await? else {
+        return Err(no_such_fact(&key));
+    };
 
-async fn send_subscriber_mail(
-    mailer: AsyncSmtpTransport<Tokio1Executor>,
-    db: Arc<Mutex<Client>>,
-) -> Result<(), anyhow::Error> {
-    let db = db.lock().await;
+    if let Some(tag_names) = tag_names {
+        state.db.batch(tags::retag_fact(id, &tag_names)).await?;
+    }
+
+    Ok(Json(record).into_response())
+}
 
-    let rows = match db.execute("SELECT email FROM subscribers").await {
-        Ok(res) => res.rows,
-        Err(e) => return Err(anyhow!("Had an error while sending emails: {e}")),
+/// `DELETE /catfact/:id` - removes a fact. If it was picked as the fact of the
+/// day for today or later, those days get a new pick.
+#[utoipa::path(
+    delete,
+    path = "/catfact/{key}",
+    tag = "facts",
+    params(("key" = i64, Path, description = "The fact's numeric id")),
+    responses(
+        (status = 204, description = "The fact was removed"),
+        (status = 404, description = "No such fact", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+pub async fn delete_record(
+    State(state): State<Arc<AppState>>,
+    Path(key): Path<String>,
+    _: Admin,
+) -> Result<StatusCode, ApiError> {
+    let Ok(id) = key.parse::<i64>() else {
+        return Err(no_such_fact(&key));
     };
 
-    if !rows.is_empty() {
-        let cat_fact = match db
-            .execute("SELECT fact FROM catfacts order by random() limit 1")
-            .await
-        {
-            Ok(res) => res.rows[0].values[0].to_string(),
-            Err(e) => return Err(anyhow!("error when trying to get a cat fact: {e}")),
-        };
+    if state.db.delete_fact(id, state.zone.today()).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(no_such_fact(&key))
+    }
+}
 
-        for row in rows {
-            let email = Message::builder()
+fn no_such_fact(key: &str) -> ApiError {
+    ApiError::NotFound(format!("There's no cat fact {key:?}"))
+}
 
-                    .from("Cat Facts".parse().unwrap())
-                    .to(row.values[0].to_string().parse().unwrap())
-                    .subject("Happy new year")
-                    .header(ContentType::TEXT_PLAIN)
-                    .body(format!("Hey there! You're receiving this message because you're subscribed to Cat Facts. \n\nDid you know {cat_fact}?"))
-                    .unwrap();
+fn invalid_address(problem: String) -> ApiError {
+    ApiError::Validation(format!("Please check your email address: {problem}"))
+}
 
-            if let Err(e) = mailer.send(email).await {
-                println!("Something went wrong while sending mail: {e}")
+/// Subscribes an address to the daily email, once it's followed the link in
+/// the confirmation email this sends. Takes JSON or a form.
+#[utoipa::path(
+    post,
+    path = "/subscribe",
+    tag = "subscriptions",
+    request_body(content = EmailRequest, content_type = "application/json"),
+    responses(
+        (status = 201, description = "A confirmation email is on its way", body = String),
+        (status = 202, description = "The list is full, so the address is on the waitlist", body = String),
+        (status = 303, description = "Off to `redirect_to`"),
+        (status = 409, description = "The address is already subscribed", body = ErrorBody),
+        (status = 422, description = "The address isn't valid", body = ErrorBody),
+    )
+)]
+pub async fn subscribe(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    JsonOrForm(req): JsonOrForm<EmailRequest>,
+) -> Result<Response, ApiError> {
+    // Forms are what bots fill in, so that's where the CAPTCHA applies; API
+    // clients posting JSON aren't affected.
+    if let Some(turnstile) = &state.turnstile {
+        if strict::is_form(&headers) {
+            let token = req.turnstile_response.as_deref().unwrap_or_default();
+            match turnstile.verify(token).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    return Err(ApiError::Forbidden(
+                        "The CAPTCHA check failed, please try again".to_string(),
+                    ))
+                }
+                Err(e) => return Err(ApiError::Upstream(e)),
             }
         }
     }
 
-    Ok(())
+    if let Some(redirect_to) = &req.redirect_to {
+        if !state.allowed_origins.allows_url(redirect_to) {
+            return Err(ApiError::BadRequest(
+                "redirect_to isn't on an allowed origin".to_string(),
+            ));
+        }
+    }
+
+    // Addresses are stored trimmed and lowercased, so `Cat@Example.com` and
+    // `cat@example.com` are the same subscriber.
+    let email = req.email.trim().to_lowercase();
+    address::check_syntax(&email).map_err(invalid_address)?;
+    if let Some(mx_check) = &state.mx_check {
+        match mx_check.check(&email).await {
+            Ok(result) => result.map_err(invalid_address)?,
+            // A resolver outage shouldn't stop anyone subscribing.
+            Err(e) => tracing::warn!("Skipping the MX check for a signup: {e}"),
+        }
+    }
+
+    let db = &*state.db;
+
+    if let Some(cap) = state.subscriber_cap {
+        if waitlist::is_full(db, cap, &email).await? {
+            waitlist::join(
+                &state,
+                &email,
+                req.delivery_window.hour(),
+                req.weekdays,
+                req.language,
+                req.timezone,
+            )
+            .await?;
+
+            return match req.redirect_to {
+                Some(redirect_to) => Ok(Redirect::to(&redirect_to).into_response()),
+                None => Ok((
+                    StatusCode::ACCEPTED,
+                    "We're full right now, so you're on the waitlist. We'll email you when there's room!".to_string(),
+                )
+                    .into_response()),
+            };
+        }
+    }
+
+    // New subscribers stay unconfirmed, and don't get the daily email, until
+    // they follow the link in the confirmation email. Signing up again before
+    // confirming sends a fresh link; signing up again after is a conflict.
+    let Some(confirmation_token) = db
+        .sign_up(
+            &email,
+            req.delivery_window,
+            req.weekdays,
+            req.language,
+            req.timezone,
+        )
+        .await?
+    else {
+        return Err(ApiError::Conflict(
+            "This address is already subscribed. You can change how you get your facts from the link in any of our emails.".to_string(),
+        ));
+    };
+
+    confirm::send_confirmation(&state, &email, &confirmation_token)
+        .await
+        .map_err(|e| {
+            ApiError::Mail(e.context(format!("couldn't send a confirmation email to {email:?}")))
+        })?;
+
+    match req.redirect_to {
+        Some(redirect_to) => Ok(Redirect::to(&redirect_to).into_response()),
+        None => Ok((
+            StatusCode::CREATED,
+            "Almost there! Check your inbox to confirm your subscription.".to_string(),
+        )
+            .into_response()),
+    }
 }
@@ -0,0 +1,32 @@
+use axum::{extract::State, http::header, response::IntoResponse};
+use std::fmt::Write;
+use std::sync::Arc;
+
+use crate::AppState;
+
+/// `GET /metrics` - counters and gauges in the Prometheus text format.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "health",
+    responses((status = 200, description = "Prometheus metrics", body = String, content_type = "text/plain"))
+)]
+pub async fn metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let mut body = String::new();
+
+    body.push_str(
+        "# HELP deprecated_route_requests_total Requests to deprecated routes since startup.\n",
+    );
+    body.push_str("# TYPE deprecated_route_requests_total counter\n");
+    for (route, hits) in state.routes.deprecated_usage() {
+        let _ = writeln!(
+            body,
+            "deprecated_route_requests_total{{method=\"{}\",route=\"{}\"}} {hits}",
+            route.method, route.path
+        );
+    }
+
+    state.email_metrics.render(&mut body);
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
@@ -0,0 +1,70 @@
+//! The admin side of the schema's post-deploy step (see `schema`). Post-deploy
+//! migrations only run when asked for with `POST /admin/migrations/post-deploy`,
+//! once whoever is deploying knows the previous version has stopped serving -
+//! the new code being up, or its backfills being done, doesn't say that.
+use axum::{extract::State, Json};
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::auth::Admin;
+use crate::{audit, backfill, error::ApiError, schema, AppState};
+
+#[derive(Serialize)]
+pub struct MigrationStatus {
+    /// Pre-deploy migrations that haven't run. Boot applies these, so this is
+    /// only non-empty if the database was changed underneath the service.
+    pre_deploy: Vec<&'static str>,
+    /// Post-deploy migrations waiting for `POST /admin/migrations/post-deploy`.
+    post_deploy: Vec<&'static str>,
+    /// Backfills that still have rows to rewrite.
+    backfills: Vec<&'static str>,
+}
+
+async fn status(state: &AppState) -> Result<MigrationStatus, ApiError> {
+    Ok(MigrationStatus {
+        pre_deploy: schema::pending(&*state.db, schema::Phase::PreDeploy).await?,
+        post_deploy: schema::pending(&*state.db, schema::Phase::PostDeploy).await?,
+        backfills: backfill::unfinished(&*state.db).await?,
+    })
+}
+
+/// `GET /admin/migrations` - what's still to run.
+pub async fn migration_status(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<MigrationStatus>, ApiError> {
+    Ok(Json(status(&state).await?))
+}
+
+/// `POST /admin/migrations/post-deploy` - applies the post-deploy migrations.
+/// Refused while a backfill is still running, since they may rely on it.
+pub async fn apply_post_deploy(
+    State(state): State<Arc<AppState>>,
+    admin: Admin,
+) -> Result<Json<MigrationStatus>, ApiError> {
+    let unfinished = backfill::unfinished(&*state.db).await?;
+    if !unfinished.is_empty() {
+        return Err(ApiError::Conflict(format!(
+            "Still backfilling {}; try again once that's done",
+            unfinished.join(", ")
+        )));
+    }
+
+    let applied = schema::apply(&*state.db, schema::Phase::PostDeploy).await?;
+    if !applied.is_empty() {
+        state
+            .db
+            .execute(audit::entry(
+                &admin.name,
+                "post_deploy_migrations",
+                &applied.join(", "),
+            ))
+            .await?;
+        tracing::info!(
+            "{} applied the post-deploy migrations {}",
+            admin.name,
+            applied.join(", ")
+        );
+    }
+
+    Ok(Json(status(&state).await?))
+}
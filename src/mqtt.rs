@@ -0,0 +1,133 @@
+use rumqttc::{AsyncClient, MqttOptions, QoS};
+use serde::Serialize;
+use shuttle_secrets::SecretStore;
+use std::time::Duration;
+
+use crate::CatFact;
+
+/// Connection settings for the optional MQTT publisher. Publishing is only
+/// enabled when the `MQTT_HOST` secret is set.
+pub struct MqttConfig {
+    host: String,
+    port: u16,
+    client_id: String,
+    credentials: Option<(String, String)>,
+    daily_topic: String,
+    new_fact_topic: String,
+    weekly_topic: String,
+}
+
+impl MqttConfig {
+    pub fn from_secrets(store: &SecretStore) -> Option<Self> {
+        let host = store.get("MQTT_HOST")?;
+
+        let credentials = match (store.get("MQTT_USER"), store.get("MQTT_PASSWORD")) {
+            (Some(user), Some(password)) => Some((user, password)),
+            _ => None,
+        };
+
+        Some(Self {
+            host,
+            port: store
+                .get("MQTT_PORT")
+                .and_then(|port| port.parse().ok())
+                .unwrap_or(1883),
+            client_id: store
+                .get("MQTT_CLIENT_ID")
+                .unwrap_or_else(|| "cat-facts-api".to_string()),
+            credentials,
+            daily_topic: store
+                .get("MQTT_DAILY_TOPIC")
+                .unwrap_or_else(|| "catfacts/daily".to_string()),
+            new_fact_topic: store
+                .get("MQTT_NEW_FACT_TOPIC")
+                .unwrap_or_else(|| "catfacts/new".to_string()),
+            weekly_topic: store
+                .get("MQTT_WEEKLY_TOPIC")
+                .unwrap_or_else(|| "catfacts/weekly".to_string()),
+        })
+    }
+}
+
+/// Publishes facts to an MQTT broker so displays can subscribe instead of polling.
+#[derive(Clone)]
+pub struct FactPublisher {
+    client: AsyncClient,
+    daily_topic: String,
+    new_fact_topic: String,
+    weekly_topic: String,
+}
+
+impl FactPublisher {
+    /// Creates the client and spawns a task to drive its event loop, which
+    /// handles (re)connecting to the broker in the background.
+    pub fn connect(config: MqttConfig) -> Self {
+        let mut options = MqttOptions::new(config.client_id, config.host, config.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if let Some((user, password)) = config.credentials {
+            options.set_credentials(user, password);
+        }
+
+        let (client, mut eventloop) = AsyncClient::new(options, 10);
+
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = eventloop.poll().await {
+                    tracing::warn!("MQTT connection error: {e}");
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+            }
+        });
+
+        Self {
+            client,
+            daily_topic: config.daily_topic,
+            new_fact_topic: config.new_fact_topic,
+            weekly_topic: config.weekly_topic,
+        }
+    }
+
+    /// Publishes the fact of the day. The message is retained so that displays
+    /// which connect later in the day still get it immediately.
+    pub async fn publish_daily(&self, fact: &str) {
+        self.publish(&self.daily_topic, &fact_message(fact), true)
+            .await
+    }
+
+    pub async fn publish_new_fact(&self, fact: &str) {
+        self.publish(&self.new_fact_topic, &fact_message(fact), false)
+            .await
+    }
+
+    /// Announces a new weekly best-of page, retained like the daily fact.
+    pub async fn publish_weekly(&self, week: &str, url: &str) {
+        let message = serde_json::json!({ "week": week, "url": url });
+        self.publish(&self.weekly_topic, &message, true).await
+    }
+
+    async fn publish(&self, topic: &str, message: &impl Serialize, retain: bool) {
+        let payload = match serde_json::to_vec(message) {
+            Ok(payload) => payload,
+            Err(e) => {
+                tracing::error!("Couldn't serialize a message for MQTT: {e}");
+                return;
+            }
+        };
+
+        if let Err(e) = self
+            .client
+            .publish(topic, QoS::AtLeastOnce, retain, payload)
+            .await
+        {
+            tracing::warn!("Something went wrong while publishing to {topic}: {e}");
+        }
+    }
+}
+
+fn fact_message(fact: &str) -> CatFact {
+    CatFact {
+        fact: fact.to_string(),
+        license: None,
+        tags: None,
+    }
+}
@@ -0,0 +1,128 @@
+//! Picks the format of a fact response from the `Accept` header: JSON (the
+//! default), Protobuf, plain text for shell scripts (just the fact, one per
+//! line) or XML. The supported type with the highest `q` wins, the earlier one
+//! on a tie; an `Accept` with nothing supported gets JSON rather than a 406.
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde_json::Value;
+
+use crate::{html, proto};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FactFormat {
+    Json,
+    Protobuf,
+    Text,
+    Xml,
+}
+
+impl FactFormat {
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type {
+            // Browsers list `application/xml` ahead of `*/*`, but would rather
+            // have the JSON they've always had.
+            "application/json" | "application/*" | "*/*" | "text/html" => Some(Self::Json),
+            proto::CONTENT_TYPE => Some(Self::Protobuf),
+            "text/plain" | "text/*" => Some(Self::Text),
+            "application/xml" | "text/xml" => Some(Self::Xml),
+            _ => None,
+        }
+    }
+
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let mut best: Option<(f32, Self)> = None;
+
+        let accepted = headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+        for entry in accepted {
+            let mut params = entry.split(';');
+            let media_type = params.next().unwrap_or("").trim().to_ascii_lowercase();
+            let Some(format) = Self::from_media_type(&media_type) else {
+                continue;
+            };
+            let q = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            if q > 0.0 && best.is_none_or(|(best_q, _)| q > best_q) {
+                best = Some((q, format));
+            }
+        }
+
+        best.map_or(Self::Json, |(_, format)| format)
+    }
+}
+
+/// `facts` (already trimmed to the requested fields) as plain text: each
+/// fact's text on its own line.
+pub fn text(facts: &[Value]) -> Response {
+    let mut body = String::new();
+    for fact in facts {
+        if let Some(text) = fact.get("fact").and_then(Value::as_str) {
+            body.push_str(text);
+            body.push('\n');
+        }
+    }
+
+    with_content_type(body, "text/plain; charset=utf-8")
+}
+
+/// One fact as `<catfact>`, with an element per field.
+pub fn xml(fact: &Value) -> Response {
+    let mut body = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    push_fact(&mut body, fact);
+
+    with_content_type(body, "application/xml; charset=utf-8")
+}
+
+/// A list of facts as `<catfacts>`.
+pub fn xml_list(facts: &[Value]) -> Response {
+    let mut body = String::from(r#"<?xml version="1.0" encoding="UTF-8"?><catfacts>"#);
+    for fact in facts {
+        push_fact(&mut body, fact);
+    }
+    body.push_str("</catfacts>");
+
+    with_content_type(body, "application/xml; charset=utf-8")
+}
+
+/// Nulls are left out, so a missing element means the field isn't set.
+fn push_fact(body: &mut String, fact: &Value) {
+    body.push_str("<catfact>");
+    if let Value::Object(fields) = fact {
+        for (name, value) in fields {
+            let value = match value {
+                Value::Null => continue,
+                Value::String(text) => html::escape(text),
+                other => html::escape(&other.to_string()),
+            };
+            body.push_str(&format!("<{name}>{value}</{name}>"));
+        }
+    }
+    body.push_str("</catfact>");
+}
+
+/// Marks `response` as depending on `Accept`, so caches keep a copy per
+/// format.
+pub fn vary(mut response: Response) -> Response {
+    response
+        .headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("accept"));
+
+    response
+}
+
+fn with_content_type(body: String, content_type: &'static str) -> Response {
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, HeaderValue::from_static(content_type))],
+        body,
+    )
+        .into_response()
+}
@@ -0,0 +1,209 @@
+//! An OpenAPI description of the public API, generated from the
+//! `#[utoipa::path]` annotations on each handler and served at
+//! `GET /openapi.json`, with Swagger UI at `GET /docs` for trying it out.
+//! `/admin` routes are left out; they're documented in the README.
+use axum::{response::Html, Json};
+use utoipa::openapi::{
+    path::PathItemType,
+    security::{ApiKey, ApiKeyValue, Http, HttpAuthScheme, SecurityScheme},
+};
+use utoipa::{Modify, OpenApi};
+
+use crate::routes::RouteRegistry;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Cat Facts API"),
+    paths(
+        crate::homepage,
+        crate::health::liveness_check,
+        crate::health::readiness_check,
+        crate::metrics::metrics,
+        crate::badge::fact_badge,
+        crate::stats::subscribers_json,
+        crate::stats::subscribers_badge,
+        crate::get_record,
+        crate::get_today,
+        crate::get_record_by_key,
+        crate::update_record,
+        crate::delete_record,
+        crate::create_record,
+        crate::bulk::import_facts,
+        crate::ingest::suggest_facts,
+        crate::list::list_facts,
+        crate::search::search_facts,
+        crate::history::facts_as_of,
+        crate::feed::rss,
+        crate::feed::atom,
+        crate::feed::json_feed,
+        crate::sitemap::sitemap,
+        crate::changelog::get_changelog,
+        crate::tags::list_tags,
+        crate::weekly::weekly_page,
+        crate::archive::archive_page,
+        crate::archive::day_page,
+        crate::votes::top_facts,
+        crate::votes::vote,
+        crate::feedback::record_feedback,
+        crate::opens::record_open,
+        crate::signup::subscribe_page,
+        crate::subscribe,
+        crate::confirm::confirm,
+        crate::data_requests::request_data,
+        crate::data_requests::export_data,
+        crate::data_requests::erase_page,
+        crate::data_requests::erase_form,
+        crate::data_requests::erase_data,
+        crate::preferences::preferences_page,
+        crate::preferences::update_preferences,
+        crate::preferences::unsubscribe,
+        crate::unsubscribe::unsubscribe,
+        crate::unsubscribe::one_click_unsubscribe,
+        crate::reengage::stay_subscribed,
+        crate::complaints::receive_complaint,
+    ),
+    components(schemas(
+        crate::error::ErrorBody,
+        crate::error::ErrorDetail,
+        crate::CatFact,
+        crate::CatFactRecord,
+        crate::EmailRequest,
+        crate::license::License,
+        crate::delivery::DeliveryWindow,
+        crate::email_format::EmailFormat,
+        crate::language::Language,
+        crate::health::Readiness,
+        crate::health::DependencyStatus,
+        crate::stats::SubscriberCount,
+        crate::list::Sort,
+        crate::list::FactPage,
+        crate::search::SearchResults,
+        crate::search::SearchResult,
+        crate::history::PastFacts,
+        crate::history::PastFact,
+        crate::bulk::ImportReport,
+        crate::ingest::FromUrl,
+        crate::ingest::Suggestions,
+        crate::ingest::Suggestion,
+        crate::changelog::Changelog,
+        crate::tags::TagCount,
+        crate::votes::Direction,
+        crate::votes::Vote,
+        crate::votes::Tally,
+        crate::votes::TopFact,
+        crate::data_requests::DataRequest,
+        crate::data_requests::Accepted,
+        crate::data_requests::TokenForm,
+        crate::data_requests::DataExport,
+        crate::data_requests::Erased,
+        crate::preferences::PreferencesForm,
+        crate::complaints::Complaint,
+    )),
+    tags(
+        (name = "facts", description = "Reading, adding and correcting cat facts"),
+        (name = "votes", description = "Voting on cat facts"),
+        (name = "subscriptions", description = "The daily cat fact email"),
+        (name = "your data", description = "Seeing and erasing what's kept about a subscriber"),
+        (name = "health", description = "Health checks and metrics"),
+        (name = "meta", description = "About the API itself"),
+    ),
+    modifiers(&SecuritySchemes)
+)]
+pub struct ApiDoc;
+
+/// The two ways admin routes accept an API key, named by each route's
+/// `security`.
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("x-api-key"))),
+        );
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+    }
+}
+
+/// Swagger UI, loaded from unpkg so the binary doesn't have to carry it.
+const DOCS_PAGE: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Cat Facts API docs</title>
+<link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+<div id="swagger-ui"></div>
+<script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js" crossorigin></script>
+<script>
+window.onload = () => {
+  window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+};
+</script>
+</body>
+</html>
+"##;
+
+/// `GET /openapi.json` - the OpenAPI 3 description of every public route.
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// `GET /docs` - Swagger UI for the spec at `/openapi.json`.
+pub async fn docs() -> Html<&'static str> {
+    Html(DOCS_PAGE)
+}
+
+/// Public routes in `registry` that the spec doesn't describe, as
+/// `GET /catfact/:key`, so a handler added without an annotation gets noticed.
+/// `/admin` routes, deprecated ones and the docs themselves don't need one.
+pub fn undocumented(registry: &RouteRegistry) -> Vec<String> {
+    let spec = ApiDoc::openapi();
+
+    registry
+        .routes()
+        .iter()
+        .filter(|route| {
+            !route.path.starts_with("/admin")
+                && !["/openapi.json", "/docs"].contains(&route.path)
+                && route.deprecation.is_none()
+        })
+        .filter(|route| {
+            let path = route
+                .path
+                .split('/')
+                .map(|segment| match segment.strip_prefix(':') {
+                    Some(name) => format!("{{{name}}}"),
+                    None => segment.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+            let Some(method) = path_item_type(route.method.as_str()) else {
+                return true;
+            };
+
+            spec.paths
+                .paths
+                .get(&path)
+                .is_none_or(|item| !item.operations.contains_key(&method))
+        })
+        .map(|route| format!("{} {}", route.method, route.path))
+        .collect()
+}
+
+fn path_item_type(method: &str) -> Option<PathItemType> {
+    Some(match method {
+        "GET" => PathItemType::Get,
+        "POST" => PathItemType::Post,
+        "PUT" => PathItemType::Put,
+        "DELETE" => PathItemType::Delete,
+        "PATCH" => PathItemType::Patch,
+        _ => return None,
+    })
+}
@@ -0,0 +1,105 @@
+//! Opens of the daily email, for the bandit experiment (see `bandit`) and for
+//! finding subscribers who've stopped reading (see `reengage`). Each HTML
+//! email ends with a one-pixel image from `GET /open/:token.gif`, where the
+//! token names the send like a feedback link's, and `daily_facts.recipients`
+//! counts how many the day's fact was sent to. A send counts as opened once,
+//! however often it's loaded, and the subscriber's `last_opened_at` is kept
+//! up to date.
+//!
+//! Plenty of mail clients block or prefetch images, so opens are only good
+//! for comparing facts with each other, not as a true open rate.
+use axum::{
+    extract::{Path, State},
+    http::header,
+    response::IntoResponse,
+};
+use chrono::{Duration, NaiveDate};
+use std::sync::Arc;
+
+use crate::feedback;
+use crate::store::{Statement, Value};
+use crate::AppState;
+
+/// A transparent 1x1 GIF.
+const PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+/// How long after a send its pixel still counts an open.
+const OPEN_DAYS: i64 = 30;
+
+/// How many sends of a fact have been opened, as a correlated subquery on
+/// `catfacts`.
+pub const OPENS: &str =
+    "(SELECT count(*) FROM fact_opens WHERE fact_opens.catfact_id = catfacts.id)";
+
+/// How many subscribers a fact has been sent to while opens were counted, as
+/// a correlated subquery on `catfacts`.
+pub const RECIPIENTS: &str = "(SELECT coalesce(sum(recipients), 0) FROM daily_facts
+    WHERE daily_facts.catfact_id = catfacts.id)";
+
+/// The pixel for the fact sent on `date` to the subscriber with `token`.
+pub fn open_url(public_url: &str, date: NaiveDate, token: &str) -> String {
+    format!(
+        "{}/open/{date}.{token}.gif",
+        public_url.trim_end_matches('/')
+    )
+}
+
+/// `GET /open/:token.gif` - the pixel in an email, counting it as opened.
+/// Answers with the image whatever the token, so a broken one doesn't show.
+#[utoipa::path(
+    get,
+    path = "/open/{token}",
+    tag = "subscriptions",
+    params(("token" = String, Path, description = "The send, from the image in an email, ending in `.gif`")),
+    responses(
+        (status = 200, description = "A transparent image", body = [u8], content_type = "image/gif"),
+    )
+)]
+pub async fn record_open(
+    State(state): State<Arc<AppState>>,
+    Path(token): Path<String>,
+) -> impl IntoResponse {
+    if let Err(e) = record(&state, &token).await {
+        tracing::warn!("Couldn't count an email open: {e}");
+    }
+
+    ([(header::CONTENT_TYPE, "image/gif")], PIXEL)
+}
+
+async fn record(state: &AppState, token: &str) -> Result<(), anyhow::Error> {
+    let Some((date, token)) = token.strip_suffix(".gif").and_then(feedback::parse_token) else {
+        return Ok(());
+    };
+    let today = state.zone.today();
+    if date > today || date <= today - Duration::days(OPEN_DAYS) {
+        return Ok(());
+    }
+    let Some(preferences) = state.db.preferences(token).await? else {
+        return Ok(());
+    };
+
+    state
+        .db
+        .batch([
+            Statement::with_args(
+                "INSERT INTO fact_opens (date, subscriber, catfact_id)
+                SELECT date, ?2, catfact_id FROM daily_facts WHERE date = ?1
+                ON CONFLICT (date, subscriber) DO NOTHING",
+                &[
+                    Value::from(date.to_string()),
+                    Value::from(state.privacy.subscriber_id(&preferences.email)),
+                ],
+            ),
+            Statement::with_args(
+                "UPDATE subscribers SET last_opened_at = current_timestamp WHERE token = ?",
+                &[token],
+            ),
+        ])
+        .await?;
+
+    Ok(())
+}
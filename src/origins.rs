@@ -0,0 +1,67 @@
+use axum::http::{header, HeaderValue, Method};
+use shuttle_secrets::SecretStore;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// Sites allowed to embed the subscribe form, from the comma-separated
+/// `SUBSCRIBE_ALLOWED_ORIGINS` secret (e.g. `https://example.com`). They may
+/// call `POST /subscribe` cross-origin and be redirected back to afterwards.
+#[derive(Clone)]
+pub struct AllowedOrigins(Vec<String>);
+
+impl AllowedOrigins {
+    pub fn from_secrets(store: &SecretStore) -> Self {
+        let origins = store
+            .get("SUBSCRIBE_ALLOWED_ORIGINS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|origin| origin.trim().trim_end_matches('/').to_lowercase())
+            .filter(|origin| !origin.is_empty())
+            .collect();
+
+        Self(origins)
+    }
+
+    /// Whether `url` is a path on this site or an absolute URL on one of the
+    /// allowed origins. Anything else is refused as a redirect target, so the
+    /// form can't be used as an open redirect.
+    pub fn allows_url(&self, url: &str) -> bool {
+        if is_local_path(url) {
+            return true;
+        }
+
+        origin(url).is_some_and(|origin| self.0.contains(&origin))
+    }
+
+    pub fn cors_layer(&self) -> CorsLayer {
+        let origins: Vec<HeaderValue> = self
+            .0
+            .iter()
+            .filter_map(|origin| HeaderValue::from_str(origin).ok())
+            .collect();
+
+        CorsLayer::new()
+            .allow_origin(AllowOrigin::list(origins))
+            .allow_methods([Method::POST])
+            .allow_headers([header::CONTENT_TYPE])
+    }
+}
+
+/// A path like `/thanks`, but not a protocol-relative `//evil.example`.
+fn is_local_path(url: &str) -> bool {
+    url.starts_with('/') && !url.starts_with("//") && !url.starts_with("/\\")
+}
+
+/// The lowercased `scheme://host[:port]` of an http(s) URL.
+fn origin(url: &str) -> Option<String> {
+    let (scheme, rest) = url.split_once("://")?;
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return None;
+    }
+
+    let authority = rest
+        .split(['/', '?', '#', '\\'])
+        .next()
+        .filter(|authority| !authority.is_empty() && !authority.contains('@'))?;
+
+    Some(format!("{scheme}://{authority}").to_lowercase())
+}
@@ -0,0 +1,468 @@
+//! The `email_outbox` table, where each daily email is written before it's
+//! sent. One that fails stays queued and is retried by the `retry` job with
+//! exponential backoff, so a blip at the SMTP relay doesn't cost anyone their
+//! fact. After `MAX_ATTEMPTS` it's given up on: it's kept as `dead` for
+//! `GET /admin/queue` (or `/admin/outbox`) to show, and recorded with its
+//! final error in `email_dead_letters`, which outlives the outbox's retention
+//! period, for `GET /admin/dead-letters`. An operator can send failed ones
+//! again once the problem's fixed. A sent one records the relay
+//! (or provider) that took it.
+use anyhow::anyhow;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::store::{self, FromRow, Row, Statement, Store, Value};
+use crate::{audit, auth::Admin, error::ApiError, mailer::Email, privacy::Privacy, AppState};
+
+/// How many tries an email gets, counting the first, before it's
+/// dead-lettered.
+pub const MAX_ATTEMPTS: i64 = 5;
+
+/// The wait before the first retry, doubled after each failure after that.
+const BACKOFF_SECS: i64 = 5 * 60;
+
+/// How long a queued email can go unmarked before it's treated as failed,
+/// e.g. if the service restarted partway through a send.
+const LEASE_SECS: i64 = 15 * 60;
+
+/// Emails that have failed at least once and aren't sent or cancelled: dead,
+/// or waiting for a retry. One scrubbed in privacy mode has no message left to
+/// send, so it can't be retried.
+const FAILED: &str = "(status = 'dead' OR (status = 'pending' AND attempts > 0))";
+
+const DEFAULT_LIMIT: u32 = 50;
+const MAX_LIMIT: u32 = 500;
+
+/// An email waiting to be retried.
+pub struct Queued {
+    pub id: i64,
+    pub email: Email,
+}
+
+impl FromRow for Queued {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            id: store::integer(row, 0)?,
+            email: serde_json::from_str(&store::text(row, 1)?)
+                .map_err(|e| anyhow!("a queued email isn't valid: {e}"))?,
+        })
+    }
+}
+
+/// Queues `email` for `recipient`, with `date`'s fact, leased for
+/// `LEASE_SECS` while the caller sends it. Returns the queued email's id.
+pub async fn enqueue(
+    db: &dyn Store,
+    recipient: &str,
+    date: NaiveDate,
+    email: &Email,
+) -> Result<i64, anyhow::Error> {
+    let message = serde_json::to_string(email)?;
+    let sender = Some(email.from.clone());
+
+    let res = db
+        .execute(Statement::with_args(
+            "INSERT INTO email_outbox (recipient, sender, message, send_date, next_attempt_at)
+            VALUES (?, ?, ?, ?, datetime('now', ?))",
+            &[
+                Some(recipient.to_string()),
+                sender,
+                Some(message),
+                Some(date.to_string()),
+                Some(format!("+{LEASE_SECS} seconds")),
+            ],
+        ))
+        .await?;
+
+    res.last_insert_rowid
+        .ok_or_else(|| anyhow!("queueing the email for {recipient} didn't return an id"))
+}
+
+/// Records that the email went out through `relay`.
+pub async fn mark_sent(db: &dyn Store, id: i64, relay: &str) -> Result<(), anyhow::Error> {
+    db.execute(Statement::with_args(
+        "UPDATE email_outbox SET status = 'sent', sent_at = current_timestamp, relay = ?2
+        WHERE id = ?1",
+        &[Value::from(id), Value::from(relay)],
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// Records a failed attempt, scheduling the next one or dead-lettering the
+/// email once it's had `MAX_ATTEMPTS`. Returns whether it was dead-lettered.
+pub async fn mark_failed(db: &dyn Store, id: i64, error: &str) -> Result<bool, anyhow::Error> {
+    let results = db
+        .batch([
+            Statement::with_args(
+                "UPDATE email_outbox SET
+                    attempts = attempts + 1,
+                    last_error = ?2,
+                    status = CASE WHEN attempts + 1 >= ?3 THEN 'dead' ELSE 'pending' END,
+                    next_attempt_at = datetime('now', '+' || (?4 << attempts) || ' seconds')
+                WHERE id = ?1",
+                &[
+                    Value::from(id),
+                    Value::from(error),
+                    Value::from(MAX_ATTEMPTS),
+                    Value::from(BACKOFF_SECS),
+                ],
+            ),
+            // An email retried by hand can die again.
+            Statement::with_args(
+                "INSERT INTO email_dead_letters (outbox_id, recipient, final_error, attempts)
+                SELECT id, recipient, last_error, attempts FROM email_outbox
+                WHERE id = ? AND status = 'dead'
+                ON CONFLICT (outbox_id) DO UPDATE SET
+                final_error = excluded.final_error,
+                attempts = email_dead_letters.attempts + excluded.attempts,
+                dead_at = current_timestamp,
+                retried_at = NULL",
+                &[id],
+            ),
+        ])
+        .await?;
+
+    Ok(results.get(1).is_some_and(|res| res.rows_affected > 0))
+}
+
+/// Up to `limit` emails due another attempt, oldest first. Any queued for
+/// someone who's since unsubscribed or been suppressed are dropped instead.
+pub async fn due(db: &dyn Store, limit: u32) -> Result<Vec<Queued>, anyhow::Error> {
+    let results = db
+        .batch([
+            Statement::new(
+                "UPDATE email_outbox SET status = 'cancelled'
+                WHERE status = 'pending' AND next_attempt_at <= datetime('now')
+                AND (recipient NOT IN (SELECT email FROM subscribers WHERE confirmed = 1)
+                    OR recipient IN (SELECT email FROM suppressions))",
+            ),
+            Statement::with_args(
+                "SELECT id, message FROM email_outbox
+                WHERE status = 'pending' AND next_attempt_at <= datetime('now')
+                ORDER BY id LIMIT ?",
+                &[limit],
+            ),
+        ])
+        .await?;
+
+    match results.get(1) {
+        Some(due) => store::rows(due),
+        None => Err(anyhow!("missing outbox results")),
+    }
+}
+
+/// How many finished emails `scrub` rewrites at a time.
+const SCRUB_PAGE: u32 = 200;
+
+struct Finished {
+    id: i64,
+    recipient: String,
+}
+
+impl FromRow for Finished {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            id: store::integer(row, 0)?,
+            recipient: store::text(row, 1)?,
+        })
+    }
+}
+
+/// In privacy mode, swaps the recipient of every finished email (sent, dead
+/// or cancelled) for its pseudonym, and drops the copy of the message, which
+/// has the address in it. An emptied message marks a row as done.
+pub async fn scrub(db: &dyn Store, privacy: &Privacy) -> Result<u64, anyhow::Error> {
+    if !privacy.enabled() {
+        return Ok(0);
+    }
+
+    let mut scrubbed = 0;
+    loop {
+        let finished: Vec<Finished> = db
+            .execute(Statement::with_args(
+                "SELECT id, recipient FROM email_outbox
+                WHERE status != 'pending' AND message != '' LIMIT ?",
+                &[SCRUB_PAGE],
+            ))
+            .await
+            .and_then(|res| store::rows(&res))?;
+        if finished.is_empty() {
+            return Ok(scrubbed);
+        }
+
+        scrubbed += finished.len() as u64;
+        db.batch(finished.iter().flat_map(|email| {
+            let args = [
+                Value::from(privacy.subscriber_id(&email.recipient)),
+                Value::from(email.id),
+            ];
+            [
+                Statement::with_args(
+                    "UPDATE email_outbox SET recipient = ?, message = '' WHERE id = ?",
+                    &args,
+                ),
+                Statement::with_args(
+                    "UPDATE email_dead_letters SET recipient = ? WHERE outbox_id = ?",
+                    &args,
+                ),
+            ]
+        }))
+        .await?;
+    }
+}
+
+#[derive(Serialize)]
+pub struct OutboxEntry {
+    id: i64,
+    recipient: String,
+    status: String,
+    attempts: i64,
+    last_error: Option<String>,
+    created_at: String,
+    next_attempt_at: String,
+    /// The SMTP relay, or the provider, that took a sent email.
+    relay: Option<String>,
+}
+
+impl FromRow for OutboxEntry {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            id: store::integer(row, 0)?,
+            recipient: store::text(row, 1)?,
+            status: store::text(row, 2)?,
+            attempts: store::integer(row, 3)?,
+            last_error: store::optional_text(row, 4)?,
+            created_at: store::text(row, 5)?,
+            next_attempt_at: store::text(row, 6)?,
+            relay: store::optional_text(row, 7)?,
+        })
+    }
+}
+
+struct StatusCount {
+    status: String,
+    count: i64,
+}
+
+impl FromRow for StatusCount {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            status: store::text(row, 0)?,
+            count: store::integer(row, 1)?,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct OutboxReport {
+    /// How many emails are in each status.
+    counts: BTreeMap<String, i64>,
+    emails: Vec<OutboxEntry>,
+}
+
+#[derive(Deserialize)]
+pub struct OutboxQuery {
+    status: Option<String>,
+    limit: Option<u32>,
+}
+
+/// `GET /admin/queue?status=dead&limit=50` (or `/admin/outbox`) - the most
+/// recent queued emails with a status (`dead` by default), newest first, with
+/// their last error. `failed` lists every one that's failed and isn't sent:
+/// dead, or waiting for a retry.
+pub async fn list_outbox(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<OutboxQuery>,
+) -> Result<Json<OutboxReport>, ApiError> {
+    let status = query.status.as_deref().unwrap_or("dead");
+    let filter = match status {
+        "failed" => FAILED.to_string(),
+        "pending" | "sent" | "dead" | "cancelled" => format!("status = '{status}'"),
+        _ => {
+            return Err(ApiError::Validation(format!(
+                "Unknown status {status:?}; it should be failed, pending, sent, dead or cancelled"
+            )))
+        }
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    let results = state
+        .db
+        .batch([
+            Statement::new("SELECT status, count(*) FROM email_outbox GROUP BY status"),
+            Statement::with_args(
+                format!(
+                    "SELECT id, recipient, status, attempts, last_error, created_at, next_attempt_at,
+                    relay FROM email_outbox WHERE {filter} ORDER BY id DESC LIMIT ?"
+                ),
+                &[limit],
+            ),
+        ])
+        .await?;
+    let (Some(counts), Some(emails)) = (results.first(), results.get(1)) else {
+        return Err(ApiError::internal("Missing outbox results"));
+    };
+
+    Ok(Json(OutboxReport {
+        counts: store::rows::<StatusCount>(counts)?
+            .into_iter()
+            .map(|count| (count.status, count.count))
+            .collect(),
+        emails: store::rows(emails)?,
+    }))
+}
+
+/// Queues failed emails matching `filter` to be sent again by the next `retry`
+/// run, with a fresh set of attempts. Returns how many there were.
+async fn retry(
+    db: &dyn Store,
+    admin: &Admin,
+    filter: &str,
+    args: &[Value],
+    detail: &str,
+) -> Result<u64, anyhow::Error> {
+    let res = db
+        .batch([
+            Statement::with_args(
+                format!(
+                    "UPDATE email_dead_letters SET retried_at = current_timestamp
+                    WHERE retried_at IS NULL AND outbox_id IN (
+                        SELECT id FROM email_outbox
+                        WHERE status = 'dead' AND message != '' AND {filter}
+                    )"
+                ),
+                args,
+            ),
+            Statement::with_args(
+                format!(
+                    "UPDATE email_outbox SET status = 'pending', attempts = 0,
+                    next_attempt_at = datetime('now')
+                    WHERE {FAILED} AND message != '' AND {filter}"
+                ),
+                args,
+            ),
+            audit::entry_if_changed(&admin.name, "outbox_retry", detail),
+        ])
+        .await?;
+
+    Ok(res.get(1).map_or(0, |retried| retried.rows_affected))
+}
+
+/// `POST /admin/queue/:id/retry` - sends a failed email again, within a minute.
+pub async fn retry_email(
+    State(state): State<Arc<AppState>>,
+    admin: Admin,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, ApiError> {
+    let retried = retry(
+        &*state.db,
+        &admin,
+        "id = ?",
+        &[Value::from(id)],
+        &format!("email {id}"),
+    )
+    .await?;
+    if retried == 0 {
+        return Err(ApiError::NotFound(format!(
+            "There's no failed email {id} that can be sent again"
+        )));
+    }
+
+    tracing::info!("{} queued email {id} to be sent again", admin.name);
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[derive(Serialize)]
+pub struct Retried {
+    retried: u64,
+}
+
+/// `POST /admin/queue/retry` - sends every failed email again, e.g. after an
+/// outage at the SMTP relay. They go out within the usual sending limits.
+pub async fn retry_all(
+    State(state): State<Arc<AppState>>,
+    admin: Admin,
+) -> Result<Json<Retried>, ApiError> {
+    let retried = retry(&*state.db, &admin, "1", &[], "every failed email").await?;
+    if retried > 0 {
+        tracing::info!(
+            "{} queued {retried} failed emails to be sent again",
+            admin.name
+        );
+    }
+
+    Ok(Json(Retried { retried }))
+}
+
+#[derive(Serialize)]
+pub struct DeadLetter {
+    outbox_id: i64,
+    recipient: String,
+    final_error: Option<String>,
+    attempts: i64,
+    dead_at: String,
+    /// When it was sent again by hand, if it has been.
+    retried_at: Option<String>,
+}
+
+impl FromRow for DeadLetter {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            outbox_id: store::integer(row, 0)?,
+            recipient: store::text(row, 1)?,
+            final_error: store::optional_text(row, 2)?,
+            attempts: store::integer(row, 3)?,
+            dead_at: store::text(row, 4)?,
+            retried_at: store::optional_text(row, 5)?,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct DeadLetterReport {
+    /// How many haven't been retried.
+    outstanding: i64,
+    dead_letters: Vec<DeadLetter>,
+}
+
+#[derive(Deserialize)]
+pub struct DeadLetterQuery {
+    limit: Option<u32>,
+}
+
+/// `GET /admin/dead-letters?limit=50` - the emails that were given up on, most
+/// recent first, with their final error.
+pub async fn list_dead_letters(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<DeadLetterQuery>,
+) -> Result<Json<DeadLetterReport>, ApiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let results = state
+        .db
+        .batch([
+            Statement::new("SELECT count(*) FROM email_dead_letters WHERE retried_at IS NULL"),
+            Statement::with_args(
+                "SELECT outbox_id, recipient, final_error, attempts, dead_at, retried_at
+                FROM email_dead_letters ORDER BY dead_at DESC, outbox_id DESC LIMIT ?",
+                &[limit],
+            ),
+        ])
+        .await?;
+    let (Some(outstanding), Some(dead_letters)) = (results.first(), results.get(1)) else {
+        return Err(ApiError::internal("Missing dead letter results"));
+    };
+
+    Ok(Json(DeadLetterReport {
+        outstanding: store::first::<i64>(outstanding)?.unwrap_or(0),
+        dead_letters: store::rows(dead_letters)?,
+    }))
+}
@@ -0,0 +1,374 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{Html, IntoResponse},
+    Form,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use crate::{
+    delivery::DeliveryWindow,
+    email_format::EmailFormat,
+    html::{self, escape},
+    language::Language,
+    store::{self, FromRow, Row, Statement, Store},
+    timezone::Timezone,
+    weekdays::Weekdays,
+    AppState,
+};
+
+const TITLE: &str = "Cat Facts - Preferences";
+
+pub struct Preferences {
+    pub email: String,
+    pub delivery_window: DeliveryWindow,
+    pub email_format: EmailFormat,
+    pub weekdays: Weekdays,
+    pub language: Language,
+    pub timezone: Timezone,
+    /// The ids of the tags they want facts about, or none for every fact.
+    pub topics: Vec<i64>,
+}
+
+impl FromRow for Preferences {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            email: store::text(row, 0)?,
+            delivery_window: DeliveryWindow::from_hour(store::integer(row, 1)?).unwrap_or_default(),
+            email_format: EmailFormat::from_name(&store::text(row, 2)?).unwrap_or_default(),
+            weekdays: Weekdays::from_mask(store::integer(row, 3)?),
+            language: Language::from_name(&store::text(row, 4)?).unwrap_or_default(),
+            timezone: Timezone::from_minutes(store::optional_integer(row, 5)?).unwrap_or_default(),
+            topics: store::optional_text(row, 6)?
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|id| id.parse().ok())
+                .collect(),
+        })
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct PreferencesForm {
+    delivery_window: DeliveryWindow,
+    #[serde(default)]
+    email_format: EmailFormat,
+    #[serde(default)]
+    #[schema(value_type = Option<String>, example = "mon,wed,fri")]
+    weekdays: Weekdays,
+    #[serde(default)]
+    language: Language,
+    #[serde(default)]
+    #[schema(value_type = Option<String>, example = "+05:30")]
+    timezone: Timezone,
+    /// A `topic_<tag id>` field for each topic checked.
+    #[serde(flatten)]
+    #[schema(value_type = Option<HashMap<String, String>>, example = json!({"topic_3": "on"}))]
+    topics: HashMap<String, String>,
+}
+
+impl PreferencesForm {
+    /// The ids of the topics checked.
+    fn topics(&self) -> Vec<i64> {
+        self.topics
+            .keys()
+            .filter_map(|key| key.strip_prefix("topic_")?.parse().ok())
+            .collect()
+    }
+}
+
+/// A tag a subscriber can choose to hear about.
+struct Topic {
+    id: i64,
+    name: String,
+}
+
+impl FromRow for Topic {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            id: store::integer(row, 0)?,
+            name: store::text(row, 1)?,
+        })
+    }
+}
+
+/// The topics the preference center offers: the tags of published facts,
+/// and any others the subscriber with `token` already chose, by name.
+async fn topics(db: &dyn Store, token: &str) -> Result<Vec<Topic>, anyhow::Error> {
+    let res = db
+        .execute(Statement::with_args(
+            "SELECT id, name FROM tags
+            WHERE EXISTS (SELECT 1 FROM catfact_tags
+                JOIN catfacts ON catfacts.id = catfact_tags.catfact_id
+                WHERE catfact_tags.tag_id = tags.id AND catfacts.needs_review = 0)
+            OR id IN (SELECT tag_id FROM subscriber_topics
+                WHERE subscriber_id = (SELECT id FROM subscribers WHERE token = ?))
+            ORDER BY name",
+            &[token],
+        ))
+        .await?;
+
+    store::rows::<Topic>(&res)
+}
+
+/// The link to a subscriber's preference center, included in every email footer.
+pub fn preferences_url(public_url: &str, token: &str) -> String {
+    format!("{}/preferences/{token}", public_url.trim_end_matches('/'))
+}
+
+/// `GET /preferences/:token` - the subscriber-facing preference center.
+#[utoipa::path(
+    get,
+    path = "/preferences/{token}",
+    tag = "subscriptions",
+    params(("token" = String, Path, description = "The subscriber's token, from the link in any email")),
+    responses(
+        (status = 200, description = "The preference center", body = String, content_type = "text/html"),
+        (status = 404, description = "The link isn't valid", body = String, content_type = "text/html"),
+    )
+)]
+pub async fn preferences_page(
+    State(state): State<Arc<AppState>>,
+    Path(token): Path<String>,
+) -> Result<impl IntoResponse, impl IntoResponse> {
+    let preferences = match state.db.preferences(&token).await {
+        Ok(Some(preferences)) => preferences,
+        Ok(None) => return Err((StatusCode::NOT_FOUND, Html(invalid_link_page()))),
+        Err(e) => return Err(html::server_error(TITLE, e)),
+    };
+    let topics = match topics(&*state.db, &token).await {
+        Ok(topics) => topics,
+        Err(e) => return Err(html::server_error(TITLE, e)),
+    };
+
+    Ok(Html(render_page(&token, &preferences, &topics, None)))
+}
+
+/// `POST /preferences/:token` - saves the preference center form.
+#[utoipa::path(
+    post,
+    path = "/preferences/{token}",
+    tag = "subscriptions",
+    params(("token" = String, Path, description = "The subscriber's token, from the link in any email")),
+    request_body(content = PreferencesForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Saved", body = String, content_type = "text/html"),
+        (status = 404, description = "The link isn't valid", body = String, content_type = "text/html"),
+    )
+)]
+pub async fn update_preferences(
+    State(state): State<Arc<AppState>>,
+    Path(token): Path<String>,
+    Form(form): Form<PreferencesForm>,
+) -> Result<impl IntoResponse, impl IntoResponse> {
+    if let Err(e) = state.db.set_topics(&token, &form.topics()).await {
+        return Err(html::server_error(TITLE, e));
+    }
+    let preferences = match state
+        .db
+        .update_preferences(
+            &token,
+            form.delivery_window,
+            form.email_format,
+            form.weekdays,
+            form.language,
+            form.timezone,
+        )
+        .await
+    {
+        Ok(Some(preferences)) => preferences,
+        Ok(None) => return Err((StatusCode::NOT_FOUND, Html(invalid_link_page()))),
+        Err(e) => return Err(html::server_error(TITLE, e)),
+    };
+    let topics = match topics(&*state.db, &token).await {
+        Ok(topics) => topics,
+        Err(e) => return Err(html::server_error(TITLE, e)),
+    };
+
+    Ok(Html(render_page(
+        &token,
+        &preferences,
+        &topics,
+        Some("Your preferences have been saved."),
+    )))
+}
+
+pub const UNSUBSCRIBED_MESSAGE: &str =
+    "<p>You've been unsubscribed and won't receive any more cat facts. Sorry to see you go!</p>";
+
+/// `POST /preferences/:token/unsubscribe` - removes the subscriber.
+#[utoipa::path(
+    post,
+    path = "/preferences/{token}/unsubscribe",
+    tag = "subscriptions",
+    params(("token" = String, Path, description = "The subscriber's token, from the link in any email")),
+    responses(
+        (status = 200, description = "Unsubscribed", body = String, content_type = "text/html"),
+        (status = 404, description = "The link isn't valid", body = String, content_type = "text/html"),
+    )
+)]
+pub async fn unsubscribe(
+    State(state): State<Arc<AppState>>,
+    Path(token): Path<String>,
+) -> Result<impl IntoResponse, impl IntoResponse> {
+    match state.db.remove_subscriber(&token).await {
+        Ok(true) => Ok(Html(html::page(TITLE, UNSUBSCRIBED_MESSAGE))),
+        Ok(false) => Err((StatusCode::NOT_FOUND, Html(invalid_link_page()))),
+        Err(e) => Err(html::server_error(TITLE, e)),
+    }
+}
+
+fn render_page(
+    token: &str,
+    preferences: &Preferences,
+    topics: &[Topic],
+    notice: Option<&str>,
+) -> String {
+    let token = escape(token);
+    let options = window_options(preferences.delivery_window);
+    let days = Weekdays::options(preferences.weekdays);
+    let formats: String = EmailFormat::ALL
+        .iter()
+        .map(|option| {
+            let selected = if *option == preferences.email_format {
+                " selected"
+            } else {
+                ""
+            };
+            format!(
+                r#"<option value="{}"{selected}>{}</option>"#,
+                option.name(),
+                option.label()
+            )
+        })
+        .collect();
+    let languages = language_options(preferences.language);
+    let timezones = timezone_options(preferences.timezone);
+    let topics = topic_checkboxes(topics, &preferences.topics);
+    let notice = notice
+        .map(|notice| format!("<p><strong>{}</strong></p>", escape(notice)))
+        .unwrap_or_default();
+
+    html::page(
+        TITLE,
+        &format!(
+            r#"<p>Managing the subscription for <strong>{email}</strong>.</p>
+{notice}
+<form method="post" action="/preferences/{token}">
+  <label for="delivery_window">Delivery time</label>
+  <select id="delivery_window" name="delivery_window">{options}</select>
+  <label for="timezone">Time zone</label>
+  <select id="timezone" name="timezone">{timezones}</select>
+  <label for="weekdays">Delivery days</label>
+  <select id="weekdays" name="weekdays">{days}</select>
+  <label for="email_format">Email format</label>
+  <select id="email_format" name="email_format">{formats}</select>
+  <label for="language">Email language</label>
+  <select id="language" name="language">{languages}</select>
+  {topics}
+  <button type="submit">Save preferences</button>
+</form>
+<form method="post" action="/preferences/{token}/unsubscribe">
+  <button type="submit">Unsubscribe</button>
+</form>"#,
+            email = escape(&preferences.email),
+        ),
+    )
+}
+
+/// A checkbox for each of `topics`, with the `chosen` ones checked, or
+/// nothing if there are no tags yet.
+fn topic_checkboxes(topics: &[Topic], chosen: &[i64]) -> String {
+    if topics.is_empty() {
+        return String::new();
+    }
+    let checkboxes: String = topics
+        .iter()
+        .map(|topic| {
+            let checked = if chosen.contains(&topic.id) {
+                " checked"
+            } else {
+                ""
+            };
+            format!(
+                r#"<label><input type="checkbox" name="topic_{id}"{checked}> {name}</label>"#,
+                id = topic.id,
+                name = escape(&topic.name),
+            )
+        })
+        .collect();
+
+    format!(
+        "<fieldset><legend>Topics</legend><p>Only get facts about these. Leave them all unchecked for every fact.</p>{checkboxes}</fieldset>"
+    )
+}
+
+/// The `<option>`s for a delivery window `<select>`.
+pub fn window_options(selected: DeliveryWindow) -> String {
+    DeliveryWindow::ALL
+        .iter()
+        .map(|window| {
+            let name = window.name();
+            let selected = if *window == selected { " selected" } else { "" };
+            format!(
+                r#"<option value="{name}"{selected}>{name} ({:02}:00)</option>"#,
+                window.hour()
+            )
+        })
+        .collect()
+}
+
+/// The `<option>`s for a language `<select>`.
+pub fn language_options(selected: Language) -> String {
+    Language::ALL
+        .iter()
+        .map(|language| {
+            let selected = if *language == selected {
+                " selected"
+            } else {
+                ""
+            };
+            format!(
+                r#"<option value="{}"{selected}>{}</option>"#,
+                language.name(),
+                language.label()
+            )
+        })
+        .collect()
+}
+
+/// The `<option>`s for a time zone `<select>`, with `selected` among them
+/// even if it isn't one of the usual offsets.
+pub fn timezone_options(selected: Timezone) -> String {
+    let mut timezones: Vec<Timezone> = Timezone::common().collect();
+    if !timezones.contains(&selected) {
+        timezones.push(selected);
+        timezones.sort();
+    }
+
+    timezones
+        .iter()
+        .map(|timezone| {
+            let selected = if *timezone == selected {
+                " selected"
+            } else {
+                ""
+            };
+            format!(
+                r#"<option value="{}"{selected}>{}</option>"#,
+                timezone.name(),
+                timezone.label()
+            )
+        })
+        .collect()
+}
+
+fn invalid_link_page() -> String {
+    html::page(
+        TITLE,
+        "<p>This link isn't valid anymore. You may already have unsubscribed.</p>",
+    )
+}
@@ -0,0 +1,112 @@
+//! Privacy mode, for running under stricter rules about personal data. With
+//! `PRIVACY_MODE=true`:
+//!
+//! - votes are keyed on a hash of the client address that changes every day,
+//!   so nothing stored can be tied back to an address, or to the same voter on
+//!   another day;
+//! - complaint reports, fact feedback, and the outbox once an email is
+//!   finished with, keep a keyed hash of the address in place of the address
+//!   itself.
+//!   The domain is kept, so per-domain counts still work;
+//! - request logs only show the first product in a user agent, e.g.
+//!   `Mozilla/5.0`.
+//!
+//! The hashes are keyed on `PRIVACY_KEY`, which has to stay the same between
+//! deploys for data requests to find an address's rows.
+use anyhow::anyhow;
+use chrono::NaiveDate;
+use sha1::{Digest, Sha1};
+use shuttle_secrets::SecretStore;
+use std::net::IpAddr;
+
+use crate::crypto;
+
+/// The longest user agent logged outside privacy mode.
+const MAX_USER_AGENT: usize = 256;
+
+#[derive(Clone, Default)]
+pub struct Privacy {
+    /// Set in privacy mode.
+    key: Option<Vec<u8>>,
+}
+
+impl Privacy {
+    pub fn from_secrets(store: &SecretStore) -> Result<Self, anyhow::Error> {
+        if store.get("PRIVACY_MODE").as_deref() != Some("true") {
+            return Ok(Self::default());
+        }
+
+        let key = store
+            .get("PRIVACY_KEY")
+            .filter(|key| !key.is_empty())
+            .ok_or_else(|| anyhow!("PRIVACY_MODE is on, so PRIVACY_KEY has to be set"))?;
+
+        Ok(Self {
+            key: Some(key.into_bytes()),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// How `voter` is stored on their votes: a hash of the address, which in
+    /// privacy mode is keyed and changes on `date`.
+    pub fn voter(&self, ip: IpAddr, date: NaiveDate) -> String {
+        match &self.key {
+            Some(key) => crypto::hex(&crypto::hmac_sha256(
+                key,
+                format!("voter:{date}:{ip}").as_bytes(),
+            )),
+            None => crypto::hex(&Sha1::digest(ip.to_string().as_bytes())),
+        }
+    }
+
+    /// Who an anonymous fact submission came from, as `submitted_by` keeps
+    /// it: a hash of the address, keyed in privacy mode.
+    pub fn submitter(&self, ip: IpAddr) -> String {
+        let hash = match &self.key {
+            Some(key) => crypto::hex(&crypto::hmac_sha256(
+                key,
+                format!("submitter:{ip}").as_bytes(),
+            )),
+            None => crypto::hex(&Sha1::digest(ip.to_string().as_bytes())),
+        };
+        format!("ip:{hash}")
+    }
+
+    /// How `email` is stored in event tables: as it is, or in privacy mode as
+    /// `<hash>@<domain>`.
+    pub fn subscriber_id(&self, email: &str) -> String {
+        match &self.key {
+            Some(key) => pseudonym(key, email),
+            None => email.to_string(),
+        }
+    }
+
+    /// Both ways `email` might be stored in event tables, for finding its
+    /// rows whether or not privacy mode was on when they were written: the
+    /// address and its pseudonym, or the address twice outside privacy mode.
+    pub fn subscriber_ids(&self, email: &str) -> [String; 2] {
+        [email.to_string(), self.subscriber_id(email)]
+    }
+
+    /// What's logged of a request's `User-Agent`.
+    pub fn user_agent(&self, user_agent: &str) -> String {
+        if self.enabled() {
+            user_agent
+                .split_whitespace()
+                .next()
+                .unwrap_or("")
+                .to_string()
+        } else {
+            user_agent.chars().take(MAX_USER_AGENT).collect()
+        }
+    }
+}
+
+fn pseudonym(key: &[u8], email: &str) -> String {
+    let domain = email.rsplit_once('@').map_or("", |(_, domain)| domain);
+    let hash = crypto::hmac_sha256(key, format!("subscriber:{email}").as_bytes());
+    format!("{}@{domain}", crypto::hex(&hash[..16]))
+}
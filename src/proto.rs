@@ -0,0 +1,29 @@
+//! Protobuf encodings of the fact responses, for consumers that send
+//! `Accept: application/x-protobuf`.
+//!
+//! The messages are generated from `proto/catfact.proto` at build time, so
+//! that file is the one to change.
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use prost::Message;
+
+pub const CONTENT_TYPE: &str = "application/x-protobuf";
+
+// Generated from `proto/catfact.proto` by `build.rs`.
+include!(concat!(env!("OUT_DIR"), "/catfacts.rs"));
+
+/// A protobuf response body, analogous to `axum::Json`.
+pub struct Protobuf<T>(pub T);
+
+impl<T: Message> IntoResponse for Protobuf<T> {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, HeaderValue::from_static(CONTENT_TYPE))],
+            self.0.encode_to_vec(),
+        )
+            .into_response()
+    }
+}
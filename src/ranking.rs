@@ -0,0 +1,329 @@
+//! Lets a deployment choose which facts `GET /catfact` returns and which one
+//! becomes the fact of the day, e.g. to prefer facts a cohort hasn't been sent
+//! yet. Without a ranker facts are picked uniformly, as they always were.
+//!
+//! A ranker is anything implementing `Ranker`, passed to `Ranking::new`, an
+//! HTTP service named by the `RANKING_URL` secret, or with
+//! `RANKING_STRATEGY=bandit`, the experimental built-in one in `bandit`. Either way it's given a
+//! random sample of the facts that could be picked and answers with the ids it
+//! prefers, best first. If it fails, times out or answers with nothing usable,
+//! the pick falls back to the uniform one, so a ranker can't take the API down.
+//!
+//! The HTTP service gets a `POST` like
+//! `{"purpose": "daily", "date": "2024-02-01", "tag": null, "count": 1,
+//! "candidates": [{"id": 12, "fact": "...", "created_at": "...", "score": 3,
+//! "times_sent": 2, "last_sent": "2024-01-03", "feedback_responses": 40,
+//! "feedback_nps": 25, "recipients": 300, "opens": 120}]}` and should answer
+//! `{"ids": [12]}`. The feedback is how subscribers scored the fact from its
+//! emails (see `feedback`), and the opens are counted while the bandit
+//! experiment is on (see `opens`).
+//! `RANKING_SECRET`, if set, is sent as a bearer token, and
+//! `RANKING_TIMEOUT_MS` (default 2000) bounds how long a pick waits.
+use anyhow::anyhow;
+use axum::async_trait;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use shuttle_secrets::SecretStore;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::bandit::{self, Bandit};
+use crate::store::{self, FromRow, Row, Statement, Store, Value};
+use crate::{feedback, opens, votes};
+
+/// How many facts a ranker is offered to choose from.
+const MAX_CANDIDATES: u32 = 100;
+
+const DEFAULT_TIMEOUT_MS: u64 = 2000;
+
+/// The share of days the bandit leaves to a uniform pick, to compare against.
+const DEFAULT_CONTROL_SHARE: f64 = 0.5;
+
+/// What a pick is for.
+#[derive(Clone, Copy)]
+pub enum Selection<'a> {
+    /// `GET /catfact`, optionally limited to facts with `tag`.
+    Random { tag: Option<&'a str> },
+    /// The fact of the day for `date`, which that day's emails send.
+    Daily { date: NaiveDate },
+}
+
+impl Selection<'_> {
+    fn purpose(&self) -> &'static str {
+        match self {
+            Self::Random { .. } => "random",
+            Self::Daily { .. } => "daily",
+        }
+    }
+}
+
+/// A fact a ranker can pick, with what we know about how it's done.
+#[derive(Serialize)]
+pub struct Candidate {
+    pub id: i64,
+    pub fact: String,
+    pub created_at: String,
+    /// Upvotes minus downvotes.
+    pub score: i64,
+    /// How many days it's been the fact of the day.
+    pub times_sent: i64,
+    /// The last day it was the fact of the day.
+    pub last_sent: Option<String>,
+    /// How many subscribers have scored it from an email.
+    pub feedback_responses: i64,
+    /// Its NPS from those scores, from -100 to 100, if it's had any.
+    pub feedback_nps: Option<i64>,
+    /// How many subscribers it's been emailed to while opens were counted.
+    pub recipients: i64,
+    /// How many of those emails were opened.
+    pub opens: i64,
+}
+
+impl FromRow for Candidate {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            id: store::integer(row, 0)?,
+            fact: store::text(row, 1)?,
+            created_at: store::text(row, 2)?,
+            score: store::integer(row, 3)?,
+            times_sent: store::integer(row, 4)?,
+            last_sent: store::optional_text(row, 5)?,
+            feedback_responses: store::integer(row, 6)?,
+            feedback_nps: store::optional_integer(row, 7)?,
+            recipients: store::integer(row, 8)?,
+            opens: store::integer(row, 9)?,
+        })
+    }
+}
+
+#[async_trait]
+pub trait Ranker: Send + Sync {
+    /// The ids of the `candidates` to pick for `selection`, best first. Only
+    /// the first `count` are used, and ids that weren't offered are ignored.
+    async fn rank(
+        &self,
+        selection: Selection<'_>,
+        count: u32,
+        candidates: &[Candidate],
+    ) -> Result<Vec<i64>, anyhow::Error>;
+}
+
+/// A ranker run as a separate HTTP service.
+pub struct HttpRanker {
+    url: String,
+    secret: Option<String>,
+    client: reqwest::Client,
+}
+
+#[derive(Serialize)]
+struct RankRequest<'a> {
+    purpose: &'static str,
+    date: Option<String>,
+    tag: Option<&'a str>,
+    count: u32,
+    candidates: &'a [Candidate],
+}
+
+#[derive(Deserialize)]
+struct RankResponse {
+    ids: Vec<i64>,
+}
+
+#[async_trait]
+impl Ranker for HttpRanker {
+    async fn rank(
+        &self,
+        selection: Selection<'_>,
+        count: u32,
+        candidates: &[Candidate],
+    ) -> Result<Vec<i64>, anyhow::Error> {
+        let (date, tag) = match selection {
+            Selection::Random { tag } => (None, tag),
+            Selection::Daily { date } => (Some(date.to_string()), None),
+        };
+        let mut request = self.client.post(&self.url).json(&RankRequest {
+            purpose: selection.purpose(),
+            date,
+            tag,
+            count,
+            candidates,
+        });
+        if let Some(secret) = &self.secret {
+            request = request.bearer_auth(secret);
+        }
+
+        let res: RankResponse = request
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| anyhow!("couldn't reach the ranker: {e}"))?
+            .json()
+            .await
+            .map_err(|e| anyhow!("unexpected response from the ranker: {e}"))?;
+
+        Ok(res.ids)
+    }
+}
+
+/// Picks facts with the configured ranker, if there is one.
+#[derive(Clone, Default)]
+pub struct Ranking {
+    ranker: Option<Arc<dyn Ranker>>,
+    timeout: Duration,
+    /// The share of days' facts picked uniformly instead, for the bandit
+    /// experiment; none otherwise.
+    control_share: f64,
+}
+
+impl Ranking {
+    pub fn new(ranker: impl Ranker + 'static) -> Self {
+        Self {
+            ranker: Some(Arc::new(ranker)),
+            timeout: Duration::from_millis(DEFAULT_TIMEOUT_MS),
+            control_share: 0.0,
+        }
+    }
+
+    pub fn from_secrets(store: &SecretStore) -> Result<Self, anyhow::Error> {
+        match store.get("RANKING_STRATEGY").as_deref().map(str::trim) {
+            None | Some("" | "uniform") => {}
+            Some("bandit") => {
+                if store.get("RANKING_URL").is_some() {
+                    return Err(anyhow!(
+                        "RANKING_STRATEGY=bandit and RANKING_URL can't both be set"
+                    ));
+                }
+                let control_share = match store.get("RANKING_CONTROL_SHARE") {
+                    Some(share) => share
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|share| (0.0..1.0).contains(share))
+                        .ok_or_else(|| {
+                            anyhow!(
+                                "RANKING_CONTROL_SHARE {share:?} should be a number from 0 up to 1"
+                            )
+                        })?,
+                    None => DEFAULT_CONTROL_SHARE,
+                };
+
+                return Ok(Self {
+                    control_share,
+                    ..Self::new(Bandit)
+                });
+            }
+            Some(strategy) => {
+                return Err(anyhow!(
+                    "RANKING_STRATEGY {strategy:?} should be uniform or bandit"
+                ))
+            }
+        }
+
+        let Some(url) = store.get("RANKING_URL") else {
+            return Ok(Self::default());
+        };
+        let timeout = match store.get("RANKING_TIMEOUT_MS") {
+            Some(ms) => ms
+                .parse()
+                .map_err(|_| anyhow!("RANKING_TIMEOUT_MS {ms:?} should be a number"))?,
+            None => DEFAULT_TIMEOUT_MS,
+        };
+
+        Ok(Self {
+            timeout: Duration::from_millis(timeout),
+            ..Self::new(HttpRanker {
+                url,
+                secret: store.get("RANKING_SECRET"),
+                client: reqwest::Client::new(),
+            })
+        })
+    }
+
+    /// Whether the bandit experiment is on.
+    pub fn is_experimenting(&self) -> bool {
+        self.control_share > 0.0
+    }
+
+    /// For the fact of the day: whether to leave this one to a uniform pick,
+    /// as the experiment's control, and if not, what `daily_facts.strategy`
+    /// to record if the ranker picks it.
+    pub fn assign(&self) -> (bool, &'static str) {
+        if !self.is_experimenting() {
+            return (false, "ranker");
+        }
+
+        (rand::random::<f64>() < self.control_share, bandit::BANDIT)
+    }
+
+    /// Up to `count` ids of facts matching `filter` (a condition on
+    /// `catfacts`, with `args`), best first, or `None` for the caller to make
+    /// its usual uniform pick instead.
+    pub async fn pick(
+        &self,
+        db: &dyn Store,
+        selection: Selection<'_>,
+        filter: &str,
+        args: &[Value],
+        count: u32,
+    ) -> Option<Vec<i64>> {
+        let ranker = self.ranker.as_ref()?;
+
+        let picked = tokio::time::timeout(self.timeout, async {
+            let mut args = args.to_vec();
+            args.push(Value::from(MAX_CANDIDATES));
+            let candidates = db
+                .execute(Statement::with_args(
+                    format!(
+                        "SELECT id, fact, created_at, {},
+                        (SELECT count(*) FROM daily_facts WHERE catfact_id = catfacts.id),
+                        (SELECT max(date) FROM daily_facts WHERE catfact_id = catfacts.id),
+                        {}, {}, {}, {}
+                        FROM catfacts WHERE {filter} ORDER BY random() LIMIT ?",
+                        votes::SCORE,
+                        feedback::RESPONSES,
+                        feedback::NPS,
+                        opens::RECIPIENTS,
+                        opens::OPENS
+                    ),
+                    &args,
+                ))
+                .await
+                .and_then(|res| store::rows::<Candidate>(&res))?;
+            if candidates.is_empty() {
+                return Ok(None);
+            }
+
+            let ranked = ranker.rank(selection, count, &candidates).await?;
+            let mut picked: Vec<i64> = Vec::new();
+            for id in ranked {
+                if candidates.iter().any(|candidate| candidate.id == id) && !picked.contains(&id) {
+                    picked.push(id);
+                }
+            }
+            picked.truncate(count as usize);
+
+            Ok::<_, anyhow::Error>(Some(picked))
+        })
+        .await
+        .map_err(|_| anyhow!("the ranker took longer than {:?}", self.timeout))
+        .and_then(|picked| picked);
+
+        match picked {
+            Ok(Some(picked)) if !picked.is_empty() => Some(picked),
+            Ok(None) => None,
+            Ok(Some(_)) => {
+                tracing::warn!(
+                    "The ranker didn't pick any {} facts, picking uniformly",
+                    selection.purpose()
+                );
+                None
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Couldn't rank {} facts, picking uniformly: {e}",
+                    selection.purpose()
+                );
+                None
+            }
+        }
+    }
+}
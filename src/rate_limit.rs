@@ -0,0 +1,450 @@
+//! Token buckets for the public write routes, so one client can't flood us
+//! with facts or signups. Each limit is a setting like `10/hour`: a client can
+//! make that many requests in a burst, and earns them back evenly over the
+//! period.
+//!
+//! Limits come in tiers. Anonymous requests are limited per IP; a request with
+//! an API key (see `auth`) is limited per key, at its tier's rate, so a partner
+//! app gets more room without switching the limit off for everyone else. Any
+//! tier's limit can be stored in the database like other settings (see
+//! `config`).
+//!
+//! - `RATE_LIMIT_SUBMIT` - fact submissions. Defaults to `10/hour`, with
+//!   `RATE_LIMIT_SUBMIT_FREE` (`100/hour`) and `RATE_LIMIT_SUBMIT_PARTNER`
+//!   (`1000/hour`) for keys.
+//! - `RATE_LIMIT_SUBSCRIBE` - signups. Defaults to `5/hour`, with
+//!   `RATE_LIMIT_SUBSCRIBE_FREE` (`20/hour`) and
+//!   `RATE_LIMIT_SUBSCRIBE_PARTNER` (`200/hour`).
+//! - `TRUSTED_PROXY` - `true` when the service is behind a proxy that
+//!   appends the client's address to `X-Forwarded-For`, like Shuttle's.
+//!   Otherwise the header is ignored and clients are told apart by the
+//!   connection's address.
+//!
+//! The buckets are our own rather than `governor`'s: its keyed limiters have
+//! one quota each, so every group of routes would need one per tier, and a
+//! key whose tier changed would have to be moved between them. A bucket here
+//! is two numbers in a map.
+use anyhow::anyhow;
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts, State},
+    http::{request::Parts, Extensions, HeaderMap, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use shuttle_secrets::SecretStore;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::auth;
+use crate::error::ApiError;
+use crate::store::{self, Statement, Store};
+use crate::AppState;
+
+/// Past this many tracked clients, buckets that have filled back up are
+/// dropped, since they'd behave the same as a new one.
+const MAX_TRACKED: usize = 10_000;
+
+/// How long a key's tier is remembered before it's looked up again, so a
+/// revoked key drops back to anonymous within this long.
+const TIER_TTL: Duration = Duration::from_secs(60);
+
+/// Who a limit applies to.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tier {
+    /// No API key, or one that isn't valid.
+    Anonymous,
+    Free,
+    Partner,
+}
+
+impl Tier {
+    fn index(&self) -> usize {
+        match self {
+            Self::Anonymous => 0,
+            Self::Free => 1,
+            Self::Partner => 2,
+        }
+    }
+
+    /// The suffix on a limit's setting for this tier.
+    fn suffix(&self) -> &'static str {
+        match self {
+            Self::Anonymous => "",
+            Self::Free => "_FREE",
+            Self::Partner => "_PARTNER",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Anonymous => "anonymous",
+            Self::Free => "free",
+            Self::Partner => "partner",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Self::Anonymous, Self::Free, Self::Partner]
+            .into_iter()
+            .find(|tier| tier.name() == name)
+    }
+}
+
+/// What a bucket is kept for: an anonymous client's address, or a key's hash.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Client {
+    Ip(IpAddr),
+    Key(String),
+}
+
+struct Bucket {
+    tier: Tier,
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// One tier's allowance.
+struct Rate {
+    capacity: f64,
+    per_sec: f64,
+}
+
+impl Rate {
+    fn parse(key: &str, value: &str) -> Result<Self, anyhow::Error> {
+        let invalid = || anyhow!("{key} {value:?} should look like 10/hour");
+
+        let (count, period) = value.split_once('/').ok_or_else(invalid)?;
+        let count: u32 = count.trim().parse().map_err(|_| invalid())?;
+        let period_secs = match period.trim() {
+            "second" => 1,
+            "minute" => 60,
+            "hour" => 60 * 60,
+            "day" => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        if count == 0 {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            capacity: f64::from(count),
+            per_sec: f64::from(count) / f64::from(period_secs),
+        })
+    }
+}
+
+pub struct RateLimit {
+    /// Indexed by `Tier::index`.
+    rates: [Rate; 3],
+    buckets: Mutex<HashMap<Client, Bucket>>,
+    addresses: ClientAddresses,
+}
+
+impl RateLimit {
+    /// Reads `key` and its tiers' settings, e.g. `RATE_LIMIT_SUBMIT` and
+    /// `RATE_LIMIT_SUBMIT_PARTNER`, falling back to `defaults` in tier order.
+    fn from_secrets(
+        store: &SecretStore,
+        key: &str,
+        defaults: [&str; 3],
+        addresses: ClientAddresses,
+    ) -> Result<Self, anyhow::Error> {
+        let rate = |tier: Tier| {
+            let key = format!("{key}{}", tier.suffix());
+            let value = store
+                .get(&key)
+                .unwrap_or_else(|| defaults[tier.index()].to_string());
+            Rate::parse(&key, &value)
+        };
+
+        Ok(Self {
+            rates: [
+                rate(Tier::Anonymous)?,
+                rate(Tier::Free)?,
+                rate(Tier::Partner)?,
+            ],
+            buckets: Mutex::new(HashMap::new()),
+            addresses,
+        })
+    }
+
+    /// Takes a token for `client`, or returns how many seconds until one's
+    /// free. A key whose tier has changed keeps its bucket, at the new tier's
+    /// rate and no more than its capacity.
+    fn take(&self, tier: Tier, client: Client) -> Result<(), u64> {
+        let now = Instant::now();
+        // A panic mid-update leaves at worst one bucket a token out.
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if buckets.len() >= MAX_TRACKED {
+            buckets.retain(|_, bucket| {
+                self.refilled(bucket, now) < self.rates[bucket.tier.index()].capacity
+            });
+        }
+
+        let rate = &self.rates[tier.index()];
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tier,
+            tokens: rate.capacity,
+            updated_at: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated_at = now;
+        if bucket.tier != tier {
+            bucket.tier = tier;
+            bucket.tokens = bucket.tokens.min(rate.capacity);
+        }
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / rate.per_sec).ceil() as u64)
+        }
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let rate = &self.rates[bucket.tier.index()];
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        (bucket.tokens + elapsed * rate.per_sec).min(rate.capacity)
+    }
+}
+
+/// The limits for each group of write routes.
+pub struct RateLimits {
+    pub submit: Arc<RateLimit>,
+    pub subscribe: Arc<RateLimit>,
+}
+
+impl RateLimits {
+    pub fn from_secrets(
+        store: &SecretStore,
+        addresses: ClientAddresses,
+    ) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            submit: Arc::new(RateLimit::from_secrets(
+                store,
+                "RATE_LIMIT_SUBMIT",
+                ["10/hour", "100/hour", "1000/hour"],
+                addresses,
+            )?),
+            subscribe: Arc::new(RateLimit::from_secrets(
+                store,
+                "RATE_LIMIT_SUBSCRIBE",
+                ["5/hour", "20/hour", "200/hour"],
+                addresses,
+            )?),
+        })
+    }
+}
+
+/// The tiers of the API keys clients send, looked up in `api_keys` and
+/// remembered for `TIER_TTL`.
+pub struct KeyTiers {
+    db: Arc<dyn Store>,
+    /// Keyed on the key's hash. `None` for a key that isn't valid.
+    known: Mutex<HashMap<String, (Option<Tier>, Instant)>>,
+}
+
+impl KeyTiers {
+    pub fn new(db: Arc<dyn Store>) -> Self {
+        Self {
+            db,
+            known: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The tier of the key with hash `key_hash`, if it's a valid one. Admin
+    /// keys count as partners.
+    async fn tier(&self, key_hash: &str) -> Option<Tier> {
+        if let Ok(known) = self.known.lock() {
+            if let Some((tier, checked_at)) = known.get(key_hash) {
+                if checked_at.elapsed() < TIER_TTL {
+                    return *tier;
+                }
+            }
+        }
+
+        let tier = match self
+            .db
+            .execute(Statement::with_args(
+                "SELECT coalesce(tier, 'partner') FROM api_keys WHERE key_hash = ? AND revoked_at IS NULL",
+                &[key_hash],
+            ))
+            .await
+            .and_then(|res| store::first::<String>(&res))
+        {
+            Ok(tier) => tier.as_deref().and_then(Tier::from_name),
+            Err(e) => {
+                tracing::warn!("Couldn't look up an API key's rate limit tier: {e}");
+                return None;
+            }
+        };
+
+        if let Ok(mut known) = self.known.lock() {
+            if known.len() >= MAX_TRACKED {
+                known.clear();
+            }
+            known.insert(key_hash.to_string(), (tier, Instant::now()));
+        }
+
+        tier
+    }
+}
+
+/// Where clients' addresses come from, set by `TRUSTED_PROXY`.
+#[derive(Clone, Copy)]
+pub struct ClientAddresses {
+    behind_proxy: bool,
+}
+
+impl ClientAddresses {
+    pub fn from_secrets(store: &SecretStore) -> Self {
+        Self {
+            behind_proxy: store.get("TRUSTED_PROXY").as_deref() == Some("true"),
+        }
+    }
+
+    /// The client's address. Behind a trusted proxy that's the last hop in
+    /// `X-Forwarded-For`, which the proxy appends and a client can't forge;
+    /// earlier entries could say anything, and without one, so could all of
+    /// it.
+    fn client_ip(&self, headers: &HeaderMap, extensions: &Extensions) -> Option<IpAddr> {
+        let forwarded = || {
+            headers
+                .get("x-forwarded-for")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.rsplit(',').next())
+                .and_then(|ip| ip.trim().parse().ok())
+        };
+        let connected = || {
+            extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip())
+        };
+
+        match self.behind_proxy {
+            true => forwarded().or_else(connected),
+            false => connected(),
+        }
+    }
+}
+
+/// Extractor for the client's address, for handlers that key something on it.
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for ClientIp {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        state
+            .client_addresses
+            .client_ip(&parts.headers, &parts.extensions)
+            .map(ClientIp)
+            .ok_or_else(|| ApiError::internal("Couldn't tell the client's address"))
+    }
+}
+
+/// Middleware that answers 429, with a `Retry-After`, once a client has used up
+/// its bucket: its key's, if it sent a valid one, or otherwise its address's.
+pub async fn limit<B>(
+    State((limit, tiers)): State<(Arc<RateLimit>, Arc<KeyTiers>)>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let key_hash = auth::provided_key(request.headers()).map(auth::hash_key);
+    let keyed = match key_hash {
+        Some(key_hash) => tiers
+            .tier(&key_hash)
+            .await
+            .map(|tier| (tier, Client::Key(key_hash))),
+        None => None,
+    };
+    let client = keyed.or_else(|| {
+        limit
+            .addresses
+            .client_ip(request.headers(), request.extensions())
+            .map(|ip| (Tier::Anonymous, Client::Ip(ip)))
+    });
+    let Some((tier, client)) = client else {
+        return next.run(request).await;
+    };
+
+    match limit.take(tier, client) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => ApiError::RateLimited(retry_after).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(rates: [&str; 3]) -> RateLimit {
+        RateLimit {
+            rates: rates.map(|rate| Rate::parse("RATE_LIMIT_TEST", rate).unwrap()),
+            buckets: Mutex::new(HashMap::new()),
+            addresses: ClientAddresses {
+                behind_proxy: false,
+            },
+        }
+    }
+
+    #[test]
+    fn parses_rates() {
+        let rate = Rate::parse("RATE_LIMIT_TEST", "10/hour").unwrap();
+        assert_eq!(rate.capacity, 10.0);
+        assert_eq!(rate.per_sec, 10.0 / 3600.0);
+        assert_eq!(
+            Rate::parse("RATE_LIMIT_TEST", " 2 / second ")
+                .unwrap()
+                .per_sec,
+            2.0
+        );
+
+        for invalid in ["10", "0/hour", "-1/hour", "10/week", "ten/hour"] {
+            assert!(
+                Rate::parse("RATE_LIMIT_TEST", invalid).is_err(),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn takes_a_burst_then_says_when_to_retry() {
+        let limit = limit(["2/minute", "100/minute", "1000/minute"]);
+        let client = || Client::Ip([192, 0, 2, 1].into());
+
+        assert_eq!(limit.take(Tier::Anonymous, client()), Ok(()));
+        assert_eq!(limit.take(Tier::Anonymous, client()), Ok(()));
+        assert_eq!(limit.take(Tier::Anonymous, client()), Err(30));
+
+        // Another client has a bucket of its own.
+        assert_eq!(
+            limit.take(Tier::Anonymous, Client::Ip([192, 0, 2, 2].into())),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn follows_a_key_to_its_new_tier() {
+        let limit = limit(["1/minute", "3/minute", "1/minute"]);
+        let key = || Client::Key("hash".to_string());
+
+        assert_eq!(limit.take(Tier::Free, key()), Ok(()));
+
+        // Moved to a tier with a smaller bucket, it keeps only as many of its
+        // two tokens left as that holds, and earns them back at the new rate.
+        assert_eq!(limit.take(Tier::Partner, key()), Ok(()));
+        assert_eq!(limit.take(Tier::Partner, key()), Err(60));
+    }
+}
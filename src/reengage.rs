@@ -0,0 +1,294 @@
+//! Re-engagement: with `REENGAGE_AFTER_MONTHS` set, the `reengage` job finds
+//! subscribers who haven't opened an email in that many months (see `opens`)
+//! and asks them once whether they still want cat facts, with a signed
+//! stay-subscribed link. Anyone who neither follows it nor opens an email
+//! within `REENGAGE_GRACE_DAYS` is unsubscribed.
+//!
+//! Opens are only a hint, since plenty of mail clients block images, which is
+//! why nobody is unsubscribed without being asked first. Nobody is asked
+//! until opens have been counted for the whole period, either.
+use anyhow::anyhow;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse},
+};
+use chrono::{Duration, Utc};
+use lettre::message::Mailbox;
+use serde::Deserialize;
+use shuttle_secrets::SecretStore;
+use std::sync::Arc;
+use utoipa::IntoParams;
+
+use crate::mailer::{Email, Mail};
+use crate::store::{self, FromRow, Row, Statement, Store, Value};
+use crate::unsubscribe::UnsubscribeSigner;
+use crate::{html, AppState};
+
+const TITLE: &str = "Cat Facts - Still there?";
+
+/// How long a subscriber has to answer, unless `REENGAGE_GRACE_DAYS` says
+/// otherwise.
+const DEFAULT_GRACE_DAYS: i64 = 14;
+
+/// The most subscribers one run of the job asks.
+const ASKED_PER_RUN: i64 = 500;
+
+/// When to ask, and how long to wait for an answer.
+#[derive(Clone)]
+pub struct Policy {
+    after_months: i64,
+    grace_days: i64,
+    signer: UnsubscribeSigner,
+}
+
+impl Policy {
+    /// `None` unless `REENGAGE_AFTER_MONTHS` is set. The links are signed with
+    /// `UNSUBSCRIBE_SIGNING_KEY`, so that has to be set too.
+    pub fn from_secrets(
+        store: &SecretStore,
+        signer: Option<UnsubscribeSigner>,
+    ) -> Result<Option<Self>, anyhow::Error> {
+        let Some(after_months) = store.get("REENGAGE_AFTER_MONTHS") else {
+            return Ok(None);
+        };
+        let after_months = positive(&after_months, "REENGAGE_AFTER_MONTHS")?;
+        let grace_days = match store.get("REENGAGE_GRACE_DAYS") {
+            Some(days) => positive(&days, "REENGAGE_GRACE_DAYS")?,
+            None => DEFAULT_GRACE_DAYS,
+        };
+        let signer = signer.ok_or_else(|| {
+            anyhow!("REENGAGE_AFTER_MONTHS needs UNSUBSCRIBE_SIGNING_KEY, to sign its links")
+        })?;
+
+        Ok(Some(Self {
+            after_months,
+            grace_days,
+            signer,
+        }))
+    }
+}
+
+fn positive(value: &str, key: &str) -> Result<i64, anyhow::Error> {
+    value
+        .trim()
+        .parse()
+        .ok()
+        .filter(|value: &i64| *value > 0)
+        .ok_or_else(|| anyhow!("{key} should be a positive number"))
+}
+
+/// Asks inactive subscribers if they want to stay, for the `reengage` job.
+pub struct Reengagement {
+    pub mailer: Mail,
+    pub sender: Option<Mailbox>,
+    pub public_url: String,
+    pub policy: Option<Policy>,
+}
+
+/// What one run of the `reengage` job did.
+#[derive(Default)]
+pub struct Reengaged {
+    pub asked: usize,
+    pub failed: usize,
+    pub unsubscribed: usize,
+}
+
+struct Inactive {
+    id: i64,
+    email: String,
+    token: String,
+}
+
+impl FromRow for Inactive {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            id: store::integer(row, 0)?,
+            email: store::text(row, 1)?,
+            token: store::text(row, 2)?,
+        })
+    }
+}
+
+impl Reengagement {
+    /// Unsubscribes whoever was asked more than the grace period ago and
+    /// hasn't answered, then asks subscribers who've gone the whole period
+    /// without opening an email, once each. Opening one after being asked
+    /// counts as an answer.
+    pub async fn run(&self, db: &dyn Store) -> Result<Reengaged, anyhow::Error> {
+        let mut report = Reengaged::default();
+        let Some(policy) = &self.policy else {
+            return Ok(report);
+        };
+        let grace = format!("-{} days", policy.grace_days);
+        let inactive = format!("-{} months", policy.after_months);
+
+        let res = db
+            .execute(Statement::with_args(
+                "SELECT token FROM subscribers
+                WHERE reengage_sent_at <= datetime('now', ?)
+                AND (last_opened_at IS NULL OR last_opened_at < reengage_sent_at)
+                AND token IS NOT NULL",
+                &[grace.as_str()],
+            ))
+            .await?;
+        for token in store::rows::<String>(&res)? {
+            if db.remove_subscriber(&token).await? {
+                report.unsubscribed += 1;
+            }
+        }
+
+        // Whoever opened an email since being asked stays, and won't be asked
+        // again for another whole period.
+        db.execute(
+            "UPDATE subscribers SET stayed_at = last_opened_at, reengage_sent_at = NULL
+            WHERE last_opened_at >= reengage_sent_at",
+        )
+        .await?;
+
+        if !self.tracked_since(db, &inactive).await? || self.mailer.is_paused().await {
+            return Ok(report);
+        }
+
+        let res = db
+            .execute(Statement::with_args(
+                "SELECT id, email, token FROM subscribers
+                WHERE confirmed = 1 AND needs_review = 0 AND token IS NOT NULL
+                AND reengage_sent_at IS NULL
+                AND max(created_at, coalesce(last_opened_at, created_at), coalesce(stayed_at, created_at))
+                <= datetime('now', ?1)
+                AND lower(email) NOT IN (SELECT email FROM suppressions)
+                ORDER BY id LIMIT ?2",
+                &[Value::from(inactive), Value::from(ASKED_PER_RUN)],
+            ))
+            .await?;
+
+        for subscriber in store::rows::<Inactive>(&res)? {
+            // Claimed before it's sent, so it only ever goes once, even if
+            // sending fails or another instance is running the job too. A
+            // failed send still starts the grace period: the address's mail
+            // isn't getting through either way.
+            let claimed = db
+                .execute(Statement::with_args(
+                    "UPDATE subscribers SET reengage_sent_at = current_timestamp
+                    WHERE id = ? AND reengage_sent_at IS NULL",
+                    &[subscriber.id],
+                ))
+                .await?;
+            if claimed.rows_affected == 0 {
+                continue;
+            }
+
+            match self.ask(policy, &subscriber).await {
+                Ok(()) => report.asked += 1,
+                Err(e) => {
+                    tracing::warn!(
+                        "Couldn't ask subscriber {} if they're still reading: {e}",
+                        subscriber.id
+                    );
+                    report.failed += 1;
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Whether opens have been counted since at least `period` ago, going by
+    /// the first day's fact that was sent with them counted.
+    async fn tracked_since(&self, db: &dyn Store, period: &str) -> Result<bool, anyhow::Error> {
+        let res = db
+            .execute(Statement::with_args(
+                "SELECT count(*) FROM daily_facts
+                WHERE recipients > 0 AND date <= date('now', ?)",
+                &[period],
+            ))
+            .await?;
+
+        Ok(store::first::<i64>(&res)?.unwrap_or(0) > 0)
+    }
+
+    async fn ask(&self, policy: &Policy, subscriber: &Inactive) -> Result<(), anyhow::Error> {
+        let Some(sender) = &self.sender else {
+            return Err(anyhow!(
+                "MAIL_FROM (or GMAIL_USER) isn't a valid email address, so re-engagement emails can't be sent"
+            ));
+        };
+        let to: Mailbox = subscriber
+            .email
+            .parse()
+            .map_err(|e| anyhow!("{:?} isn't a valid email address: {e}", subscriber.email))?;
+        let unsubscribe_url = policy
+            .signer
+            .unsubscribe_url(&self.public_url, &subscriber.token);
+
+        let email = Email {
+            unsubscribe_url: Some(unsubscribe_url.clone()),
+            ..Email::new(sender, &to, "Still want cat facts?", format!(
+                "Hey there! It looks like you haven't opened a cat fact in a while, so we're checking you still want them.\n\nTo keep getting them, follow this link: {}\n\nIf we don't hear from you by {}, we'll unsubscribe you. To stop now, follow this one instead: {unsubscribe_url}",
+                policy.signer.stay_subscribed_url(&self.public_url, &subscriber.token),
+                (Utc::now() + Duration::days(policy.grace_days)).format("%B %-d"),
+            ))
+        };
+
+        self.mailer.send(&email).await
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StayQuery {
+    /// The signed token from the re-engagement email.
+    token: String,
+}
+
+/// `GET /stay-subscribed?token=` - keeps a subscriber who was asked if they
+/// still want cat facts.
+#[utoipa::path(
+    get,
+    path = "/stay-subscribed",
+    tag = "subscriptions",
+    params(StayQuery),
+    responses(
+        (status = 200, description = "Still subscribed", body = String, content_type = "text/html"),
+        (status = 404, description = "The link isn't valid (anymore)", body = String, content_type = "text/html"),
+    )
+)]
+pub async fn stay_subscribed(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<StayQuery>,
+) -> Result<impl IntoResponse, impl IntoResponse> {
+    let not_found = || {
+        (
+            StatusCode::NOT_FOUND,
+            Html(html::page(
+                TITLE,
+                "<p>This link isn't valid anymore. You may already have been unsubscribed; you're welcome to sign up again.</p>",
+            )),
+        )
+    };
+    let Some(token) = state
+        .unsubscribe
+        .as_ref()
+        .and_then(|signer| signer.verify_stay_subscribed(&query.token))
+    else {
+        return Err(not_found());
+    };
+
+    match state
+        .db
+        .execute(Statement::with_args(
+            "UPDATE subscribers SET stayed_at = current_timestamp, reengage_sent_at = NULL
+            WHERE token = ?",
+            &[token],
+        ))
+        .await
+    {
+        Ok(res) if res.rows_affected > 0 => Ok(Html(html::page(
+            TITLE,
+            "<p>Great, you're staying! The cat facts will keep coming.</p>",
+        ))),
+        Ok(_) => Err(not_found()),
+        Err(e) => Err(html::server_error(TITLE, e)),
+    }
+}
@@ -0,0 +1,74 @@
+//! Gives every request an ID, which tags each log line written while handling
+//! it and is echoed back in `x-request-id`, so a user reporting a problem can
+//! quote it. A caller (or a proxy in front of us) can send its own. The span
+//! also has the caller's user agent, cut down to the first product in
+//! privacy mode.
+use axum::{
+    extract::State,
+    http::{header, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use std::time::Instant;
+use tracing::Instrument;
+
+use crate::privacy::Privacy;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+const MAX_LEN: usize = 64;
+
+/// The caller's ID, as long as it's short and safe to log; otherwise a new one.
+fn request_id<B>(request: &Request<B>) -> String {
+    request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_LEN
+                && id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        })
+        .map_or_else(|| uuid::Uuid::new_v4().to_string(), str::to_string)
+}
+
+/// Middleware that runs the rest of the request inside a span carrying its ID.
+pub async fn tag<B>(
+    State(privacy): State<Privacy>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let id = request_id(&request);
+    let user_agent = request
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(|user_agent| privacy.user_agent(user_agent));
+    let span = tracing::info_span!(
+        "request",
+        request_id = %id,
+        method = %request.method(),
+        path = %request.uri().path(),
+        user_agent = user_agent.as_deref(),
+    );
+
+    async move {
+        let started = Instant::now();
+        let mut response = next.run(request).await;
+        tracing::info!(
+            status = response.status().as_u16(),
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "Handled request"
+        );
+
+        if let Ok(value) = HeaderValue::from_str(&id) {
+            response.headers_mut().insert(REQUEST_ID_HEADER, value);
+        }
+
+        response
+    }
+    .instrument(span)
+    .await
+}
@@ -0,0 +1,195 @@
+//! Keeps the event tables from growing forever. The `maintenance` job deletes
+//! rows older than each table's retention period, first adding them to daily
+//! counts in `event_rollups`, so the totals survive after the rows are gone.
+//!
+//! - `RETENTION_AUDIT_LOG_DAYS` - `audit_log`, counted by action. Defaults to
+//!   365.
+//! - `RETENTION_SUBSCRIBER_EVENTS_DAYS` - `subscriber_events`, counted by
+//!   event. Defaults to 180. Subscriber analytics read the counts along with
+//!   the rows, so they don't change when rows are rolled up.
+//! - `RETENTION_EMAIL_OUTBOX_DAYS` - `email_outbox`, counted by status.
+//!   Defaults to 30.
+//! - `RETENTION_COMPLAINT_EVENTS_DAYS` - `complaint_events`, counted by
+//!   feedback type. Defaults to 365. Suppressions are kept regardless.
+//!
+//! `0` keeps a table's rows forever. Request logs only go to the service's log
+//! output, so they aren't kept here at all.
+use anyhow::anyhow;
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use shuttle_secrets::SecretStore;
+use std::sync::Arc;
+
+use crate::error::ApiError;
+use crate::store::{self, FromRow, Row, Statement, Store, Value};
+use crate::AppState;
+
+/// A table that's trimmed, with the columns its rows are dated and counted by.
+struct Table {
+    name: &'static str,
+    timestamp: &'static str,
+    kind: &'static str,
+    key: &'static str,
+    default_days: u32,
+}
+
+const TABLES: &[Table] = &[
+    Table {
+        name: "audit_log",
+        timestamp: "created_at",
+        kind: "action",
+        key: "RETENTION_AUDIT_LOG_DAYS",
+        default_days: 365,
+    },
+    Table {
+        name: "subscriber_events",
+        timestamp: "occurred_at",
+        kind: "event",
+        key: "RETENTION_SUBSCRIBER_EVENTS_DAYS",
+        default_days: 180,
+    },
+    Table {
+        name: "email_outbox",
+        timestamp: "created_at",
+        kind: "status",
+        key: "RETENTION_EMAIL_OUTBOX_DAYS",
+        default_days: 30,
+    },
+    Table {
+        name: "complaint_events",
+        timestamp: "received_at",
+        kind: "feedback_type",
+        key: "RETENTION_COMPLAINT_EVENTS_DAYS",
+        default_days: 365,
+    },
+];
+
+/// How many days of each table's rows to keep. `None` keeps them all.
+#[derive(Clone)]
+pub struct Retention {
+    days: Vec<Option<u32>>,
+}
+
+impl Retention {
+    pub fn from_secrets(store: &SecretStore) -> Result<Self, anyhow::Error> {
+        let days = TABLES
+            .iter()
+            .map(|table| match store.get(table.key) {
+                Some(days) => days
+                    .trim()
+                    .parse::<u32>()
+                    .map_err(|_| anyhow!("{} {days:?} should be a number of days", table.key)),
+                None => Ok(table.default_days),
+            })
+            .map(|days| days.map(|days| (days > 0).then_some(days)))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { days })
+    }
+
+    /// Rolls up and deletes each table's rows from before its retention
+    /// period, a table at a time. Days are rolled up whole, so a day's count
+    /// is either all in `event_rollups` or all still in the table.
+    pub async fn enforce(&self, db: &dyn Store) -> Result<(), anyhow::Error> {
+        let today = Utc::now().date_naive();
+
+        for (table, days) in TABLES.iter().zip(&self.days) {
+            let Some(days) = days else {
+                continue;
+            };
+            let cutoff = (today - Duration::days(i64::from(*days))).to_string();
+            let Table {
+                name,
+                timestamp,
+                kind,
+                ..
+            } = table;
+
+            let results = db
+                .batch([
+                    Statement::with_args(
+                        format!(
+                            "INSERT INTO event_rollups (source, day, kind, count)
+                            SELECT '{name}', date({timestamp}), {kind}, count(*) FROM {name}
+                            WHERE date({timestamp}) < ? GROUP BY date({timestamp}), {kind}
+                            ON CONFLICT (source, day, kind) DO UPDATE SET count = count + excluded.count"
+                        ),
+                        &[&cutoff],
+                    ),
+                    Statement::with_args(
+                        format!("DELETE FROM {name} WHERE date({timestamp}) < ?"),
+                        &[&cutoff],
+                    ),
+                ])
+                .await
+                .map_err(|e| anyhow!("couldn't trim {name}: {e}"))?;
+
+            let deleted = results.get(1).map_or(0, |res| res.rows_affected);
+            if deleted > 0 {
+                tracing::info!("Rolled up and deleted {deleted} {name} rows from before {cutoff}");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+pub struct Rollup {
+    source: String,
+    day: String,
+    kind: String,
+    count: i64,
+}
+
+impl FromRow for Rollup {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            source: store::text(row, 0)?,
+            day: store::text(row, 1)?,
+            kind: store::text(row, 2)?,
+            count: store::integer(row, 3)?,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RollupQuery {
+    source: Option<String>,
+}
+
+/// `GET /admin/rollups?source=audit_log` - the daily counts kept for rows
+/// that have been deleted, oldest first.
+pub async fn list_rollups(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<RollupQuery>,
+) -> Result<Json<Vec<Rollup>>, ApiError> {
+    if let Some(source) = &query.source {
+        if !TABLES.iter().any(|table| table.name == source) {
+            return Err(ApiError::Validation(format!(
+                "There are no rollups for {source:?}; the sources are {}",
+                TABLES
+                    .iter()
+                    .map(|table| table.name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )));
+        }
+    }
+
+    let rollups = state
+        .db
+        .execute(Statement::with_args(
+            "SELECT source, day, kind, count FROM event_rollups
+            WHERE ?1 IS NULL OR source = ?1 ORDER BY day, source, kind",
+            &[query.source.as_deref().map_or(Value::Null, Value::from)],
+        ))
+        .await
+        .and_then(|res| store::rows::<Rollup>(&res))?;
+
+    Ok(Json(rollups))
+}
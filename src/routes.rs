@@ -0,0 +1,317 @@
+//! Metadata about every route the API serves. Deprecated routes get
+//! `Deprecation`, `Sunset` and `Link` headers automatically, and their usage is
+//! counted so we can tell when it's safe to remove them. Unknown paths get a
+//! problem+json 404 suggesting the closest registered routes.
+use axum::{
+    extract::{MatchedPath, State},
+    http::{header, HeaderValue, Method, Request, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::NaiveDate;
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::AppState;
+
+/// The most suggestions a 404 will list.
+const MAX_SUGGESTIONS: usize = 3;
+
+pub struct Deprecation {
+    /// When the route was deprecated.
+    pub since: NaiveDate,
+    /// When the route will stop working.
+    pub sunset: NaiveDate,
+    /// The route clients should move to.
+    pub successor: &'static str,
+}
+
+pub struct RouteInfo {
+    pub method: Method,
+    pub path: &'static str,
+    pub deprecation: Option<Deprecation>,
+}
+
+impl RouteInfo {
+    fn new(method: Method, path: &'static str) -> Self {
+        Self {
+            method,
+            path,
+            deprecation: None,
+        }
+    }
+
+    fn deprecated(mut self, since: NaiveDate, sunset: NaiveDate, successor: &'static str) -> Self {
+        self.deprecation = Some(Deprecation {
+            since,
+            sunset,
+            successor,
+        });
+        self
+    }
+}
+
+pub struct RouteRegistry {
+    routes: Vec<RouteInfo>,
+    deprecated_hits: Vec<AtomicU64>,
+}
+
+impl RouteRegistry {
+    /// Every public route. Keep this in step with the router in `main`.
+    pub fn new() -> Self {
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+
+        let routes = vec![
+            RouteInfo::new(Method::GET, "/"),
+            RouteInfo::new(Method::GET, "/health").deprecated(
+                date(2026, 10, 14),
+                date(2027, 4, 14),
+                "/health/live",
+            ),
+            RouteInfo::new(Method::GET, "/health/live"),
+            RouteInfo::new(Method::GET, "/health/ready"),
+            RouteInfo::new(Method::GET, "/metrics"),
+            RouteInfo::new(Method::GET, "/badge.svg"),
+            RouteInfo::new(Method::GET, "/stats/subscribers"),
+            RouteInfo::new(Method::GET, "/stats/subscribers.svg"),
+            RouteInfo::new(Method::GET, "/catfact"),
+            RouteInfo::new(Method::GET, "/catfact/:key"),
+            RouteInfo::new(Method::PUT, "/catfact/:key"),
+            RouteInfo::new(Method::DELETE, "/catfact/:key"),
+            RouteInfo::new(Method::GET, "/catfact/today"),
+            RouteInfo::new(Method::POST, "/catfact/bulk"),
+            RouteInfo::new(Method::POST, "/catfact/from-url"),
+            RouteInfo::new(Method::GET, "/catfacts"),
+            RouteInfo::new(Method::GET, "/catfacts/top"),
+            RouteInfo::new(Method::GET, "/catfacts/search"),
+            RouteInfo::new(Method::GET, "/catfacts/as-of"),
+            RouteInfo::new(Method::GET, "/changelog"),
+            RouteInfo::new(Method::GET, "/openapi.json"),
+            RouteInfo::new(Method::GET, "/docs"),
+            RouteInfo::new(Method::GET, "/feed.xml"),
+            RouteInfo::new(Method::GET, "/feed.rss"),
+            RouteInfo::new(Method::GET, "/feed.atom"),
+            RouteInfo::new(Method::GET, "/feed.json"),
+            RouteInfo::new(Method::GET, "/sitemap.xml"),
+            RouteInfo::new(Method::GET, "/tags"),
+            RouteInfo::new(Method::GET, "/weekly/:week"),
+            RouteInfo::new(Method::GET, "/archive"),
+            RouteInfo::new(Method::GET, "/archive/:date"),
+            RouteInfo::new(Method::POST, "/catfact/:key/vote"),
+            RouteInfo::new(Method::GET, "/feedback/:token/:score"),
+            RouteInfo::new(Method::GET, "/open/:token"),
+            RouteInfo::new(Method::POST, "/catfact/create").deprecated(
+                date(2026, 10, 14),
+                date(2027, 4, 14),
+                "/v1/catfacts",
+            ),
+            RouteInfo::new(Method::POST, "/v1/catfacts"),
+            RouteInfo::new(Method::GET, "/subscribe"),
+            RouteInfo::new(Method::POST, "/subscribe"),
+            RouteInfo::new(Method::GET, "/confirm"),
+            RouteInfo::new(Method::POST, "/subscriber/data-request"),
+            RouteInfo::new(Method::GET, "/subscriber/data"),
+            RouteInfo::new(Method::GET, "/subscriber/erase"),
+            RouteInfo::new(Method::POST, "/subscriber/erase"),
+            RouteInfo::new(Method::DELETE, "/subscriber"),
+            RouteInfo::new(Method::GET, "/preferences/:token"),
+            RouteInfo::new(Method::POST, "/preferences/:token"),
+            RouteInfo::new(Method::POST, "/preferences/:token/unsubscribe"),
+            RouteInfo::new(Method::GET, "/unsubscribe"),
+            RouteInfo::new(Method::POST, "/unsubscribe"),
+            RouteInfo::new(Method::GET, "/stay-subscribed"),
+            RouteInfo::new(Method::POST, "/webhooks/complaints"),
+            RouteInfo::new(Method::GET, "/admin/analytics/subscribers"),
+            RouteInfo::new(Method::GET, "/admin/analytics/domains"),
+            RouteInfo::new(Method::GET, "/admin/sync/facts"),
+            RouteInfo::new(Method::POST, "/admin/sync/pull"),
+            RouteInfo::new(Method::POST, "/admin/send-daily"),
+            RouteInfo::new(Method::POST, "/admin/send-digest"),
+            RouteInfo::new(Method::GET, "/admin/waitlist"),
+            RouteInfo::new(Method::POST, "/admin/waitlist/release"),
+            RouteInfo::new(Method::GET, "/admin/catfacts/flagged"),
+            RouteInfo::new(Method::GET, "/admin/config/export"),
+            RouteInfo::new(Method::GET, "/admin/export/facts"),
+            RouteInfo::new(Method::POST, "/admin/config/import"),
+            RouteInfo::new(Method::POST, "/admin/templates/lint"),
+            RouteInfo::new(Method::GET, "/admin/lockdown"),
+            RouteInfo::new(Method::POST, "/admin/lockdown"),
+            RouteInfo::new(Method::POST, "/admin/lockdown/clear"),
+            RouteInfo::new(Method::GET, "/admin/audit-log"),
+            RouteInfo::new(Method::GET, "/admin/rollups"),
+            RouteInfo::new(Method::GET, "/admin/outbox"),
+            RouteInfo::new(Method::GET, "/admin/queue"),
+            RouteInfo::new(Method::GET, "/admin/dead-letters"),
+            RouteInfo::new(Method::GET, "/admin/sends/:date/report.csv"),
+            RouteInfo::new(Method::POST, "/admin/queue/retry"),
+            RouteInfo::new(Method::POST, "/admin/queue/:id/retry"),
+            RouteInfo::new(Method::GET, "/admin/migrations"),
+            RouteInfo::new(Method::POST, "/admin/migrations/post-deploy"),
+            RouteInfo::new(Method::GET, "/admin/feedback"),
+            RouteInfo::new(Method::GET, "/admin/tag-rules"),
+            RouteInfo::new(Method::POST, "/admin/tag-rules"),
+            RouteInfo::new(Method::DELETE, "/admin/tag-rules/:id"),
+            RouteInfo::new(Method::GET, "/admin/tags"),
+            RouteInfo::new(Method::POST, "/admin/tags/:id/rename"),
+            RouteInfo::new(Method::POST, "/admin/tags/merge"),
+            RouteInfo::new(Method::GET, "/admin/tags/review"),
+            RouteInfo::new(Method::POST, "/admin/tags/review"),
+            RouteInfo::new(Method::GET, "/admin/ranking/experiment"),
+            RouteInfo::new(Method::GET, "/admin/calendar/:date"),
+            RouteInfo::new(Method::PUT, "/admin/calendar/:date"),
+            RouteInfo::new(Method::DELETE, "/admin/calendar/:date"),
+            RouteInfo::new(Method::POST, "/admin/catfacts/:id/approve"),
+            RouteInfo::new(Method::POST, "/admin/suppressions"),
+            RouteInfo::new(Method::GET, "/admin/suppressions/:email"),
+            RouteInfo::new(Method::DELETE, "/admin/suppressions/:email"),
+            RouteInfo::new(Method::GET, "/admin/api-keys"),
+            RouteInfo::new(Method::POST, "/admin/api-keys"),
+            RouteInfo::new(Method::DELETE, "/admin/api-keys/:id"),
+        ];
+        let deprecated_hits = routes.iter().map(|_| AtomicU64::new(0)).collect();
+
+        Self {
+            routes,
+            deprecated_hits,
+        }
+    }
+
+    fn position(&self, method: &Method, path: &str) -> Option<usize> {
+        self.routes
+            .iter()
+            .position(|route| route.method == method && route.path == path)
+    }
+
+    pub fn routes(&self) -> &[RouteInfo] {
+        &self.routes
+    }
+
+    /// Registered paths that look like typos of `path`, closest first.
+    /// Parameter segments such as `:key` match any single segment.
+    pub fn suggestions(&self, path: &str) -> Vec<&'static str> {
+        let mut candidates: Vec<(usize, &'static str)> = Vec::new();
+
+        for route in &self.routes {
+            if candidates.iter().any(|(_, seen)| *seen == route.path) {
+                continue;
+            }
+
+            let distance = levenshtein(&fill_params(route.path, path), path);
+            if distance <= (path.len() / 3).max(3) {
+                candidates.push((distance, route.path));
+            }
+        }
+
+        candidates.sort();
+        candidates
+            .into_iter()
+            .take(MAX_SUGGESTIONS)
+            .map(|(_, path)| path)
+            .collect()
+    }
+
+    /// How many times each deprecated route has been called since startup.
+    pub fn deprecated_usage(&self) -> impl Iterator<Item = (&RouteInfo, u64)> {
+        self.routes
+            .iter()
+            .zip(&self.deprecated_hits)
+            .filter(|(route, _)| route.deprecation.is_some())
+            .map(|(route, hits)| (route, hits.load(Ordering::Relaxed)))
+    }
+}
+
+/// Middleware that adds deprecation headers to, and counts calls of, any route
+/// the registry marks as deprecated.
+pub async fn track_deprecations<B>(
+    State(registry): State<Arc<RouteRegistry>>,
+    matched_path: Option<MatchedPath>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let position = matched_path.and_then(|path| registry.position(request.method(), path.as_str()));
+
+    let mut response = next.run(request).await;
+
+    let Some(position) = position else {
+        return response;
+    };
+    let Some(deprecation) = &registry.routes[position].deprecation else {
+        return response;
+    };
+
+    registry.deprecated_hits[position].fetch_add(1, Ordering::Relaxed);
+
+    let headers = response.headers_mut();
+    let since = deprecation.since.and_hms_opt(0, 0, 0).unwrap().timestamp();
+    let sunset = deprecation.sunset.and_hms_opt(0, 0, 0).unwrap();
+
+    if let Ok(value) = HeaderValue::from_str(&format!("@{since}")) {
+        headers.insert("deprecation", value);
+    }
+    if let Ok(value) =
+        HeaderValue::from_str(&sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+    {
+        headers.insert("sunset", value);
+    }
+    if let Ok(value) = HeaderValue::from_str(&format!(
+        "<{}>; rel=\"successor-version\"",
+        deprecation.successor
+    )) {
+        headers.insert(header::LINK, value);
+    }
+
+    response
+}
+
+/// Fallback for requests that don't match any route.
+pub async fn not_found(State(state): State<Arc<AppState>>, uri: Uri) -> Response {
+    let body = json!({
+        "type": "about:blank",
+        "title": "Not Found",
+        "status": 404,
+        "detail": format!("No route matches {}", uri.path()),
+        "did_you_mean": state.routes.suggestions(uri.path()),
+    });
+
+    (
+        StatusCode::NOT_FOUND,
+        [(header::CONTENT_TYPE, "application/problem+json")],
+        body.to_string(),
+    )
+        .into_response()
+}
+
+/// Replaces each `:param` segment of `route` with the matching segment of
+/// `path`, so `/catfact/:key` is compared to `/catfact/42` as `/catfact/42`.
+fn fill_params(route: &str, path: &str) -> String {
+    let mut requested = path.split('/');
+
+    route
+        .split('/')
+        .map(|segment| {
+            let actual = requested.next();
+            match (segment.starts_with(':'), actual) {
+                (true, Some(actual)) if !actual.is_empty() => actual,
+                _ => segment,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
@@ -0,0 +1,110 @@
+//! Cleans user-submitted content (facts, mostly) before it goes into an email.
+//! Facts are untrusted input, so they must never be able to add headers, inject
+//! markup or smuggle in invisible control characters.
+use crate::html;
+
+/// Makes `text` safe for a single-line header field such as the subject: line
+/// breaks would otherwise let a fact start new headers, so all whitespace runs
+/// (including CR/LF) collapse to single spaces and control characters are dropped.
+pub fn header_value(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| word.chars().filter(|c| is_safe(*c)).collect::<String>())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Makes `text` safe for a plain-text body: line endings are normalised to
+/// `\n`, and control and bidi-override characters are removed. Tabs and
+/// newlines are kept.
+pub fn plain_text(text: &str) -> String {
+    text.replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .chars()
+        .filter(|c| matches!(c, '\n' | '\t') || is_safe(*c))
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Makes `text` safe to interpolate into an HTML body.
+pub fn html_text(text: &str) -> String {
+    html::escape(&plain_text(text))
+}
+
+fn is_safe(c: char) -> bool {
+    !c.is_control() && !is_bidi_control(c)
+}
+
+// Explicit directional formatting characters can make an email display
+// something different from what it contains.
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_value_cannot_start_new_headers() {
+        assert_eq!(
+            header_value("Cats purr\r\nBcc: victim@example.com"),
+            "Cats purr Bcc: victim@example.com"
+        );
+        assert_eq!(header_value("a\rb\nc\r\n\r\nd"), "a b c d");
+        assert!(!header_value("x\u{85}Bcc: y\u{2028}z").contains(['\r', '\n', '\u{85}']));
+    }
+
+    #[test]
+    fn header_value_drops_controls_and_bidi_overrides() {
+        assert_eq!(header_value("cat\0s \u{7}purr"), "cats purr");
+        assert_eq!(header_value("gpj.\u{202E}exe"), "gpj.exe");
+        assert_eq!(header_value("\u{2066}isolated\u{2069}"), "isolated");
+        // Nothing but controls leaves nothing, not a stray space.
+        assert_eq!(header_value(" \0 \u{202E} "), "");
+    }
+
+    #[test]
+    fn plain_text_normalises_line_endings_and_strips_controls() {
+        assert_eq!(plain_text("one\r\ntwo\rthree\n"), "one\ntwo\nthree");
+        assert_eq!(plain_text("tab\there"), "tab\there");
+        assert_eq!(plain_text("nul\0 \u{1b}[31mred\u{202D}"), "nul [31mred");
+    }
+
+    #[test]
+    fn html_text_escapes_markup() {
+        assert_eq!(
+            html_text("<script>alert(1)</script>"),
+            "&lt;script&gt;alert(1)&lt;/script&gt;"
+        );
+        // Breaking out of an attribute the text is interpolated into.
+        assert_eq!(
+            html_text(r#"" onmouseover="alert(1)"#),
+            "&quot; onmouseover=&quot;alert(1)"
+        );
+        assert_eq!(html_text("it's"), "it&apos;s");
+    }
+
+    #[test]
+    fn html_text_escapes_entities_rather_than_decoding_them() {
+        // An entity that spells out markup stays inert text.
+        assert_eq!(html_text("&lt;b&gt;"), "&amp;lt;b&amp;gt;");
+        assert_eq!(html_text("&#60;script&#62;"), "&amp;#60;script&amp;#62;");
+        // Lookalike and full-width brackets aren't markup, so they're kept.
+        assert_eq!(html_text("\u{FF1C}b\u{FF1E}"), "\u{FF1C}b\u{FF1E}");
+        assert_eq!(html_text("<\u{202E}script>"), "&lt;script&gt;");
+    }
+
+    #[test]
+    fn over_long_input_is_cleaned_throughout() {
+        let long = "<purr\0\r\n".repeat(100_000);
+        let header = header_value(&long);
+        assert!(!header.contains(['\r', '\n', '\0']));
+        assert_eq!(header.split(' ').count(), 100_000);
+
+        let html = html_text(&long);
+        assert!(!html.contains(['<', '\0', '\r']));
+        assert_eq!(html.matches("&lt;").count(), 100_000);
+    }
+}
@@ -0,0 +1,409 @@
+//! Runs the service's recurring jobs on cron schedules. Expressions use the
+//! `cron` crate's syntax, which starts with a seconds field:
+//! `sec min hour day-of-month month day-of-week [year]`.
+//!
+//! - `SCHEDULE_CRON` - when the daily send runs, e.g. `0 0 * * * *` (the
+//!   default, the top of every hour). Each run sends to every delivery window
+//!   that's opened since the last one sent, from yesterday's on, so with
+//!   `0 0 8,18 * * *` the 08:00 run sends the midnight and morning windows and
+//!   the 18:00 run the noon and evening ones. It can also be a `;`-separated list of `job=expression`s
+//!   to override several jobs at once, e.g.
+//!   `send=0 0 8,18 * * *;pick_fact=0 30 23 * * *`. The `weekly` job compiles
+//!   the previous week's best-of page, by default at 01:00 on Mondays, and
+//!   the `maintenance` job trims the event tables (see `retention`), by
+//!   default at 03:30 every day. The `retry` job resends failed emails from
+//!   the outbox once they're due, checking every minute, and the
+//!   `confirmations` job reminds and then deletes signups nobody's confirmed
+//!   (see `confirm`), at quarter past every hour. The `reengage` job asks
+//!   subscribers who've stopped opening emails if they still want them, and
+//!   unsubscribes those who don't answer (see `reengage`), at 04:45 every day.
+//! - `SCHEDULE_TIMEZONE` - the zone the schedules are in, and the delivery
+//!   hours of subscribers without a time zone of their own (see `timezone`):
+//!   `local` (the default), `UTC`, or a fixed offset such as `+05:30`.
+//!
+//! On a stop, the job that's running is left to finish and no more are
+//! started (see `shutdown`). The `retry` job, and each start, also resume any
+//! send a stopped instance saved.
+use anyhow::anyhow;
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use cron::Schedule;
+use shuttle_secrets::SecretStore;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::sleep;
+
+use crate::confirm::Confirmations;
+use crate::ranking::Ranking;
+use crate::reengage::Reengagement;
+use crate::retention::Retention;
+use crate::shutdown::Shutdown;
+use crate::store::Store;
+use crate::timezone::Timezone;
+use crate::weekly::{IsoWeek, WeeklyDigest};
+use crate::{daily, dispatch::Dispatcher};
+
+/// The jobs the scheduler knows how to run, with their default schedules.
+#[derive(Clone, Copy, PartialEq)]
+enum Task {
+    /// Picks tomorrow's fact of the day, so tomorrow's readers and mail find
+    /// it ready.
+    PickFact,
+    /// Sends the fact of the day to whoever is due.
+    Send,
+    /// Puts together last week's best-of page.
+    Weekly,
+    /// Rolls up and deletes old events.
+    Maintenance,
+    /// Sends queued emails that are due another attempt.
+    Retry,
+    /// Follows up on unconfirmed signups.
+    Confirmations,
+    /// Checks in with subscribers who've stopped opening emails.
+    Reengage,
+}
+
+impl Task {
+    /// In the order they run when due at the same moment.
+    const ALL: [Task; 7] = [
+        Self::PickFact,
+        Self::Send,
+        Self::Retry,
+        Self::Confirmations,
+        Self::Reengage,
+        Self::Weekly,
+        Self::Maintenance,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::PickFact => "pick_fact",
+            Self::Send => "send",
+            Self::Weekly => "weekly",
+            Self::Maintenance => "maintenance",
+            Self::Retry => "retry",
+            Self::Confirmations => "confirmations",
+            Self::Reengage => "reengage",
+        }
+    }
+
+    fn default_schedule(&self) -> &'static str {
+        match self {
+            Self::PickFact => "0 0 0 * * *",
+            Self::Send => "0 0 * * * *",
+            Self::Weekly => "0 0 1 * * Mon",
+            Self::Maintenance => "0 30 3 * * *",
+            Self::Retry => "0 * * * * *",
+            Self::Confirmations => "0 15 * * * *",
+            Self::Reengage => "0 45 4 * * *",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|task| task.name() == name)
+    }
+}
+
+struct Job {
+    task: Task,
+    schedule: Schedule,
+}
+
+/// What the jobs need besides the dispatcher and database.
+pub struct Jobs {
+    pub weekly: WeeklyDigest,
+    pub ranking: Ranking,
+    pub retention: Retention,
+    pub confirmations: Confirmations,
+    pub reengagement: Reengagement,
+}
+
+/// What the jobs run against.
+struct Context<'a> {
+    zone: Zone,
+    dispatcher: &'a Mutex<Dispatcher>,
+    db: &'a dyn Store,
+    weekly: &'a WeeklyDigest,
+    ranking: &'a Ranking,
+    retention: &'a Retention,
+    confirmations: &'a Confirmations,
+    reengagement: &'a Reengagement,
+}
+
+/// The time zone the schedules run in, which also decides the date of "today"
+/// for the fact of the day.
+#[derive(Clone, Copy)]
+pub enum Zone {
+    Local,
+    Fixed(FixedOffset),
+}
+
+impl Zone {
+    fn parse(zone: &str) -> Result<Self, anyhow::Error> {
+        match zone.trim() {
+            "" | "local" => Ok(Self::Local),
+            offset => Timezone::parse(offset)
+                .and_then(|offset| offset.offset())
+                .map(Self::Fixed)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "SCHEDULE_TIMEZONE {zone:?} should be local, UTC or an offset like +05:30"
+                    )
+                }),
+        }
+    }
+
+    /// When `schedule` next fires after `after`.
+    fn next_after(&self, schedule: &Schedule, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Local => schedule
+                .after(&after.with_timezone(&Local))
+                .next()
+                .map(|at| at.with_timezone(&Utc)),
+            Self::Fixed(offset) => schedule
+                .after(&after.with_timezone(offset))
+                .next()
+                .map(|at| at.with_timezone(&Utc)),
+        }
+    }
+
+    /// Today's date in this zone.
+    pub fn today(&self) -> NaiveDate {
+        self.now().date()
+    }
+
+    /// The wall-clock time in this zone now.
+    pub fn now(&self) -> NaiveDateTime {
+        self.wall_clock(Utc::now())
+    }
+
+    /// The wall-clock time in this zone at `at`.
+    pub fn wall_clock(&self, at: DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            Self::Local => at.with_timezone(&Local).naive_local(),
+            Self::Fixed(offset) => at.with_timezone(offset).naive_local(),
+        }
+    }
+
+    /// When the wall clock in this zone reads `time`. A time the clocks
+    /// skip, going forward for daylight saving time, is taken as the hour
+    /// after; one they pass twice, going back, as the first.
+    pub fn instant(&self, time: NaiveDateTime) -> DateTime<Utc> {
+        match self {
+            Self::Local => Local
+                .from_local_datetime(&time)
+                .earliest()
+                .or_else(|| {
+                    Local
+                        .from_local_datetime(&(time + chrono::Duration::hours(1)))
+                        .earliest()
+                })
+                .map_or_else(|| Utc.from_utc_datetime(&time), |at| at.with_timezone(&Utc)),
+            Self::Fixed(offset) => Utc.from_utc_datetime(&(time - *offset)),
+        }
+    }
+}
+
+pub struct Scheduler {
+    zone: Zone,
+    jobs: Vec<Job>,
+}
+
+impl Scheduler {
+    /// Reads the schedules, failing at boot on an expression or job name that
+    /// doesn't parse.
+    pub fn from_secrets(store: &SecretStore) -> Result<Self, anyhow::Error> {
+        let zone = Zone::parse(&store.get("SCHEDULE_TIMEZONE").unwrap_or_default())?;
+        let cron = store.get("SCHEDULE_CRON").unwrap_or_default();
+        let overrides = parse_overrides(&cron)?;
+
+        let jobs = Task::ALL
+            .into_iter()
+            .map(|task| {
+                let expression = overrides
+                    .iter()
+                    .find(|(name, _)| *name == task)
+                    .map_or(task.default_schedule(), |(_, expression)| expression);
+
+                Schedule::from_str(expression)
+                    .map(|schedule| Job { task, schedule })
+                    .map_err(|e| {
+                        anyhow!(
+                            "the {} schedule {expression:?} isn't valid: {e}",
+                            task.name()
+                        )
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { zone, jobs })
+    }
+
+    pub fn zone(&self) -> Zone {
+        self.zone
+    }
+
+    /// Runs the jobs until `shutdown`, each time sleeping until the next one
+    /// is due.
+    pub async fn run(
+        self,
+        dispatcher: Arc<Mutex<Dispatcher>>,
+        db: Arc<dyn Store>,
+        jobs: Jobs,
+        shutdown: Shutdown,
+    ) {
+        resume(&dispatcher, self.zone.today()).await;
+        let context = Context {
+            zone: self.zone,
+            dispatcher: &dispatcher,
+            db: &*db,
+            weekly: &jobs.weekly,
+            ranking: &jobs.ranking,
+            retention: &jobs.retention,
+            confirmations: &jobs.confirmations,
+            reengagement: &jobs.reengagement,
+        };
+        let mut cursor = Utc::now();
+        // When the send job last ran, or before that, when this started.
+        let mut last_send = cursor;
+
+        loop {
+            let Some(next) = self
+                .jobs
+                .iter()
+                .filter_map(|job| self.zone.next_after(&job.schedule, cursor))
+                .min()
+            else {
+                tracing::error!("No scheduled jobs will ever run again");
+                return;
+            };
+
+            if let Ok(wait) = next.signed_duration_since(Utc::now()).to_std() {
+                tokio::select! {
+                    _ = sleep(wait) => {},
+                    _ = shutdown.requested() => return,
+                }
+            }
+
+            for job in &self.jobs {
+                if self.zone.next_after(&job.schedule, cursor) == Some(next) {
+                    run_job(job.task, last_send, next, &context).await;
+                    if job.task == Task::Send {
+                        last_send = next;
+                    }
+                }
+                if shutdown.is_requested() {
+                    return;
+                }
+            }
+
+            cursor = next;
+        }
+    }
+}
+
+/// Runs `task`, due at `now`. `since` is when the send job last ran.
+#[tracing::instrument(skip(task, since, context), fields(job = task.name()))]
+async fn run_job(task: Task, since: DateTime<Utc>, now: DateTime<Utc>, context: &Context<'_>) {
+    let Context {
+        zone,
+        dispatcher,
+        db,
+        weekly,
+        ranking,
+        retention,
+        confirmations,
+        reengagement,
+    } = *context;
+    let today = zone.wall_clock(now).date();
+    match task {
+        Task::PickFact => {
+            if let Some(tomorrow) = today.succ_opt() {
+                if let Err(e) = daily::materialize(db, ranking, tomorrow).await {
+                    tracing::error!("Couldn't pick the fact of the day for {tomorrow}: {e}");
+                }
+            }
+        }
+        Task::Send => {
+            if let Err(e) = dispatcher.lock().await.send_scheduled(since, now).await {
+                tracing::error!("Something went wrong trying to send subscriber mail: {e}");
+            }
+        }
+        Task::Weekly => {
+            if let Err(e) = weekly.compile(db, IsoWeek::before(today)).await {
+                tracing::error!("Couldn't compile the weekly page: {e}");
+            }
+        }
+        Task::Retry => {
+            resume(dispatcher, today).await;
+            if let Err(e) = dispatcher.lock().await.retry_failed().await {
+                tracing::error!("Couldn't retry queued emails: {e}");
+            }
+        }
+        Task::Confirmations => match confirmations.follow_up(db).await {
+            Ok(report) => {
+                if report.reminded + report.failed > 0 || report.purged > 0 {
+                    tracing::info!(
+                        "Reminded {} unconfirmed signups ({} failed) and deleted {} expired ones",
+                        report.reminded,
+                        report.failed,
+                        report.purged
+                    );
+                }
+            }
+            Err(e) => tracing::error!("Couldn't follow up on unconfirmed signups: {e}"),
+        },
+        Task::Reengage => match reengagement.run(db).await {
+            Ok(report) => {
+                if report.asked + report.failed + report.unsubscribed > 0 {
+                    tracing::info!(
+                        "Asked {} inactive subscribers if they're still reading ({} failed) and unsubscribed {} who didn't answer",
+                        report.asked,
+                        report.failed,
+                        report.unsubscribed
+                    );
+                }
+            }
+            Err(e) => tracing::error!("Couldn't check in with inactive subscribers: {e}"),
+        },
+        Task::Maintenance => {
+            if let Err(e) = retention.enforce(db).await {
+                tracing::error!("Couldn't trim old events: {e}");
+            }
+        }
+    }
+}
+
+/// Sends whatever a stopped instance saved of its queue, with `date`'s fact.
+async fn resume(dispatcher: &Mutex<Dispatcher>, date: NaiveDate) {
+    if let Err(e) = dispatcher.lock().await.resume(date).await {
+        tracing::error!("Couldn't resume a stopped instance's send: {e}");
+    }
+}
+
+/// Parses `SCHEDULE_CRON`: either a bare expression for the send job, or a
+/// list of `job=expression`s.
+fn parse_overrides(value: &str) -> Result<Vec<(Task, &str)>, anyhow::Error> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(Vec::new());
+    }
+    if !value.contains('=') {
+        return Ok(vec![(Task::Send, value)]);
+    }
+
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (name, expression) = entry.split_once('=').ok_or_else(|| {
+                anyhow!("SCHEDULE_CRON entry {entry:?} should look like job=expression")
+            })?;
+
+            Task::from_name(name.trim())
+                .map(|task| (task, expression.trim()))
+                .ok_or_else(|| anyhow!("SCHEDULE_CRON names an unknown job {name:?}"))
+        })
+        .collect()
+}
@@ -0,0 +1,914 @@
+//! The database schema, changed in expand/contract steps so a deploy never
+//! has to stop the service:
+//!
+//! - `migrate` runs at boot, before the new code serves any traffic. It only
+//!   makes additive changes (new tables, columns and indexes) that the
+//!   previous version of the code keeps working with, plus any
+//!   `Phase::PreDeploy` migrations.
+//! - `Phase::PostDeploy` migrations contract the schema - dropping, tightening
+//!   or rewriting what the previous version still relied on. They only run
+//!   when asked for with `POST /admin/migrations/post-deploy` (see
+//!   `migrations`), once the previous version has stopped and the backfills
+//!   are done.
+//!
+//! Named migrations run once each, and are recorded in `schema_migrations`.
+use anyhow::anyhow;
+
+use crate::store::{self, FromRow, Row, Statement, Store};
+
+/// The tables and columns (with their declared types) the code expects once
+/// `migrate` has run. Keep this in step with any new migration.
+const EXPECTED: &[(&str, &[(&str, &str)])] = &[
+    (
+        "catfacts",
+        &[
+            ("id", "integer"),
+            ("fact", "text"),
+            ("created_at", "datetime"),
+            ("slug", "text"),
+            ("fact_id", "text"),
+            ("spam_score", "real"),
+            ("needs_review", "integer"),
+            ("license", "text"),
+            ("submitted_by", "text"),
+        ],
+    ),
+    (
+        "subscribers",
+        &[
+            ("id", "integer"),
+            ("email", "text"),
+            ("created_at", "datetime"),
+            ("delivery_hour", "integer"),
+            ("token", "text"),
+            ("needs_review", "integer"),
+            ("email_format", "text"),
+            ("confirmed", "integer"),
+            ("confirmation_token", "text"),
+            ("weekdays", "integer"),
+            ("confirmation_sent_at", "datetime"),
+            ("confirmation_reminded", "integer"),
+            ("language", "text"),
+            ("utc_offset", "integer"),
+            ("last_opened_at", "datetime"),
+            ("reengage_sent_at", "datetime"),
+            ("stayed_at", "datetime"),
+        ],
+    ),
+    (
+        "suppressions",
+        &[
+            ("email", "text"),
+            ("reason", "text"),
+            ("created_at", "datetime"),
+        ],
+    ),
+    (
+        "complaint_events",
+        &[
+            ("id", "integer"),
+            ("email", "text"),
+            ("source", "text"),
+            ("feedback_type", "text"),
+            ("received_at", "datetime"),
+        ],
+    ),
+    (
+        "subscriber_events",
+        &[
+            ("id", "integer"),
+            ("event", "text"),
+            ("delivery_hour", "integer"),
+            ("occurred_at", "datetime"),
+        ],
+    ),
+    (
+        "sync_checkpoints",
+        &[
+            ("source", "text"),
+            ("last_id", "integer"),
+            ("synced_at", "datetime"),
+        ],
+    ),
+    (
+        "data_requests",
+        &[
+            ("token", "text"),
+            ("email", "text"),
+            ("created_at", "datetime"),
+        ],
+    ),
+    (
+        "event_rollups",
+        &[
+            ("source", "text"),
+            ("day", "text"),
+            ("kind", "text"),
+            ("count", "integer"),
+        ],
+    ),
+    (
+        "email_outbox",
+        &[
+            ("id", "integer"),
+            ("recipient", "text"),
+            ("sender", "text"),
+            ("message", "text"),
+            ("status", "text"),
+            ("attempts", "integer"),
+            ("last_error", "text"),
+            ("next_attempt_at", "datetime"),
+            ("created_at", "datetime"),
+            ("sent_at", "datetime"),
+            ("relay", "text"),
+            ("send_date", "text"),
+        ],
+    ),
+    (
+        "email_dead_letters",
+        &[
+            ("outbox_id", "integer"),
+            ("recipient", "text"),
+            ("final_error", "text"),
+            ("attempts", "integer"),
+            ("dead_at", "datetime"),
+            ("retried_at", "datetime"),
+        ],
+    ),
+    (
+        "daily_facts",
+        &[
+            ("date", "text"),
+            ("catfact_id", "integer"),
+            ("created_at", "datetime"),
+            ("recipients", "integer"),
+            ("strategy", "text"),
+        ],
+    ),
+    (
+        "fact_opens",
+        &[
+            ("date", "text"),
+            ("subscriber", "text"),
+            ("catfact_id", "integer"),
+            ("opened_at", "datetime"),
+        ],
+    ),
+    (
+        "fact_feedback",
+        &[
+            ("date", "text"),
+            ("subscriber", "text"),
+            ("catfact_id", "integer"),
+            ("score", "integer"),
+            ("created_at", "datetime"),
+            ("updated_at", "datetime"),
+        ],
+    ),
+    (
+        "dispatch_queue",
+        &[
+            ("id", "integer"),
+            ("delivery_window", "text"),
+            ("after", "integer"),
+            ("reached", "text"),
+            ("domains", "text"),
+            ("queued_at", "integer"),
+            ("saved_at", "datetime"),
+        ],
+    ),
+    (
+        "dispatch_windows",
+        &[("delivery_window", "text"), ("started_at", "datetime")],
+    ),
+    (
+        "schema_migrations",
+        &[
+            ("name", "text"),
+            ("phase", "text"),
+            ("applied_at", "datetime"),
+        ],
+    ),
+    (
+        "waitlist",
+        &[
+            ("id", "integer"),
+            ("email", "text"),
+            ("delivery_hour", "integer"),
+            ("weekdays", "integer"),
+            ("created_at", "datetime"),
+            ("language", "text"),
+            ("utc_offset", "integer"),
+        ],
+    ),
+    (
+        "settings",
+        &[
+            ("key", "text"),
+            ("value", "text"),
+            ("updated_at", "datetime"),
+        ],
+    ),
+    (
+        "weekly_digests",
+        &[
+            ("week", "text"),
+            ("html", "text"),
+            ("created_at", "datetime"),
+        ],
+    ),
+    (
+        "tags",
+        &[
+            ("id", "integer"),
+            ("name", "text"),
+            ("created_at", "datetime"),
+        ],
+    ),
+    (
+        "subscriber_topics",
+        &[("subscriber_id", "integer"), ("tag_id", "integer")],
+    ),
+    (
+        "catfact_tags",
+        &[
+            ("catfact_id", "integer"),
+            ("tag_id", "integer"),
+            ("rule_id", "integer"),
+            ("reviewed_at", "datetime"),
+        ],
+    ),
+    (
+        "tag_rules",
+        &[
+            ("id", "integer"),
+            ("tag_id", "integer"),
+            ("kind", "text"),
+            ("pattern", "text"),
+            ("created_at", "datetime"),
+        ],
+    ),
+    (
+        "votes",
+        &[
+            ("catfact_id", "integer"),
+            ("voter", "text"),
+            ("value", "integer"),
+            ("created_at", "datetime"),
+            ("updated_at", "datetime"),
+        ],
+    ),
+    (
+        "calendar",
+        &[
+            ("date", "text"),
+            ("catfact_id", "integer"),
+            ("note", "text"),
+            ("updated_at", "datetime"),
+        ],
+    ),
+    (
+        "content_version",
+        &[("id", "integer"), ("version", "integer")],
+    ),
+    (
+        "catfact_revisions",
+        &[
+            ("id", "integer"),
+            ("catfact_id", "integer"),
+            ("fact", "text"),
+            ("fact_id", "text"),
+            ("license", "text"),
+            ("needs_review", "integer"),
+            ("deleted", "integer"),
+            ("changed_at", "datetime"),
+        ],
+    ),
+    (
+        "audit_log",
+        &[
+            ("id", "integer"),
+            ("actor", "text"),
+            ("action", "text"),
+            ("detail", "text"),
+            ("created_at", "datetime"),
+        ],
+    ),
+    (
+        "lockdown",
+        &[
+            ("id", "integer"),
+            ("reason", "text"),
+            ("started_by", "text"),
+            ("started_at", "datetime"),
+        ],
+    ),
+    (
+        "api_keys",
+        &[
+            ("id", "integer"),
+            ("name", "text"),
+            ("key_hash", "text"),
+            ("created_at", "datetime"),
+            ("revoked_at", "datetime"),
+            ("tier", "text"),
+        ],
+    ),
+];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Phase {
+    /// Needed before the new code can serve traffic; `GET /health/ready`
+    /// fails until these have been applied.
+    PreDeploy,
+    /// Only safe once the previous version has stopped serving.
+    PostDeploy,
+}
+
+impl Phase {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::PreDeploy => "pre_deploy",
+            Self::PostDeploy => "post_deploy",
+        }
+    }
+}
+
+struct Migration {
+    name: &'static str,
+    phase: Phase,
+    /// Run together in one transaction.
+    statements: &'static [&'static str],
+}
+
+/// Named migrations, in the order they run. Never edit or reorder one that's
+/// shipped; add a new one instead.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        name: "unique_subscriber_emails",
+        phase: Phase::PostDeploy,
+        // Addresses used to be stored as typed, so the same person could be
+        // subscribed several times. Keep one row per address, preferring a
+        // confirmed one, before making addresses unique. Signups compare
+        // addresses case-insensitively, so they're correct on both sides of
+        // this.
+        statements: &[
+            "DELETE FROM subscribers WHERE id != (
+            SELECT keep.id FROM subscribers keep
+            WHERE lower(trim(keep.email)) = lower(trim(subscribers.email))
+            ORDER BY keep.confirmed DESC, keep.id LIMIT 1
+            )",
+            "UPDATE subscribers SET email = lower(trim(email)) WHERE email != lower(trim(email))",
+            "CREATE UNIQUE INDEX IF NOT EXISTS subscribers_email ON subscribers (email)",
+        ],
+    },
+    Migration {
+        name: "catfacts_search",
+        phase: Phase::PreDeploy,
+        // A full-text index over facts for `GET /catfacts/search`, kept in
+        // step with `catfacts` by triggers and filled from the facts already
+        // there.
+        statements: &[
+            "CREATE VIRTUAL TABLE IF NOT EXISTS catfacts_fts USING fts5(
+            fact, content='catfacts', content_rowid='id'
+            )",
+            "CREATE TRIGGER IF NOT EXISTS catfacts_fts_insert AFTER INSERT ON catfacts BEGIN
+            INSERT INTO catfacts_fts (rowid, fact) VALUES (new.id, new.fact);
+            END",
+            "CREATE TRIGGER IF NOT EXISTS catfacts_fts_delete AFTER DELETE ON catfacts BEGIN
+            INSERT INTO catfacts_fts (catfacts_fts, rowid, fact) VALUES ('delete', old.id, old.fact);
+            END",
+            "CREATE TRIGGER IF NOT EXISTS catfacts_fts_update AFTER UPDATE OF fact ON catfacts BEGIN
+            INSERT INTO catfacts_fts (catfacts_fts, rowid, fact) VALUES ('delete', old.id, old.fact);
+            INSERT INTO catfacts_fts (rowid, fact) VALUES (new.id, new.fact);
+            END",
+            "INSERT INTO catfacts_fts (catfacts_fts) VALUES ('rebuild')",
+        ],
+    },
+    Migration {
+        name: "catfact_revisions",
+        phase: Phase::PreDeploy,
+        // A revision for every change to a fact, for `GET /catfacts/as-of`,
+        // starting from each fact's current version as of when it was
+        // created. Filling in a fact id or slug isn't a change worth keeping.
+        statements: &[
+            "INSERT INTO catfact_revisions (catfact_id, fact, fact_id, license, needs_review, changed_at)
+            SELECT id, fact, fact_id, license, needs_review, coalesce(created_at, current_timestamp)
+            FROM catfacts ORDER BY id",
+            "CREATE TRIGGER IF NOT EXISTS catfact_revisions_insert AFTER INSERT ON catfacts BEGIN
+            INSERT INTO catfact_revisions (catfact_id, fact, fact_id, license, needs_review)
+            VALUES (new.id, new.fact, new.fact_id, new.license, new.needs_review);
+            END",
+            "CREATE TRIGGER IF NOT EXISTS catfact_revisions_update AFTER UPDATE OF fact, license, needs_review ON catfacts
+            WHEN old.fact IS NOT new.fact OR old.license IS NOT new.license OR old.needs_review IS NOT new.needs_review
+            BEGIN
+            INSERT INTO catfact_revisions (catfact_id, fact, fact_id, license, needs_review)
+            VALUES (new.id, new.fact, new.fact_id, new.license, new.needs_review);
+            END",
+            "CREATE TRIGGER IF NOT EXISTS catfact_revisions_delete AFTER DELETE ON catfacts BEGIN
+            INSERT INTO catfact_revisions (catfact_id, fact, fact_id, license, needs_review, deleted)
+            VALUES (old.id, old.fact, old.fact_id, old.license, old.needs_review, 1);
+            END",
+        ],
+    },
+    Migration {
+        name: "content_version",
+        phase: Phase::PreDeploy,
+        // A counter that goes up whenever anything the feeds and sitemap are
+        // made from changes, so their rendered copies (see `feed::FeedCache`)
+        // can tell they're out of date, whichever instance made the change.
+        statements: &[
+            "INSERT OR IGNORE INTO content_version (id, version) VALUES (1, 0)",
+            "CREATE TRIGGER IF NOT EXISTS content_version_fact_insert AFTER INSERT ON catfacts BEGIN
+            UPDATE content_version SET version = version + 1;
+            END",
+            "CREATE TRIGGER IF NOT EXISTS content_version_fact_update AFTER UPDATE ON catfacts BEGIN
+            UPDATE content_version SET version = version + 1;
+            END",
+            "CREATE TRIGGER IF NOT EXISTS content_version_fact_delete AFTER DELETE ON catfacts BEGIN
+            UPDATE content_version SET version = version + 1;
+            END",
+            "CREATE TRIGGER IF NOT EXISTS content_version_tag_insert AFTER INSERT ON catfact_tags BEGIN
+            UPDATE content_version SET version = version + 1;
+            END",
+            "CREATE TRIGGER IF NOT EXISTS content_version_tag_delete AFTER DELETE ON catfact_tags BEGIN
+            UPDATE content_version SET version = version + 1;
+            END",
+            "CREATE TRIGGER IF NOT EXISTS content_version_tag_rename AFTER UPDATE OF name ON tags BEGIN
+            UPDATE content_version SET version = version + 1;
+            END",
+            "CREATE TRIGGER IF NOT EXISTS content_version_day_insert AFTER INSERT ON daily_facts BEGIN
+            UPDATE content_version SET version = version + 1;
+            END",
+            "CREATE TRIGGER IF NOT EXISTS content_version_day_update AFTER UPDATE OF catfact_id ON daily_facts BEGIN
+            UPDATE content_version SET version = version + 1;
+            END",
+            "CREATE TRIGGER IF NOT EXISTS content_version_day_delete AFTER DELETE ON daily_facts BEGIN
+            UPDATE content_version SET version = version + 1;
+            END",
+        ],
+    },
+    Migration {
+        name: "subscriber_topics_cleanup",
+        phase: Phase::PreDeploy,
+        // Topics go with their subscriber, or with a tag that's deleted.
+        statements: &[
+            "CREATE TRIGGER IF NOT EXISTS subscriber_topics_subscriber AFTER DELETE ON subscribers BEGIN
+            DELETE FROM subscriber_topics WHERE subscriber_id = old.id;
+            END",
+            "CREATE TRIGGER IF NOT EXISTS subscriber_topics_tag AFTER DELETE ON tags BEGIN
+            DELETE FROM subscriber_topics WHERE tag_id = old.id;
+            END",
+        ],
+    },
+    Migration {
+        name: "subscriber_last_opened",
+        phase: Phase::PreDeploy,
+        // Opens counted before `last_opened_at` was. In privacy mode they're
+        // stored under a pseudonym, so those subscribers start from none.
+        statements: &[
+            "UPDATE subscribers SET last_opened_at = (
+            SELECT max(opened_at) FROM fact_opens WHERE fact_opens.subscriber = subscribers.email
+            ) WHERE last_opened_at IS NULL",
+        ],
+    },
+];
+
+/// Applies any migrations in `phase` that haven't run yet, returning their
+/// names.
+pub async fn apply(db: &dyn Store, phase: Phase) -> Result<Vec<&'static str>, anyhow::Error> {
+    let pending = pending(db, phase).await?;
+
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|migration| pending.contains(&migration.name))
+    {
+        let mut statements: Vec<Statement> = migration
+            .statements
+            .iter()
+            .map(|statement| Statement::new(*statement))
+            .collect();
+        statements.push(Statement::with_args(
+            "INSERT INTO schema_migrations (name, phase) VALUES (?, ?)",
+            &[migration.name, migration.phase.name()],
+        ));
+
+        db.batch(statements)
+            .await
+            .map_err(|e| anyhow!("the {} migration failed: {e}", migration.name))?;
+        tracing::info!("Applied the {} migration", migration.name);
+    }
+
+    Ok(pending)
+}
+
+/// The names of the migrations in `phase` that haven't been applied.
+pub async fn pending(db: &dyn Store, phase: Phase) -> Result<Vec<&'static str>, anyhow::Error> {
+    let applied = db
+        .execute("SELECT name FROM schema_migrations")
+        .await
+        .and_then(|res| store::rows::<String>(&res))?;
+
+    Ok(MIGRATIONS
+        .iter()
+        .filter(|migration| {
+            migration.phase == phase && !applied.iter().any(|name| name == migration.name)
+        })
+        .map(|migration| migration.name)
+        .collect())
+}
+
+/// A row of `PRAGMA table_info`.
+struct Column {
+    name: String,
+    declared_type: String,
+}
+
+impl FromRow for Column {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            name: store::text(row, 1)?,
+            declared_type: store::text(row, 2)?,
+        })
+    }
+}
+
+/// Creates the tables if they don't exist yet, then adds any columns that were
+/// introduced after a table was first created.
+pub async fn migrate(db: &dyn Store) -> Result<(), anyhow::Error> {
+    let fresh = columns(db, "subscribers").await?.is_empty();
+    db.batch([
+        "CREATE TABLE IF NOT EXISTS catfacts (
+        id integer primary key autoincrement,
+        fact text not null,
+        created_at datetime default current_timestamp 
+        )",
+        "CREATE TABLE IF NOT EXISTS subscribers (
+                    id integer primary key autoincrement,
+                    email text not null,
+        created_at datetime default current_timestamp 
+                )",
+        "CREATE TABLE IF NOT EXISTS suppressions (
+        email text primary key,
+        reason text not null,
+        created_at datetime default current_timestamp
+        )",
+        "CREATE TABLE IF NOT EXISTS complaint_events (
+        id integer primary key autoincrement,
+        email text not null,
+        source text not null,
+        feedback_type text not null,
+        received_at datetime default current_timestamp
+        )",
+        "CREATE TABLE IF NOT EXISTS subscriber_events (
+        id integer primary key autoincrement,
+        event text not null,
+        delivery_hour integer not null,
+        occurred_at datetime default current_timestamp
+        )",
+        "CREATE TABLE IF NOT EXISTS sync_checkpoints (
+        source text primary key,
+        last_id integer not null,
+        synced_at datetime default current_timestamp
+        )",
+        "CREATE TABLE IF NOT EXISTS api_keys (
+        id integer primary key autoincrement,
+        name text not null,
+        key_hash text not null unique,
+        created_at datetime default current_timestamp,
+        revoked_at datetime
+        )",
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+        name text primary key,
+        phase text not null,
+        applied_at datetime default current_timestamp
+        )",
+        "CREATE TABLE IF NOT EXISTS waitlist (
+        id integer primary key autoincrement,
+        email text not null unique,
+        delivery_hour integer not null,
+        weekdays integer not null,
+        created_at datetime default current_timestamp
+        )",
+        "CREATE TABLE IF NOT EXISTS settings (
+        key text primary key,
+        value text not null,
+        updated_at datetime default current_timestamp
+        )",
+        "CREATE TABLE IF NOT EXISTS weekly_digests (
+        week text primary key,
+        html text not null,
+        created_at datetime default current_timestamp
+        )",
+        "CREATE TABLE IF NOT EXISTS tags (
+        id integer primary key autoincrement,
+        name text not null unique,
+        created_at datetime default current_timestamp
+        )",
+        "CREATE TABLE IF NOT EXISTS catfact_tags (
+        catfact_id integer not null,
+        tag_id integer not null,
+        primary key (catfact_id, tag_id)
+        )",
+        // The tags a subscriber wants facts about, or none for every fact.
+        "CREATE TABLE IF NOT EXISTS subscriber_topics (
+        subscriber_id integer not null,
+        tag_id integer not null,
+        primary key (subscriber_id, tag_id)
+        )",
+        "CREATE TABLE IF NOT EXISTS tag_rules (
+        id integer primary key autoincrement,
+        tag_id integer not null,
+        kind text not null check (kind in ('keyword', 'regex')),
+        pattern text not null,
+        created_at datetime default current_timestamp
+        )",
+        "CREATE TABLE IF NOT EXISTS votes (
+        catfact_id integer not null,
+        voter text not null,
+        value integer not null check (value in (-1, 1)),
+        created_at datetime default current_timestamp,
+        updated_at datetime default current_timestamp,
+        primary key (catfact_id, voter)
+        )",
+        "CREATE TABLE IF NOT EXISTS calendar (
+        date text primary key,
+        catfact_id integer not null,
+        note text,
+        updated_at datetime default current_timestamp
+        )",
+        "CREATE TABLE IF NOT EXISTS audit_log (
+        id integer primary key autoincrement,
+        actor text not null,
+        action text not null,
+        detail text not null,
+        created_at datetime default current_timestamp
+        )",
+        "CREATE TABLE IF NOT EXISTS lockdown (
+        id integer primary key check (id = 1),
+        reason text not null,
+        started_by text not null,
+        started_at datetime default current_timestamp
+        )",
+        "CREATE TABLE IF NOT EXISTS data_requests (
+        token text primary key,
+        email text not null,
+        created_at datetime default current_timestamp
+        )",
+        "CREATE TABLE IF NOT EXISTS event_rollups (
+        source text not null,
+        day text not null,
+        kind text not null,
+        count integer not null,
+        primary key (source, day, kind)
+        )",
+        "CREATE TABLE IF NOT EXISTS email_outbox (
+        id integer primary key autoincrement,
+        recipient text not null,
+        sender text,
+        message text not null,
+        status text not null default 'pending',
+        attempts integer not null default 0,
+        last_error text,
+        next_attempt_at datetime not null,
+        created_at datetime default current_timestamp,
+        sent_at datetime
+        )",
+        "CREATE INDEX IF NOT EXISTS email_outbox_due ON email_outbox (status, next_attempt_at)",
+        // Outlives the outbox's retention, so a permanent failure is never
+        // lost track of.
+        "CREATE TABLE IF NOT EXISTS email_dead_letters (
+        outbox_id integer primary key,
+        recipient text not null,
+        final_error text,
+        attempts integer not null,
+        dead_at datetime default current_timestamp,
+        retried_at datetime
+        )",
+        "CREATE TABLE IF NOT EXISTS catfact_revisions (
+        id integer primary key autoincrement,
+        catfact_id integer not null,
+        fact text not null,
+        fact_id text,
+        license text not null,
+        needs_review integer not null,
+        deleted integer not null default 0,
+        changed_at datetime default current_timestamp
+        )",
+        "CREATE INDEX IF NOT EXISTS catfact_revisions_changed ON catfact_revisions (changed_at)",
+        "CREATE TABLE IF NOT EXISTS content_version (
+        id integer primary key check (id = 1),
+        version integer not null
+        )",
+        "CREATE TABLE IF NOT EXISTS daily_facts (
+        date text primary key,
+        catfact_id integer not null,
+        created_at datetime default current_timestamp
+        )",
+        "CREATE TABLE IF NOT EXISTS fact_feedback (
+        date text not null,
+        subscriber text not null,
+        catfact_id integer not null,
+        score integer not null check (score between 0 and 10),
+        created_at datetime default current_timestamp,
+        updated_at datetime default current_timestamp,
+        primary key (date, subscriber)
+        )",
+        "CREATE INDEX IF NOT EXISTS fact_feedback_catfact ON fact_feedback (catfact_id)",
+        "CREATE TABLE IF NOT EXISTS fact_opens (
+        date text not null,
+        subscriber text not null,
+        catfact_id integer not null,
+        opened_at datetime default current_timestamp,
+        primary key (date, subscriber)
+        )",
+        "CREATE INDEX IF NOT EXISTS fact_opens_catfact ON fact_opens (catfact_id)",
+        "CREATE TABLE IF NOT EXISTS dispatch_queue (
+        id integer primary key autoincrement,
+        delivery_window text not null,
+        after integer not null,
+        reached text not null,
+        domains text,
+        queued_at integer not null,
+        saved_at datetime default current_timestamp
+        )",
+        "CREATE TABLE IF NOT EXISTS dispatch_windows (
+        delivery_window text primary key,
+        started_at datetime default current_timestamp
+        )",
+    ])
+    .await?;
+
+    add_column(
+        db,
+        "subscribers",
+        "delivery_hour",
+        "integer not null default 0",
+    )
+    .await?;
+    add_column(db, "subscribers", "token", "text").await?;
+    add_column(db, "catfacts", "slug", "text").await?;
+    add_column(db, "catfacts", "fact_id", "text").await?;
+    add_column(db, "catfacts", "spam_score", "real").await?;
+    add_column(db, "catfacts", "needs_review", "integer not null default 0").await?;
+    add_column(db, "catfacts", "license", "text not null default 'cc-by'").await?;
+    add_column(db, "catfacts", "submitted_by", "text").await?;
+    add_column(
+        db,
+        "subscribers",
+        "needs_review",
+        "integer not null default 0",
+    )
+    .await?;
+    add_column(
+        db,
+        "subscribers",
+        "email_format",
+        "text not null default 'standard'",
+    )
+    .await?;
+    // Everyone who subscribed before double opt-in counts as confirmed.
+    add_column(db, "subscribers", "confirmed", "integer not null default 1").await?;
+    add_column(db, "subscribers", "confirmation_token", "text").await?;
+    add_column(
+        db,
+        "subscribers",
+        "weekdays",
+        "integer not null default 127",
+    )
+    .await?;
+    // Unset until a signup's link is sent again; until then, the link was
+    // sent when the row was created.
+    add_column(db, "subscribers", "confirmation_sent_at", "datetime").await?;
+    add_column(
+        db,
+        "subscribers",
+        "confirmation_reminded",
+        "integer not null default 0",
+    )
+    .await?;
+    add_column(db, "subscribers", "language", "text not null default 'en'").await?;
+    add_column(db, "waitlist", "language", "text not null default 'en'").await?;
+    // Minutes east of UTC, or unset for the schedule's zone (see `timezone`).
+    add_column(db, "subscribers", "utc_offset", "integer").await?;
+    add_column(db, "waitlist", "utc_offset", "integer").await?;
+    // For the `reengage` job: when the subscriber last opened an email, when
+    // they were asked if they still want them, and when they last said yes.
+    add_column(db, "subscribers", "last_opened_at", "datetime").await?;
+    add_column(db, "subscribers", "reengage_sent_at", "datetime").await?;
+    add_column(db, "subscribers", "stayed_at", "datetime").await?;
+    add_column(db, "email_outbox", "relay", "text").await?;
+    // The date of the fact a daily email was sent with (see `send_report`).
+    add_column(db, "email_outbox", "send_date", "text").await?;
+    // Set when a tag rule, not a person, applied the tag.
+    add_column(db, "catfact_tags", "rule_id", "integer").await?;
+    add_column(db, "catfact_tags", "reviewed_at", "datetime").await?;
+    // Counted along with opens, so zero from before they were (see `opens`).
+    add_column(
+        db,
+        "daily_facts",
+        "recipients",
+        "integer not null default 0",
+    )
+    .await?;
+    add_column(db, "daily_facts", "strategy", "text").await?;
+    // Keys from before tiers are admin keys.
+    add_column(db, "api_keys", "tier", "text").await?;
+    // A new database has no duplicate addresses for `unique_subscriber_emails`
+    // to clean up, so it can have the unique index from the start.
+    if fresh {
+        db.execute("CREATE UNIQUE INDEX IF NOT EXISTS subscribers_email ON subscribers (email)")
+            .await?;
+    }
+
+    db.batch([
+        "UPDATE subscribers SET token = lower(hex(randomblob(16))) WHERE token IS NULL",
+        "CREATE UNIQUE INDEX IF NOT EXISTS subscribers_token ON subscribers (token)",
+        "CREATE UNIQUE INDEX IF NOT EXISTS subscribers_confirmation_token ON subscribers (confirmation_token)",
+        "CREATE UNIQUE INDEX IF NOT EXISTS catfacts_slug ON catfacts (slug)",
+        "CREATE INDEX IF NOT EXISTS catfacts_fact_id ON catfacts (fact_id)",
+        "CREATE INDEX IF NOT EXISTS email_outbox_send_date ON email_outbox (send_date)",
+        // Subscribers from before the event log existed count as signups on the
+        // day they subscribed. An empty log that's been rolled up has just
+        // been trimmed, though.
+        "INSERT INTO subscriber_events (event, delivery_hour, occurred_at)
+        SELECT 'subscribed', delivery_hour, created_at FROM subscribers
+        WHERE NOT EXISTS (SELECT 1 FROM subscriber_events)
+        AND NOT EXISTS (SELECT 1 FROM event_rollups WHERE source = 'subscriber_events')",
+    ])
+    .await?;
+
+    apply(db, Phase::PreDeploy).await?;
+    Ok(())
+}
+
+/// `ALTER TABLE ... ADD COLUMN` fails if the column is already there, so check
+/// the table definition first.
+async fn add_column(
+    db: &dyn Store,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), anyhow::Error> {
+    let exists = columns(db, table)
+        .await?
+        .iter()
+        .any(|existing| existing.name == column);
+
+    if !exists {
+        db.execute(format!(
+            "ALTER TABLE {table} ADD COLUMN {column} {definition}"
+        ))
+        .await
+        .map_err(|e| anyhow!("couldn't add {column} to {table}: {e}"))?;
+    }
+
+    Ok(())
+}
+
+/// Compares the live schema against `EXPECTED`, so a mismatch fails at boot with
+/// a list of exactly what's wrong instead of surfacing later as a confusing SQL
+/// error in some handler.
+pub async fn verify(db: &dyn Store) -> Result<(), anyhow::Error> {
+    let mut problems = Vec::new();
+
+    for (table, expected_columns) in EXPECTED {
+        let actual = columns(db, table).await?;
+
+        if actual.is_empty() {
+            problems.push(format!("table {table} is missing"));
+            continue;
+        }
+
+        for (name, declared_type) in *expected_columns {
+            match actual.iter().find(|column| column.name == *name) {
+                None => problems.push(format!("{table}.{name} is missing")),
+                Some(column) if !column.declared_type.eq_ignore_ascii_case(declared_type) => {
+                    problems.push(format!(
+                        "{table}.{name} is {}, expected {declared_type}",
+                        column.declared_type
+                    ))
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "the database schema doesn't match what the code expects:\n  - {}",
+            problems.join("\n  - ")
+        ))
+    }
+}
+
+async fn columns(db: &dyn Store, table: &str) -> Result<Vec<Column>, anyhow::Error> {
+    let res = db
+        .execute(format!("PRAGMA table_info({table})"))
+        .await
+        .map_err(|e| anyhow!("couldn't read the columns of {table}: {e}"))?;
+
+    store::rows(&res)
+}
@@ -0,0 +1,118 @@
+//! Full-text search over facts, using the `catfacts_fts` FTS5 index.
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::store::{self, FromRow, Row, Statement, Value};
+use crate::{error::ApiError, html, AppState, CatFactRecord, CATFACT_COLUMNS};
+
+const DEFAULT_LIMIT: u32 = 20;
+const MAX_LIMIT: u32 = 100;
+const MAX_TERMS: usize = 10;
+
+/// Marks matches in FTS5 snippets. Control characters can't appear in a
+/// stored fact, so they can't be confused with the text around them.
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchQuery {
+    /// The words to look for.
+    q: String,
+    /// Up to 100; defaults to 20.
+    limit: Option<u32>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    fact: CatFactRecord,
+    /// The part of the fact around the matches, HTML-escaped, with each
+    /// match wrapped in `<mark>`.
+    snippet: String,
+}
+
+impl FromRow for SearchResult {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            fact: CatFactRecord::from_row(row)?,
+            snippet: highlight(&store::text(row, 6)?),
+        })
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SearchResults {
+    query: String,
+    results: Vec<SearchResult>,
+}
+
+/// Turns what someone typed into an FTS5 query that matches facts containing
+/// every word. Each word is quoted, so operators and punctuation in the input
+/// are searched for rather than interpreted.
+fn match_expression(q: &str) -> Option<String> {
+    let terms: Vec<String> = q
+        .split_whitespace()
+        .take(MAX_TERMS)
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect();
+
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+fn highlight(snippet: &str) -> String {
+    html::escape(snippet)
+        .replace(MATCH_START, "<mark>")
+        .replace(MATCH_END, "</mark>")
+}
+
+/// `GET /catfacts/search?q=whiskers&limit=20` - facts containing every word
+/// of `q`, best match first.
+#[utoipa::path(
+    get,
+    path = "/catfacts/search",
+    tag = "facts",
+    params(SearchQuery),
+    responses(
+        (status = 200, description = "The matching facts", body = SearchResults),
+        (status = 400, description = "`q` has no words in it", body = ErrorBody),
+    )
+)]
+pub async fn search_facts(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<SearchResults>, ApiError> {
+    let Some(expression) = match_expression(&query.q) else {
+        return Err(ApiError::BadRequest(
+            "Give some words to search for, e.g. ?q=whiskers".to_string(),
+        ));
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    let results = state
+        .db
+        .execute(Statement::with_args(
+            format!(
+                "SELECT {CATFACT_COLUMNS}, snippet FROM catfacts
+                JOIN (
+                    SELECT rowid AS match_id, rank AS match_rank,
+                    snippet(catfacts_fts, 0, char(2), char(3), '…', 16) AS snippet
+                    FROM catfacts_fts WHERE catfacts_fts MATCH ?
+                ) ON catfacts.id = match_id
+                WHERE needs_review = 0 ORDER BY match_rank LIMIT ?"
+            ),
+            &[Value::from(expression), Value::from(limit)],
+        ))
+        .await
+        .and_then(|res| store::rows::<SearchResult>(&res))?;
+
+    Ok(Json(SearchResults {
+        query: query.q,
+        results,
+    }))
+}
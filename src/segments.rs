@@ -0,0 +1,64 @@
+//! Subscribers picked out by email domain, so a send can be aimed at one
+//! provider's addresses, e.g. to check whether Outlook is still bouncing
+//! without mailing everyone else again.
+
+use crate::error::ApiError;
+use crate::store::Value;
+
+/// The lowercased domain of the address in `column`.
+pub fn domain_of(column: &str) -> String {
+    format!("lower(substr({column}, instr({column}, '@') + 1))")
+}
+
+/// One or more email domains, e.g. `outlook.com,hotmail.com,live.com` for
+/// everyone Microsoft delivers to.
+#[derive(Clone)]
+pub struct Segment {
+    domains: Vec<String>,
+}
+
+impl Segment {
+    /// Parses a comma-separated list of domains, ignoring case and any
+    /// leading `@`.
+    pub fn parse(list: &str) -> Result<Self, ApiError> {
+        let mut domains: Vec<String> = Vec::new();
+        for domain in list.split(',') {
+            let domain = domain.trim().trim_start_matches('@').to_lowercase();
+            if domain.is_empty() {
+                continue;
+            }
+            if domain.contains('@') || !domain.contains('.') {
+                return Err(ApiError::Validation(format!(
+                    "{domain:?} isn't an email domain like outlook.com"
+                )));
+            }
+            if !domains.contains(&domain) {
+                domains.push(domain);
+            }
+        }
+
+        if domains.is_empty() {
+            return Err(ApiError::Validation(
+                "domain should list at least one email domain".to_string(),
+            ));
+        }
+
+        Ok(Self { domains })
+    }
+
+    /// A condition on `subscribers` matching this segment, adding its
+    /// arguments to `args`.
+    pub fn condition(&self, args: &mut Vec<Value>) -> String {
+        args.extend(self.domains.iter().map(Value::from));
+
+        format!(
+            "{} IN ({})",
+            domain_of("email"),
+            vec!["?"; self.domains.len()].join(", ")
+        )
+    }
+
+    pub fn domains(&self) -> &[String] {
+        &self.domains
+    }
+}
@@ -0,0 +1,288 @@
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::NaiveDate;
+use lettre::message::Mailbox;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::{
+    daily,
+    delivery::DeliveryWindow,
+    dispatch::{self, SendReport, Window},
+    error::ApiError,
+    sanitize,
+    segments::Segment,
+    AppState,
+};
+
+#[derive(Deserialize)]
+pub struct SendDailyQuery {
+    #[serde(default)]
+    dry_run: bool,
+    /// The day to simulate, as YYYY-MM-DD. Defaults to today.
+    date: Option<String>,
+    /// Only count subscribers at these comma-separated email domains.
+    domain: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SendDigestQuery {
+    #[serde(default)]
+    dry_run: bool,
+    /// The delivery window to send to. Defaults to midnight.
+    #[serde(default)]
+    window: DeliveryWindow,
+    /// The day whose fact to send, as YYYY-MM-DD. Defaults to today.
+    date: Option<String>,
+    /// Only send to subscribers at these comma-separated email domains.
+    domain: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct DigestSent {
+    date: String,
+    window: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    domains: Option<Vec<String>>,
+    #[serde(flatten)]
+    report: SendReport,
+}
+
+#[derive(Serialize)]
+pub struct DryRun {
+    date: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    domains: Option<Vec<String>>,
+    fact: Option<String>,
+    windows: Vec<WindowReport>,
+    render_errors: Vec<RenderError>,
+}
+
+/// What the scheduler would do for one delivery window.
+#[derive(Serialize)]
+pub struct WindowReport {
+    window: &'static str,
+    hour: u32,
+    recipients: usize,
+    rendered: usize,
+}
+
+#[derive(Serialize)]
+pub struct RenderError {
+    window: &'static str,
+    email: String,
+    error: String,
+}
+
+/// `POST /admin/send-daily?dry_run=true` - runs the daily send's selection,
+/// rendering and recipient resolution for every delivery window without
+/// sending anything. Picking the day's fact is the one side effect, and it's
+/// the same pick the scheduler would make.
+pub async fn send_daily(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SendDailyQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !query.dry_run {
+        return Err(ApiError::BadRequest(
+            "Only dry runs are supported (?dry_run=true); use POST /admin/send-digest to send"
+                .to_string(),
+        ));
+    }
+
+    let date = parse_date(query.date.as_deref(), state.zone.today())?;
+    let segment = parse_segment(query.domain.as_deref())?;
+    let from = sender(&state)?;
+    let fact = fact_for(&state, date).await?;
+
+    let mut render_errors = Vec::new();
+    let mut windows = Vec::new();
+    for window in DeliveryWindow::ALL {
+        windows.push(
+            render_window(
+                &state,
+                &from,
+                date,
+                window,
+                segment.as_ref(),
+                fact.as_deref(),
+                &mut render_errors,
+            )
+            .await?,
+        );
+    }
+
+    Ok(Json(DryRun {
+        date: date.to_string(),
+        domains: domains(segment.as_ref()),
+        fact,
+        windows,
+        render_errors,
+    }))
+}
+
+/// `POST /admin/send-digest` - sends the daily email to one delivery window
+/// now, as the scheduler would at that window's hour, e.g. to test the mail
+/// pipeline end to end. With `?dry_run=true` it only renders the emails, and
+/// reports like `send-daily` does for that window.
+///
+/// Subscribers sent to here will be sent to again when the scheduler reaches
+/// their window, so point this at a test database or a window that's already
+/// gone out.
+pub async fn send_digest(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SendDigestQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let date = parse_date(query.date.as_deref(), state.zone.today())?;
+    let segment = parse_segment(query.domain.as_deref())?;
+
+    if query.dry_run {
+        let from = sender(&state)?;
+        let fact = fact_for(&state, date).await?;
+        let mut render_errors = Vec::new();
+        let window = render_window(
+            &state,
+            &from,
+            date,
+            query.window,
+            segment.as_ref(),
+            fact.as_deref(),
+            &mut render_errors,
+        )
+        .await?;
+
+        return Ok(Json(DryRun {
+            date: date.to_string(),
+            domains: domains(segment.as_ref()),
+            fact,
+            windows: vec![window],
+            render_errors,
+        })
+        .into_response());
+    }
+
+    sender(&state)?;
+    let domains = domains(segment.as_ref());
+    let report = state
+        .dispatcher
+        .lock()
+        .await
+        .send_subscriber_mail(date, query.window.hour(), segment)
+        .await
+        .map_err(ApiError::Mail)?;
+
+    tracing::info!(
+        "Manual send to the {} window{}: {} sent, {} failed, {} deferred",
+        query.window.name(),
+        domains
+            .as_ref()
+            .map(|domains| format!(" at {}", domains.join(", ")))
+            .unwrap_or_default(),
+        report.sent,
+        report.failed,
+        report.deferred
+    );
+
+    Ok(Json(DigestSent {
+        date: date.to_string(),
+        window: query.window.name(),
+        domains,
+        report,
+    })
+    .into_response())
+}
+
+fn parse_date(date: Option<&str>, today: NaiveDate) -> Result<NaiveDate, ApiError> {
+    match date {
+        Some(date) => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|e| ApiError::BadRequest(format!("date should look like 2024-01-31: {e}"))),
+        None => Ok(today),
+    }
+}
+
+fn parse_segment(domain: Option<&str>) -> Result<Option<Segment>, ApiError> {
+    domain.map(Segment::parse).transpose()
+}
+
+fn domains(segment: Option<&Segment>) -> Option<Vec<String>> {
+    segment.map(|segment| segment.domains().to_vec())
+}
+
+fn sender(state: &AppState) -> Result<Mailbox, ApiError> {
+    state.sender.clone().ok_or_else(|| {
+        ApiError::Unavailable(
+            "MAIL_FROM (or GMAIL_USER) isn't a valid email address, so nothing would be sent"
+                .to_string(),
+        )
+    })
+}
+
+async fn fact_for(state: &AppState, date: NaiveDate) -> Result<Option<String>, ApiError> {
+    Ok(daily::fact_for_date(&*state.db, &state.ranking, date)
+        .await?
+        .map(|fact| sanitize::plain_text(&fact)))
+}
+
+/// Renders the email for everyone in one window, collecting any failures.
+async fn render_window(
+    state: &AppState,
+    from: &Mailbox,
+    date: NaiveDate,
+    window: DeliveryWindow,
+    segment: Option<&Segment>,
+    fact: Option<&str>,
+    render_errors: &mut Vec<RenderError>,
+) -> Result<WindowReport, ApiError> {
+    let due = Window {
+        date,
+        hour: window.hour(),
+        timezone: None,
+    };
+    let mut recipients = 0;
+    let mut rendered = 0;
+    let mut after = 0;
+
+    loop {
+        let page = dispatch::recipients_page(&*state.db, due, after, &[], segment).await?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        after = last.id;
+        recipients += page.len();
+
+        let Some(fact) = fact else {
+            continue;
+        };
+        for recipient in &page {
+            let composed = recipient
+                .email
+                .parse::<Mailbox>()
+                .map_err(|e| format!("invalid address: {e}"))
+                .and_then(|to| {
+                    state
+                        .composer
+                        .compose(from, &to, recipient, date, fact)
+                        .to_message()
+                        .map_err(|e| e.to_string())
+                });
+
+            match composed {
+                Ok(_) => rendered += 1,
+                Err(error) => render_errors.push(RenderError {
+                    window: window.name(),
+                    email: recipient.email.clone(),
+                    error,
+                }),
+            }
+        }
+    }
+
+    Ok(WindowReport {
+        window: window.name(),
+        hour: window.hour(),
+        recipients,
+        rendered,
+    })
+}
@@ -0,0 +1,193 @@
+//! `GET /admin/sends/:date/report.csv`, one row per recipient of a day's
+//! email: how delivery went, going by the outbox, and whether it was opened
+//! (see `opens`). For looking at engagement in a spreadsheet.
+//!
+//! Recipients are as the outbox has them, so in privacy mode they're
+//! pseudonyms once their email is finished with, and emails go from the
+//! report when `RETENTION_EMAIL_OUTBOX_DAYS` trims them. Clicks aren't
+//! tracked, so there's nothing to report about them.
+use axum::{
+    body::{boxed, Body, Bytes},
+    extract::{Path, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::calendar;
+use crate::error::ApiError;
+use crate::export::csv_field;
+use crate::privacy::Privacy;
+use crate::store::{self, FromRow, Row, Statement, Store, Value};
+use crate::AppState;
+
+const PAGE_SIZE: u32 = 500;
+
+const CSV_HEADER: &str = "recipient,status,attempts,last_error,sent_at,relay,opened,opened_at\r\n";
+
+struct Delivery {
+    id: i64,
+    recipient: String,
+    status: String,
+    attempts: i64,
+    last_error: Option<String>,
+    sent_at: Option<String>,
+    relay: Option<String>,
+}
+
+impl FromRow for Delivery {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            id: store::integer(row, 0)?,
+            recipient: store::text(row, 1)?,
+            status: store::text(row, 2)?,
+            attempts: store::integer(row, 3)?,
+            last_error: store::optional_text(row, 4)?,
+            sent_at: store::optional_text(row, 5)?,
+            relay: store::optional_text(row, 6)?,
+        })
+    }
+}
+
+impl Delivery {
+    /// `sent`, `failed` (waiting for a retry), `dead_lettered`, `cancelled`,
+    /// or `pending` for one that hasn't been tried yet.
+    fn status(&self) -> &str {
+        match self.status.as_str() {
+            "dead" => "dead_lettered",
+            "pending" if self.attempts > 0 => "failed",
+            status => status,
+        }
+    }
+
+    fn to_csv(&self, opened_at: Option<&str>) -> String {
+        let fields = [
+            csv_field(&self.recipient),
+            self.status().to_string(),
+            self.attempts.to_string(),
+            csv_field(self.last_error.as_deref().unwrap_or_default()),
+            csv_field(self.sent_at.as_deref().unwrap_or_default()),
+            csv_field(self.relay.as_deref().unwrap_or_default()),
+            i64::from(opened_at.is_some()).to_string(),
+            csv_field(opened_at.unwrap_or_default()),
+        ];
+
+        format!("{}\r\n", fields.join(","))
+    }
+}
+
+/// When each subscriber who opened `date`'s email first opened it, keyed on
+/// how `fact_opens` stores them.
+async fn opens(db: &dyn Store, date: &str) -> Result<HashMap<String, String>, anyhow::Error> {
+    let res = db
+        .execute(Statement::with_args(
+            "SELECT subscriber, opened_at FROM fact_opens WHERE date = ?",
+            &[date],
+        ))
+        .await?;
+
+    res.rows
+        .iter()
+        .map(|row| Ok((store::text(row, 0)?, store::text(row, 1)?)))
+        .collect()
+}
+
+/// When `recipient` opened the email, whichever way their opens are stored.
+fn opened_at<'a>(
+    opens: &'a HashMap<String, String>,
+    privacy: &Privacy,
+    recipient: &str,
+) -> Option<&'a str> {
+    privacy
+        .subscriber_ids(recipient)
+        .iter()
+        .find_map(|id| opens.get(id))
+        .map(String::as_str)
+}
+
+async fn page(db: &dyn Store, date: &str, after: i64) -> Result<Vec<Delivery>, anyhow::Error> {
+    let res = db
+        .execute(Statement::with_args(
+            "SELECT id, recipient, status, attempts, last_error, sent_at, relay
+            FROM email_outbox WHERE send_date = ? AND id > ? ORDER BY id LIMIT ?",
+            &[
+                Value::from(date),
+                Value::from(after),
+                Value::from(PAGE_SIZE),
+            ],
+        ))
+        .await?;
+
+    store::rows::<Delivery>(&res)
+}
+
+/// `GET /admin/sends/:date/report.csv` - everyone `date`'s email went to,
+/// streamed as it's read.
+pub async fn send_report(
+    State(state): State<Arc<AppState>>,
+    Path(date): Path<String>,
+) -> Result<Response, ApiError> {
+    let date = calendar::parse_date(&date)?.to_string();
+    let sent = state
+        .db
+        .execute(Statement::with_args(
+            "SELECT count(*) FROM email_outbox WHERE send_date = ?",
+            &[date.as_str()],
+        ))
+        .await
+        .and_then(|res| store::first::<i64>(&res))?;
+    if sent.unwrap_or(0) == 0 {
+        return Err(ApiError::NotFound(format!(
+            "There are no emails in the outbox with {date}'s fact"
+        )));
+    }
+    let opens = opens(&*state.db, &date).await?;
+
+    let (mut sender, body) = Body::channel();
+    let db = state.db.clone();
+    let privacy = state.privacy.clone();
+    let filename = format!("attachment; filename=\"sends-{date}.csv\"");
+
+    tokio::spawn(async move {
+        if sender.send_data(Bytes::from(CSV_HEADER)).await.is_err() {
+            return;
+        }
+
+        let mut after = 0;
+        loop {
+            let deliveries = match page(&*db, &date, after).await {
+                Ok(deliveries) => deliveries,
+                Err(e) => {
+                    // Cuts the response off, so the client can tell the
+                    // report is incomplete.
+                    tracing::error!("Stopped the {date} send report after outbox id {after}: {e}");
+                    sender.abort();
+                    return;
+                }
+            };
+            let Some(last) = deliveries.last() else {
+                break;
+            };
+            after = last.id;
+
+            let chunk: String = deliveries
+                .iter()
+                .map(|delivery| delivery.to_csv(opened_at(&opens, &privacy, &delivery.recipient)))
+                .collect();
+            // The client went away.
+            if sender.send_data(Bytes::from(chunk)).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+            (header::CONTENT_DISPOSITION, filename),
+        ],
+        boxed(body),
+    )
+        .into_response())
+}
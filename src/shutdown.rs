@@ -0,0 +1,63 @@
+//! Graceful stops, so a deploy doesn't cut the daily send off partway. Once
+//! one's requested - standalone, by SIGTERM or Ctrl-C - the server stops
+//! taking connections and finishes the requests it has, the scheduler runs no
+//! more jobs, and the dispatcher stops before its next recipient once the
+//! email in flight has gone and been recorded. What's left of its queue is
+//! saved, and the next instance picks it up (see `Dispatcher::save_queue` and
+//! `Dispatcher::resume`).
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Whether a stop has been asked for, shared by everything that needs to
+/// wind down.
+#[derive(Clone)]
+pub struct Shutdown(Arc<watch::Sender<bool>>);
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self(Arc::new(watch::channel(false).0))
+    }
+}
+
+impl Shutdown {
+    pub fn request(&self) {
+        self.0.send_replace(true);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Waits until a stop has been asked for.
+    pub async fn requested(&self) {
+        let mut requested = self.0.subscribe();
+        // The sender is `self`, so it can't be dropped while this waits.
+        let _ = requested.wait_for(|requested| *requested).await;
+    }
+
+    /// Asks for a stop on SIGTERM (what container hosts send before killing
+    /// a process) or Ctrl-C.
+    pub async fn listen(self) {
+        let terminate = async {
+            #[cfg(unix)]
+            match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+                Ok(mut signal) => {
+                    signal.recv().await;
+                }
+                Err(e) => {
+                    tracing::warn!("Couldn't listen for SIGTERM: {e}");
+                    std::future::pending::<()>().await;
+                }
+            }
+            #[cfg(not(unix))]
+            std::future::pending::<()>().await;
+        };
+
+        tokio::select! {
+            _ = terminate => {},
+            _ = tokio::signal::ctrl_c() => {},
+        }
+        tracing::info!("Stopping: finishing open requests and the email in flight");
+        self.request();
+    }
+}
@@ -0,0 +1,82 @@
+use axum::{
+    extract::{Query, State},
+    response::Html,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use utoipa::IntoParams;
+
+use crate::{
+    delivery::DeliveryWindow,
+    html,
+    language::Language,
+    preferences::{language_options, timezone_options, window_options},
+    timezone::Timezone,
+    turnstile, AppState,
+};
+
+/// Where the hosted page sends visitors once they've subscribed.
+const THANKS_PATH: &str = "/subscribe?subscribed=true";
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SignupQuery {
+    /// Show the thank-you message instead of the form.
+    #[serde(default)]
+    subscribed: bool,
+}
+
+/// `GET /subscribe` - a hosted signup page for sites that can't embed a form.
+/// It posts to `POST /subscribe` like any other form, so it goes through the
+/// same validation and, when configured, the Turnstile check.
+#[utoipa::path(
+    get,
+    path = "/subscribe",
+    tag = "subscriptions",
+    params(SignupQuery),
+    responses((status = 200, description = "The signup page", body = String, content_type = "text/html"))
+)]
+pub async fn subscribe_page(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SignupQuery>,
+) -> Html<String> {
+    if query.subscribed {
+        return Html(html::page(
+            "Cat Facts - Check your inbox",
+            "<p>Almost there! Check your inbox and follow the link to confirm your subscription.</p>",
+        ));
+    }
+
+    let captcha = match &state.turnstile {
+        Some(turnstile) => format!(
+            r#"<div class="cf-turnstile" data-sitekey="{}" data-response-field-name="{}"></div>
+<script src="https://challenges.cloudflare.com/turnstile/v0/api.js" async defer></script>"#,
+            html::escape(turnstile.site_key()),
+            turnstile::RESPONSE_FIELD,
+        ),
+        None => String::new(),
+    };
+
+    Html(html::page(
+        "Cat Facts - Subscribe",
+        &format!(
+            r#"<p>Get a cat fact in your inbox every day.</p>
+<form method="post" action="/subscribe">
+  <label for="email">Email</label>
+  <input type="email" id="email" name="email" required>
+  <label for="delivery_window">Delivery time</label>
+  <select id="delivery_window" name="delivery_window">{options}</select>
+  <label for="timezone">Time zone</label>
+  <select id="timezone" name="timezone">{timezones}</select>
+  <label for="language">Email language</label>
+  <select id="language" name="language">{languages}</select>
+  <input type="hidden" name="redirect_to" value="{THANKS_PATH}">
+  {captcha}
+  <button type="submit">Subscribe</button>
+</form>"#,
+            options = window_options(DeliveryWindow::default()),
+            timezones = timezone_options(Timezone::default()),
+            languages = language_options(Language::default()),
+        ),
+    ))
+}
@@ -0,0 +1,94 @@
+//! `GET /sitemap.xml`, so search engines find every fact and archive page:
+//! the home page, the archive, each day's fact of the day so far and each
+//! fact in circulation's own page. It's rendered through the same cache as
+//! the feeds (see `feed::FeedCache`), once per content version and day.
+use axum::{extract::State, http::HeaderMap, response::Response};
+use chrono::NaiveDate;
+use std::sync::Arc;
+
+use crate::feed::{self, fact_url};
+use crate::html::escape;
+use crate::store::{self, Statement, Value};
+use crate::{error::ApiError, AppState};
+
+/// The most URLs one sitemap can list.
+const MAX_URLS: u32 = 50_000;
+
+/// The pages listed before any days or facts.
+const PAGES: &[&str] = &["/", "/archive", "/subscribe"];
+
+/// `GET /sitemap.xml` - every public page, as a sitemap.
+#[utoipa::path(
+    get,
+    path = "/sitemap.xml",
+    tag = "facts",
+    responses(
+        (status = 200, description = "The sitemap", body = String, content_type = "application/xml"),
+        (status = 304, description = "The sitemap hasn't changed since the `If-None-Match` copy"),
+    )
+)]
+pub async fn sitemap(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let version = feed::content_version(&*state.db).await?;
+    // Days show up once they've started, so it changes at midnight too.
+    let today = state.zone.today();
+
+    feed::cached(
+        &state.feeds,
+        &headers,
+        "sitemap".to_string(),
+        format!("{version}-{today}"),
+        "application/xml; charset=utf-8",
+        || render(&state, today),
+    )
+    .await
+}
+
+async fn render(state: &AppState, today: NaiveDate) -> Result<String, ApiError> {
+    let base = state.public_url.trim_end_matches('/');
+    let room = MAX_URLS - PAGES.len() as u32;
+
+    let results = state
+        .db
+        .batch([
+            Statement::with_args(
+                "SELECT daily_facts.date FROM daily_facts
+                JOIN catfacts ON catfacts.id = daily_facts.catfact_id
+                WHERE daily_facts.date <= ? ORDER BY daily_facts.date DESC LIMIT ?",
+                &[Value::from(today.to_string()), Value::from(room / 2)],
+            ),
+            Statement::with_args(
+                "SELECT id FROM catfacts WHERE needs_review = 0 ORDER BY id DESC LIMIT ?",
+                &[room / 2],
+            ),
+        ])
+        .await?;
+    let (Some(days), Some(facts)) = (results.first(), results.get(1)) else {
+        return Err(ApiError::internal("Missing sitemap results"));
+    };
+    let days = store::rows::<String>(days)?;
+    let facts = store::rows::<i64>(facts)?;
+
+    let mut urls = String::new();
+    let mut push = |url: String| {
+        urls.push_str(&format!("<url><loc>{}</loc></url>\n", escape(&url)));
+    };
+    for page in PAGES {
+        push(format!("{base}{page}"));
+    }
+    for day in days {
+        push(format!("{base}/archive/{day}"));
+    }
+    for id in facts {
+        push(fact_url(base, id));
+    }
+
+    Ok(format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+{urls}</urlset>
+"#
+    ))
+}
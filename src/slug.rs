@@ -0,0 +1,85 @@
+use anyhow::anyhow;
+
+use crate::store::{self, FromRow, Row, Statement, Store, Value};
+
+const MAX_WORDS: usize = 6;
+
+/// Builds a URL-friendly slug from the first few words of a fact. The id suffix
+/// keeps slugs unique even when two facts start the same way.
+pub fn slugify(fact: &str, id: i64) -> String {
+    format!("{}{id}", slug_prefix(fact))
+}
+
+/// The part of a fact's slug before its id, e.g. `cats-sleep-a-lot-`.
+pub fn slug_prefix(fact: &str) -> String {
+    let words: Vec<String> = fact
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .take(MAX_WORDS)
+        .map(str::to_lowercase)
+        .collect();
+
+    if words.is_empty() {
+        "fact-".to_string()
+    } else {
+        format!("{}-", words.join("-"))
+    }
+}
+
+pub async fn set_slug(db: &dyn Store, id: i64, fact: &str) -> Result<(), anyhow::Error> {
+    db.execute(Statement::with_args(
+        "UPDATE catfacts SET slug = ? WHERE id = ?",
+        &[Value::from(slugify(fact, id)), Value::from(id)],
+    ))
+    .await
+    .map_err(|e| anyhow!("couldn't set the slug for fact {id}: {e}"))?;
+
+    Ok(())
+}
+
+struct Unslugged {
+    id: i64,
+    fact: String,
+}
+
+impl FromRow for Unslugged {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            id: store::integer(row, 0)?,
+            fact: store::text(row, 1)?,
+        })
+    }
+}
+
+/// Gives a slug to up to `limit` facts that don't have one yet, such as facts
+/// created before slugs existed. Returns how many it updated.
+pub async fn backfill_batch(db: &dyn Store, limit: u32) -> Result<usize, anyhow::Error> {
+    let res = db
+        .execute(Statement::with_args(
+            "SELECT id, fact FROM catfacts WHERE slug IS NULL ORDER BY id LIMIT ?",
+            &[limit],
+        ))
+        .await
+        .map_err(|e| anyhow!("couldn't find facts without slugs: {e}"))?;
+    let facts = store::rows::<Unslugged>(&res)?;
+
+    if !facts.is_empty() {
+        let updates: Vec<Statement> = facts
+            .iter()
+            .map(|fact| {
+                Statement::with_args(
+                    "UPDATE catfacts SET slug = ? WHERE id = ?",
+                    &[
+                        Value::from(slugify(&fact.fact, fact.id)),
+                        Value::from(fact.id),
+                    ],
+                )
+            })
+            .collect();
+        db.batch(updates)
+            .await
+            .map_err(|e| anyhow!("couldn't backfill slugs: {e}"))?;
+    }
+
+    Ok(facts.len())
+}
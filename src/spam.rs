@@ -0,0 +1,384 @@
+//! Scores submitted facts for spam. Each signal measures one thing about a
+//! submission, and the score is the weighted sum of the signals. Above
+//! `SPAM_REJECT_AT` a submission is turned away; above `SPAM_FLAG_AT` it's
+//! stored but held back for review.
+//!
+//! Weights come from `SPAM_WEIGHTS`, e.g. `links=2,repetition=4,phrases=3,velocity=0.5`
+//! (any left out keep their defaults), and `SPAM_PHRASES` adds comma-separated
+//! phrases to the built-in list.
+use anyhow::anyhow;
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::Serialize;
+use shuttle_secrets::SecretStore;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use crate::auth;
+use crate::error::ApiError;
+use crate::store::{self, FromRow, Row, Statement, Value};
+use crate::AppState;
+
+/// Submissions in the last ten minutes beyond this many count towards velocity.
+const VELOCITY_ALLOWANCE: i64 = 5;
+
+/// How many facts one submitter can have waiting for review, unless
+/// `PENDING_SUBMISSION_CAP` says otherwise.
+const DEFAULT_PENDING_CAP: i64 = 10;
+
+/// The `Retry-After` for a submitter at their pending cap. Nothing frees a
+/// slot but a review, so it's a guess at how long one takes.
+pub const PENDING_RETRY_AFTER: u64 = 60 * 60;
+
+const DEFAULT_PHRASES: &[&str] = &[
+    "buy now",
+    "click here",
+    "free money",
+    "limited offer",
+    "act now",
+    "work from home",
+    "casino",
+    "viagra",
+    "crypto",
+    "promo code",
+];
+
+/// What a signal gets to look at.
+pub struct Submission<'a> {
+    pub text: &'a str,
+    /// Facts submitted in the last ten minutes, by anyone.
+    pub recent_submissions: i64,
+}
+
+/// One measurable property of a submission. Higher values are spammier.
+pub trait Signal: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn default_weight(&self) -> f64;
+    fn measure(&self, submission: &Submission) -> f64;
+}
+
+/// How many links the text contains.
+struct Links;
+
+impl Signal for Links {
+    fn name(&self) -> &'static str {
+        "links"
+    }
+
+    fn default_weight(&self) -> f64 {
+        2.0
+    }
+
+    fn measure(&self, submission: &Submission) -> f64 {
+        let text = submission.text.to_lowercase();
+        let links = text.matches("http://").count()
+            + text.matches("https://").count()
+            + text.matches("www.").count();
+
+        links as f64
+    }
+}
+
+/// The share of words that repeat an earlier word, from 0 to 1. Too-short
+/// texts don't count, since a few repeats there are normal.
+struct Repetition;
+
+impl Signal for Repetition {
+    fn name(&self) -> &'static str {
+        "repetition"
+    }
+
+    fn default_weight(&self) -> f64 {
+        4.0
+    }
+
+    fn measure(&self, submission: &Submission) -> f64 {
+        let words: Vec<String> = submission
+            .text
+            .split_whitespace()
+            .map(|word| {
+                word.trim_matches(|c: char| !c.is_alphanumeric())
+                    .to_lowercase()
+            })
+            .filter(|word| !word.is_empty())
+            .collect();
+
+        if words.len() < 5 {
+            return 0.0;
+        }
+
+        let mut unique = words.clone();
+        unique.sort();
+        unique.dedup();
+
+        1.0 - unique.len() as f64 / words.len() as f64
+    }
+}
+
+/// How many known spam phrases the text contains.
+struct Phrases {
+    phrases: Vec<String>,
+}
+
+impl Signal for Phrases {
+    fn name(&self) -> &'static str {
+        "phrases"
+    }
+
+    fn default_weight(&self) -> f64 {
+        3.0
+    }
+
+    fn measure(&self, submission: &Submission) -> f64 {
+        let text = submission.text.to_lowercase();
+
+        self.phrases
+            .iter()
+            .filter(|phrase| text.contains(phrase.as_str()))
+            .count() as f64
+    }
+}
+
+/// How far recent submissions are over the usual rate, which catches floods
+/// of facts that each look fine on their own.
+struct Velocity;
+
+impl Signal for Velocity {
+    fn name(&self) -> &'static str {
+        "velocity"
+    }
+
+    fn default_weight(&self) -> f64 {
+        0.5
+    }
+
+    fn measure(&self, submission: &Submission) -> f64 {
+        (submission.recent_submissions - VELOCITY_ALLOWANCE).max(0) as f64
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Verdict {
+    Accept,
+    /// Stored, but kept out of circulation until an admin approves it.
+    Flag,
+    Reject,
+}
+
+pub struct SpamScore {
+    pub score: f64,
+    pub verdict: Verdict,
+}
+
+pub struct SpamScorer {
+    signals: Vec<(Box<dyn Signal>, f64)>,
+    flag_at: f64,
+    reject_at: f64,
+    /// The most flagged facts one submitter can have waiting for review.
+    pub pending_cap: i64,
+}
+
+impl SpamScorer {
+    pub fn from_secrets(store: &SecretStore) -> Result<Self, anyhow::Error> {
+        let mut phrases: Vec<String> = DEFAULT_PHRASES.iter().map(|p| p.to_string()).collect();
+        if let Some(extra) = store.get("SPAM_PHRASES") {
+            phrases.extend(
+                extra
+                    .split(',')
+                    .map(|phrase| phrase.trim().to_lowercase())
+                    .filter(|phrase| !phrase.is_empty()),
+            );
+        }
+
+        let signals: Vec<Box<dyn Signal>> = vec![
+            Box::new(Links),
+            Box::new(Repetition),
+            Box::new(Phrases { phrases }),
+            Box::new(Velocity),
+        ];
+
+        let weights = parse_weights(&store.get("SPAM_WEIGHTS").unwrap_or_default())?;
+        for (name, _) in &weights {
+            if !signals.iter().any(|signal| signal.name() == name) {
+                return Err(anyhow!("SPAM_WEIGHTS names an unknown signal {name:?}"));
+            }
+        }
+
+        let signals = signals
+            .into_iter()
+            .map(|signal| {
+                let weight = weights
+                    .iter()
+                    .find(|(name, _)| name == signal.name())
+                    .map_or(signal.default_weight(), |(_, weight)| *weight);
+                (signal, weight)
+            })
+            .collect();
+
+        Ok(Self {
+            signals,
+            flag_at: threshold(store, "SPAM_FLAG_AT", 3.0)?,
+            reject_at: threshold(store, "SPAM_REJECT_AT", 6.0)?,
+            pending_cap: pending_cap(store)?,
+        })
+    }
+
+    pub fn score(&self, submission: &Submission) -> SpamScore {
+        let score: f64 = self
+            .signals
+            .iter()
+            .map(|(signal, weight)| weight * signal.measure(submission))
+            .sum();
+
+        let verdict = if score >= self.reject_at {
+            Verdict::Reject
+        } else if score >= self.flag_at {
+            Verdict::Flag
+        } else {
+            Verdict::Accept
+        };
+
+        SpamScore { score, verdict }
+    }
+}
+
+fn parse_weights(value: &str) -> Result<Vec<(String, f64)>, anyhow::Error> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (name, weight) = entry.split_once('=').ok_or_else(|| {
+                anyhow!("SPAM_WEIGHTS entry {entry:?} should look like name=weight")
+            })?;
+            let weight = weight
+                .trim()
+                .parse()
+                .map_err(|e| anyhow!("SPAM_WEIGHTS weight for {name:?} isn't a number: {e}"))?;
+
+            Ok((name.trim().to_string(), weight))
+        })
+        .collect()
+}
+
+fn pending_cap(store: &SecretStore) -> Result<i64, anyhow::Error> {
+    match store.get("PENDING_SUBMISSION_CAP") {
+        Some(value) => value
+            .trim()
+            .parse()
+            .ok()
+            .filter(|cap: &i64| *cap > 0)
+            .ok_or_else(|| anyhow!("PENDING_SUBMISSION_CAP should be a positive number")),
+        None => Ok(DEFAULT_PENDING_CAP),
+    }
+}
+
+/// Who a submission counts against for `PENDING_SUBMISSION_CAP`: the API key
+/// it was sent with, if that's a valid key, or otherwise the client's address.
+/// An invalid key counts as anonymous, so making keys up doesn't get around
+/// the cap.
+pub async fn submitter(
+    state: &AppState,
+    headers: &HeaderMap,
+    ip: IpAddr,
+) -> Result<String, anyhow::Error> {
+    if let Some(key_hash) = auth::provided_key(headers).map(auth::hash_key) {
+        let res = state
+            .db
+            .execute(Statement::with_args(
+                "SELECT 1 FROM api_keys WHERE key_hash = ? AND revoked_at IS NULL",
+                &[key_hash.as_str()],
+            ))
+            .await?;
+        if store::first::<i64>(&res)?.is_some() {
+            return Ok(format!("key:{key_hash}"));
+        }
+    }
+
+    Ok(state.privacy.submitter(ip))
+}
+
+fn threshold(store: &SecretStore, key: &str, default: f64) -> Result<f64, anyhow::Error> {
+    match store.get(key) {
+        Some(value) => value
+            .trim()
+            .parse()
+            .map_err(|e| anyhow!("{key} isn't a number: {e}")),
+        None => Ok(default),
+    }
+}
+
+#[derive(Serialize)]
+pub struct FlaggedFact {
+    id: i64,
+    fact: String,
+    spam_score: Option<f64>,
+    created_at: String,
+}
+
+impl FromRow for FlaggedFact {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        let spam_score = match row.values.get(2) {
+            Some(Value::Float { value }) => Some(*value),
+            Some(Value::Integer { value }) => Some(*value as f64),
+            _ => None,
+        };
+
+        Ok(Self {
+            id: store::integer(row, 0)?,
+            fact: store::text(row, 1)?,
+            spam_score,
+            created_at: store::text(row, 3)?,
+        })
+    }
+}
+
+/// `GET /admin/catfacts/flagged` - submissions held back for review.
+pub async fn flagged_facts(
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let facts = state
+        .db
+        .execute(
+            "SELECT id, fact, spam_score, created_at FROM catfacts WHERE needs_review = 1 ORDER BY id",
+        )
+        .await
+        .and_then(|res| store::rows::<FlaggedFact>(&res))?;
+
+    Ok(Json(facts))
+}
+
+/// `POST /admin/catfacts/:id/approve` - puts a flagged fact into circulation.
+pub async fn approve_fact(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, ApiError> {
+    let res = state
+        .db
+        .batch([
+            Statement::with_args(
+                "UPDATE catfacts SET needs_review = 0 WHERE id = ? AND needs_review = 1",
+                &[id],
+            ),
+            Statement::with_args("SELECT fact FROM catfacts WHERE id = ?", &[id]),
+        ])
+        .await?;
+
+    if res.first().map_or(0, |updated| updated.rows_affected) == 0 {
+        return Err(ApiError::NotFound(format!(
+            "There's no flagged cat fact {id}"
+        )));
+    }
+
+    if let (Some(mqtt), Some(fact)) = (&state.mqtt, res.get(1)) {
+        if let Some(fact) = store::first::<String>(fact)? {
+            mqtt.publish_new_fact(&fact).await;
+        }
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,90 @@
+//! Runs the API without Shuttle, e.g. locally or on any container host:
+//! `cargo run --release --features standalone`. Everything that would go in
+//! `Secrets.toml` is read from environment variables of the same name instead,
+//! and the service listens on `HOST:PORT` (`0.0.0.0:8000` by default).
+//! SIGTERM or Ctrl-C stops it gracefully (see `shutdown`).
+use anyhow::{anyhow, Context};
+use shuttle_runtime::Service;
+use shuttle_secrets::SecretStore;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tracing_subscriber::EnvFilter;
+
+use crate::store::{LibsqlStore, SqliteStore, Store};
+
+const DEFAULT_HOST: &str = "0.0.0.0";
+const DEFAULT_PORT: u16 = 8000;
+
+pub async fn run() -> Result<(), anyhow::Error> {
+    // Shuttle sets up logging itself; here it's `RUST_LOG`, or `info`.
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()))
+        .init();
+
+    let mut env: BTreeMap<String, String> = std::env::vars_os()
+        .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)))
+        .collect();
+    let addr = address(&env)?;
+    // Links in emails would otherwise point at the shuttle.rs deployment.
+    env.entry("PUBLIC_URL".to_string())
+        .or_insert_with(|| format!("http://localhost:{}", addr.port()));
+    let secrets = SecretStore::new(env);
+
+    let db = database(&secrets).await?;
+    let service = crate::service(db, secrets).await?;
+    tokio::spawn(service.shutdown().listen());
+
+    tracing::info!("Listening on {addr}");
+    service
+        .bind(addr)
+        .await
+        .map_err(|e| anyhow!("the service stopped: {e}"))
+}
+
+fn address(env: &BTreeMap<String, String>) -> Result<SocketAddr, anyhow::Error> {
+    let host = env.get("HOST").map_or(DEFAULT_HOST, String::as_str);
+    let host: IpAddr = host
+        .parse()
+        .with_context(|| format!("HOST should be an IP address to listen on, not {host:?}"))?;
+    let port = match env.get("PORT") {
+        Some(port) => port
+            .parse()
+            .with_context(|| format!("PORT should be a port number, not {port:?}"))?,
+        None => DEFAULT_PORT,
+    };
+
+    Ok(SocketAddr::new(host, port))
+}
+
+/// The SQLite database at `DATABASE_URL` if that's set, or else the Turso
+/// database at `TURSO_ADDR`.
+async fn database(secrets: &SecretStore) -> Result<Arc<dyn Store>, anyhow::Error> {
+    if let Some(url) = secrets.get("DATABASE_URL") {
+        return Ok(Arc::new(SqliteStore::open(&url)?));
+    }
+
+    let addr = secrets
+        .get("TURSO_ADDR")
+        .filter(|addr| !addr.is_empty())
+        .ok_or_else(|| {
+            anyhow!("Set DATABASE_URL, or TURSO_ADDR and TURSO_TOKEN, to pick a database")
+        })?;
+    // As with Shuttle, an address without a scheme is a Turso database.
+    let addr = if addr.contains("://") {
+        addr
+    } else {
+        format!("libsql://{addr}")
+    };
+
+    let client = libsql_client::Client::from_config(libsql_client::Config {
+        url: addr
+            .parse()
+            .with_context(|| format!("TURSO_ADDR {addr:?} isn't a valid address"))?,
+        auth_token: secrets.get("TURSO_TOKEN"),
+    })
+    .await
+    .with_context(|| format!("couldn't connect to {addr}"))?;
+
+    Ok(Arc::new(LibsqlStore::new(client)))
+}
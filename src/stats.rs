@@ -0,0 +1,106 @@
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::Serialize;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use crate::{badge, error::ApiError, store, AppState};
+
+/// Counts below this are shown as "<10" rather than rounded.
+const MIN_SHOWN: i64 = 10;
+
+/// The public subscriber count. Only a rounded figure is ever exposed, so the
+/// widget can't be polled to learn the exact size of the list or to spot
+/// individual signups.
+#[derive(Serialize, ToSchema)]
+pub struct SubscriberCount {
+    /// The count rounded down (see `SubscriberCount::new`); 0 below 10.
+    rounded: i64,
+    /// The rounded count for display, e.g. "50+", "1,200+" or "<10".
+    display: String,
+}
+
+impl SubscriberCount {
+    fn new(exact: i64) -> Self {
+        if exact < MIN_SHOWN {
+            return Self {
+                rounded: 0,
+                display: format!("<{MIN_SHOWN}"),
+            };
+        }
+
+        // Round down to the nearest ten, and to two significant figures once
+        // there are more than that.
+        let mut step = 10;
+        while exact / step >= 100 {
+            step *= 10;
+        }
+        let rounded = exact / step * step;
+
+        Self {
+            rounded,
+            display: format!("{}+", group_thousands(rounded)),
+        }
+    }
+}
+
+fn group_thousands(n: i64) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::new();
+
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+
+    grouped
+}
+
+async fn subscriber_count(state: &AppState) -> Result<SubscriberCount, ApiError> {
+    let res = state
+        .db
+        .execute("SELECT count(*) FROM subscribers WHERE confirmed = 1")
+        .await?;
+
+    let exact: i64 = store::first(&res)?.unwrap_or(0);
+
+    Ok(SubscriberCount::new(exact))
+}
+
+/// `GET /stats/subscribers` - the rounded subscriber count as JSON.
+#[utoipa::path(
+    get,
+    path = "/stats/subscribers",
+    tag = "subscriptions",
+    responses((status = 200, description = "The rounded count", body = SubscriberCount))
+)]
+pub async fn subscribers_json(
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    subscriber_count(&state).await.map(Json)
+}
+
+/// `GET /stats/subscribers.svg` - the rounded subscriber count as a badge.
+#[utoipa::path(
+    get,
+    path = "/stats/subscribers.svg",
+    tag = "subscriptions",
+    responses((status = 200, description = "An SVG badge", body = String, content_type = "image/svg+xml"))
+)]
+pub async fn subscribers_badge(
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let count = subscriber_count(&state).await?;
+
+    Ok::<_, ApiError>((
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "image/svg+xml")],
+        badge::render("subscribers", &count.display),
+    ))
+}
@@ -0,0 +1,205 @@
+use axum::async_trait;
+use chrono::NaiveDate;
+
+use super::{first, rows, Database, Statement, Value};
+use crate::license::License;
+use crate::{fact_id, tags, CatFactRecord, CATFACT_COLUMNS};
+
+/// The facts that can be handed out, for `?1` set to a tag to only include
+/// facts with it, or `NULL` for all of them.
+pub fn in_circulation() -> String {
+    format!("needs_review = 0 AND (?1 IS NULL OR {})", tags::HAS_TAG)
+}
+
+/// The `catfacts` table.
+#[async_trait]
+pub trait FactStore: Database {
+    /// The facts with `ids`, in no particular order.
+    async fn facts_by_id(&self, ids: &[i64]) -> Result<Vec<CatFactRecord>, anyhow::Error> {
+        let placeholders = vec!["?"; ids.len()].join(", ");
+        let args: Vec<Value> = ids.iter().map(|id| Value::from(*id)).collect();
+        let res = self
+            .run(Statement::with_args(
+                format!("SELECT {CATFACT_COLUMNS} FROM catfacts WHERE id IN ({placeholders})"),
+                &args,
+            ))
+            .await?;
+
+        rows(&res)
+    }
+
+    /// Up to `count` random facts in circulation, optionally only ones tagged
+    /// `tag`.
+    async fn random_facts(
+        &self,
+        tag: Option<&str>,
+        count: u32,
+    ) -> Result<Vec<CatFactRecord>, anyhow::Error> {
+        let res = self
+            .run(Statement::with_args(
+                format!(
+                    "SELECT {CATFACT_COLUMNS} FROM catfacts WHERE {} order by random() limit ?2",
+                    in_circulation()
+                ),
+                &[tag.map_or(Value::Null, Value::from), Value::from(count)],
+            ))
+            .await?;
+
+        rows(&res)
+    }
+
+    /// The `count` newest facts in circulation, newest first, optionally only
+    /// ones tagged `tag`.
+    async fn recent_facts(
+        &self,
+        tag: Option<&str>,
+        count: u32,
+    ) -> Result<Vec<CatFactRecord>, anyhow::Error> {
+        let res = self
+            .run(Statement::with_args(
+                format!(
+                    "SELECT {CATFACT_COLUMNS} FROM catfacts WHERE {} ORDER BY id DESC LIMIT ?2",
+                    in_circulation()
+                ),
+                &[tag.map_or(Value::Null, Value::from), Value::from(count)],
+            ))
+            .await?;
+
+        rows(&res)
+    }
+
+    /// The fact picked for `date`, if one has been.
+    async fn fact_of_day(&self, date: NaiveDate) -> Result<Option<CatFactRecord>, anyhow::Error> {
+        let res = self
+            .run(Statement::with_args(
+                format!(
+                    "SELECT {CATFACT_COLUMNS} FROM catfacts
+                    WHERE id = (SELECT catfact_id FROM daily_facts WHERE date = ?)"
+                ),
+                &[date.to_string()],
+            ))
+            .await?;
+
+        first(&res)
+    }
+
+    /// Looks a fact up by numeric id, by fact id, or by slug otherwise.
+    async fn fact_by_key(&self, key: &str) -> Result<Option<CatFactRecord>, anyhow::Error> {
+        let stmt = match key.parse::<i64>() {
+            Ok(id) => Statement::with_args(
+                format!("SELECT {CATFACT_COLUMNS} FROM catfacts WHERE id = ?"),
+                &[id],
+            ),
+            Err(_) if fact_id::is_fact_id(key) => Statement::with_args(
+                format!(
+                    "SELECT {CATFACT_COLUMNS} FROM catfacts WHERE fact_id = ? ORDER BY id LIMIT 1"
+                ),
+                &[key],
+            ),
+            Err(_) => Statement::with_args(
+                format!("SELECT {CATFACT_COLUMNS} FROM catfacts WHERE slug = ?"),
+                &[key],
+            ),
+        };
+
+        first(&self.run(stmt).await?)
+    }
+
+    /// How many facts were submitted in the last ten minutes.
+    async fn recent_submissions(&self) -> Result<i64, anyhow::Error> {
+        let res = self
+            .run(Statement::new(
+                "SELECT count(*) FROM catfacts WHERE created_at > datetime('now', '-10 minutes')",
+            ))
+            .await?;
+
+        Ok(first(&res)?.unwrap_or(0))
+    }
+
+    /// How many of `submitted_by`'s facts are waiting for review.
+    async fn pending_submissions(&self, submitted_by: &str) -> Result<i64, anyhow::Error> {
+        let res = self
+            .run(Statement::with_args(
+                "SELECT count(*) FROM catfacts WHERE needs_review = 1 AND submitted_by = ?",
+                &[submitted_by],
+            ))
+            .await?;
+
+        Ok(first(&res)?.unwrap_or(0))
+    }
+
+    /// Adds a fact, held back from circulation if it `needs_review`. Returns
+    /// its id.
+    async fn insert_fact(
+        &self,
+        fact: &str,
+        license: License,
+        spam_score: f64,
+        needs_review: bool,
+        submitted_by: &str,
+    ) -> Result<Option<i64>, anyhow::Error> {
+        let res = self
+            .run(Statement::with_args(
+                "INSERT into CATFACTS (fact, fact_id, license, spam_score, needs_review, submitted_by) VALUES (?, ?, ?, ?, ?, ?)",
+                &[
+                    Value::from(fact),
+                    Value::from(fact_id::fact_id(fact)),
+                    Value::from(license.name()),
+                    Value::from(spam_score),
+                    Value::from(i64::from(needs_review)),
+                    Value::from(submitted_by),
+                ],
+            ))
+            .await?;
+
+        Ok(res.last_insert_rowid)
+    }
+
+    /// Corrects a fact's text, and its license if one is given. The slug is
+    /// kept; the fact id follows the new text. Returns the corrected fact, or
+    /// `None` if there's no fact `id`.
+    async fn update_fact(
+        &self,
+        id: i64,
+        fact: &str,
+        license: Option<License>,
+    ) -> Result<Option<CatFactRecord>, anyhow::Error> {
+        let res = self
+            .run_batch(vec![
+                Statement::with_args(
+                    "UPDATE catfacts SET fact = ?, fact_id = ?, license = coalesce(?, license) WHERE id = ?",
+                    &[
+                        Value::from(fact),
+                        Value::from(fact_id::fact_id(fact)),
+                        license.map_or(Value::Null, |license| Value::from(license.name())),
+                        Value::from(id),
+                    ],
+                ),
+                Statement::with_args(
+                    format!("SELECT {CATFACT_COLUMNS} FROM catfacts WHERE id = ?"),
+                    &[id],
+                ),
+            ])
+            .await?;
+
+        res.get(1).map(first).transpose().map(Option::flatten)
+    }
+
+    /// Removes a fact and its tags. If it was picked as the fact of the day
+    /// for `today` or later, those days get a new pick. Returns false if there
+    /// was no fact `id`.
+    async fn delete_fact(&self, id: i64, today: NaiveDate) -> Result<bool, anyhow::Error> {
+        let res = self
+            .run_batch(vec![
+                Statement::with_args(
+                    "DELETE FROM daily_facts WHERE catfact_id = ? AND date >= ?",
+                    &[Value::from(id), Value::from(today.to_string())],
+                ),
+                Statement::with_args("DELETE FROM catfacts WHERE id = ?", &[id]),
+                Statement::with_args("DELETE FROM catfact_tags WHERE catfact_id = ?", &[id]),
+            ])
+            .await?;
+
+        Ok(res.get(1).is_some_and(|deleted| deleted.rows_affected > 0))
+    }
+}
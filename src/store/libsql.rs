@@ -0,0 +1,40 @@
+use axum::async_trait;
+use libsql_client::client::Client;
+
+use super::{Database, FactStore, ResultSet, Statement, SubscriberStore};
+
+/// A libsql database, local or remote, e.g. a Turso one.
+pub struct LibsqlStore {
+    client: Client,
+}
+
+impl LibsqlStore {
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+}
+
+impl From<Statement> for libsql_client::Statement {
+    fn from(stmt: Statement) -> Self {
+        Self::with_args(stmt.sql, &stmt.args)
+    }
+}
+
+#[async_trait]
+impl Database for LibsqlStore {
+    async fn run(&self, stmt: Statement) -> Result<ResultSet, anyhow::Error> {
+        self.client
+            .execute(libsql_client::Statement::from(stmt))
+            .await
+    }
+
+    async fn run_batch(&self, stmts: Vec<Statement>) -> Result<Vec<ResultSet>, anyhow::Error> {
+        self.client
+            .batch(stmts.into_iter().map(libsql_client::Statement::from))
+            .await
+    }
+}
+
+impl FactStore for LibsqlStore {}
+
+impl SubscriberStore for LibsqlStore {}
@@ -0,0 +1,169 @@
+//! Where the data lives. Handlers reach the database through `AppState`'s
+//! `Store`, never a particular client, so the crate isn't tied to Turso:
+//!
+//! - `LibsqlStore` - a libsql client, e.g. the Turso database Shuttle
+//!   provisions. The default.
+//! - `SqliteStore` - a SQLite file, or an in-memory database, for local
+//!   development and tests. Used when the `DATABASE_URL` secret is set.
+//!
+//! `FactStore` and `SubscriberStore` hold the queries behind the fact and
+//! signup routes. Everything else writes its own SQL and runs it through
+//! `execute` and `batch`, which every store has, so a store has to speak
+//! SQLite's dialect.
+//!
+//! The rest of this module is typed access to query results. Rows hold
+//! loosely typed `Value`s, and `Value`'s `Display` impl formats text as JSON -
+//! so `to_string()` on a fact gives `"\"Cats purr\""`. Anything reading rows
+//! should map them through here instead.
+use anyhow::anyhow;
+use axum::async_trait;
+use std::future::Future;
+
+mod facts;
+mod libsql;
+mod sqlite;
+mod subscribers;
+
+pub use facts::{in_circulation, FactStore};
+pub use libsql::LibsqlStore;
+pub use libsql_client::{ResultSet, Row, Value};
+pub use sqlite::SqliteStore;
+pub use subscribers::SubscriberStore;
+
+/// An SQL statement and its arguments.
+#[derive(Clone, Debug)]
+pub struct Statement {
+    sql: String,
+    args: Vec<Value>,
+}
+
+impl Statement {
+    pub fn new(sql: impl Into<String>) -> Self {
+        Self {
+            sql: sql.into(),
+            args: Vec::new(),
+        }
+    }
+
+    pub fn with_args(sql: impl Into<String>, args: &[impl Into<Value> + Clone]) -> Self {
+        Self {
+            sql: sql.into(),
+            args: args.iter().cloned().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<String> for Statement {
+    fn from(sql: String) -> Self {
+        Self::new(sql)
+    }
+}
+
+impl From<&str> for Statement {
+    fn from(sql: &str) -> Self {
+        Self::new(sql)
+    }
+}
+
+/// Runs statements against a database.
+#[async_trait]
+pub trait Database: Send + Sync {
+    async fn run(&self, stmt: Statement) -> Result<ResultSet, anyhow::Error>;
+
+    /// Runs `stmts` in one transaction: if any fails, none of them take
+    /// effect.
+    async fn run_batch(&self, stmts: Vec<Statement>) -> Result<Vec<ResultSet>, anyhow::Error>;
+}
+
+/// Everything a handler needs from the database.
+pub trait Store: FactStore + SubscriberStore {}
+
+impl<T: FactStore + SubscriberStore> Store for T {}
+
+impl dyn Store + '_ {
+    pub fn execute(
+        &self,
+        stmt: impl Into<Statement>,
+    ) -> impl Future<Output = Result<ResultSet, anyhow::Error>> + Send + '_ {
+        self.run(stmt.into())
+    }
+
+    /// Runs `stmts` in one transaction, like `Database::run_batch`.
+    pub fn batch(
+        &self,
+        stmts: impl IntoIterator<Item = impl Into<Statement>>,
+    ) -> impl Future<Output = Result<Vec<ResultSet>, anyhow::Error>> + Send + '_ {
+        self.run_batch(stmts.into_iter().map(Into::into).collect())
+    }
+}
+
+/// Builds a value from one result row.
+pub trait FromRow: Sized {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error>;
+}
+
+impl FromRow for String {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        text(row, 0)
+    }
+}
+
+impl FromRow for i64 {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        integer(row, 0)
+    }
+}
+
+/// Maps every row of a result set.
+pub fn rows<T: FromRow>(res: &ResultSet) -> Result<Vec<T>, anyhow::Error> {
+    res.rows.iter().map(T::from_row).collect()
+}
+
+/// Maps the first row of a result set, if there is one.
+pub fn first<T: FromRow>(res: &ResultSet) -> Result<Option<T>, anyhow::Error> {
+    res.rows.first().map(T::from_row).transpose()
+}
+
+pub fn text(row: &Row, idx: usize) -> Result<String, anyhow::Error> {
+    match value(row, idx)? {
+        Value::Text { value } => Ok(value.clone()),
+        other => Err(anyhow!("expected text in column {idx}, got {other:?}")),
+    }
+}
+
+pub fn optional_text(row: &Row, idx: usize) -> Result<Option<String>, anyhow::Error> {
+    match value(row, idx)? {
+        Value::Null => Ok(None),
+        _ => text(row, idx).map(Some),
+    }
+}
+
+pub fn integer(row: &Row, idx: usize) -> Result<i64, anyhow::Error> {
+    match value(row, idx)? {
+        Value::Integer { value } => Ok(*value),
+        other => Err(anyhow!(
+            "expected an integer in column {idx}, got {other:?}"
+        )),
+    }
+}
+
+pub fn optional_integer(row: &Row, idx: usize) -> Result<Option<i64>, anyhow::Error> {
+    match value(row, idx)? {
+        Value::Null => Ok(None),
+        _ => integer(row, idx).map(Some),
+    }
+}
+
+/// Whether `e` is SQLite refusing a write that would break the unique index
+/// on `column`, given as `table.column`. Both backends only pass SQLite's
+/// message along, so that's what's checked.
+pub fn violates_unique(e: &anyhow::Error, column: &str) -> bool {
+    e.to_string()
+        .contains(&format!("UNIQUE constraint failed: {column}"))
+}
+
+fn value(row: &Row, idx: usize) -> Result<&Value, anyhow::Error> {
+    row.values
+        .get(idx)
+        .ok_or_else(|| anyhow!("missing column {idx}"))
+}
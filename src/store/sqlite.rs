@@ -0,0 +1,133 @@
+use anyhow::anyhow;
+use axum::async_trait;
+use rusqlite::{types::Value as SqliteValue, Connection};
+use std::sync::{Arc, Mutex};
+
+use super::{Database, FactStore, ResultSet, Row, Statement, SubscriberStore, Value};
+
+/// A SQLite database in a file, or in memory.
+///
+/// libsql's own local client doesn't report affected rows or inserted ids,
+/// which plenty of handlers rely on, so this talks to SQLite directly.
+#[derive(Clone)]
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    /// Opens `url`: `:memory:` for a database that's gone when the service
+    /// stops, or the path of a SQLite file, optionally as a `file:` URL.
+    pub fn open(url: &str) -> Result<Self, anyhow::Error> {
+        let conn = match url.trim() {
+            ":memory:" => Connection::open_in_memory(),
+            url => Connection::open(url.strip_prefix("file://").unwrap_or(url)),
+        }
+        .map_err(|e| anyhow!("couldn't open the SQLite database {url:?}: {e}"))?;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Runs `f` with the connection, off the async runtime's threads.
+    async fn with_conn<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> Result<T, anyhow::Error> + Send + 'static,
+    ) -> Result<T, anyhow::Error> {
+        let conn = self.conn.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn
+                .lock()
+                .map_err(|_| anyhow!("the SQLite connection was poisoned"))?;
+            f(&mut conn)
+        })
+        .await?
+    }
+}
+
+fn run_on(conn: &Connection, stmt: Statement) -> Result<ResultSet, anyhow::Error> {
+    let mut prepared = conn
+        .prepare(&stmt.sql)
+        .map_err(|e| anyhow!("{e} in {:?}", stmt.sql))?;
+    let columns: Vec<String> = prepared
+        .column_names()
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+    let changes_before = total_changes(conn)?;
+
+    let mut rows = Vec::new();
+    let mut results = prepared.query(rusqlite::params_from_iter(
+        stmt.args.into_iter().map(to_sqlite),
+    ))?;
+    while let Some(row) = results.next()? {
+        let values = (0..columns.len())
+            .map(|idx| row.get::<_, SqliteValue>(idx).map(from_sqlite))
+            .collect::<Result<_, _>>()?;
+        rows.push(Row { values });
+    }
+    drop(results);
+
+    // `changes` and `last_insert_rowid` are left over from the last write, so
+    // they only mean something for this statement if it wrote.
+    let wrote = total_changes(conn)? > changes_before;
+    let rows_affected = if wrote { conn.changes() } else { 0 };
+
+    Ok(ResultSet {
+        columns,
+        rows,
+        rows_affected,
+        last_insert_rowid: (rows_affected > 0).then(|| conn.last_insert_rowid()),
+    })
+}
+
+/// How many rows have been written since the connection was opened.
+fn total_changes(conn: &Connection) -> Result<i64, rusqlite::Error> {
+    conn.query_row("SELECT total_changes()", [], |row| row.get(0))
+}
+
+fn to_sqlite(value: Value) -> SqliteValue {
+    match value {
+        Value::Null => SqliteValue::Null,
+        Value::Integer { value } => SqliteValue::Integer(value),
+        Value::Float { value } => SqliteValue::Real(value),
+        Value::Text { value } => SqliteValue::Text(value),
+        Value::Blob { value } => SqliteValue::Blob(value),
+    }
+}
+
+fn from_sqlite(value: SqliteValue) -> Value {
+    match value {
+        SqliteValue::Null => Value::Null,
+        SqliteValue::Integer(value) => Value::Integer { value },
+        SqliteValue::Real(value) => Value::Float { value },
+        SqliteValue::Text(value) => Value::Text { value },
+        SqliteValue::Blob(value) => Value::Blob { value },
+    }
+}
+
+#[async_trait]
+impl Database for SqliteStore {
+    async fn run(&self, stmt: Statement) -> Result<ResultSet, anyhow::Error> {
+        self.with_conn(move |conn| run_on(conn, stmt)).await
+    }
+
+    async fn run_batch(&self, stmts: Vec<Statement>) -> Result<Vec<ResultSet>, anyhow::Error> {
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let results = stmts
+                .into_iter()
+                .map(|stmt| run_on(&tx, stmt))
+                .collect::<Result<_, _>>()?;
+            tx.commit()?;
+
+            Ok(results)
+        })
+        .await
+    }
+}
+
+impl FactStore for SqliteStore {}
+
+impl SubscriberStore for SqliteStore {}
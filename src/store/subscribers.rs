@@ -0,0 +1,181 @@
+use anyhow::anyhow;
+use axum::async_trait;
+
+use super::{first, violates_unique, Database, Statement, Value};
+use crate::delivery::DeliveryWindow;
+use crate::email_format::EmailFormat;
+use crate::language::Language;
+use crate::preferences::Preferences;
+use crate::timezone::Timezone;
+use crate::weekdays::Weekdays;
+
+/// What `Preferences` is read from.
+const PREFERENCE_COLUMNS: &str =
+    "email, delivery_hour, email_format, weekdays, language, utc_offset,
+    (SELECT group_concat(tag_id) FROM subscriber_topics WHERE subscriber_id = subscribers.id)";
+
+/// The `subscribers` table.
+#[async_trait]
+pub trait SubscriberStore: Database {
+    /// Signs `email` up, unconfirmed, with a new confirmation token, which is
+    /// returned. Signing up again before confirming replaces the token and
+    /// the chosen schedule, and restarts the wait for a reminder (see
+    /// `confirm::Confirmations`); signing up after confirming returns `None`.
+    /// Addresses are compared case-insensitively, so this also finds rows
+    /// stored before addresses were normalized. Losing a race with another
+    /// signup for the same new address also returns `None`, once the unique
+    /// index on addresses is there to catch it.
+    async fn sign_up(
+        &self,
+        email: &str,
+        delivery_window: DeliveryWindow,
+        weekdays: Weekdays,
+        language: Language,
+        timezone: Timezone,
+    ) -> Result<Option<String>, anyhow::Error> {
+        let token: String = first(
+            &self
+                .run(Statement::new("SELECT lower(hex(randomblob(16)))"))
+                .await?,
+        )?
+        .ok_or_else(|| anyhow!("couldn't generate a confirmation token"))?;
+
+        let values = [
+            Value::from(delivery_window.hour()),
+            Value::from(weekdays.mask()),
+            Value::from(&token),
+            Value::from(email),
+            Value::from(language.name()),
+            Value::from(timezone.minutes()),
+        ];
+        let res = self
+            .run_batch(vec![
+                Statement::with_args(
+                    "UPDATE subscribers SET delivery_hour = ?1, weekdays = ?2, language = ?5,
+                    utc_offset = ?6, confirmation_token = ?3, confirmation_sent_at = current_timestamp, confirmation_reminded = 0
+                    WHERE lower(trim(email)) = ?4 AND confirmed = 0",
+                    &values,
+                ),
+                Statement::with_args(
+                    "INSERT INTO subscribers (delivery_hour, weekdays, confirmation_token, email, language, utc_offset, token, confirmed)
+                    SELECT ?1, ?2, ?3, ?4, ?5, ?6, lower(hex(randomblob(16))), 0
+                    WHERE NOT EXISTS (SELECT 1 FROM subscribers WHERE lower(trim(email)) = ?4)",
+                    &values,
+                ),
+            ])
+            .await;
+        let changed: u64 = match res {
+            Ok(res) => res.iter().map(|res| res.rows_affected).sum(),
+            Err(e) if violates_unique(&e, "subscribers.email") => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        Ok((changed > 0).then_some(token))
+    }
+
+    /// Confirms the subscription waiting on `confirmation_token`, logging the
+    /// signup. Returns false if no subscription is.
+    async fn confirm(&self, confirmation_token: &str) -> Result<bool, anyhow::Error> {
+        let res = self
+            .run_batch(vec![
+                Statement::with_args(
+                    "INSERT INTO subscriber_events (event, delivery_hour)
+                    SELECT 'subscribed', delivery_hour FROM subscribers WHERE confirmation_token = ?",
+                    &[confirmation_token],
+                ),
+                Statement::with_args(
+                    "UPDATE subscribers SET confirmed = 1, confirmation_token = NULL WHERE confirmation_token = ?",
+                    &[confirmation_token],
+                ),
+            ])
+            .await?;
+
+        Ok(res.get(1).is_some_and(|updated| updated.rows_affected > 0))
+    }
+
+    /// The preferences of the subscriber with `token`.
+    async fn preferences(&self, token: &str) -> Result<Option<Preferences>, anyhow::Error> {
+        let res = self
+            .run(Statement::with_args(
+                format!("SELECT {PREFERENCE_COLUMNS} FROM subscribers WHERE token = ?"),
+                &[token],
+            ))
+            .await?;
+
+        first(&res)
+    }
+
+    /// Changes the preferences of the subscriber with `token`. Returns their
+    /// updated preferences, or `None` if there's no such subscriber.
+    async fn update_preferences(
+        &self,
+        token: &str,
+        delivery_window: DeliveryWindow,
+        email_format: EmailFormat,
+        weekdays: Weekdays,
+        language: Language,
+        timezone: Timezone,
+    ) -> Result<Option<Preferences>, anyhow::Error> {
+        let res = self
+            .run_batch(vec![
+                Statement::with_args(
+                    "UPDATE subscribers SET delivery_hour = ?, email_format = ?, weekdays = ?, language = ?,
+                    utc_offset = ? WHERE token = ?",
+                    &[
+                        Value::from(delivery_window.hour()),
+                        Value::from(email_format.name()),
+                        Value::from(weekdays.mask()),
+                        Value::from(language.name()),
+                        Value::from(timezone.minutes()),
+                        Value::from(token),
+                    ],
+                ),
+                Statement::with_args(
+                    format!("SELECT {PREFERENCE_COLUMNS} FROM subscribers WHERE token = ?"),
+                    &[token],
+                ),
+            ])
+            .await?;
+
+        res.get(1).map(first).transpose().map(Option::flatten)
+    }
+
+    /// Replaces the topics of the subscriber with `token` with the tags in
+    /// `tag_ids`, skipping any that don't exist. None means every fact.
+    async fn set_topics(&self, token: &str, tag_ids: &[i64]) -> Result<(), anyhow::Error> {
+        let subscriber = "(SELECT id FROM subscribers WHERE token = ?)";
+        let mut statements = vec![Statement::with_args(
+            format!("DELETE FROM subscriber_topics WHERE subscriber_id = {subscriber}"),
+            &[token],
+        )];
+        statements.extend(tag_ids.iter().map(|tag_id| {
+            Statement::with_args(
+                format!(
+                    "INSERT OR IGNORE INTO subscriber_topics (subscriber_id, tag_id)
+                    SELECT {subscriber}, id FROM tags WHERE id = ? AND {subscriber} IS NOT NULL"
+                ),
+                &[Value::from(token), Value::from(*tag_id), Value::from(token)],
+            )
+        }));
+        self.run_batch(statements).await?;
+
+        Ok(())
+    }
+
+    /// Deletes the subscriber with `token`, logging the unsubscribe. Returns
+    /// false if there was no such subscriber.
+    async fn remove_subscriber(&self, token: &str) -> Result<bool, anyhow::Error> {
+        let res = self
+            .run_batch(vec![
+                Statement::with_args(
+                    "INSERT INTO subscriber_events (event, delivery_hour)
+                    SELECT 'unsubscribed', delivery_hour FROM subscribers WHERE token = ? AND confirmed = 1",
+                    &[token],
+                ),
+                Statement::with_args("DELETE FROM subscribers WHERE token = ?", &[token]),
+            ])
+            .await?;
+
+        Ok(res.get(1).is_some_and(|deleted| deleted.rows_affected > 0))
+    }
+}
@@ -0,0 +1,84 @@
+use axum::{
+    async_trait,
+    extract::{
+        rejection::{FormRejection, JsonRejection},
+        FromRequest,
+    },
+    http::{header, HeaderMap, Request},
+    Form, Json,
+};
+use serde::de::DeserializeOwned;
+
+use crate::error::ApiError;
+
+/// A `Json` extractor for request bodies whose type is marked
+/// `#[serde(deny_unknown_fields)]`. Rather than axum's plain-text rejection, a
+/// bad body gets a JSON error explaining what was wrong - e.g. that `Fact`
+/// isn't a known field and `fact` was expected.
+pub struct StrictJson<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for StrictJson<T>
+where
+    T: DeserializeOwned,
+    Json<T>: FromRequest<S, B, Rejection = JsonRejection>,
+    S: Send + Sync,
+    B: Send + 'static,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        match Json::<T>::from_request(req, state).await {
+            Ok(Json(value)) => Ok(Self(value)),
+            Err(rejection) => Err(invalid_body(rejection)),
+        }
+    }
+}
+
+/// Like `StrictJson`, but also accepts `application/x-www-form-urlencoded`
+/// bodies, so plain HTML forms can post to the same route.
+pub struct JsonOrForm<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for JsonOrForm<T>
+where
+    T: DeserializeOwned,
+    Json<T>: FromRequest<S, B, Rejection = JsonRejection>,
+    Form<T>: FromRequest<S, B, Rejection = FormRejection>,
+    S: Send + Sync,
+    B: Send + 'static,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        if is_form(req.headers()) {
+            match Form::<T>::from_request(req, state).await {
+                Ok(Form(value)) => Ok(Self(value)),
+                Err(rejection) => Err(ApiError::InvalidBody(
+                    rejection.status(),
+                    rejection.body_text(),
+                )),
+            }
+        } else {
+            let StrictJson(value) = StrictJson::from_request(req, state).await?;
+            Ok(Self(value))
+        }
+    }
+}
+
+/// Whether a request's body is `application/x-www-form-urlencoded`.
+pub fn is_form(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"))
+}
+
+fn invalid_body(rejection: JsonRejection) -> ApiError {
+    // Bodies that parse as JSON but don't match the schema (unknown or missing
+    // fields, wrong types) are 422s; other rejections keep axum's status code.
+    match rejection {
+        JsonRejection::JsonDataError(_) => ApiError::Validation(rejection.body_text()),
+        other => ApiError::InvalidBody(other.status(), other.body_text()),
+    }
+}
@@ -0,0 +1,133 @@
+//! Manual management of the suppression list, for support to check an address
+//! or lift a suppression after a transient bounce.
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::store::{self, FromRow, Row, Statement};
+use crate::strict::StrictJson;
+use crate::{crypto, error::ApiError, AppState};
+
+#[derive(Serialize)]
+pub struct SuppressionStatus {
+    email: String,
+    suppressed: bool,
+    reason: Option<String>,
+    created_at: Option<String>,
+}
+
+struct Suppression {
+    reason: String,
+    created_at: String,
+}
+
+impl FromRow for Suppression {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            reason: store::text(row, 0)?,
+            created_at: store::text(row, 1)?,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewSuppression {
+    email: String,
+    #[serde(default = "manual_reason")]
+    reason: String,
+}
+
+fn manual_reason() -> String {
+    "manual".to_string()
+}
+
+/// Suppressions are keyed on the lowercased address, like the complaints webhook stores them.
+fn normalize(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// `GET /admin/suppressions/:email` - whether an address is suppressed, and why.
+pub async fn get_suppression(
+    State(state): State<Arc<AppState>>,
+    Path(email): Path<String>,
+) -> Result<Json<SuppressionStatus>, ApiError> {
+    let email = normalize(&email);
+
+    let suppression = state
+        .db
+        .execute(Statement::with_args(
+            "SELECT reason, created_at FROM suppressions WHERE email = ?",
+            &[&email],
+        ))
+        .await
+        .and_then(|res| store::first::<Suppression>(&res))?;
+
+    Ok(Json(SuppressionStatus {
+        email,
+        suppressed: suppression.is_some(),
+        reason: suppression.as_ref().map(|s| s.reason.clone()),
+        created_at: suppression.map(|s| s.created_at),
+    }))
+}
+
+/// `POST /admin/suppressions` - suppresses an address by hand.
+pub async fn create_suppression(
+    State(state): State<Arc<AppState>>,
+    StrictJson(new): StrictJson<NewSuppression>,
+) -> Result<impl IntoResponse, ApiError> {
+    let email = normalize(&new.email);
+    if email.is_empty() {
+        return Err(ApiError::BadRequest("email can't be empty".to_string()));
+    }
+
+    state
+        .db
+        .execute(Statement::with_args(
+            "INSERT INTO suppressions (email, reason) VALUES (?, ?)
+            ON CONFLICT (email) DO UPDATE SET reason = excluded.reason",
+            &[email.as_str(), new.reason.as_str()],
+        ))
+        .await?;
+
+    tracing::info!(
+        "Suppressed address {} by hand ({})",
+        crypto::email_hash(&email),
+        new.reason
+    );
+
+    Ok((StatusCode::CREATED, format!("Suppressed {email}")))
+}
+
+/// `DELETE /admin/suppressions/:email` - lifts a suppression so the address
+/// gets mail again.
+pub async fn delete_suppression(
+    State(state): State<Arc<AppState>>,
+    Path(email): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let email = normalize(&email);
+
+    let res = state
+        .db
+        .execute(Statement::with_args(
+            "DELETE FROM suppressions WHERE email = ?",
+            &[&email],
+        ))
+        .await?;
+
+    if res.rows_affected == 0 {
+        return Err(ApiError::NotFound(format!("{email} isn't suppressed")));
+    }
+
+    tracing::info!(
+        "Lifted the suppression on address {}",
+        crypto::email_hash(&email)
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
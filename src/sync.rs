@@ -0,0 +1,303 @@
+//! Keeps one environment's facts aligned with another's: `POST
+//! /admin/sync/pull` fetches new facts from a source instance's `GET
+//! /admin/sync/facts` and inserts any this instance doesn't have yet.
+use anyhow::anyhow;
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use shuttle_secrets::SecretStore;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::license::License;
+use crate::mqtt::FactPublisher;
+use crate::store::{self, FromRow, Row, Statement, Store, Value};
+use crate::{error::ApiError, fact_id, slug, AppState, CatFactRecord, CATFACT_COLUMNS};
+
+/// The most facts one page of `GET /admin/sync/facts` returns.
+const PAGE_SIZE: u32 = 500;
+
+/// The instance to pull facts from, set with the `SYNC_SOURCE_URL` and
+/// `SYNC_SOURCE_KEY` (the source's `ADMIN_TOKEN`) secrets.
+#[derive(Clone)]
+pub struct SyncSource {
+    url: String,
+    key: String,
+    client: reqwest::Client,
+    /// Held for the length of a pull, so two overlapping pulls can't both
+    /// insert the same facts.
+    running: Arc<Mutex<()>>,
+}
+
+impl SyncSource {
+    pub fn from_secrets(store: &SecretStore) -> Option<Self> {
+        Some(Self {
+            url: store
+                .get("SYNC_SOURCE_URL")?
+                .trim_end_matches('/')
+                .to_string(),
+            key: store.get("SYNC_SOURCE_KEY")?,
+            client: reqwest::Client::new(),
+            running: Arc::new(Mutex::new(())),
+        })
+    }
+
+    async fn fetch_page(&self, after: i64) -> Result<RemotePage, anyhow::Error> {
+        let res = self
+            .client
+            .get(format!("{}/admin/sync/facts", self.url))
+            .bearer_auth(&self.key)
+            .query(&[("after", after), ("limit", i64::from(PAGE_SIZE))])
+            .send()
+            .await
+            .map_err(|e| anyhow!("couldn't reach {}: {e}", self.url))?;
+
+        if !res.status().is_success() {
+            return Err(anyhow!("{} responded with {}", self.url, res.status()));
+        }
+
+        res.json()
+            .await
+            .map_err(|e| anyhow!("unexpected response from {}: {e}", self.url))
+    }
+}
+
+/// A page of `GET /admin/sync/facts`. Instances from before snapshot markers
+/// send just the facts.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RemotePage {
+    Snapshot {
+        snapshot: Snapshot,
+        facts: Vec<RemoteFact>,
+    },
+    Facts(Vec<RemoteFact>),
+}
+
+impl RemotePage {
+    /// The facts, and where the next page starts.
+    fn into_parts(self, after: i64) -> (Vec<RemoteFact>, i64) {
+        match self {
+            Self::Snapshot { snapshot, facts } => (facts, snapshot.next_after),
+            Self::Facts(facts) => {
+                let next_after = facts.last().map_or(after, |fact| fact.id);
+                (facts, next_after)
+            }
+        }
+    }
+}
+
+/// A fact as the source instance returns it. Older instances may not send a
+/// `fact_id`, so it's recomputed when missing.
+#[derive(Deserialize)]
+struct RemoteFact {
+    id: i64,
+    fact: String,
+    #[serde(default)]
+    fact_id: Option<String>,
+    created_at: String,
+    /// Missing from older instances, and possibly one this instance doesn't
+    /// know; either way the fact gets the default.
+    #[serde(default)]
+    license: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct FactsQuery {
+    #[serde(default)]
+    after: i64,
+    limit: Option<u32>,
+}
+
+/// Where a page of facts sits in the source's history, read in the same
+/// transaction as the facts themselves.
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    /// When the page was read, per the database's clock.
+    taken_at: String,
+    /// The newest fact there was to export when the page was read. A consumer
+    /// that has reached it is caught up as of `taken_at`.
+    max_id: i64,
+    /// The `after` to ask for next. New facts always get higher ids, so
+    /// resuming from it repeats none and misses none added since. (A flagged
+    /// fact approved after it's been passed is the exception.)
+    next_after: i64,
+}
+
+impl FromRow for Snapshot {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            max_id: store::integer(row, 0)?,
+            taken_at: store::text(row, 1)?,
+            next_after: 0,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct FactsPage {
+    snapshot: Snapshot,
+    facts: Vec<CatFactRecord>,
+}
+
+/// `GET /admin/sync/facts?after=<id>` - facts with ids after `after`, oldest
+/// first, for other instances to pull from, with a snapshot marker saying
+/// where to resume.
+pub async fn list_facts(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<FactsQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let limit = query.limit.unwrap_or(PAGE_SIZE).min(PAGE_SIZE);
+
+    // One batch is one transaction, so the marker describes exactly the
+    // state the page was read from.
+    let res = state
+        .db
+        .batch([
+            Statement::with_args(
+                format!("SELECT {CATFACT_COLUMNS} FROM catfacts WHERE id > ? AND needs_review = 0 ORDER BY id LIMIT ?"),
+                &[Value::from(query.after), Value::from(limit)],
+            ),
+            Statement::new(
+                "SELECT coalesce(max(id), 0), strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+                FROM catfacts WHERE needs_review = 0",
+            ),
+        ])
+        .await?;
+    let facts = res
+        .first()
+        .map(store::rows::<CatFactRecord>)
+        .transpose()?
+        .unwrap_or_default();
+    let mut snapshot = res
+        .get(1)
+        .map(store::first::<Snapshot>)
+        .transpose()?
+        .flatten()
+        .ok_or_else(|| ApiError::internal("Couldn't read the snapshot marker"))?;
+    snapshot.next_after = facts.last().map_or(query.after, |fact| fact.id);
+
+    Ok(Json(FactsPage { snapshot, facts }))
+}
+
+#[derive(Serialize)]
+pub struct SyncReport {
+    /// Facts inserted into this instance.
+    pulled: u64,
+    /// Facts skipped because a fact with the same `fact_id` already exists.
+    skipped: u64,
+    /// The last source id seen; the next pull starts after it.
+    checkpoint: i64,
+}
+
+/// `POST /admin/sync/pull` - pulls facts added to the source instance since the
+/// last sync.
+pub async fn pull(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse, ApiError> {
+    let Some(source) = &state.sync_source else {
+        return Err(ApiError::Unavailable(
+            "No sync source is configured".to_string(),
+        ));
+    };
+
+    let Ok(_running) = source.running.try_lock() else {
+        return Err(ApiError::Conflict("A sync is already running".to_string()));
+    };
+
+    let report = pull_from(&*state.db, state.mqtt.as_ref(), source)
+        .await
+        .map_err(ApiError::Upstream)?;
+
+    tracing::info!(
+        "Synced from {}: {} pulled, {} skipped",
+        source.url,
+        report.pulled,
+        report.skipped
+    );
+
+    Ok(Json(report))
+}
+
+/// Pulls from `source` into `db`, announcing each new fact over `mqtt`.
+async fn pull_from(
+    db: &dyn Store,
+    mqtt: Option<&FactPublisher>,
+    source: &SyncSource,
+) -> Result<SyncReport, anyhow::Error> {
+    let res = db
+        .execute(Statement::with_args(
+            "SELECT last_id FROM sync_checkpoints WHERE source = ?",
+            &[&source.url],
+        ))
+        .await?;
+    let mut report = SyncReport {
+        pulled: 0,
+        skipped: 0,
+        checkpoint: store::first(&res)?.unwrap_or(0),
+    };
+
+    loop {
+        let (page, last_id) = source
+            .fetch_page(report.checkpoint)
+            .await?
+            .into_parts(report.checkpoint);
+        if page.is_empty() {
+            break;
+        }
+
+        for remote in page {
+            let id = remote
+                .fact_id
+                .filter(|id| fact_id::is_fact_id(id))
+                .unwrap_or_else(|| fact_id::fact_id(&remote.fact));
+
+            let existing = db
+                .execute(Statement::with_args(
+                    "SELECT 1 FROM catfacts WHERE fact_id = ? LIMIT 1",
+                    &[&id],
+                ))
+                .await?;
+            if !existing.rows.is_empty() {
+                report.skipped += 1;
+                continue;
+            }
+
+            let inserted = db
+                .execute(Statement::with_args(
+                    "INSERT INTO catfacts (fact, fact_id, created_at, license) VALUES (?, ?, ?, ?)",
+                    &[
+                        remote.fact.as_str(),
+                        id.as_str(),
+                        remote.created_at.as_str(),
+                        remote
+                            .license
+                            .as_deref()
+                            .and_then(License::from_name)
+                            .unwrap_or_default()
+                            .name(),
+                    ],
+                ))
+                .await?;
+            if let Some(local_id) = inserted.last_insert_rowid {
+                slug::set_slug(db, local_id, &remote.fact).await?;
+            }
+            if let Some(mqtt) = mqtt {
+                mqtt.publish_new_fact(&remote.fact).await;
+            }
+            report.pulled += 1;
+        }
+
+        // Saved after every page so an interrupted sync resumes where it stopped.
+        report.checkpoint = last_id;
+        db.execute(Statement::with_args(
+            "INSERT INTO sync_checkpoints (source, last_id) VALUES (?, ?)
+            ON CONFLICT (source) DO UPDATE SET last_id = excluded.last_id, synced_at = current_timestamp",
+            &[Value::from(source.url.as_str()), Value::from(last_id)],
+        ))
+        .await?;
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,350 @@
+//! Rules that tag facts automatically, to save tagging big imports by hand.
+//! Each rule maps a keyword or a regular expression to a tag, and facts added
+//! with `POST /v1/catfacts` or `POST /catfact/bulk` get the tags of every rule
+//! that matches their text, as well as any they were given. A keyword
+//! matches as a whole word or phrase, ignoring case; a regex is used as it's
+//! written, so add `(?i)` to it to ignore case.
+//!
+//! Tags a rule applied are marked with the rule's id in `catfact_tags`, and
+//! wait in `GET /admin/tags/review` until a moderator keeps or removes them.
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::auth::Admin;
+use crate::store::{self, FromRow, Row, Statement, Store, Value};
+use crate::strict::StrictJson;
+use crate::tags::{self, Applied};
+use crate::{audit, error::ApiError, AppState};
+
+/// How big a compiled pattern can get, so a rule can't slow every import down.
+const MAX_PATTERN_SIZE: usize = 1 << 20;
+const MAX_PATTERN_LEN: usize = 500;
+
+const DEFAULT_REVIEW_LIMIT: u32 = 50;
+const MAX_REVIEW_LIMIT: u32 = 500;
+
+fn compile(kind: &str, pattern: &str) -> Result<Regex, regex::Error> {
+    let pattern = match kind {
+        "keyword" => format!(r"(?i)\b{}\b", regex::escape(pattern.trim())),
+        _ => pattern.to_string(),
+    };
+
+    RegexBuilder::new(&pattern)
+        .size_limit(MAX_PATTERN_SIZE)
+        .build()
+}
+
+struct Rule {
+    id: i64,
+    tag: String,
+    kind: String,
+    pattern: String,
+}
+
+impl FromRow for Rule {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            id: store::integer(row, 0)?,
+            tag: store::text(row, 1)?,
+            kind: store::text(row, 2)?,
+            pattern: store::text(row, 3)?,
+        })
+    }
+}
+
+/// The rules, compiled, for tagging a batch of new facts.
+pub struct Rules(Vec<(Rule, Regex)>);
+
+impl Rules {
+    pub async fn load(db: &dyn Store) -> Result<Self, anyhow::Error> {
+        let res = db
+            .execute(
+                "SELECT tag_rules.id, tags.name, tag_rules.kind, tag_rules.pattern FROM tag_rules
+                JOIN tags ON tags.id = tag_rules.tag_id ORDER BY tag_rules.id",
+            )
+            .await?;
+        let mut rules = Vec::new();
+        for rule in store::rows::<Rule>(&res)? {
+            // Patterns are checked when they're added, so this would take a
+            // change to the regex crate.
+            match compile(&rule.kind, &rule.pattern) {
+                Ok(regex) => rules.push((rule, regex)),
+                Err(e) => tracing::warn!("Skipping tag rule {} that doesn't compile: {e}", rule.id),
+            }
+        }
+
+        Ok(Self(rules))
+    }
+
+    /// `tags`, chosen by hand, then the tags of the rules matching `fact` that
+    /// aren't among them, up to the most a fact can have.
+    pub fn apply<'a>(&'a self, fact: &str, tags: &'a [String]) -> Vec<Applied<'a>> {
+        let mut applied = tags::by_hand(tags);
+        for (rule, regex) in &self.0 {
+            if applied.len() >= tags::MAX_TAGS {
+                break;
+            }
+            if !applied.iter().any(|tag| tag.name == rule.tag) && regex.is_match(fact) {
+                applied.push(Applied {
+                    name: &rule.tag,
+                    rule_id: Some(rule.id),
+                });
+            }
+        }
+
+        applied
+    }
+}
+
+#[derive(Serialize)]
+pub struct TagRule {
+    id: i64,
+    tag: String,
+    /// `keyword` or `regex`.
+    kind: String,
+    pattern: String,
+    created_at: String,
+    /// How many facts have a tag this rule applied.
+    applied: i64,
+}
+
+impl FromRow for TagRule {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            id: store::integer(row, 0)?,
+            tag: store::text(row, 1)?,
+            kind: store::text(row, 2)?,
+            pattern: store::text(row, 3)?,
+            created_at: store::text(row, 4)?,
+            applied: store::integer(row, 5)?,
+        })
+    }
+}
+
+const RULE_COLUMNS: &str = "tag_rules.id, tags.name, tag_rules.kind, tag_rules.pattern,
+    tag_rules.created_at,
+    (SELECT count(*) FROM catfact_tags WHERE catfact_tags.rule_id = tag_rules.id)";
+
+/// `GET /admin/tag-rules` - every rule, oldest first.
+pub async fn list_rules(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<TagRule>>, ApiError> {
+    let res = state
+        .db
+        .execute(format!(
+            "SELECT {RULE_COLUMNS} FROM tag_rules
+            JOIN tags ON tags.id = tag_rules.tag_id ORDER BY tag_rules.id"
+        ))
+        .await?;
+
+    Ok(Json(store::rows(&res)?))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewRule {
+    tag: String,
+    keyword: Option<String>,
+    regex: Option<String>,
+}
+
+/// `POST /admin/tag-rules` - adds a rule, `{"tag": "...", "keyword": "..."}`
+/// or `{"tag": "...", "regex": "..."}`. It applies to facts added from now on.
+pub async fn create_rule(
+    State(state): State<Arc<AppState>>,
+    admin: Admin,
+    StrictJson(new): StrictJson<NewRule>,
+) -> Result<(StatusCode, Json<TagRule>), ApiError> {
+    let tag = tags::normalize_one(&new.tag)?;
+    let (kind, pattern) = match (new.keyword, new.regex) {
+        (Some(keyword), None) if !keyword.trim().is_empty() => ("keyword", keyword),
+        (None, Some(regex)) if !regex.is_empty() => ("regex", regex),
+        _ => {
+            return Err(ApiError::Validation(
+                "Give a rule either a keyword or a regex".to_string(),
+            ))
+        }
+    };
+    if pattern.len() > MAX_PATTERN_LEN {
+        return Err(ApiError::Validation(format!(
+            "A rule's {kind} can be at most {MAX_PATTERN_LEN} characters"
+        )));
+    }
+    compile(kind, &pattern)
+        .map_err(|e| ApiError::Validation(format!("That regex isn't valid: {e}")))?;
+
+    let results = state
+        .db
+        .batch([
+            Statement::with_args("INSERT OR IGNORE INTO tags (name) VALUES (?)", &[&tag]),
+            Statement::with_args(
+                "INSERT INTO tag_rules (tag_id, kind, pattern)
+                SELECT id, ?, ? FROM tags WHERE name = ?",
+                &[kind, pattern.as_str(), tag.as_str()],
+            ),
+            audit::entry(
+                &admin.name,
+                "tag_rule_created",
+                &format!("{kind} {pattern:?} tags {tag}"),
+            ),
+        ])
+        .await?;
+    let id = results
+        .get(1)
+        .and_then(|inserted| inserted.last_insert_rowid)
+        .ok_or_else(|| ApiError::internal("Adding the tag rule returned no id"))?;
+
+    let rule = state
+        .db
+        .execute(Statement::with_args(
+            format!(
+                "SELECT {RULE_COLUMNS} FROM tag_rules
+                JOIN tags ON tags.id = tag_rules.tag_id WHERE tag_rules.id = ?"
+            ),
+            &[id],
+        ))
+        .await
+        .and_then(|res| store::first::<TagRule>(&res))?
+        .ok_or_else(|| ApiError::internal("The new tag rule wasn't found"))?;
+
+    tracing::info!("{} added tag rule {id} for {tag}", admin.name);
+    Ok((StatusCode::CREATED, Json(rule)))
+}
+
+/// `DELETE /admin/tag-rules/:id` - removes a rule. Tags it already applied
+/// stay, and can still be reviewed.
+pub async fn delete_rule(
+    State(state): State<Arc<AppState>>,
+    admin: Admin,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, ApiError> {
+    let results = state
+        .db
+        .batch([
+            Statement::with_args("DELETE FROM tag_rules WHERE id = ?", &[id]),
+            audit::entry_if_changed(&admin.name, "tag_rule_deleted", &format!("rule {id}")),
+        ])
+        .await?;
+    if results.first().map_or(0, |deleted| deleted.rows_affected) == 0 {
+        return Err(ApiError::NotFound(format!("There's no tag rule {id}")));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Serialize)]
+pub struct PendingTag {
+    catfact_id: i64,
+    fact: String,
+    tag: String,
+    rule_id: i64,
+    /// The rule's keyword or regex, if it hasn't been deleted since.
+    pattern: Option<String>,
+}
+
+impl FromRow for PendingTag {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            catfact_id: store::integer(row, 0)?,
+            fact: store::text(row, 1)?,
+            tag: store::text(row, 2)?,
+            rule_id: store::integer(row, 3)?,
+            pattern: store::optional_text(row, 4)?,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ReviewQuery {
+    limit: Option<u32>,
+}
+
+/// `GET /admin/tags/review?limit=50` - tags applied by rules that nobody's
+/// looked at yet, newest facts first.
+pub async fn list_pending(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ReviewQuery>,
+) -> Result<Json<Vec<PendingTag>>, ApiError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_REVIEW_LIMIT)
+        .clamp(1, MAX_REVIEW_LIMIT);
+    let res = state
+        .db
+        .execute(Statement::with_args(
+            "SELECT catfacts.id, catfacts.fact, tags.name, catfact_tags.rule_id, tag_rules.pattern
+            FROM catfact_tags
+            JOIN catfacts ON catfacts.id = catfact_tags.catfact_id
+            JOIN tags ON tags.id = catfact_tags.tag_id
+            LEFT JOIN tag_rules ON tag_rules.id = catfact_tags.rule_id
+            WHERE catfact_tags.rule_id IS NOT NULL AND catfact_tags.reviewed_at IS NULL
+            ORDER BY catfacts.id DESC, tags.name LIMIT ?",
+            &[limit],
+        ))
+        .await?;
+
+    Ok(Json(store::rows(&res)?))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Review {
+    catfact_id: i64,
+    tag: String,
+    /// Whether the tag stays on the fact.
+    keep: bool,
+}
+
+/// `POST /admin/tags/review` - `{"catfact_id": 12, "tag": "...", "keep": true}`
+/// keeps a tag a rule applied, and `"keep": false` removes it.
+pub async fn review(
+    State(state): State<Arc<AppState>>,
+    admin: Admin,
+    StrictJson(review): StrictJson<Review>,
+) -> Result<StatusCode, ApiError> {
+    let tag = tags::normalize_one(&review.tag)?;
+    let (statement, action) = if review.keep {
+        (
+            "UPDATE catfact_tags SET reviewed_at = current_timestamp
+            WHERE catfact_id = ? AND tag_id = (SELECT id FROM tags WHERE name = ?)
+            AND rule_id IS NOT NULL AND reviewed_at IS NULL",
+            "auto_tag_kept",
+        )
+    } else {
+        (
+            "DELETE FROM catfact_tags
+            WHERE catfact_id = ? AND tag_id = (SELECT id FROM tags WHERE name = ?)
+            AND rule_id IS NOT NULL AND reviewed_at IS NULL",
+            "auto_tag_removed",
+        )
+    };
+
+    let results = state
+        .db
+        .batch([
+            Statement::with_args(
+                statement,
+                &[Value::from(review.catfact_id), Value::from(&tag)],
+            ),
+            audit::entry_if_changed(
+                &admin.name,
+                action,
+                &format!("{tag} on fact {}", review.catfact_id),
+            ),
+        ])
+        .await?;
+    if results.first().map_or(0, |changed| changed.rows_affected) == 0 {
+        return Err(ApiError::NotFound(format!(
+            "Fact {} has no {tag} tag waiting for review",
+            review.catfact_id
+        )));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,390 @@
+//! Tags for grouping facts by topic, e.g. `behavior` or `history`. Names are
+//! lowercase words joined by hyphens, and each fact can have a handful. Tags
+//! are either given by whoever adds a fact or applied by a rule (see
+//! `tag_rules`), and `catfact_tags.rule_id` says which. Admins can rename a
+//! tag, or merge duplicates like `kitten` and `kittens` into one.
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use crate::auth::Admin;
+use crate::store::{self, FromRow, Row, Statement, Store, Value};
+use crate::strict::StrictJson;
+use crate::{audit, error::ApiError, AppState};
+
+pub const MAX_TAGS: usize = 10;
+const MAX_TAG_LEN: usize = 32;
+
+/// Matches facts tagged with the name in `?1`, as a condition on `catfacts`.
+pub const HAS_TAG: &str = "EXISTS (SELECT 1 FROM catfact_tags
+    JOIN tags ON tags.id = catfact_tags.tag_id
+    WHERE catfact_tags.catfact_id = catfacts.id AND tags.name = ?1)";
+
+/// Tidies submitted tag names, dropping duplicates, and rejects ones that
+/// can't be used.
+pub fn normalize(tags: &[String]) -> Result<Vec<String>, ApiError> {
+    let mut names: Vec<String> = Vec::new();
+    for tag in tags {
+        let name = normalize_one(tag)?;
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    if names.len() > MAX_TAGS {
+        return Err(ApiError::Validation(format!(
+            "A fact can have at most {MAX_TAGS} tags"
+        )));
+    }
+
+    Ok(names)
+}
+
+/// Tidies one tag name, e.g. for a `?tag=` filter.
+pub fn normalize_one(tag: &str) -> Result<String, ApiError> {
+    let name = tag.trim().to_lowercase();
+    if name.is_empty()
+        || name.len() > MAX_TAG_LEN
+        || name.starts_with('-')
+        || name.ends_with('-')
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return Err(ApiError::Validation(format!(
+            "{tag:?} isn't a valid tag: use up to {MAX_TAG_LEN} letters, digits and hyphens"
+        )));
+    }
+
+    Ok(name)
+}
+
+/// A tag to apply, and the rule it comes from if it isn't a person's choice.
+pub struct Applied<'a> {
+    pub name: &'a str,
+    pub rule_id: Option<i64>,
+}
+
+/// `names`, as chosen by a person.
+pub fn by_hand(names: &[String]) -> Vec<Applied<'_>> {
+    names
+        .iter()
+        .map(|name| Applied {
+            name,
+            rule_id: None,
+        })
+        .collect()
+}
+
+/// Statements that tag the fact with `fact`'s id (SQL taking `arg`) with
+/// `tags`, creating any new ones. A tag the fact already has is left as it
+/// is, so put the ones chosen by hand first.
+fn tag_statements(fact: &str, arg: Value, tags: &[Applied]) -> Vec<Statement> {
+    tags.iter()
+        .flat_map(|tag| {
+            [
+                Statement::with_args("INSERT OR IGNORE INTO tags (name) VALUES (?)", &[tag.name]),
+                Statement::with_args(
+                    format!(
+                        "INSERT OR IGNORE INTO catfact_tags (catfact_id, tag_id, rule_id)
+                        SELECT {fact}, id, ? FROM tags WHERE name = ?"
+                    ),
+                    &[
+                        arg.clone(),
+                        tag.rule_id.map_or(Value::Null, Value::from),
+                        Value::from(tag.name),
+                    ],
+                ),
+            ]
+        })
+        .collect()
+}
+
+/// Statements that tag fact `id` with `tags`. Run them in one batch.
+pub fn tag_fact(id: i64, tags: &[Applied]) -> Vec<Statement> {
+    tag_statements("?", Value::from(id), tags)
+}
+
+/// Like `tag_fact`, for a fact inserted earlier in the same batch, whose id
+/// isn't known yet. It's found by its `fact_id`.
+pub fn tag_fact_by_hash(fact_id: &str, tags: &[Applied]) -> Vec<Statement> {
+    tag_statements(
+        "(SELECT max(id) FROM catfacts WHERE fact_id = ?)",
+        Value::from(fact_id),
+        tags,
+    )
+}
+
+/// Like `tag_fact`, but removing the fact's other tags first.
+pub fn retag_fact(id: i64, names: &[String]) -> Vec<Statement> {
+    let mut statements = vec![Statement::with_args(
+        "DELETE FROM catfact_tags WHERE catfact_id = ?",
+        &[id],
+    )];
+    statements.extend(tag_fact(id, &by_hand(names)));
+    statements
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TagCount {
+    name: String,
+    facts: i64,
+}
+
+impl FromRow for TagCount {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            name: store::text(row, 0)?,
+            facts: store::integer(row, 1)?,
+        })
+    }
+}
+
+/// `GET /tags` - every tag in use, with how many facts have it.
+#[utoipa::path(
+    get,
+    path = "/tags",
+    tag = "facts",
+    responses((status = 200, description = "Every tag in use", body = [TagCount]))
+)]
+pub async fn list_tags(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<TagCount>>, ApiError> {
+    let tags = state
+        .db
+        .execute(
+            "SELECT tags.name, count(*) FROM tags
+            JOIN catfact_tags ON catfact_tags.tag_id = tags.id
+            JOIN catfacts ON catfacts.id = catfact_tags.catfact_id
+            WHERE catfacts.needs_review = 0
+            GROUP BY tags.id ORDER BY tags.name",
+        )
+        .await
+        .and_then(|res| store::rows::<TagCount>(&res))?;
+
+    Ok(Json(tags))
+}
+
+#[derive(Serialize)]
+pub struct Tag {
+    id: i64,
+    name: String,
+    /// How many facts have it, including ones waiting for review.
+    facts: i64,
+}
+
+impl FromRow for Tag {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            id: store::integer(row, 0)?,
+            name: store::text(row, 1)?,
+            facts: store::integer(row, 2)?,
+        })
+    }
+}
+
+const TAG_COLUMNS: &str = "tags.id, tags.name,
+    (SELECT count(*) FROM catfact_tags WHERE catfact_tags.tag_id = tags.id)";
+
+async fn find_tag(db: &dyn Store, id: i64) -> Result<Option<Tag>, anyhow::Error> {
+    let res = db
+        .execute(Statement::with_args(
+            format!("SELECT {TAG_COLUMNS} FROM tags WHERE id = ?"),
+            &[id],
+        ))
+        .await?;
+
+    store::first(&res)
+}
+
+/// `GET /admin/tags` - every tag with its id, including ones nothing has.
+pub async fn list_all_tags(State(state): State<Arc<AppState>>) -> Result<Json<Vec<Tag>>, ApiError> {
+    let res = state
+        .db
+        .execute(format!("SELECT {TAG_COLUMNS} FROM tags ORDER BY tags.name"))
+        .await?;
+
+    Ok(Json(store::rows(&res)?))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rename {
+    name: String,
+}
+
+/// `POST /admin/tags/:id/rename` - `{"name": "..."}` renames a tag on every
+/// fact that has it. A name another tag already has is a conflict; merge
+/// them instead.
+pub async fn rename_tag(
+    State(state): State<Arc<AppState>>,
+    admin: Admin,
+    Path(id): Path<i64>,
+    StrictJson(rename): StrictJson<Rename>,
+) -> Result<Json<Tag>, ApiError> {
+    let name = normalize_one(&rename.name)?;
+    let db = &*state.db;
+    let tag = find_tag(db, id)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("There's no tag {id}")))?;
+    if tag.name == name {
+        return Ok(Json(tag));
+    }
+
+    let taken = db
+        .execute(Statement::with_args(
+            "SELECT id FROM tags WHERE name = ?",
+            &[&name],
+        ))
+        .await
+        .and_then(|res| store::first::<i64>(&res))?;
+    if let Some(other) = taken {
+        return Err(ApiError::Conflict(format!(
+            "Tag {other} is already called {name}; merge {id} into it with POST /admin/tags/merge"
+        )));
+    }
+
+    db.batch([
+        Statement::with_args(
+            "UPDATE tags SET name = ? WHERE id = ?",
+            &[Value::from(&name), Value::from(id)],
+        ),
+        audit::entry_if_changed(
+            &admin.name,
+            "tag_renamed",
+            &format!("{} ({id}) to {name}", tag.name),
+        ),
+    ])
+    .await?;
+
+    tracing::info!(
+        "{} renamed tag {id} from {} to {name}",
+        admin.name,
+        tag.name
+    );
+    Ok(Json(Tag { name, ..tag }))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Merge {
+    /// The tags to merge away.
+    from: Vec<i64>,
+    /// The tag they're merged into, which stays.
+    into: i64,
+}
+
+#[derive(Serialize)]
+pub struct Merged {
+    /// The names of the tags merged away.
+    merged: Vec<String>,
+    into: Tag,
+}
+
+/// `POST /admin/tags/merge` - `{"from": [3, 4], "into": 5}` moves every fact
+/// and tag rule from tags 3 and 4 to tag 5, then deletes 3 and 4, all in one
+/// transaction.
+pub async fn merge_tags(
+    State(state): State<Arc<AppState>>,
+    admin: Admin,
+    StrictJson(merge): StrictJson<Merge>,
+) -> Result<Json<Merged>, ApiError> {
+    let mut from = merge.from;
+    from.sort_unstable();
+    from.dedup();
+    let into = merge.into;
+    if from.is_empty() {
+        return Err(ApiError::Validation(
+            "Give at least one tag to merge in from".to_string(),
+        ));
+    }
+    if from.contains(&into) {
+        return Err(ApiError::Validation(format!(
+            "Tag {into} can't be merged into itself"
+        )));
+    }
+
+    let db = &*state.db;
+    let target = find_tag(db, into)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("There's no tag {into}")))?;
+    let placeholders = vec!["?"; from.len()].join(", ");
+    let ids: Vec<Value> = from.iter().map(|id| Value::from(*id)).collect();
+    let merged = db
+        .execute(Statement::with_args(
+            format!("SELECT name FROM tags WHERE id IN ({placeholders}) ORDER BY id"),
+            &ids,
+        ))
+        .await
+        .and_then(|res| store::rows::<String>(&res))?;
+    if merged.len() < from.len() {
+        return Err(ApiError::NotFound(
+            "Some of the tags to merge in from don't exist".to_string(),
+        ));
+    }
+
+    let mut with_target = vec![Value::from(into)];
+    with_target.extend(ids.iter().cloned());
+    db.batch([
+        // Subscribers who chose a merged tag as a topic get the one it goes into.
+        Statement::with_args(
+            format!(
+                "INSERT OR IGNORE INTO subscriber_topics (subscriber_id, tag_id)
+                SELECT subscriber_id, ? FROM subscriber_topics WHERE tag_id IN ({placeholders})"
+            ),
+            &with_target,
+        ),
+        // A fact that already has the tag merged into keeps that association.
+        Statement::with_args(
+            format!(
+                "INSERT OR IGNORE INTO catfact_tags (catfact_id, tag_id, rule_id, reviewed_at)
+                SELECT catfact_id, ?, rule_id, reviewed_at FROM catfact_tags
+                WHERE tag_id IN ({placeholders})"
+            ),
+            &with_target,
+        ),
+        // And if a person chose one of the tags, it's no longer up for review.
+        Statement::with_args(
+            format!(
+                "UPDATE catfact_tags SET rule_id = NULL
+                WHERE tag_id = ? AND rule_id IS NOT NULL AND catfact_id IN (
+                    SELECT catfact_id FROM catfact_tags
+                    WHERE tag_id IN ({placeholders}) AND rule_id IS NULL
+                )"
+            ),
+            &with_target,
+        ),
+        Statement::with_args(
+            format!("DELETE FROM catfact_tags WHERE tag_id IN ({placeholders})"),
+            &ids,
+        ),
+        Statement::with_args(
+            format!("UPDATE tag_rules SET tag_id = ? WHERE tag_id IN ({placeholders})"),
+            &with_target,
+        ),
+        Statement::with_args(
+            format!("DELETE FROM tags WHERE id IN ({placeholders})"),
+            &ids,
+        ),
+        audit::entry(
+            &admin.name,
+            "tags_merged",
+            &format!("{} into {} ({into})", merged.join(", "), target.name),
+        ),
+    ])
+    .await?;
+
+    tracing::info!(
+        "{} merged tags {} into {}",
+        admin.name,
+        merged.join(", "),
+        target.name
+    );
+    let into = find_tag(db, into)
+        .await?
+        .ok_or_else(|| ApiError::internal("The merged tag disappeared"))?;
+
+    Ok(Json(Merged { merged, into }))
+}
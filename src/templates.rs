@@ -0,0 +1,801 @@
+//! The daily email's subject line and bodies. Each is a
+//! [Tera](https://keats.github.io/tera/docs/) template with
+//! `{{placeholder}}`s, so the copy can be changed through secrets without a
+//! code change, and can use Tera's conditionals and filters, like
+//! `{% if unsubscribe_url %}`:
+//!
+//! - `EMAIL_SUBJECT` - the subject line. Defaults to `Today's cat fact: {{fact}}`.
+//! - `EMAIL_TEMPLATE_TEXT` - the plain-text body. If only the HTML template is
+//!   customised, this is generated from it, so the two parts say the same thing.
+//! - `EMAIL_TEMPLATE_HTML` - the standard HTML body. The accessible layout is
+//!   always the built-in one.
+//!
+//! The placeholders are `fact`, `preferences_url`, `unsubscribe_url`,
+//! `unsubscribe_link`, `feedback_url` and `feedback_link`. In HTML templates
+//! values are escaped, and `unsubscribe_link` is a ready-made
+//! ` | <a>Unsubscribe</a>` (or nothing when one-click unsubscribe isn't
+//! configured). `feedback_link` is a ready-made "Was this fact interesting?"
+//! line, and `feedback_url` the link it's built from, to end with a score from
+//! `/0` to `/10`. In the subject, `fact` is cut down to a readable length.
+//!
+//! Each subscriber gets the email in their language (see `language`). The
+//! built-in templates and the ready-made links are translated, and a language
+//! can have its own subject and bodies, e.g. `EMAIL_SUBJECT_FR` and
+//! `EMAIL_TEMPLATE_HTML_FR`. A language without its own subject uses
+//! `EMAIL_SUBJECT`, and one without its own bodies uses the English ones, so
+//! customised copy is never swapped for a built-in translation; when neither
+//! is set, the built-in template in the language is used.
+//!
+//! `POST /admin/templates/lint` checks templates for things that hurt
+//! deliverability, which are also logged at boot and returned from a config
+//! import.
+use anyhow::anyhow;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use shuttle_secrets::SecretStore;
+use std::fmt;
+use tera::{Context, Tera};
+
+use crate::feedback::MAX_SCORE;
+use crate::strict::StrictJson;
+use crate::{email_format::EmailFormat, html, html_text, language::Language, sanitize};
+
+/// The copy of the built-in templates and ready-made links, in one language.
+/// `subject` and `did_you_know` are template sources with `{{fact}}` in them.
+struct Phrases {
+    subject: &'static str,
+    /// The accessible email's title and heading.
+    heading: &'static str,
+    greeting: &'static str,
+    reason: &'static str,
+    did_you_know: &'static str,
+    manage: &'static str,
+    /// `manage`, for the accessible email, which also offers the format.
+    manage_all: &'static str,
+    unsubscribe: &'static str,
+    unsubscribe_in_one_click: &'static str,
+    feedback: &'static str,
+    not_really: &'static str,
+    yes: &'static str,
+}
+
+const ENGLISH: Phrases = Phrases {
+    subject: "Today's cat fact: {{fact}}",
+    heading: "Today's cat fact",
+    greeting: "Hey there!",
+    reason: "You're receiving this message because you're subscribed to Cat Facts.",
+    did_you_know: "Did you know {{fact}}?",
+    manage: "Change your delivery time or unsubscribe",
+    manage_all: "Change your delivery time, email format, or unsubscribe",
+    unsubscribe: "Unsubscribe",
+    unsubscribe_in_one_click: "Unsubscribe in one click:",
+    feedback: "Was this fact interesting?",
+    not_really: "Not really",
+    yes: "Yes",
+};
+
+const GERMAN: Phrases = Phrases {
+    subject: "Der Katzenfakt des Tages: {{fact}}",
+    heading: "Der Katzenfakt des Tages",
+    greeting: "Hallo!",
+    reason: "Du bekommst diese Nachricht, weil du Cat Facts abonniert hast.",
+    did_you_know: "Wusstest du schon: {{fact}}?",
+    manage: "Zustellzeit ändern oder abbestellen",
+    manage_all: "Zustellzeit oder E-Mail-Format ändern oder abbestellen",
+    unsubscribe: "Abbestellen",
+    unsubscribe_in_one_click: "Mit einem Klick abbestellen:",
+    feedback: "War dieser Fakt interessant?",
+    not_really: "Nicht wirklich",
+    yes: "Ja",
+};
+
+const SPANISH: Phrases = Phrases {
+    subject: "El dato gatuno de hoy: {{fact}}",
+    heading: "El dato gatuno de hoy",
+    greeting: "¡Hola!",
+    reason: "Recibes este mensaje porque estás suscrito a Cat Facts.",
+    did_you_know: "¿Sabías que {{fact}}?",
+    manage: "Cambia tu hora de entrega o date de baja",
+    manage_all: "Cambia tu hora de entrega o el formato del correo, o date de baja",
+    unsubscribe: "Darse de baja",
+    unsubscribe_in_one_click: "Date de baja con un clic:",
+    feedback: "¿Te pareció interesante este dato?",
+    not_really: "No mucho",
+    yes: "Sí",
+};
+
+const FRENCH: Phrases = Phrases {
+    subject: "L'anecdote féline du jour : {{fact}}",
+    heading: "L'anecdote féline du jour",
+    greeting: "Bonjour !",
+    reason: "Vous recevez ce message parce que vous êtes abonné à Cat Facts.",
+    did_you_know: "Saviez-vous que {{fact}} ?",
+    manage: "Modifier l'heure d'envoi ou se désabonner",
+    manage_all: "Modifier l'heure d'envoi ou le format de l'e-mail, ou se désabonner",
+    unsubscribe: "Se désabonner",
+    unsubscribe_in_one_click: "Se désabonner en un clic :",
+    feedback: "Cette anecdote vous a-t-elle plu ?",
+    not_really: "Pas vraiment",
+    yes: "Oui",
+};
+
+fn phrases(language: Language) -> &'static Phrases {
+    match language {
+        Language::En => &ENGLISH,
+        Language::De => &GERMAN,
+        Language::Es => &SPANISH,
+        Language::Fr => &FRENCH,
+    }
+}
+
+fn default_text(phrases: &Phrases) -> String {
+    format!(
+        "{} {} \n\n{}{{{{feedback_link}}}}\n\n--\n{}: {{{{preferences_url}}}}{{{{unsubscribe_link}}}}",
+        phrases.greeting, phrases.reason, phrases.did_you_know, phrases.manage
+    )
+}
+
+fn default_standard_html(phrases: &Phrases) -> String {
+    format!(
+        "<p>{} {}</p>\n<p>{}</p>{{{{feedback_link}}}}\n<hr>\n<p><a href=\"{{{{preferences_url}}}}\">{}</a>{{{{unsubscribe_link}}}}</p>",
+        phrases.greeting,
+        phrases.reason,
+        phrases.did_you_know,
+        phrases.manage
+    )
+}
+
+/// A complete document rather than a fragment, so screen readers get the
+/// language and a heading to navigate by, with black-on-white text at a
+/// readable size.
+fn accessible_html(language: Language) -> String {
+    let phrases = phrases(language);
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="{lang}">
+<head>
+<meta charset="utf-8">
+<title>{heading}</title>
+</head>
+<body style="margin:0;padding:24px;background:#ffffff;color:#000000;font-family:Arial,Helvetica,sans-serif;font-size:20px;line-height:1.6">
+<main>
+<h1 style="font-size:28px;margin:0 0 16px">{heading}</h1>
+<p>{did_you_know}</p>{{{{feedback_link}}}}
+</main>
+<footer style="margin-top:32px;border-top:2px solid #000000;padding-top:16px">
+<p>{reason}</p>
+<p><a href="{{{{preferences_url}}}}" style="color:#0000ee;text-decoration:underline">{manage_all}</a>{{{{unsubscribe_link}}}}</p>
+</footer>
+</body>
+</html>"#,
+        lang = language.name(),
+        heading = phrases.heading,
+        did_you_know = phrases.did_you_know,
+        reason = phrases.reason,
+        manage_all = phrases.manage_all,
+    )
+}
+
+const ACCESSIBLE_LINK_STYLE: &str = r#" style="color:#0000ee;text-decoration:underline""#;
+
+/// Subjects longer than this get the fact trimmed with an ellipsis.
+const MAX_SUBJECT_FACT_CHARS: usize = 60;
+
+/// More links than this in a body, not counting the preferences,
+/// unsubscribe and feedback links, look like spam.
+const MAX_LINKS: usize = 5;
+
+/// An HTML body with images and fewer words of its own text than this reads to
+/// spam filters as an image-only email.
+const MIN_WORDS_WITH_IMAGES: usize = 10;
+
+/// The name each template is kept under in its `Tera`.
+const TEMPLATE: &str = "template";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Placeholder {
+    Fact,
+    PreferencesUrl,
+    UnsubscribeUrl,
+    UnsubscribeLink,
+    FeedbackUrl,
+    FeedbackLink,
+}
+
+impl Placeholder {
+    const ALL: [Placeholder; 6] = [
+        Self::Fact,
+        Self::PreferencesUrl,
+        Self::UnsubscribeUrl,
+        Self::UnsubscribeLink,
+        Self::FeedbackUrl,
+        Self::FeedbackLink,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Fact => "fact",
+            Self::PreferencesUrl => "preferences_url",
+            Self::UnsubscribeUrl => "unsubscribe_url",
+            Self::UnsubscribeLink => "unsubscribe_link",
+            Self::FeedbackUrl => "feedback_url",
+            Self::FeedbackLink => "feedback_link",
+        }
+    }
+}
+
+/// How a template's values are made safe.
+#[derive(Clone, Copy)]
+enum Kind {
+    Subject,
+    Text,
+    Html { link_style: &'static str },
+}
+
+/// A parsed template. Tera only finds an unknown placeholder when it renders,
+/// so each one is rendered once with sample values when it's parsed, and a
+/// typo in a secret fails at boot rather than in the middle of a send.
+pub struct Template {
+    kind: Kind,
+    phrases: &'static Phrases,
+    tera: Tera,
+}
+
+impl Template {
+    fn parse(
+        name: &str,
+        kind: Kind,
+        language: Language,
+        source: &str,
+    ) -> Result<Self, anyhow::Error> {
+        let mut tera = Tera::default();
+        // Values are made safe for each kind of template before they go in,
+        // so Tera mustn't escape them again.
+        tera.autoescape_on(Vec::new());
+        tera.add_raw_template(TEMPLATE, source)
+            .map_err(|e| anyhow!("{name} doesn't parse: {}", describe(&e)))?;
+
+        let template = Self {
+            kind,
+            phrases: phrases(language),
+            tera,
+        };
+        template
+            .try_render(&Values::SAMPLE)
+            .map_err(|e| anyhow!("{name} doesn't render: {}", describe(&e)))?;
+
+        Ok(template)
+    }
+
+    fn try_render(&self, values: &Values) -> Result<String, tera::Error> {
+        let mut context = Context::new();
+        for placeholder in Placeholder::ALL {
+            context.insert(placeholder.name(), &self.value(placeholder, values));
+        }
+
+        self.tera.render(TEMPLATE, &context)
+    }
+
+    /// Every placeholder is always given a value and the template rendered
+    /// when it was parsed, so this only fails on something like a filter
+    /// that rejects one recipient's value. That's logged, and renders as
+    /// nothing.
+    pub fn render(&self, values: &Values) -> String {
+        self.try_render(values).unwrap_or_else(|e| {
+            tracing::error!("Couldn't render a template: {}", describe(&e));
+            String::new()
+        })
+    }
+
+    fn value(&self, placeholder: Placeholder, values: &Values) -> String {
+        let unsubscribe_url = values.unsubscribe_url.unwrap_or_default();
+        let feedback_url = values.feedback_url.unwrap_or_default();
+        let Phrases {
+            unsubscribe,
+            unsubscribe_in_one_click,
+            feedback,
+            not_really,
+            yes,
+            ..
+        } = self.phrases;
+
+        match (self.kind, placeholder) {
+            (Kind::Subject, Placeholder::Fact) => teaser(values.fact),
+            (Kind::Subject, Placeholder::UnsubscribeLink) => String::new(),
+            (Kind::Subject, Placeholder::PreferencesUrl) => values.preferences_url.to_string(),
+            (Kind::Subject, Placeholder::UnsubscribeUrl) => unsubscribe_url.to_string(),
+            (Kind::Subject, Placeholder::FeedbackLink) => String::new(),
+            (Kind::Subject, Placeholder::FeedbackUrl) => feedback_url.to_string(),
+
+            (Kind::Text, Placeholder::Fact) => sanitize::plain_text(values.fact),
+            (Kind::Text, Placeholder::PreferencesUrl) => values.preferences_url.to_string(),
+            (Kind::Text, Placeholder::UnsubscribeUrl) => unsubscribe_url.to_string(),
+            (Kind::Text, Placeholder::UnsubscribeLink) => values
+                .unsubscribe_url
+                .map(|url| format!("\n{unsubscribe_in_one_click} {url}"))
+                .unwrap_or_default(),
+            (Kind::Text, Placeholder::FeedbackUrl) => feedback_url.to_string(),
+            (Kind::Text, Placeholder::FeedbackLink) => values
+                .feedback_url
+                .map(|url| {
+                    format!("\n\n{feedback} 😿 {not_really}: {url}/0 | 😺 {yes}: {url}/{MAX_SCORE}")
+                })
+                .unwrap_or_default(),
+
+            (Kind::Html { .. }, Placeholder::Fact) => sanitize::html_text(values.fact),
+            (Kind::Html { .. }, Placeholder::PreferencesUrl) => {
+                html::escape(values.preferences_url)
+            }
+            (Kind::Html { .. }, Placeholder::UnsubscribeUrl) => html::escape(unsubscribe_url),
+            (Kind::Html { link_style }, Placeholder::UnsubscribeLink) => values
+                .unsubscribe_url
+                .map(|url| {
+                    format!(
+                        r#" | <a href="{}"{link_style}>{unsubscribe}</a>"#,
+                        html::escape(url)
+                    )
+                })
+                .unwrap_or_default(),
+            (Kind::Html { .. }, Placeholder::FeedbackUrl) => html::escape(feedback_url),
+            (Kind::Html { link_style }, Placeholder::FeedbackLink) => values
+                .feedback_url
+                .map(|url| {
+                    let url = html::escape(url);
+                    format!(
+                        "\n<p>{feedback} <a href=\"{url}/0\"{link_style}>😿 {not_really}</a> | <a href=\"{url}/{MAX_SCORE}\"{link_style}>😺 {yes}</a></p>"
+                    )
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// A Tera error with its causes, which say what's actually wrong.
+fn describe(e: &tera::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = std::error::Error::source(e);
+    while let Some(cause) = source {
+        message.push_str(&format!(": {cause}"));
+        source = cause.source();
+    }
+
+    message
+}
+
+/// What the placeholders are filled in with for one recipient.
+pub struct Values<'a> {
+    pub fact: &'a str,
+    pub preferences_url: &'a str,
+    /// Only set when `UNSUBSCRIBE_SIGNING_KEY` is configured.
+    pub unsubscribe_url: Option<&'a str>,
+    /// Without a score on the end. Only set for a recipient with a token.
+    pub feedback_url: Option<&'a str>,
+}
+
+impl Values<'static> {
+    /// What templates are tried out with when they're parsed.
+    const SAMPLE: Self = Self {
+        fact: "cats sleep for around 13 to 16 hours a day",
+        preferences_url: "https://example.com/preferences/token",
+        unsubscribe_url: Some("https://example.com/unsubscribe/token"),
+        feedback_url: Some("https://example.com/feedback/token"),
+    };
+}
+
+/// The daily email's templates in one language.
+struct Variant {
+    subject: Template,
+    text: Template,
+    standard_html: Template,
+    accessible_html: Template,
+}
+
+/// The daily email's templates, in every language.
+pub struct Templates {
+    /// In the order of `Language::ALL`.
+    variants: Vec<Variant>,
+}
+
+/// The sources of one language's configurable templates, with defaults
+/// filled in.
+struct Sources {
+    language: Language,
+    subject: String,
+    /// Which secret `subject` came from, if any.
+    subject_secret: String,
+    text: String,
+    /// Set when the plain-text body is generated from the HTML one.
+    text_generated: bool,
+    html: String,
+    /// Which secrets the bodies came from: the language's own or English.
+    body_language: Language,
+}
+
+impl Sources {
+    fn new(
+        language: Language,
+        subject: Option<String>,
+        text: Option<String>,
+        html: Option<String>,
+    ) -> Self {
+        let phrases = phrases(language);
+        let (text, text_generated) = match (text, &html) {
+            (Some(text), _) => (text, false),
+            (None, Some(html)) => (html_text::to_text(html), true),
+            (None, None) => (default_text(phrases), false),
+        };
+
+        Self {
+            language,
+            subject: subject.unwrap_or_else(|| phrases.subject.to_string()),
+            subject_secret: language.secret("EMAIL_SUBJECT"),
+            text,
+            text_generated,
+            html: html.unwrap_or_else(|| default_standard_html(phrases)),
+            body_language: language,
+        }
+    }
+
+    /// `language`'s templates from `store`, falling back to the English
+    /// subject and bodies where it has none of its own.
+    fn from_secrets(store: &SecretStore, language: Language) -> Self {
+        let own = |secret: &str| store.get(&language.secret(secret));
+        let (subject, subject_secret) = match own("EMAIL_SUBJECT") {
+            Some(subject) => (Some(subject), language.secret("EMAIL_SUBJECT")),
+            None => (store.get("EMAIL_SUBJECT"), "EMAIL_SUBJECT".to_string()),
+        };
+        // The bodies go together, so a language with either of its own
+        // doesn't mix them with English.
+        let (text, html, body_language) =
+            match (own("EMAIL_TEMPLATE_TEXT"), own("EMAIL_TEMPLATE_HTML")) {
+                (None, None) => (
+                    store.get("EMAIL_TEMPLATE_TEXT"),
+                    store.get("EMAIL_TEMPLATE_HTML"),
+                    Language::En,
+                ),
+                (text, html) => (text, html, language),
+            };
+
+        Self {
+            subject_secret,
+            body_language,
+            ..Self::new(language, subject, text, html)
+        }
+    }
+
+    /// Whether `store` has any templates of `language`'s own.
+    fn customised(store: &SecretStore, language: Language) -> bool {
+        [
+            "EMAIL_SUBJECT",
+            "EMAIL_TEMPLATE_TEXT",
+            "EMAIL_TEMPLATE_HTML",
+        ]
+        .iter()
+        .any(|secret| store.get(&language.secret(secret)).is_some())
+    }
+
+    fn html_name(&self) -> String {
+        self.body_language.secret("EMAIL_TEMPLATE_HTML")
+    }
+
+    fn text_name(&self) -> String {
+        if self.text_generated {
+            format!("the plain-text version of {}", self.html_name())
+        } else {
+            self.body_language.secret("EMAIL_TEMPLATE_TEXT")
+        }
+    }
+}
+
+impl Variant {
+    fn parse(sources: &Sources) -> Result<Self, anyhow::Error> {
+        let language = sources.language;
+
+        Ok(Self {
+            subject: Template::parse(
+                &sources.subject_secret,
+                Kind::Subject,
+                language,
+                &sources.subject,
+            )?,
+            text: Template::parse(&sources.text_name(), Kind::Text, language, &sources.text)?,
+            standard_html: Template::parse(
+                &sources.html_name(),
+                Kind::Html { link_style: "" },
+                language,
+                &sources.html,
+            )?,
+            accessible_html: Template::parse(
+                &format!("the accessible template ({})", language.name()),
+                Kind::Html {
+                    link_style: ACCESSIBLE_LINK_STYLE,
+                },
+                language,
+                &accessible_html(language),
+            )?,
+        })
+    }
+}
+
+impl Templates {
+    pub fn from_secrets(store: &SecretStore) -> Result<Self, anyhow::Error> {
+        let variants = Language::ALL
+            .into_iter()
+            .map(|language| Variant::parse(&Sources::from_secrets(store, language)))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { variants })
+    }
+
+    fn variant(&self, language: Language) -> &Variant {
+        let idx = Language::ALL
+            .iter()
+            .position(|candidate| *candidate == language)
+            .unwrap_or(0);
+
+        &self.variants[idx]
+    }
+
+    /// The subject line, which always ends up on a single line.
+    pub fn subject(&self, language: Language, values: &Values) -> String {
+        sanitize::header_value(&self.variant(language).subject.render(values))
+    }
+
+    pub fn text(&self, language: Language, values: &Values) -> String {
+        self.variant(language).text.render(values)
+    }
+
+    pub fn html(&self, language: Language, format: EmailFormat, values: &Values) -> String {
+        let variant = self.variant(language);
+        match format {
+            EmailFormat::Standard => variant.standard_html.render(values),
+            EmailFormat::Accessible => variant.accessible_html.render(values),
+        }
+    }
+}
+
+/// The fact cut down to a readable length for the subject line.
+pub fn teaser(fact: &str) -> String {
+    let fact = sanitize::header_value(fact);
+    if fact.chars().count() <= MAX_SUBJECT_FACT_CHARS {
+        return fact;
+    }
+
+    let teaser: String = fact.chars().take(MAX_SUBJECT_FACT_CHARS - 1).collect();
+    format!("{}…", teaser.trim_end())
+}
+
+#[derive(Serialize)]
+pub struct LintWarning {
+    /// Which template it's about, e.g. `EMAIL_TEMPLATE_HTML` or
+    /// `EMAIL_SUBJECT_FR`.
+    template: String,
+    /// `invalid_placeholder`, `missing_unsubscribe`, `image_only` or
+    /// `too_many_links`.
+    code: &'static str,
+    message: String,
+}
+
+impl LintWarning {
+    fn new(template: &str, code: &'static str, message: String) -> Self {
+        Self {
+            template: template.to_string(),
+            code,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Deliverability problems with the templates in `store`: the English ones,
+/// and those of each language with any of its own.
+pub fn lint_secrets(store: &SecretStore) -> Vec<LintWarning> {
+    Language::ALL
+        .into_iter()
+        .filter(|language| *language == Language::En || Sources::customised(store, *language))
+        .flat_map(|language| lint(&Sources::from_secrets(store, language)))
+        .collect()
+}
+
+fn lint(sources: &Sources) -> Vec<LintWarning> {
+    let mut warnings = Vec::new();
+    let language = sources.language;
+    // Another language's checks only cover the templates of its own, not the
+    // English ones it falls back to.
+    let own = |secret_language: Language| language == Language::En || secret_language == language;
+    let subject_language = if sources.subject_secret == "EMAIL_SUBJECT" {
+        Language::En
+    } else {
+        language
+    };
+
+    let mut templates = Vec::new();
+    if own(subject_language) {
+        templates.push((
+            sources.subject_secret.clone(),
+            Kind::Subject,
+            &sources.subject,
+        ));
+    }
+    if own(sources.body_language) {
+        // A generated plain-text body has the HTML one's placeholders and
+        // links, so it would only repeat its warnings.
+        if !sources.text_generated {
+            templates.push((sources.text_name(), Kind::Text, &sources.text));
+        }
+        templates.push((
+            sources.html_name(),
+            Kind::Html { link_style: "" },
+            &sources.html,
+        ));
+    }
+
+    for (name, kind, source) in templates {
+        let name = name.as_str();
+        if let Err(e) = Template::parse(name, kind, language, source) {
+            warnings.push(LintWarning::new(
+                name,
+                "invalid_placeholder",
+                format!(
+                    "{e}; the placeholders are fact, preferences_url, unsubscribe_url, unsubscribe_link, feedback_url and feedback_link"
+                ),
+            ));
+        }
+
+        let links = match kind {
+            Kind::Subject => continue,
+            Kind::Text => source.matches("http://").count() + source.matches("https://").count(),
+            Kind::Html { .. } => opening_tags(source, "a")
+                .iter()
+                .filter(|tag| {
+                    !mentions(
+                        tag,
+                        &[
+                            Placeholder::PreferencesUrl,
+                            Placeholder::UnsubscribeUrl,
+                            Placeholder::FeedbackUrl,
+                        ],
+                    )
+                })
+                .count(),
+        };
+        if links > MAX_LINKS {
+            warnings.push(LintWarning::new(
+                name,
+                "too_many_links",
+                format!("{name} has {links} links besides the preferences, unsubscribe and feedback ones; more than {MAX_LINKS} looks like spam"),
+            ));
+        }
+
+        let unsubscribe = mentions(
+            source,
+            &[
+                Placeholder::PreferencesUrl,
+                Placeholder::UnsubscribeUrl,
+                Placeholder::UnsubscribeLink,
+            ],
+        );
+        if !unsubscribe {
+            warnings.push(LintWarning::new(
+                name,
+                "missing_unsubscribe",
+                format!("{name} has no way to unsubscribe; add {{{{unsubscribe_link}}}}, {{{{unsubscribe_url}}}} or {{{{preferences_url}}}}"),
+            ));
+        }
+
+        if matches!(kind, Kind::Html { .. }) && !opening_tags(source, "img").is_empty() {
+            let words = without_tags(&html_text::to_text(source))
+                .split_whitespace()
+                .filter(|word| word.chars().any(char::is_alphanumeric))
+                .count();
+            if words < MIN_WORDS_WITH_IMAGES {
+                warnings.push(LintWarning::new(
+                    name,
+                    "image_only",
+                    format!("{name} is mostly images, with {words} words of its own text; spam filters distrust emails without much text"),
+                ));
+            }
+        }
+    }
+
+    warnings
+}
+
+/// Where each Tera tag - `{{ ... }}`, `{% ... %}` or `{# ... #}` - in
+/// `source` starts and ends, up to the first unclosed one.
+fn tag_spans(source: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut from = 0;
+
+    while let Some(start) = ["{{", "{%", "{#"]
+        .iter()
+        .filter_map(|open| source[from..].find(open).map(|idx| from + idx))
+        .min()
+    {
+        let close = match &source[start..start + 2] {
+            "{{" => "}}",
+            "{%" => "%}",
+            _ => "#}",
+        };
+        let Some(len) = source[start + 2..].find(close) else {
+            break;
+        };
+        let end = start + 2 + len + 2;
+        spans.push((start, end));
+        from = end;
+    }
+
+    spans
+}
+
+/// Whether any tag in `source` uses one of `placeholders`, e.g.
+/// `{{ unsubscribe_url }}` or `{% if unsubscribe_url %}`.
+fn mentions(source: &str, placeholders: &[Placeholder]) -> bool {
+    tag_spans(source).into_iter().any(|(start, end)| {
+        source[start + 2..end - 2]
+            .split(|c: char| !c.is_alphanumeric() && c != '_')
+            .any(|word| placeholders.iter().any(|p| p.name() == word))
+    })
+}
+
+fn without_tags(source: &str) -> String {
+    let mut text = String::new();
+    let mut last = 0;
+    for (start, end) in tag_spans(source) {
+        text.push_str(&source[last..start]);
+        last = end;
+    }
+    text.push_str(&source[last..]);
+
+    text
+}
+
+/// Each `<name ...>` tag in `html`, lowercased.
+fn opening_tags(html: &str, name: &str) -> Vec<String> {
+    let lower = html.to_lowercase();
+    let open = format!("<{name}");
+
+    lower
+        .match_indices(&open)
+        .filter(|(idx, _)| {
+            lower[idx + open.len()..]
+                .starts_with(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        })
+        .map(|(idx, _)| {
+            let end = lower[idx..]
+                .find('>')
+                .map_or(lower.len(), |end| idx + end + 1);
+            lower[idx..end].to_string()
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LintRequest {
+    /// Which language's built-in templates fill in the ones left out.
+    #[serde(default)]
+    language: Language,
+    subject: Option<String>,
+    text: Option<String>,
+    html: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct LintReport {
+    warnings: Vec<LintWarning>,
+}
+
+/// `POST /admin/templates/lint` - `{"subject": "...", "text": "...", "html":
+/// "..."}`, any of them, checked as they'd be sent, with the defaults for the
+/// ones left out (in `"language": "fr"`, say, or English).
+pub async fn lint_templates(StrictJson(req): StrictJson<LintRequest>) -> Json<LintReport> {
+    Json(LintReport {
+        warnings: lint(&Sources::new(req.language, req.subject, req.text, req.html)),
+    })
+}
@@ -0,0 +1,106 @@
+use chrono::FixedOffset;
+use serde::Deserialize;
+
+/// The time zone a subscriber's delivery hour is in. It's a fixed offset from
+/// UTC, so it doesn't follow daylight saving time, stored in the `utc_offset`
+/// column as minutes east of UTC. Without one, it's `SCHEDULE_TIMEZONE`'s.
+///
+/// Deserializes from an offset like "+05:30" or "-03:00", or "UTC", with an
+/// empty string for the schedule's zone.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Timezone {
+    #[default]
+    Schedule,
+    Offset(i32),
+}
+
+impl TryFrom<String> for Timezone {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        Self::parse(&name).ok_or_else(|| {
+            format!("{name:?} isn't a time zone: give an offset from UTC like +05:30, or UTC")
+        })
+    }
+}
+
+impl Timezone {
+    /// The furthest zones from UTC there are, in minutes.
+    const MIN_OFFSET: i32 = -12 * 60;
+    const MAX_OFFSET: i32 = 14 * 60;
+
+    /// The offsets the preference center offers: every whole hour, and the
+    /// half and three-quarter hours in use somewhere.
+    pub fn common() -> impl Iterator<Item = Self> {
+        let odd = [-210, 210, 270, 330, 345, 390, 525, 570, 630, 765];
+        let mut minutes: Vec<i32> = (-12..=14).map(|hours| hours * 60).chain(odd).collect();
+        minutes.sort_unstable();
+
+        std::iter::once(Self::Schedule).chain(minutes.into_iter().map(Self::Offset))
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        let (sign, rest) = match name.trim() {
+            "" => return Some(Self::Schedule),
+            "UTC" | "utc" | "Z" => return Some(Self::Offset(0)),
+            name => match (name.strip_prefix('+'), name.strip_prefix('-')) {
+                (Some(rest), _) => (1, rest),
+                (_, Some(rest)) => (-1, rest),
+                _ => return None,
+            },
+        };
+        let (hours, minutes) = rest.split_once(':').unwrap_or((rest, "0"));
+        let (hours, minutes) = (hours.parse::<i32>().ok()?, minutes.parse::<i32>().ok()?);
+        if !(0..60).contains(&minutes) {
+            return None;
+        }
+
+        Self::from_minutes(Some(i64::from(sign * (hours * 60 + minutes))))
+    }
+
+    /// The zone in a `utc_offset` column.
+    pub fn from_minutes(minutes: Option<i64>) -> Option<Self> {
+        match minutes {
+            None => Some(Self::Schedule),
+            Some(minutes) => i32::try_from(minutes)
+                .ok()
+                .filter(|minutes| (Self::MIN_OFFSET..=Self::MAX_OFFSET).contains(minutes))
+                .map(Self::Offset),
+        }
+    }
+
+    /// What's kept in the `utc_offset` column.
+    pub fn minutes(&self) -> Option<i32> {
+        match self {
+            Self::Schedule => None,
+            Self::Offset(minutes) => Some(*minutes),
+        }
+    }
+
+    pub fn offset(&self) -> Option<FixedOffset> {
+        self.minutes()
+            .and_then(|minutes| FixedOffset::east_opt(minutes * 60))
+    }
+
+    /// Like `+05:30`, as in JSON bodies and HTML forms. The schedule's zone
+    /// is an empty string.
+    pub fn name(&self) -> String {
+        match self {
+            Self::Schedule => String::new(),
+            Self::Offset(minutes) => {
+                let sign = if *minutes < 0 { '-' } else { '+' };
+                let minutes = minutes.abs();
+                format!("{sign}{:02}:{:02}", minutes / 60, minutes % 60)
+            }
+        }
+    }
+
+    /// What the preference center calls this zone.
+    pub fn label(&self) -> String {
+        match self {
+            Self::Schedule => "Same as Cat Facts".to_string(),
+            Self::Offset(_) => format!("UTC{}", self.name()),
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use anyhow::anyhow;
+use serde::Deserialize;
+use shuttle_secrets::SecretStore;
+
+const SITEVERIFY_URL: &str = "https://challenges.cloudflare.com/turnstile/v0/siteverify";
+
+/// The form field the Turnstile widget puts its token in.
+pub const RESPONSE_FIELD: &str = "cf-turnstile-response";
+
+/// Cloudflare Turnstile CAPTCHA checks for form signups. Enabled when both the
+/// `TURNSTILE_SITE_KEY` and `TURNSTILE_SECRET_KEY` secrets are set.
+#[derive(Clone)]
+pub struct Turnstile {
+    site_key: String,
+    secret_key: String,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct SiteverifyResponse {
+    success: bool,
+    #[serde(default, rename = "error-codes")]
+    error_codes: Vec<String>,
+}
+
+impl Turnstile {
+    pub fn from_secrets(store: &SecretStore) -> Option<Self> {
+        Some(Self {
+            site_key: store.get("TURNSTILE_SITE_KEY")?,
+            secret_key: store.get("TURNSTILE_SECRET_KEY")?,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    pub fn site_key(&self) -> &str {
+        &self.site_key
+    }
+
+    /// Asks Cloudflare whether `token` is a valid, unused challenge response.
+    pub async fn verify(&self, token: &str) -> Result<bool, anyhow::Error> {
+        let res: SiteverifyResponse = self
+            .client
+            .post(SITEVERIFY_URL)
+            .form(&[("secret", self.secret_key.as_str()), ("response", token)])
+            .send()
+            .await
+            .map_err(|e| anyhow!("couldn't reach Turnstile: {e}"))?
+            .json()
+            .await
+            .map_err(|e| anyhow!("unexpected response from Turnstile: {e}"))?;
+
+        if !res.success {
+            tracing::info!("Turnstile rejected a signup: {:?}", res.error_codes);
+        }
+
+        Ok(res.success)
+    }
+}
@@ -0,0 +1,207 @@
+//! One-click unsubscribe links. Each email links to `GET /unsubscribe?token=`
+//! with the subscriber's token signed under the `UNSUBSCRIBE_SIGNING_KEY`
+//! secret, so only links this service issued work, and changing the key
+//! retires them all.
+//!
+//! The signature doesn't protect the token itself: anyone with a subscriber's
+//! token can already unsubscribe them from the preference center
+//! (`POST /preferences/:token/unsubscribe`), which is why tokens are long and
+//! random and only ever sent to the subscriber.
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse},
+};
+use lettre::message::header::{Header, HeaderName, HeaderValue};
+use serde::Deserialize;
+use shuttle_secrets::SecretStore;
+use std::sync::Arc;
+use utoipa::IntoParams;
+
+use crate::{crypto, html, preferences, AppState};
+
+const TITLE: &str = "Cat Facts - Unsubscribe";
+
+/// Prefixed to the token a stay-subscribed link signs.
+const STAY_SUBSCRIBED: &str = "stay-subscribed:";
+
+#[derive(Clone)]
+pub struct UnsubscribeSigner {
+    key: Vec<u8>,
+}
+
+impl UnsubscribeSigner {
+    pub fn from_secrets(store: &SecretStore) -> Option<Self> {
+        store
+            .get("UNSUBSCRIBE_SIGNING_KEY")
+            .filter(|key| !key.is_empty())
+            .map(|key| Self {
+                key: key.into_bytes(),
+            })
+    }
+
+    /// `<token>.<signature>`, for use in an unsubscribe link.
+    pub fn sign(&self, token: &str) -> String {
+        format!("{token}.{}", self.signature(token))
+    }
+
+    /// Returns the subscriber token from a signed token, if the signature is
+    /// valid. Links signed with HMAC-SHA1, in emails sent before links were
+    /// signed with SHA-256, still work.
+    pub fn verify<'a>(&self, signed: &'a str) -> Option<&'a str> {
+        let (token, signature) = signed.rsplit_once('.')?;
+        let legacy = crypto::hex(&crypto::legacy_hmac_sha1(&self.key, token.as_bytes()));
+        (crypto::constant_time_eq(&self.signature(token), signature)
+            || crypto::constant_time_eq(&legacy, signature))
+        .then_some(token)
+    }
+
+    /// The link in a re-engagement email (see `reengage`). It's signed apart
+    /// from unsubscribe links, so neither works as the other.
+    pub fn stay_subscribed_url(&self, public_url: &str, token: &str) -> String {
+        format!(
+            "{}/stay-subscribed?token={token}.{}",
+            public_url.trim_end_matches('/'),
+            self.signature(&format!("{STAY_SUBSCRIBED}{token}"))
+        )
+    }
+
+    /// Like `verify`, for a stay-subscribed link's token.
+    pub fn verify_stay_subscribed<'a>(&self, signed: &'a str) -> Option<&'a str> {
+        let (token, signature) = signed.rsplit_once('.')?;
+        let expected = self.signature(&format!("{STAY_SUBSCRIBED}{token}"));
+        crypto::constant_time_eq(&expected, signature).then_some(token)
+    }
+
+    pub fn unsubscribe_url(&self, public_url: &str, token: &str) -> String {
+        format!(
+            "{}/unsubscribe?token={}",
+            public_url.trim_end_matches('/'),
+            self.sign(token)
+        )
+    }
+
+    fn signature(&self, message: &str) -> String {
+        crypto::hex(&crypto::hmac_sha256(&self.key, message.as_bytes()))
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UnsubscribeQuery {
+    /// The signed token from the link in the email.
+    token: String,
+}
+
+/// `GET /unsubscribe?token=` - removes the subscriber.
+#[utoipa::path(
+    get,
+    path = "/unsubscribe",
+    tag = "subscriptions",
+    params(UnsubscribeQuery),
+    responses(
+        (status = 200, description = "Unsubscribed", body = String, content_type = "text/html"),
+        (status = 404, description = "The link isn't valid (anymore)", body = String, content_type = "text/html"),
+        (status = 503, description = "Unsubscribe links aren't set up", body = String, content_type = "text/html"),
+    )
+)]
+pub async fn unsubscribe(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<UnsubscribeQuery>,
+) -> Result<impl IntoResponse, impl IntoResponse> {
+    let Some(signer) = &state.unsubscribe else {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            Html(html::page(
+                TITLE,
+                "<p>Unsubscribe links aren't set up. Use the preferences link in your email instead.</p>",
+            )),
+        ));
+    };
+
+    let Some(token) = signer.verify(&query.token) else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Html(html::page(
+                TITLE,
+                "<p>This unsubscribe link isn't valid.</p>",
+            )),
+        ));
+    };
+
+    match state.db.remove_subscriber(token).await {
+        Ok(true) => Ok(Html(html::page(TITLE, preferences::UNSUBSCRIBED_MESSAGE))),
+        Ok(false) => Err((
+            StatusCode::NOT_FOUND,
+            Html(html::page(
+                TITLE,
+                "<p>This link isn't valid anymore. You may already have unsubscribed.</p>",
+            )),
+        )),
+        Err(e) => Err(html::server_error(TITLE, e)),
+    }
+}
+
+/// `POST /unsubscribe?token=` - what mail clients send for an RFC 8058
+/// one-click unsubscribe, which does the same as following the link.
+#[utoipa::path(
+    post,
+    path = "/unsubscribe",
+    tag = "subscriptions",
+    params(UnsubscribeQuery),
+    responses(
+        (status = 200, description = "Unsubscribed", body = String, content_type = "text/html"),
+        (status = 404, description = "The link isn't valid (anymore)", body = String, content_type = "text/html"),
+        (status = 503, description = "Unsubscribe links aren't set up", body = String, content_type = "text/html"),
+    )
+)]
+pub async fn one_click_unsubscribe(
+    state: State<Arc<AppState>>,
+    query: Query<UnsubscribeQuery>,
+) -> impl IntoResponse {
+    unsubscribe(state, query).await
+}
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The `List-Unsubscribe` email header, pointing at a one-click link.
+#[derive(Clone)]
+pub struct ListUnsubscribe(pub String);
+
+impl Header for ListUnsubscribe {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("List-Unsubscribe")
+    }
+
+    fn parse(s: &str) -> Result<Self, BoxError> {
+        Ok(Self(
+            s.trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string(),
+        ))
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), format!("<{}>", self.0))
+    }
+}
+
+/// The `List-Unsubscribe-Post` header, which tells mail clients the
+/// `List-Unsubscribe` link accepts a one-click `POST`.
+#[derive(Clone)]
+pub struct ListUnsubscribePost;
+
+impl Header for ListUnsubscribePost {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("List-Unsubscribe-Post")
+    }
+
+    fn parse(_: &str) -> Result<Self, BoxError> {
+        Ok(Self)
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), "List-Unsubscribe=One-Click".to_string())
+    }
+}
@@ -0,0 +1,173 @@
+//! Up and down votes on facts, one per fact from each client address. A fact's
+//! score is its upvotes minus its downvotes; facts voted below zero are left
+//! out of the fact of the day while there are others to pick.
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::rate_limit::ClientIp;
+use crate::store::{self, FromRow, Row, Statement, Value};
+use crate::strict::StrictJson;
+use crate::{error::ApiError, AppState, CatFactRecord, CATFACT_COLUMNS};
+
+const DEFAULT_TOP: u32 = 10;
+const MAX_TOP: u32 = 100;
+
+/// A fact's net score, as a correlated subquery on `catfacts`.
+pub const SCORE: &str =
+    "coalesce((SELECT sum(value) FROM votes WHERE votes.catfact_id = catfacts.id), 0)";
+
+#[derive(Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+impl Direction {
+    fn value(self) -> i64 {
+        match self {
+            Self::Up => 1,
+            Self::Down => -1,
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Vote {
+    #[schema(inline)]
+    vote: Direction,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Tally {
+    catfact_id: i64,
+    score: i64,
+    upvotes: i64,
+    downvotes: i64,
+}
+
+impl FromRow for Tally {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            catfact_id: store::integer(row, 0)?,
+            score: store::integer(row, 1)?,
+            upvotes: store::integer(row, 2)?,
+            downvotes: store::integer(row, 3)?,
+        })
+    }
+}
+
+/// `POST /catfact/:id/vote` - `{"vote": "up"}` or `{"vote": "down"}`. Voting
+/// again on the same fact replaces the earlier vote. Votes are keyed on a hash
+/// of the client's address rather than the address itself; see `privacy`.
+#[utoipa::path(
+    post,
+    path = "/catfact/{key}/vote",
+    tag = "votes",
+    params(("key" = i64, Path, description = "The fact's numeric id")),
+    request_body = Vote,
+    responses(
+        (status = 200, description = "The fact's votes so far", body = Tally),
+        (status = 404, description = "No such fact", body = ErrorBody),
+    )
+)]
+pub async fn vote(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    client: ClientIp,
+    StrictJson(vote): StrictJson<Vote>,
+) -> Result<Json<Tally>, ApiError> {
+    let res = state
+        .db
+        .batch([
+            Statement::with_args(
+                "INSERT INTO votes (catfact_id, voter, value)
+                SELECT id, ?2, ?3 FROM catfacts WHERE id = ?1 AND needs_review = 0
+                ON CONFLICT (catfact_id, voter) DO UPDATE SET
+                value = excluded.value,
+                updated_at = current_timestamp",
+                &[
+                    Value::from(id),
+                    Value::from(state.privacy.voter(client.0, Utc::now().date_naive())),
+                    Value::from(vote.vote.value()),
+                ],
+            ),
+            Statement::with_args(
+                "SELECT ?1, coalesce(sum(value), 0), coalesce(sum(value > 0), 0),
+                coalesce(sum(value < 0), 0)
+                FROM votes WHERE catfact_id = ?1",
+                &[id],
+            ),
+        ])
+        .await?;
+
+    if res.first().map_or(0, |voted| voted.rows_affected) == 0 {
+        return Err(ApiError::NotFound(format!("There's no cat fact {id}")));
+    }
+
+    res.get(1)
+        .map(store::first::<Tally>)
+        .transpose()?
+        .flatten()
+        .map(Json)
+        .ok_or_else(|| ApiError::internal("Missing vote tally"))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TopFact {
+    #[serde(flatten)]
+    fact: CatFactRecord,
+    score: i64,
+}
+
+impl FromRow for TopFact {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            fact: CatFactRecord::from_row(row)?,
+            score: store::integer(row, 6)?,
+        })
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TopQuery {
+    /// Up to 100; defaults to 10.
+    limit: Option<u32>,
+}
+
+/// `GET /catfacts/top?limit=10` - the highest-scoring facts, best first. Facts
+/// nobody has voted up aren't included.
+#[utoipa::path(
+    get,
+    path = "/catfacts/top",
+    tag = "votes",
+    params(TopQuery),
+    responses((status = 200, description = "The top facts, each with its score", body = [TopFact]))
+)]
+pub async fn top_facts(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<TopQuery>,
+) -> Result<Json<Vec<TopFact>>, ApiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_TOP).clamp(1, MAX_TOP);
+    let facts = state
+        .db
+        .execute(Statement::with_args(
+            format!(
+                "SELECT {CATFACT_COLUMNS}, {SCORE} AS score FROM catfacts
+                WHERE needs_review = 0 AND score > 0 ORDER BY score DESC, id LIMIT ?"
+            ),
+            &[limit],
+        ))
+        .await
+        .and_then(|res| store::rows::<TopFact>(&res))?;
+
+    Ok(Json(facts))
+}
@@ -0,0 +1,233 @@
+//! A soft cap on subscribers, to stay under the SMTP provider's sending
+//! limits. Set `SUBSCRIBER_CAP` and signups past it join a waitlist instead,
+//! which an admin releases in batches as there's room.
+use anyhow::anyhow;
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use lettre::message::Mailbox;
+use serde::{Deserialize, Serialize};
+use shuttle_secrets::SecretStore;
+use std::sync::Arc;
+
+use crate::store::{self, FromRow, Row, Statement, Store, Value};
+use crate::{
+    confirm, error::ApiError, language::Language, mailer::Email, timezone::Timezone,
+    weekdays::Weekdays, AppState,
+};
+
+const DEFAULT_RELEASE: u32 = 50;
+
+pub fn cap_from_secrets(store: &SecretStore) -> Option<i64> {
+    store.get("SUBSCRIBER_CAP").and_then(|cap| cap.parse().ok())
+}
+
+/// Whether a signup from `email` should go on the waitlist: the cap has been
+/// reached and they aren't already a subscriber.
+pub async fn is_full(db: &dyn Store, cap: i64, email: &str) -> Result<bool, anyhow::Error> {
+    let full = db
+        .execute(Statement::with_args(
+            "SELECT (SELECT count(*) FROM subscribers) >= ?
+            AND NOT EXISTS (SELECT 1 FROM subscribers WHERE lower(trim(email)) = ?)",
+            &[Value::from(cap), Value::from(email)],
+        ))
+        .await
+        .and_then(|res| store::first::<i64>(&res))?;
+
+    Ok(full == Some(1))
+}
+
+/// Puts `email` on the waitlist and lets them know. Errs with a conflict if
+/// they're already on it.
+pub async fn join(
+    state: &AppState,
+    email: &str,
+    delivery_hour: u32,
+    weekdays: Weekdays,
+    language: Language,
+    timezone: Timezone,
+) -> Result<(), ApiError> {
+    let joined = state
+        .db
+        .execute(Statement::with_args(
+            "INSERT INTO waitlist (email, delivery_hour, weekdays, language, utc_offset) VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT (email) DO NOTHING",
+            &[
+                Value::from(email),
+                Value::from(delivery_hour),
+                Value::from(weekdays.mask()),
+                Value::from(language.name()),
+                Value::from(timezone.minutes()),
+            ],
+        ))
+        .await?
+        .rows_affected;
+
+    if joined == 0 {
+        return Err(ApiError::Conflict(
+            "This address is already on the waitlist. We'll email you as soon as there's room!"
+                .to_string(),
+        ));
+    }
+
+    send_waitlisted(state, email).await.map_err(|e| {
+        ApiError::Mail(e.context(format!("couldn't send a waitlist email to {email:?}")))
+    })
+}
+
+async fn send_waitlisted(state: &AppState, to: &str) -> Result<(), anyhow::Error> {
+    let Some(sender) = state.sender.clone() else {
+        return Err(anyhow!(
+            "MAIL_FROM (or GMAIL_USER) isn't a valid email address, so waitlist emails can't be sent"
+        ));
+    };
+    let to: Mailbox = to
+        .parse()
+        .map_err(|e| anyhow!("{to:?} isn't a valid email address: {e}"))?;
+
+    let email = Email::new(&sender, &to, "You're on the Cat Facts waitlist", 
+        "Hey there! Cat Facts is full right now, but you're on the list.\n\nWe'll email you a link to confirm your subscription as soon as there's room.".to_string(),
+    );
+
+    state.mailer.send(&email).await
+}
+
+#[derive(Serialize)]
+pub struct WaitlistStatus {
+    waiting: i64,
+    subscribers: i64,
+    cap: Option<i64>,
+}
+
+/// `GET /admin/waitlist` - how many are waiting, against the cap.
+pub async fn waitlist_status(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<WaitlistStatus>, ApiError> {
+    let res = state
+        .db
+        .batch([
+            "SELECT count(*) FROM waitlist",
+            "SELECT count(*) FROM subscribers",
+        ])
+        .await?;
+    let count = |idx: usize| -> Result<i64, ApiError> {
+        Ok(res
+            .get(idx)
+            .map(store::first::<i64>)
+            .transpose()?
+            .flatten()
+            .unwrap_or(0))
+    };
+
+    Ok(Json(WaitlistStatus {
+        waiting: count(0)?,
+        subscribers: count(1)?,
+        cap: state.subscriber_cap,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct ReleaseQuery {
+    count: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct Released {
+    released: usize,
+    /// Released, but the confirmation email didn't go out. They can sign up
+    /// again to get a fresh link.
+    emails_failed: usize,
+}
+
+struct Waiting {
+    id: i64,
+    email: String,
+    delivery_hour: i64,
+    weekdays: i64,
+    language: String,
+    utc_offset: Option<i64>,
+}
+
+impl FromRow for Waiting {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            id: store::integer(row, 0)?,
+            email: store::text(row, 1)?,
+            delivery_hour: store::integer(row, 2)?,
+            weekdays: store::integer(row, 3)?,
+            language: store::text(row, 4)?,
+            utc_offset: store::optional_integer(row, 5)?,
+        })
+    }
+}
+
+/// `POST /admin/waitlist/release?count=50` - lets the longest-waiting signups
+/// in. They become unconfirmed subscribers and get the usual confirmation
+/// email. This doesn't check the cap, so it's up to the caller how many fit.
+pub async fn release(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ReleaseQuery>,
+) -> Result<Json<Released>, ApiError> {
+    let count = query.count.unwrap_or(DEFAULT_RELEASE);
+    let waiting = state
+        .db
+        .execute(Statement::with_args(
+            "SELECT id, email, delivery_hour, weekdays, language, utc_offset FROM waitlist
+            ORDER BY id LIMIT ?",
+            &[count],
+        ))
+        .await
+        .and_then(|res| store::rows::<Waiting>(&res))?;
+
+    let mut released = Released {
+        released: 0,
+        emails_failed: 0,
+    };
+
+    for waiting in waiting {
+        let token = state
+            .db
+            .execute("SELECT lower(hex(randomblob(16)))")
+            .await
+            .and_then(|res| store::first::<String>(&res))?
+            .ok_or_else(|| ApiError::internal("Couldn't generate a confirmation token"))?;
+
+        let res = state
+            .db
+            .batch([
+                Statement::with_args(
+                    "INSERT INTO subscribers (email, delivery_hour, weekdays, language, utc_offset, token, confirmed, confirmation_token)
+                    SELECT ?1, ?2, ?3, ?5, ?6, lower(hex(randomblob(16))), 0, ?4
+                    WHERE NOT EXISTS (SELECT 1 FROM subscribers WHERE lower(trim(email)) = ?1)",
+                    &[
+                        Value::from(&waiting.email),
+                        Value::from(waiting.delivery_hour),
+                        Value::from(waiting.weekdays),
+                        Value::from(&token),
+                        Value::from(&waiting.language),
+                        Value::from(waiting.utc_offset),
+                    ],
+                ),
+                Statement::with_args("DELETE FROM waitlist WHERE id = ?", &[waiting.id]),
+            ])
+            .await?;
+        // They subscribed some other way while they were waiting.
+        if res.first().map_or(0, |inserted| inserted.rows_affected) == 0 {
+            continue;
+        }
+        released.released += 1;
+
+        if let Err(e) = confirm::send_confirmation(&state, &waiting.email, &token).await {
+            tracing::warn!(
+                "Released {:?} from the waitlist but couldn't email them: {e}",
+                waiting.email
+            );
+            released.emails_failed += 1;
+        }
+    }
+
+    tracing::info!("Released {} signups from the waitlist", released.released);
+
+    Ok(Json(released))
+}
@@ -0,0 +1,154 @@
+use chrono::{NaiveDate, Weekday};
+use serde::Deserialize;
+
+static DAYS: [(Weekday, &str, &str); 7] = [
+    (Weekday::Mon, "mon", "Monday"),
+    (Weekday::Tue, "tue", "Tuesday"),
+    (Weekday::Wed, "wed", "Wednesday"),
+    (Weekday::Thu, "thu", "Thursday"),
+    (Weekday::Fri, "fri", "Friday"),
+    (Weekday::Sat, "sat", "Saturday"),
+    (Weekday::Sun, "sun", "Sunday"),
+];
+
+/// Which days of the week a subscriber gets their email, stored in the
+/// `weekdays` column as a bitmask with Monday in the lowest bit.
+///
+/// Deserializes from a preset ("every_day", "weekdays" or "weekends"), a
+/// comma-separated list of days such as "mon,wed,fri", or a JSON array of days.
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "WeekdaysRepr")]
+pub struct Weekdays(u8);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WeekdaysRepr {
+    Text(String),
+    List(Vec<String>),
+}
+
+impl Weekdays {
+    pub const EVERY_DAY: Self = Self(0b111_1111);
+    pub const WEEKDAYS: Self = Self(0b001_1111);
+    pub const WEEKENDS: Self = Self(0b110_0000);
+
+    const PRESETS: [(Self, &'static str, &'static str); 3] = [
+        (Self::EVERY_DAY, "every_day", "Every day"),
+        (Self::WEEKDAYS, "weekdays", "Weekdays only"),
+        (Self::WEEKENDS, "weekends", "Weekends only"),
+    ];
+
+    /// Reads the `weekdays` column, falling back to every day for anything
+    /// that doesn't name at least one day.
+    pub fn from_mask(mask: i64) -> Self {
+        match u8::try_from(mask & i64::from(Self::EVERY_DAY.0)) {
+            Ok(0) | Err(_) => Self::EVERY_DAY,
+            Ok(mask) => Self(mask),
+        }
+    }
+
+    pub fn mask(&self) -> u8 {
+        self.0
+    }
+
+    /// The bit for `date`'s day of the week, for matching against the column.
+    pub fn bit_for(date: NaiveDate) -> u8 {
+        use chrono::Datelike;
+
+        1 << date.weekday().num_days_from_monday()
+    }
+
+    /// The preset or comma-separated list this parses back from.
+    pub fn name(&self) -> String {
+        if let Some((_, name, _)) = Self::PRESETS.iter().find(|(days, ..)| days == self) {
+            return name.to_string();
+        }
+
+        self.days()
+            .map(|(_, short, _)| *short)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// What the preference center calls this selection.
+    pub fn label(&self) -> String {
+        if let Some((_, _, label)) = Self::PRESETS.iter().find(|(days, ..)| days == self) {
+            return label.to_string();
+        }
+
+        self.days()
+            .map(|(_, _, long)| *long)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// The `<option>`s for a weekday `<select>`. A custom selection made
+    /// through the API gets its own option so saving the form keeps it.
+    pub fn options(selected: Weekdays) -> String {
+        let mut choices: Vec<Weekdays> = Self::PRESETS.iter().map(|(days, ..)| *days).collect();
+        if !choices.contains(&selected) {
+            choices.push(selected);
+        }
+
+        choices
+            .into_iter()
+            .map(|days| {
+                let attr = if days == selected { " selected" } else { "" };
+                format!(
+                    r#"<option value="{}"{attr}>{}</option>"#,
+                    days.name(),
+                    days.label()
+                )
+            })
+            .collect()
+    }
+
+    fn days(&self) -> impl Iterator<Item = &'static (Weekday, &'static str, &'static str)> + '_ {
+        DAYS.iter()
+            .filter(|(day, ..)| self.0 & (1 << day.num_days_from_monday()) != 0)
+    }
+
+    fn parse_day(day: &str) -> Result<u8, String> {
+        let day = day.trim().to_lowercase();
+
+        DAYS.iter()
+            .find(|(_, short, long)| day == *short || day == long.to_lowercase())
+            .map(|(weekday, ..)| 1 << weekday.num_days_from_monday())
+            .ok_or_else(|| format!("{day:?} isn't a day of the week"))
+    }
+
+    fn from_days<'a>(days: impl IntoIterator<Item = &'a str>) -> Result<Self, String> {
+        let mut mask = 0;
+        for day in days {
+            mask |= Self::parse_day(day)?;
+        }
+
+        if mask == 0 {
+            Err("pick at least one day of the week".to_string())
+        } else {
+            Ok(Self(mask))
+        }
+    }
+}
+
+impl Default for Weekdays {
+    fn default() -> Self {
+        Self::EVERY_DAY
+    }
+}
+
+impl TryFrom<WeekdaysRepr> for Weekdays {
+    type Error = String;
+
+    fn try_from(repr: WeekdaysRepr) -> Result<Self, Self::Error> {
+        match repr {
+            WeekdaysRepr::Text(text) => {
+                match Self::PRESETS.iter().find(|(_, name, _)| *name == text) {
+                    Some((days, ..)) => Ok(*days),
+                    None => Self::from_days(text.split(',').filter(|day| !day.trim().is_empty())),
+                }
+            }
+            WeekdaysRepr::List(days) => Self::from_days(days.iter().map(String::as_str)),
+        }
+    }
+}
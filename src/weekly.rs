@@ -0,0 +1,189 @@
+//! A weekly "best of" page: the facts voted up most during an ISO week,
+//! compiled once the week is over and kept at `GET /weekly/:week` (e.g.
+//! `/weekly/2024-W05`). When MQTT is configured, each new page is announced
+//! on `MQTT_WEEKLY_TOPIC` so it can be reposted elsewhere.
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Html,
+};
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use std::sync::Arc;
+
+use crate::store::{self, FromRow, Row, Statement, Store, Value};
+use crate::{html, mqtt::FactPublisher, sanitize, AppState};
+
+/// How many facts make the page.
+const TOP: i64 = 5;
+
+/// An ISO week, written like `2024-W05`.
+#[derive(Clone, Copy)]
+pub struct IsoWeek {
+    year: i32,
+    week: u32,
+}
+
+impl IsoWeek {
+    fn parse(week: &str) -> Option<Self> {
+        let (year, number) = week.split_once("-W")?;
+        let week = Self {
+            year: year.parse().ok()?,
+            week: number.parse().ok()?,
+        };
+
+        week.monday().map(|_| week)
+    }
+
+    /// The week before the one `date` is in.
+    pub fn before(date: NaiveDate) -> Self {
+        let week = (date - Duration::days(7)).iso_week();
+        Self {
+            year: week.year(),
+            week: week.week(),
+        }
+    }
+
+    fn monday(&self) -> Option<NaiveDate> {
+        NaiveDate::from_isoywd_opt(self.year, self.week, Weekday::Mon)
+    }
+
+    fn name(&self) -> String {
+        format!("{}-W{:02}", self.year, self.week)
+    }
+}
+
+struct TopFact {
+    fact: String,
+    score: i64,
+}
+
+impl FromRow for TopFact {
+    fn from_row(row: &Row) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            fact: store::text(row, 0)?,
+            score: store::integer(row, 1)?,
+        })
+    }
+}
+
+/// Compiles the weekly pages. Held by the scheduler.
+pub struct WeeklyDigest {
+    pub public_url: String,
+    pub mqtt: Option<FactPublisher>,
+}
+
+impl WeeklyDigest {
+    /// Stores the page for `week`, replacing any earlier version, and
+    /// announces it. A week nobody voted in gets no page.
+    pub async fn compile(&self, db: &dyn Store, week: IsoWeek) -> Result<(), anyhow::Error> {
+        let Some(monday) = week.monday() else {
+            return Ok(());
+        };
+        let next_monday = monday + Duration::days(7);
+
+        let facts = db
+            .execute(Statement::with_args(
+                "SELECT catfacts.fact, sum(votes.value) AS score FROM votes
+                JOIN catfacts ON catfacts.id = votes.catfact_id
+                WHERE catfacts.needs_review = 0 AND votes.updated_at >= ? AND votes.updated_at < ?
+                GROUP BY catfacts.id HAVING score > 0
+                ORDER BY score DESC, catfacts.id LIMIT ?",
+                &[
+                    Value::from(monday.to_string()),
+                    Value::from(next_monday.to_string()),
+                    Value::from(TOP),
+                ],
+            ))
+            .await
+            .and_then(|res| store::rows::<TopFact>(&res))?;
+
+        let name = week.name();
+        if facts.is_empty() {
+            tracing::info!("No facts were voted up in {name}, so there's no weekly page");
+            return Ok(());
+        }
+
+        db.execute(Statement::with_args(
+            "INSERT INTO weekly_digests (week, html) VALUES (?, ?)
+            ON CONFLICT (week) DO UPDATE SET html = excluded.html, created_at = current_timestamp",
+            &[name.clone(), render(&name, monday, &facts)],
+        ))
+        .await?;
+        tracing::info!("Compiled the weekly page for {name}");
+
+        if let Some(mqtt) = &self.mqtt {
+            let url = format!("{}/weekly/{name}", self.public_url.trim_end_matches('/'));
+            mqtt.publish_weekly(&name, &url).await;
+        }
+
+        Ok(())
+    }
+}
+
+fn render(name: &str, monday: NaiveDate, facts: &[TopFact]) -> String {
+    let items: String = facts
+        .iter()
+        .map(|fact| {
+            let votes = if fact.score == 1 { "vote" } else { "votes" };
+            format!(
+                "<li>{} <small>({} {votes})</small></li>\n",
+                sanitize::html_text(&fact.fact),
+                fact.score
+            )
+        })
+        .collect();
+
+    html::page(
+        &format!("Best cat facts of {name}"),
+        &format!(
+            "<h2>The best cat facts of the week of {}</h2>\n<ol>\n{items}</ol>\n<p><a href=\"/subscribe\">Get a cat fact in your inbox every day</a></p>",
+            monday.format("%-d %B %Y")
+        ),
+    )
+}
+
+/// `GET /weekly/:week` - the best-of page for an ISO week, e.g. `2024-W05`.
+#[utoipa::path(
+    get,
+    path = "/weekly/{week}",
+    tag = "votes",
+    params(("week" = String, Path, description = "An ISO week, e.g. `2024-W05`")),
+    responses(
+        (status = 200, description = "The week's best-of page", body = String, content_type = "text/html"),
+        (status = 404, description = "No page for that week (yet)", body = String, content_type = "text/html"),
+    )
+)]
+pub async fn weekly_page(
+    State(state): State<Arc<AppState>>,
+    Path(week): Path<String>,
+) -> Result<Html<String>, (StatusCode, Html<String>)> {
+    const TITLE: &str = "Best cat facts of the week";
+
+    let not_found = || {
+        (
+            StatusCode::NOT_FOUND,
+            Html(html::page(
+                TITLE,
+                "<p>There's no best-of page for that week. Pages are put together the Monday after each week ends.</p>",
+            )),
+        )
+    };
+
+    let Some(week) = IsoWeek::parse(&week) else {
+        return Err(not_found());
+    };
+
+    match state
+        .db
+        .execute(Statement::with_args(
+            "SELECT html FROM weekly_digests WHERE week = ?",
+            &[week.name()],
+        ))
+        .await
+        .and_then(|res| store::first::<String>(&res))
+    {
+        Ok(Some(page)) => Ok(Html(page)),
+        Ok(None) => Err(not_found()),
+        Err(e) => Err(html::server_error(TITLE, e)),
+    }
+}